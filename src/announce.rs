@@ -0,0 +1,69 @@
+// Spoken track announcements for listening without looking at the screen,
+// through a text-to-speech command set with `announce_command`
+
+use crate::hooks;
+use crate::now_playing::NowPlaying;
+use std::io;
+use std::process::{Child, Stdio};
+
+// Speech engines found on each system, reading the text from CLAP_ANNOUNCEMENT
+#[cfg(target_os = "macos")]
+pub const DEFAULT_COMMAND: &str = "say \"$CLAP_ANNOUNCEMENT\"";
+#[cfg(windows)]
+pub const DEFAULT_COMMAND: &str = "powershell -NoProfile -Command \"Add-Type -AssemblyName System.Speech; \
+    (New-Object System.Speech.Synthesis.SpeechSynthesizer).Speak($env:CLAP_ANNOUNCEMENT)\"";
+#[cfg(not(any(target_os = "macos", windows)))]
+pub const DEFAULT_COMMAND: &str = "espeak-ng \"$CLAP_ANNOUNCEMENT\"";
+
+#[derive(Default)]
+pub struct Announcer {
+    speaking: Option<Child>,
+}
+
+impl Announcer {
+    /// Starts saying which track is playing, cutting off an announcement
+    /// still being spoken.
+    pub fn announce(&mut self, command: &str, now_playing: &NowPlaying) -> io::Result<()> {
+        self.stop();
+        let text = if now_playing.artist.is_empty() {
+            format!("Now playing: {}", now_playing.title)
+        } else {
+            format!("Now playing: {} – {}", now_playing.artist, now_playing.title)
+        };
+        let mut shell = hooks::with_track(command, "announce", now_playing);
+        shell.env("CLAP_ANNOUNCEMENT", text);
+        // Output would scribble over the interface
+        shell.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+        self.speaking = Some(shell.spawn()?);
+        Ok(())
+    }
+
+    /// Whether an announcement is still being spoken.
+    pub fn speaking(&mut self) -> bool {
+        let Some(child) = &mut self.speaking else {
+            return false;
+        };
+        match child.try_wait() {
+            Ok(None) => return true,
+            Ok(Some(status)) if !status.success() => {
+                log::warn!(target: "playback", "the announcement command failed ({})", status);
+            }
+            _ => {}
+        }
+        self.speaking = None;
+        false
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut child) = self.speaking.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Drop for Announcer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
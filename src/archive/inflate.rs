@@ -0,0 +1,374 @@
+// DEFLATE decompressor (RFC 1951), following the structure of zlib's puff,
+// turned inside out so the output is read a piece at a time instead of
+// being decompressed into memory at once
+
+use std::io::{self, Read};
+
+const MAX_BITS: usize = 15;
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// Order in which code length code lengths are stored in dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Back-references reach this far into what was already written
+const WINDOW: usize = 1 << 15;
+
+type Result<T> = std::result::Result<T, &'static str>;
+
+enum Block {
+    // Between blocks, where the next one's header comes unless the last is done
+    Header,
+    // Bytes left of a stored block
+    Stored(usize),
+    Codes(Huffman, Huffman),
+    Done,
+}
+
+/// Decompresses a DEFLATE stream as it is read.
+pub struct Inflater<R> {
+    input: Bits<R>,
+    block: Block,
+    last: bool,
+    window: Vec<u8>,
+    written: usize,
+    // Distance and bytes left of a back-reference being copied out
+    copy: (usize, usize),
+}
+
+impl<R: Read> Inflater<R> {
+    pub fn new(input: R) -> Inflater<R> {
+        Inflater {
+            input: Bits { input, buffer: 0, count: 0 },
+            block: Block::Header,
+            last: false,
+            window: vec![0; WINDOW],
+            written: 0,
+            copy: (0, 0),
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> u8 {
+        self.window[self.written % WINDOW] = byte;
+        self.written += 1;
+        byte
+    }
+
+    // The next byte of output, None at the end of the stream
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        loop {
+            let (distance, left) = self.copy;
+            if left > 0 {
+                self.copy.1 -= 1;
+                let byte = self.window[(self.written - distance) % WINDOW];
+                return Ok(Some(self.push(byte)));
+            }
+            match &mut self.block {
+                Block::Done => return Ok(None),
+                Block::Header if self.last => self.block = Block::Done,
+                Block::Header => {
+                    self.last = self.input.bits(1)? == 1;
+                    self.block = match self.input.bits(2)? {
+                        0 => Block::Stored(self.input.stored_len()?),
+                        1 => {
+                            let (lengths, distances) = fixed_tables();
+                            Block::Codes(lengths, distances)
+                        }
+                        2 => {
+                            let (lengths, distances) = dynamic_tables(&mut self.input)?;
+                            Block::Codes(lengths, distances)
+                        }
+                        _ => return Err("invalid block type"),
+                    };
+                }
+                Block::Stored(0) => self.block = Block::Header,
+                Block::Stored(left) => {
+                    *left -= 1;
+                    let byte = self.input.byte()?;
+                    return Ok(Some(self.push(byte)));
+                }
+                Block::Codes(lengths, distances) => {
+                    let symbol = lengths.decode(&mut self.input)? as usize;
+                    match symbol {
+                        0..=255 => return Ok(Some(self.push(symbol as u8))),
+                        256 => self.block = Block::Header,
+                        _ => {
+                            let symbol = symbol - 257;
+                            if symbol >= LENGTH_BASE.len() {
+                                return Err("invalid length code");
+                            }
+                            let len = LENGTH_BASE[symbol] as usize + self.input.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+                            let symbol = distances.decode(&mut self.input)? as usize;
+                            if symbol >= DIST_BASE.len() {
+                                return Err("invalid distance code");
+                            }
+                            let distance = DIST_BASE[symbol] as usize + self.input.bits(DIST_EXTRA[symbol] as u32)? as usize;
+                            if distance > self.written.min(WINDOW) {
+                                return Err("distance too far back");
+                            }
+                            // Copied a byte at a time since the source may overlap the output
+                            self.copy = (distance, len);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for Inflater<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() {
+            match self.next_byte().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))? {
+                Some(byte) => buf[n] = byte,
+                None => break,
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+struct Bits<R> {
+    input: R,
+    buffer: u32,
+    count: u32,
+}
+
+impl<R: Read> Bits<R> {
+    fn byte(&mut self) -> Result<u8> {
+        let mut byte = [0];
+        self.input.read_exact(&mut byte).map_err(|_| "compressed data ends early")?;
+        Ok(byte[0])
+    }
+
+    fn bits(&mut self, need: u32) -> Result<u32> {
+        while self.count < need {
+            let byte = self.byte()?;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u32 << need) - 1);
+        self.buffer >>= need;
+        self.count -= need;
+        Ok(value)
+    }
+
+    // Length of a stored block, which starts at a byte boundary
+    fn stored_len(&mut self) -> Result<usize> {
+        self.buffer = 0;
+        self.count = 0;
+        let header = [self.byte()?, self.byte()?, self.byte()?, self.byte()?];
+        let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+        let inverse = u16::from_le_bytes([header[2], header[3]]) as usize;
+        if len != !inverse & 0xFFFF {
+            return Err("corrupt stored block");
+        }
+        Ok(len)
+    }
+}
+
+struct Huffman {
+    // Number of codes of each length and the symbols ordered by code
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+    // Some bit patterns are left without a symbol
+    incomplete: bool,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        // Patterns left for the longer codes, as counted in puff: more codes
+        // of a length than there are patterns for means a corrupt stream
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err("over-subscribed Huffman code");
+            }
+        }
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols, incomplete: left > 0 })
+    }
+
+    // Incomplete codes are only allowed as a lone one-bit code, or none,
+    // which a block with at most one distance uses
+    fn valid(&self) -> bool {
+        !self.incomplete || self.counts[2..].iter().all(|&count| count == 0)
+    }
+
+    fn decode<R: Read>(&self, input: &mut Bits<R>) -> Result<u16> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for length in 1..=MAX_BITS {
+            code |= input.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code")
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    // Fixed tables are always valid
+    (Huffman::new(&lengths).unwrap(), Huffman::new(&[5; 30]).unwrap())
+}
+
+fn dynamic_tables<R: Read>(input: &mut Bits<R>) -> Result<(Huffman, Huffman)> {
+    let literal_count = input.bits(5)? as usize + 257;
+    let distance_count = input.bits(5)? as usize + 1;
+    let code_count = input.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err("too many codes");
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER.iter().take(code_count) {
+        code_lengths[index] = input.bits(3)? as u8;
+    }
+    let code_table = Huffman::new(&code_lengths)?;
+    if code_table.incomplete {
+        return Err("incomplete code length codes");
+    }
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = code_table.decode(input)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths[..i].last().ok_or("repeat with no previous length")?;
+                (previous, 3 + input.bits(2)? as usize)
+            }
+            17 => (0, 3 + input.bits(3)? as usize),
+            _ => (0, 11 + input.bits(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            return Err("too many code lengths");
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err("missing end-of-block code");
+    }
+
+    let (literals, distances) = (Huffman::new(&lengths[..literal_count])?, Huffman::new(&lengths[literal_count..])?);
+    if !literals.valid() || !distances.valid() {
+        return Err("incomplete Huffman code");
+    }
+    Ok((literals, distances))
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::Inflater;
+    use std::io::{self, Read};
+
+    fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        Inflater::new(data).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    fn invalid(data: &[u8]) -> bool {
+        inflate(data).is_err_and(|e| e.kind() == io::ErrorKind::InvalidData)
+    }
+
+    // Streams below are from zlib with raw DEFLATE output
+    const STORED: [u8; 25] = [
+        0x01, 0x14, 0x00, 0xeb, 0xff, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x64,
+        0x20, 0x62, 0x6c, 0x6f, 0x63, 0x6b, 0x21,
+    ];
+    const FIXED: [u8; 12] = [0xf3, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0xf0, 0x40, 0xa2, 0x14, 0x01];
+    // 40 times `a`, one literal and a copy from one byte back
+    const RUN: [u8; 6] = [0x4b, 0x4c, 0x24, 0x0e, 0x00, 0x00];
+    pub const DYNAMIC: [u8; 85] = [
+        0xb5, 0xcb, 0xc9, 0x11, 0x80, 0x20, 0x10, 0x44, 0xd1, 0x54, 0x3a, 0x02, 0x13, 0x30, 0x1a, 0x50, 0x94, 0x51,
+        0x61, 0x10, 0x59, 0x84, 0xe8, 0x9d, 0x32, 0x02, 0x2f, 0x1e, 0xbb, 0xfe, 0xeb, 0x64, 0x0d, 0xce, 0x4c, 0xd3,
+        0x0e, 0x1d, 0xb9, 0x7a, 0x2c, 0x7c, 0x63, 0xcb, 0x2e, 0x5c, 0xe0, 0x62, 0x22, 0x92, 0xe4, 0x43, 0xf5, 0x86,
+        0x99, 0xd7, 0xf1, 0x5d, 0xff, 0xe0, 0xa0, 0xc4, 0xb9, 0x06, 0x2d, 0xa8, 0x52, 0xb2, 0x58, 0xa8, 0x18, 0x49,
+        0xdd, 0x78, 0x1c, 0x74, 0x66, 0x8e, 0xf2, 0x5d, 0xaf, 0xe1, 0x3b, 0x7c, 0x00,
+    ];
+
+    pub fn dynamic_text() -> Vec<u8> {
+        [
+            "the quick brown fox jumps over the lazy dog; ".repeat(3),
+            "pack my box with five dozen liquor jugs. ".repeat(2),
+        ]
+        .concat()
+        .into_bytes()
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(inflate(&STORED).unwrap(), b"Hello, stored block!");
+        assert_eq!(inflate(&FIXED).unwrap(), b"Hello, Hello, Hello!");
+        assert_eq!(inflate(&DYNAMIC).unwrap(), dynamic_text());
+    }
+
+    #[test]
+    fn overlapping_copies() {
+        assert_eq!(inflate(&RUN).unwrap(), [b'a'; 40]);
+    }
+
+    #[test]
+    fn truncated() {
+        assert!(invalid(&STORED[..10]));
+        assert!(invalid(&FIXED[..6]));
+        assert!(invalid(&DYNAMIC[..40]));
+        assert!(invalid(&[]));
+    }
+
+    #[test]
+    fn corrupt() {
+        // Block type 3
+        assert!(invalid(&[0x07]));
+        // Stored length not matching its complement
+        assert!(invalid(&[0x01, 0x14, 0x00, 0xeb, 0xfe]));
+        // Four code length codes of one bit
+        assert!(invalid(&[0x05, 0x00, 0x92, 0x04]));
+        // A single code length code
+        assert!(invalid(&[0x05, 0x00, 0x02, 0x00]));
+        // A distance before the start of the output
+        assert!(invalid(&[0x03, 0x02, 0x00]));
+    }
+}
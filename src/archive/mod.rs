@@ -0,0 +1,330 @@
+// Reads audio files stored inside .zip archives
+//
+// Tracks inside an archive are addressed as if the archive were a directory,
+// e.g. `Music/album.zip/disc1/01.flac`.
+
+mod inflate;
+
+use inflate::Inflater;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use symphonia::core::io::MediaSource;
+
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+const ZIP64_LOCATOR: u32 = 0x0706_4b50;
+const DIRECTORY_ENTRY: u32 = 0x0201_4b50;
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+// End record plus the longest possible comment
+const MAX_END_RECORD: u64 = 22 + 0xFFFF;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+const ENCRYPTED: u16 = 1;
+// What a 32-bit field holds when the real value is in a zip64 record
+const ZIP64_MARK: u32 = 0xFFFF_FFFF;
+const ZIP64_UNSUPPORTED: &str = "zip64 archives, over 4 GB or with more than 65 535 files, are not supported";
+
+pub fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+/// One file in an archive's central directory.
+pub struct Entry {
+    pub name: String,
+    pub size: u64,
+    method: u16,
+    flags: u16,
+    compressed_size: u64,
+    header_offset: u64,
+}
+
+/// Lists the files in a zip archive, skipping directories.
+pub fn entries(archive: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let mut file = File::open(archive)?;
+    let len = file.metadata()?.len();
+    let tail_len = len.min(MAX_END_RECORD);
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = vec![0; tail_len as usize];
+    file.read_exact(&mut tail)?;
+
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| u32_at(&tail, i) == END_OF_DIRECTORY)
+        .ok_or("not a zip archive")?;
+    // Zip64 archives put a locator for their own end record just before this one
+    if end >= 20 && u32_at(&tail, end - 20) == ZIP64_LOCATOR {
+        return Err(ZIP64_UNSUPPORTED.into());
+    }
+    let count = u16_at(&tail, end + 10) as usize;
+    let directory_len = u32_at(&tail, end + 12) as usize;
+    let directory_offset = u32_at(&tail, end + 16);
+    if count == 0xFFFF || directory_offset == ZIP64_MARK {
+        return Err(ZIP64_UNSUPPORTED.into());
+    }
+    let directory_offset = directory_offset as u64;
+
+    file.seek(SeekFrom::Start(directory_offset))?;
+    let mut directory = vec![0; directory_len];
+    file.read_exact(&mut directory)?;
+
+    let mut entries = Vec::with_capacity(count);
+    let mut pos = 0;
+    for _ in 0..count {
+        if pos + 46 > directory.len() || u32_at(&directory, pos) != DIRECTORY_ENTRY {
+            return Err("corrupt zip directory".into());
+        }
+        let name_len = u16_at(&directory, pos + 28) as usize;
+        let extra_len = u16_at(&directory, pos + 30) as usize;
+        let comment_len = u16_at(&directory, pos + 32) as usize;
+        let name = directory
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or("corrupt zip directory")?;
+        let (compressed_size, size, header_offset) =
+            (u32_at(&directory, pos + 20), u32_at(&directory, pos + 24), u32_at(&directory, pos + 42));
+        if [compressed_size, size, header_offset].contains(&ZIP64_MARK) {
+            return Err(ZIP64_UNSUPPORTED.into());
+        }
+        let entry = Entry {
+            name: String::from_utf8_lossy(name).replace('\\', "/"),
+            size: size as u64,
+            method: u16_at(&directory, pos + 10),
+            flags: u16_at(&directory, pos + 8),
+            compressed_size: compressed_size as u64,
+            header_offset: header_offset as u64,
+        };
+        if !entry.name.ends_with('/') {
+            entries.push(entry);
+        }
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+/// Splits a path into the archive it points into and the entry name, or
+/// returns None for ordinary files.
+pub fn split(path: &Path) -> Option<(PathBuf, String)> {
+    if path.exists() {
+        return None;
+    }
+    let archive = path
+        .ancestors()
+        .skip(1)
+        .find(|ancestor| is_archive(ancestor) && ancestor.is_file())?;
+    let name = path
+        .strip_prefix(archive)
+        .ok()?
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    Some((archive.to_path_buf(), name))
+}
+
+/// Opens one entry of an archive for reading, decompressed as it is read.
+pub fn open(archive: &Path, name: &str) -> Result<EntryReader, Box<dyn Error>> {
+    let entry = entries(archive)?
+        .into_iter()
+        .find(|entry| entry.name == name)
+        .ok_or_else(|| format!("{} not found in {}", name, archive.display()))?;
+    if entry.flags & ENCRYPTED != 0 {
+        return Err("encrypted archive entries are not supported".into());
+    }
+    if entry.method != STORED && entry.method != DEFLATED {
+        return Err(format!("unsupported zip compression method {}", entry.method).into());
+    }
+
+    let mut file = File::open(archive)?;
+    file.seek(SeekFrom::Start(entry.header_offset))?;
+    let mut header = [0; 30];
+    file.read_exact(&mut header)?;
+    if u32_at(&header, 0) != LOCAL_HEADER {
+        return Err("corrupt zip entry".into());
+    }
+    // The local header's name and extra field can differ from the directory's
+    let start = entry.header_offset + 30 + u16_at(&header, 26) as u64 + u16_at(&header, 28) as u64;
+    let mut reader = EntryReader {
+        archive: archive.to_path_buf(),
+        start,
+        entry,
+        position: 0,
+        data: Box::new(io::empty()),
+    };
+    reader.restart(0)?;
+    Ok(reader)
+}
+
+/// An archive entry read straight from the archive. Stored entries seek in
+/// place; compressed ones are decompressed forward to a later position and
+/// from the start again to an earlier one.
+pub struct EntryReader {
+    archive: PathBuf,
+    // Where the entry's data begins in the archive
+    start: u64,
+    entry: Entry,
+    position: u64,
+    data: Box<dyn Read + Send + Sync>,
+}
+
+impl EntryReader {
+    fn restart(&mut self, position: u64) -> io::Result<()> {
+        let mut file = File::open(&self.archive)?;
+        if self.entry.method == STORED {
+            file.seek(SeekFrom::Start(self.start + position))?;
+            self.data = Box::new(BufReader::new(file).take(self.entry.size - position));
+            self.position = position;
+            return Ok(());
+        }
+        file.seek(SeekFrom::Start(self.start))?;
+        self.data = Box::new(Inflater::new(BufReader::new(file).take(self.entry.compressed_size)));
+        self.position = 0;
+        self.skip(position)
+    }
+
+    fn skip(&mut self, len: u64) -> io::Result<()> {
+        self.position += io::copy(&mut (&mut self.data).take(len), &mut io::sink())?;
+        Ok(())
+    }
+}
+
+impl Read for EntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.data.read(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for EntryReader {
+    fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+        let target = match from {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.entry.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        let target = target
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the entry"))?
+            .min(self.entry.size);
+        if self.entry.method == DEFLATED && target >= self.position {
+            self.skip(target - self.position)?;
+        } else if target != self.position {
+            self.restart(target)?;
+        }
+        Ok(self.position)
+    }
+}
+
+impl MediaSource for EntryReader {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.entry.size)
+    }
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::inflate::tests::{dynamic_text, DYNAMIC};
+    use super::{entries, open, DEFLATED, DIRECTORY_ENTRY, END_OF_DIRECTORY, LOCAL_HEADER, STORED, ZIP64_LOCATOR};
+    use std::fs;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::path::PathBuf;
+
+    // An archive holding one entry, written to a file named after the test
+    fn archive(test: &str, method: u16, flags: u16, data: &[u8], size: usize) -> PathBuf {
+        let (name, lengths) = ("album/01.flac", [data.len() as u32, size as u32]);
+        let mut zip = Vec::new();
+        zip.extend(LOCAL_HEADER.to_le_bytes());
+        [20, flags, method, 0, 0].iter().for_each(|field| zip.extend(field.to_le_bytes()));
+        [0, lengths[0], lengths[1]].iter().for_each(|field| zip.extend(field.to_le_bytes()));
+        [name.len() as u16, 0].iter().for_each(|field| zip.extend(field.to_le_bytes()));
+        zip.extend(name.as_bytes());
+        zip.extend(data);
+
+        let directory = zip.len() as u32;
+        zip.extend(DIRECTORY_ENTRY.to_le_bytes());
+        [20, 20, flags, method, 0, 0].iter().for_each(|field| zip.extend(field.to_le_bytes()));
+        [0, lengths[0], lengths[1]].iter().for_each(|field| zip.extend(field.to_le_bytes()));
+        [name.len() as u16, 0, 0, 0, 0].iter().for_each(|field| zip.extend(field.to_le_bytes()));
+        [0u32, 0].iter().for_each(|field| zip.extend(field.to_le_bytes()));
+        zip.extend(name.as_bytes());
+
+        let directory_len = zip.len() as u32 - directory;
+        zip.extend(END_OF_DIRECTORY.to_le_bytes());
+        [0u16, 0, 1, 1].iter().for_each(|field| zip.extend(field.to_le_bytes()));
+        [directory_len, directory].iter().for_each(|field| zip.extend(field.to_le_bytes()));
+        zip.extend(0u16.to_le_bytes());
+
+        let path = std::env::temp_dir().join(format!("clap-archive-{}-{}.zip", test, std::process::id()));
+        fs::write(&path, zip).unwrap();
+        path
+    }
+
+    fn read_at(reader: &mut (impl Read + Seek), from: SeekFrom, len: usize) -> Vec<u8> {
+        reader.seek(from).unwrap();
+        let mut read = vec![0; len];
+        reader.read_exact(&mut read).unwrap();
+        read
+    }
+
+    #[test]
+    fn seeking() {
+        let text = dynamic_text();
+        for (test, method, data) in [("stored", STORED, &text[..]), ("deflated", DEFLATED, &DYNAMIC[..])] {
+            let path = archive(test, method, 0, data, text.len());
+            let names: Vec<String> = entries(&path).unwrap().into_iter().map(|entry| entry.name).collect();
+            assert_eq!(names, ["album/01.flac"]);
+            let mut reader = open(&path, "album/01.flac").unwrap();
+            assert_eq!(read_at(&mut reader, SeekFrom::Start(150), 20), &text[150..170], "{}", test);
+            assert_eq!(read_at(&mut reader, SeekFrom::Start(4), 10), &text[4..14], "{}", test);
+            assert_eq!(read_at(&mut reader, SeekFrom::Current(30), 5), &text[44..49], "{}", test);
+            assert_eq!(read_at(&mut reader, SeekFrom::End(-8), 8), &text[text.len() - 8..], "{}", test);
+            assert!(reader.seek(SeekFrom::Current(-1000)).is_err());
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn corrupt_entries() {
+        let path = archive("corrupt", DEFLATED, 0, &DYNAMIC[..30], 217);
+        let mut read = Vec::new();
+        let error = open(&path, "album/01.flac").unwrap().read_to_end(&mut read).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(open(&path, "album/02.flac").is_err());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn unsupported() {
+        let path = archive("encrypted", STORED, 1, b"secret", 6);
+        assert!(open(&path, "album/01.flac").is_err());
+        let _ = fs::remove_file(path);
+
+        let path = archive("zip64", STORED, 0, b"big", 3);
+        let mut zip = fs::read(&path).unwrap();
+        let end = zip.len() - 22;
+        let locator: Vec<u8> = ZIP64_LOCATOR.to_le_bytes().into_iter().chain([0; 16]).collect();
+        zip.splice(end..end, locator);
+        fs::write(&path, zip).unwrap();
+        assert!(entries(&path).is_err_and(|e| e.to_string().contains("zip64")));
+        let _ = fs::remove_file(path);
+
+        let path = std::env::temp_dir().join(format!("clap-archive-none-{}.zip", std::process::id()));
+        fs::write(&path, b"not a zip").unwrap();
+        assert!(entries(&path).is_err());
+        let _ = fs::remove_file(path);
+    }
+}
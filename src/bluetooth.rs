@@ -0,0 +1,119 @@
+// Bluetooth speakers and headphones, found, paired and connected through
+// BlueZ's bluetoothctl. Once one is connected the system's audio server
+// offers it as an output, and the player reopens its output there.
+
+use std::error::Error;
+use std::io::ErrorKind;
+use std::process::Command;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+// A2DP sink, the profile of devices that play audio
+const AUDIO_SINK: &str = "0000110b-0000-1000-8000-00805f9b34fb";
+// Time the audio server takes to set up a device after it connects
+const SETTLE: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug)]
+pub struct Device {
+    pub address: String,
+    pub name: String,
+    pub paired: bool,
+    pub connected: bool,
+}
+
+/// What the background work reports back to the main loop.
+pub enum Event {
+    Found(Result<Vec<Device>, String>),
+    Connected(Device, Result<(), String>),
+    Disconnected(Device, Result<(), String>),
+}
+
+/// Scans for `timeout` in the background and sends the audio devices in
+/// range or paired before, connected ones first.
+pub fn discover(timeout: Duration, events: Sender<Event>) {
+    thread::spawn(move || {
+        let found = scan(timeout).map_err(|e| e.to_string());
+        let _ = events.send(Event::Found(found));
+    });
+}
+
+/// Connects in the background, pairing first if needed.
+pub fn connect(device: Device, events: Sender<Event>) {
+    thread::spawn(move || {
+        let result = pair_and_connect(&device).map_err(|e| e.to_string());
+        if result.is_ok() {
+            thread::sleep(SETTLE);
+        }
+        log::info!(target: "playback", "bluetooth: connecting {} ({}): {:?}", device.name, device.address, result);
+        let _ = events.send(Event::Connected(device, result));
+    });
+}
+
+pub fn disconnect(device: Device, events: Sender<Event>) {
+    thread::spawn(move || {
+        let result = run(&["disconnect", &device.address])
+            .and_then(|output| expect(&output, "Successful disconnected"))
+            .map_err(|e| e.to_string());
+        log::info!(target: "playback", "bluetooth: disconnecting {} ({}): {:?}", device.name, device.address, result);
+        let _ = events.send(Event::Disconnected(device, result));
+    });
+}
+
+fn scan(timeout: Duration) -> Result<Vec<Device>, Box<dyn Error>> {
+    run(&["--timeout", &timeout.as_secs().max(1).to_string(), "scan", "on"])?;
+    let mut devices = Vec::new();
+    // `Device AA:BB:CC:DD:EE:FF Name`
+    for line in run(&["devices"])?.lines() {
+        let Some(rest) = line.trim().strip_prefix("Device ") else {
+            continue;
+        };
+        let (address, name) = rest.split_once(' ').unwrap_or((rest, rest));
+        let info = run(&["info", address]).unwrap_or_default();
+        if !info.to_lowercase().contains(AUDIO_SINK) && !info.contains("Icon: audio") {
+            continue;
+        }
+        devices.push(Device {
+            address: address.to_string(),
+            name: name.trim().to_string(),
+            paired: info_says(&info, "Paired"),
+            connected: info_says(&info, "Connected"),
+        });
+    }
+    devices.sort_by_key(|device| (!device.connected, !device.paired));
+    Ok(devices)
+}
+
+fn pair_and_connect(device: &Device) -> Result<(), Box<dyn Error>> {
+    if !device.paired {
+        expect(&run(&["pair", &device.address])?, "Pairing successful")?;
+        // Trusted devices may connect again by themselves later
+        run(&["trust", &device.address])?;
+    }
+    expect(&run(&["connect", &device.address])?, "Connection successful")
+}
+
+// `Paired: yes` in the output of `info`
+fn info_says(info: &str, field: &str) -> bool {
+    info.lines().any(|line| line.trim() == format!("{}: yes", field))
+}
+
+// bluetoothctl doesn't always fail with a status, so its output is checked
+fn expect(output: &str, success: &str) -> Result<(), Box<dyn Error>> {
+    if output.contains(success) {
+        return Ok(());
+    }
+    let reason = output.lines().map(str::trim).rfind(|line| !line.is_empty()).unwrap_or("no answer");
+    Err(reason.to_string().into())
+}
+
+fn run(args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("bluetoothctl").args(args).output().map_err(|e| -> Box<dyn Error> {
+        if e.kind() == ErrorKind::NotFound {
+            "bluetoothctl not found; Bluetooth devices need BlueZ on Linux, or the system settings elsewhere".into()
+        } else {
+            e.into()
+        }
+    })?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
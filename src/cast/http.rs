@@ -0,0 +1,221 @@
+use crate::decode;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Option<Url> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().ok()?),
+            None => (authority, 80),
+        };
+        Some(Url {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    pub fn authority(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    // Resolves a possibly relative reference found in a device description
+    pub fn join(&self, reference: &str) -> String {
+        if reference.starts_with("http://") {
+            reference.to_string()
+        } else if reference.starts_with('/') {
+            format!("http://{}{}", self.authority(), reference)
+        } else {
+            format!("http://{}/{}", self.authority(), reference)
+        }
+    }
+}
+
+fn connect(url: &Url) -> io::Result<TcpStream> {
+    let addr = url
+        .authority()
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unresolvable host"))?;
+    let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    Ok(stream)
+}
+
+// Sends a request with `Connection: close` and returns the response body
+pub fn request(method: &str, url: &Url, headers: &[(&str, &str)], body: &str) -> io::Result<String> {
+    let mut stream = connect(url)?;
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        url.path,
+        url.authority(),
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+    stream.write_all(request.as_bytes())?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status_ok = head.split_whitespace().nth(1).is_some_and(|code| code.starts_with('2'));
+    if !status_ok {
+        let status = head.lines().next().unwrap_or_default();
+        return Err(io::Error::other(status.to_string()));
+    }
+    Ok(body.to_string())
+}
+
+/// Serves the track currently being cast so the renderer can fetch it. It
+/// listens only on the address the renderer is reached from, answers only
+/// under a random path made for the session, and stops when dropped.
+pub struct MediaServer {
+    address: SocketAddr,
+    // The first part of every path served, so nobody else on the network can
+    // guess their way to the files
+    token: String,
+    current: Arc<Mutex<Option<PathBuf>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl MediaServer {
+    pub fn start(ip: IpAddr) -> io::Result<MediaServer> {
+        let listener = TcpListener::bind((ip, 0))?;
+        let address = listener.local_addr()?;
+        let token = random_token();
+        let current: Arc<Mutex<Option<PathBuf>>> = Arc::default();
+        let stopped: Arc<AtomicBool> = Arc::default();
+        let (shared, stop, prefix) = (current.clone(), stopped.clone(), format!("/{}/", token));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let current = shared.lock().unwrap().clone();
+                let prefix = prefix.clone();
+                thread::spawn(move || {
+                    let _ = serve(stream, &prefix, current);
+                });
+            }
+        });
+        Ok(MediaServer { address, token, current, stopped })
+    }
+
+    pub fn set_track(&self, path: PathBuf) {
+        *self.current.lock().unwrap() = Some(path);
+    }
+
+    /// Where the renderer fetches the current track. A new `n` for each
+    /// track keeps renderers from reusing a cached stream.
+    pub fn url(&self, n: u32) -> String {
+        format!("http://{}/{}/track/{}", self.address, self.token, n)
+    }
+}
+
+impl Drop for MediaServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // Wakes the accept loop up so it sees the flag
+        let _ = TcpStream::connect_timeout(&self.address, TIMEOUT);
+    }
+}
+
+// 128 bits in hex, from the randomly seeded keys std gives each hasher
+fn random_token() -> String {
+    (0..2)
+        .map(|_| format!("{:016x}", RandomState::new().build_hasher().finish()))
+        .collect()
+}
+
+fn serve(mut stream: TcpStream, prefix: &str, track: Option<PathBuf>) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let head_only = request_line.starts_with("HEAD");
+    let requested = request_line.split_whitespace().nth(1).unwrap_or_default();
+
+    let mut range_start = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("range") {
+                range_start = value
+                    .trim()
+                    .strip_prefix("bytes=")
+                    .and_then(|range| range.split('-').next())
+                    .and_then(|start| start.parse::<u64>().ok());
+            }
+        }
+    }
+
+    // Tracks inside archives are served decompressed
+    let track = track.filter(|_| requested.starts_with(prefix));
+    let Some((path, mut file)) = track.and_then(|path| decode::open_media(&path).ok().map(|file| (path, file))) else {
+        return stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+    };
+
+    let len = file.byte_len().unwrap_or(0);
+    let start = range_start.unwrap_or(0).min(len);
+    file.seek(SeekFrom::Start(start))?;
+    let status = if range_start.is_some() { "206 Partial Content" } else { "200 OK" };
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n",
+        status,
+        content_type(&path),
+        len - start
+    );
+    if range_start.is_some() {
+        head.push_str(&format!("Content-Range: bytes {}-{}/{}\r\n", start, len.saturating_sub(1), len));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    if !head_only {
+        io::copy(&mut file, &mut stream)?;
+    }
+    Ok(())
+}
+
+pub fn content_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+        .as_str()
+    {
+        "mp3" => "audio/mpeg",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,193 @@
+mod http;
+mod ssdp;
+
+pub use ssdp::{discover, Renderer};
+
+use http::{MediaServer, Url};
+use std::io;
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const AV_TRANSPORT: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+// How often the renderer is asked for its playback position
+const POSITION_POLL: Duration = Duration::from_secs(2);
+
+pub enum CastCommand {
+    Load { path: PathBuf, title: String },
+    Play,
+    Pause,
+    Stop,
+    Seek(Duration),
+}
+
+// Last position reported by the renderer and when it was received
+type Position = Arc<Mutex<Option<(Duration, Instant)>>>;
+
+/// Mirrors playback to a DLNA renderer. The renderer pulls the current file
+/// from a small HTTP server, all network calls happen on a worker thread.
+pub struct CastSession {
+    pub renderer: Renderer,
+    commands: Sender<CastCommand>,
+    position: Position,
+    error: Arc<Mutex<Option<String>>>,
+}
+
+impl CastSession {
+    pub fn start(renderer: Renderer) -> io::Result<CastSession> {
+        let control = Url::parse(&renderer.control_url)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid control URL"))?;
+        let server = MediaServer::start(local_ip(&control)?)?;
+        let (commands, rx) = mpsc::channel();
+        let position: Position = Arc::default();
+        let error: Arc<Mutex<Option<String>>> = Arc::default();
+
+        let worker_position = position.clone();
+        let worker_error = error.clone();
+        thread::spawn(move || {
+            let mut loads = 0;
+            let mut playing = false;
+            loop {
+                let result = match rx.recv_timeout(POSITION_POLL) {
+                    Ok(command) => {
+                        if let CastCommand::Load { .. } = command {
+                            loads += 1;
+                            *worker_position.lock().unwrap() = None;
+                        }
+                        playing = matches!(command, CastCommand::Load { .. } | CastCommand::Play);
+                        run_command(&control, &server, loads, command)
+                    }
+                    Err(RecvTimeoutError::Timeout) if playing => {
+                        get_position(&control).map(|position| {
+                            *worker_position.lock().unwrap() = Some((position, Instant::now()));
+                        })
+                    }
+                    Err(RecvTimeoutError::Timeout) => Ok(()),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                *worker_error.lock().unwrap() = result.err().map(|e| e.to_string());
+            }
+            let _ = soap(&control, "Stop", "");
+        });
+
+        Ok(CastSession {
+            renderer,
+            commands,
+            position,
+            error,
+        })
+    }
+
+    pub fn send(&self, command: CastCommand) {
+        let _ = self.commands.send(command);
+    }
+
+    // Renderer position, extrapolated from the last report while playing
+    pub fn position(&self, playing: bool) -> Option<Duration> {
+        let (position, at) = (*self.position.lock().unwrap())?;
+        Some(if playing { position + at.elapsed() } else { position })
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.error.lock().unwrap().clone()
+    }
+}
+
+fn run_command(control: &Url, server: &MediaServer, loads: u32, command: CastCommand) -> io::Result<()> {
+    match command {
+        CastCommand::Load { path, title } => {
+            let content_type = http::content_type(&path);
+            server.set_track(path);
+            let url = server.url(loads);
+            let metadata = format!(
+                "<DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\" \
+                 xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+                 xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\">\
+                 <item id=\"0\" parentID=\"-1\" restricted=\"1\"><dc:title>{}</dc:title>\
+                 <upnp:class>object.item.audioItem.musicTrack</upnp:class>\
+                 <res protocolInfo=\"http-get:*:{}:*\">{}</res></item></DIDL-Lite>",
+                escape(&title),
+                content_type,
+                escape(&url)
+            );
+            soap(
+                control,
+                "SetAVTransportURI",
+                &format!(
+                    "<CurrentURI>{}</CurrentURI><CurrentURIMetaData>{}</CurrentURIMetaData>",
+                    escape(&url),
+                    escape(&metadata)
+                ),
+            )?;
+            soap(control, "Play", "<Speed>1</Speed>")
+        }
+        CastCommand::Play => soap(control, "Play", "<Speed>1</Speed>"),
+        CastCommand::Pause => soap(control, "Pause", ""),
+        CastCommand::Stop => soap(control, "Stop", ""),
+        CastCommand::Seek(position) => {
+            let secs = position.as_secs();
+            let target = format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+            soap(control, "Seek", &format!("<Unit>REL_TIME</Unit><Target>{}</Target>", target))
+        }
+    }
+}
+
+fn get_position(control: &Url) -> io::Result<Duration> {
+    let response = soap_response(control, "GetPositionInfo", "")?;
+    ssdp::tag(&response, "RelTime")
+        .and_then(parse_time)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "renderer sent no position"))
+}
+
+// Parses `h:mm:ss` with optional fractional seconds
+fn parse_time(time: &str) -> Option<Duration> {
+    let mut secs = 0.0;
+    for part in time.trim().split(':') {
+        secs = secs * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(Duration::from_secs_f64(secs))
+}
+
+fn soap(control: &Url, action: &str, args: &str) -> io::Result<()> {
+    soap_response(control, action, args).map(|_| ())
+}
+
+fn soap_response(control: &Url, action: &str, args: &str) -> io::Result<String> {
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body>\
+         <u:{action} xmlns:u=\"{service}\"><InstanceID>0</InstanceID>{args}</u:{action}>\
+         </s:Body></s:Envelope>",
+        action = action,
+        service = AV_TRANSPORT,
+        args = args
+    );
+    let soap_action = format!("\"{}#{}\"", AV_TRANSPORT, action);
+    http::request(
+        "POST",
+        control,
+        &[
+            ("Content-Type", "text/xml; charset=\"utf-8\""),
+            ("SOAPAction", &soap_action),
+        ],
+        &body,
+    )
+}
+
+// Address of the interface that routes to the renderer
+fn local_ip(control: &Url) -> io::Result<std::net::IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(control.authority())?;
+    Ok(socket.local_addr()?.ip())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
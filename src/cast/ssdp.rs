@@ -0,0 +1,72 @@
+use super::http::{self, Url};
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+const AV_TRANSPORT: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+
+#[derive(Clone, Debug)]
+pub struct Renderer {
+    pub name: String,
+    pub control_url: String,
+}
+
+/// Finds UPnP/DLNA media renderers on the local network.
+pub fn discover(timeout: Duration) -> Vec<Renderer> {
+    let mut locations = Vec::new();
+    if let Ok(socket) = UdpSocket::bind("0.0.0.0:0") {
+        let search = format!(
+            "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {}\r\n\r\n",
+            AV_TRANSPORT
+        );
+        if socket.send_to(search.as_bytes(), "239.255.255.250:1900").is_ok() {
+            let deadline = Instant::now() + timeout;
+            let mut buf = [0u8; 2048];
+            while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+                if remaining.is_zero() || socket.set_read_timeout(Some(remaining)).is_err() {
+                    break;
+                }
+                let Ok((len, _)) = socket.recv_from(&mut buf) else {
+                    break;
+                };
+                let response = String::from_utf8_lossy(&buf[..len]);
+                let location = response.lines().find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("location").then(|| value.trim().to_string())
+                });
+                if let Some(location) = location {
+                    if !locations.contains(&location) {
+                        locations.push(location);
+                    }
+                }
+            }
+        }
+    }
+
+    locations.iter().filter_map(|location| describe(location)).collect()
+}
+
+// Reads the device description for its name and AVTransport control endpoint
+fn describe(location: &str) -> Option<Renderer> {
+    let url = Url::parse(location)?;
+    let description = http::request("GET", &url, &[], "").ok()?;
+    let name = tag(&description, "friendlyName").unwrap_or("DLNA renderer").to_string();
+
+    let service = description
+        .split("<service>")
+        .find(|service| service.contains(AV_TRANSPORT))?;
+    let control = tag(service, "controlURL")?;
+    let base = tag(&description, "URLBase")
+        .and_then(Url::parse)
+        .unwrap_or(url);
+    Some(Renderer {
+        name,
+        control_url: base.join(control.trim()),
+    })
+}
+
+// Text of the first `<name>` element, good enough for device descriptions
+pub fn tag<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = xml[start..].find(&format!("</{}>", name))? + start;
+    Some(&xml[start..end])
+}
@@ -0,0 +1,161 @@
+// Chapters of audiobooks and long mixes, from the Nero chapter list in MP4
+// files (M4B, M4A) or CHAPTERxxx Vorbis comments in FLAC and Ogg files
+
+use crate::decode::{self, extension};
+use crate::utils::parse_time;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision};
+use symphonia::core::probe::Hint;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chapter {
+    pub start: Duration,
+    pub title: String,
+}
+
+/// Reads a track's chapters, sorted by start. Files without any give an
+/// empty list.
+pub fn read(path: &Path) -> Vec<Chapter> {
+    let mut chapters = match extension(path).as_str() {
+        "m4a" | "m4b" | "mp4" => read_mp4(path),
+        "flac" | "ogg" | "oga" => read_vorbis(path),
+        _ => None,
+    }
+    .unwrap_or_default();
+    chapters.sort_by_key(|chapter| chapter.start);
+    // A single chapter spanning the file is no use for jumping around
+    if chapters.len() < 2 {
+        chapters.clear();
+    }
+    chapters
+}
+
+/// The chapter playing at a position, the last one starting at or before it.
+pub fn at(chapters: &[Chapter], position: Duration) -> Option<usize> {
+    chapters.iter().rposition(|chapter| chapter.start <= position)
+}
+
+// The chpl box sits in moov/udta. moov can come after the audio data, so the
+// top-level boxes are skipped over rather than read.
+fn read_mp4(path: &Path) -> Option<Vec<Chapter>> {
+    let mut file = decode::open_media(path).ok()?;
+    let length = file.seek(SeekFrom::End(0)).ok()?;
+    let mut offset = 0;
+    while offset + 8 <= length {
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        let mut header = [0; 16];
+        file.read_exact(&mut header[..8]).ok()?;
+        let (kind, mut size, mut header_size) = box_header(&header);
+        if size == 1 {
+            file.read_exact(&mut header[8..]).ok()?;
+            size = u64::from_be_bytes(header[8..16].try_into().ok()?);
+            header_size = 16;
+        } else if size == 0 {
+            size = length - offset;
+        }
+        if size < header_size {
+            return None;
+        }
+        if &kind == b"moov" {
+            let mut moov = vec![0; (size - header_size) as usize];
+            file.read_exact(&mut moov).ok()?;
+            let udta = find_box(&moov, b"udta")?;
+            return parse_chpl(find_box(udta, b"chpl")?);
+        }
+        offset += size;
+    }
+    None
+}
+
+fn box_header(bytes: &[u8]) -> ([u8; 4], u64, u64) {
+    let size = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64;
+    ([bytes[4], bytes[5], bytes[6], bytes[7]], size, 8)
+}
+
+// The contents of a box among those directly inside another, already read
+fn find_box<'a>(mut data: &'a [u8], wanted: &[u8; 4]) -> Option<&'a [u8]> {
+    while data.len() >= 8 {
+        let (kind, size, header_size) = box_header(data);
+        let (size, header_size) = match size {
+            0 => (data.len() as u64, header_size),
+            1 if data.len() >= 16 => (u64::from_be_bytes(data[8..16].try_into().ok()?), 16),
+            _ => (size, header_size),
+        };
+        if size < header_size || size > data.len() as u64 {
+            return None;
+        }
+        if &kind == wanted {
+            return Some(&data[header_size as usize..size as usize]);
+        }
+        data = &data[size as usize..];
+    }
+    None
+}
+
+// Version and flags, a reserved word in version 1, the chapter count, then
+// each start in 100 ns units followed by a length-prefixed title
+fn parse_chpl(data: &[u8]) -> Option<Vec<Chapter>> {
+    let mut at = if *data.first()? == 0 { 4 } else { 8 };
+    let count = *data.get(at)?;
+    at += 1;
+    let mut chapters = Vec::new();
+    for _ in 0..count {
+        let start = u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?);
+        let length = *data.get(at + 8)? as usize;
+        let title = data.get(at + 9..at + 9 + length)?;
+        at += 9 + length;
+        chapters.push(Chapter {
+            start: Duration::from_nanos(start.saturating_mul(100)),
+            title: String::from_utf8_lossy(title).trim().to_string(),
+        });
+    }
+    Some(chapters)
+}
+
+// CHAPTER001=00:00:00.000 gives the start and CHAPTER001NAME the title
+fn read_vorbis(path: &Path) -> Option<Vec<Chapter>> {
+    let stream = MediaSourceStream::new(decode::open_media(path).ok()?, Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(&extension(path));
+    let mut probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .ok()?;
+    let mut comments = Vec::new();
+    let mut take = |revision: &MetadataRevision| {
+        comments.extend(revision.tags().iter().map(|tag| (tag.key.to_uppercase(), tag.value.to_string())));
+    };
+    if let Some(revision) = probed.format.metadata().current() {
+        take(revision);
+    }
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|metadata| metadata.current()) {
+        take(revision);
+    }
+
+    let mut chapters: Vec<(String, Chapter)> = Vec::new();
+    for (key, value) in &comments {
+        let Some(number) = key.strip_prefix("CHAPTER") else {
+            continue;
+        };
+        if !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) {
+            if let Some(start) = parse_time(value.trim()) {
+                let title = format!("Chapter {}", number.parse::<u32>().unwrap_or(0));
+                chapters.push((number.to_string(), Chapter { start, title }));
+            }
+        }
+    }
+    for (key, value) in &comments {
+        let Some(number) = key.strip_prefix("CHAPTER").and_then(|key| key.strip_suffix("NAME")) else {
+            continue;
+        };
+        if let Some((_, chapter)) = chapters.iter_mut().find(|(n, _)| n == number) {
+            if !value.trim().is_empty() {
+                chapter.title = value.trim().to_string();
+            }
+        }
+    }
+    Some(chapters.into_iter().map(|(_, chapter)| chapter).collect())
+}
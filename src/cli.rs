@@ -0,0 +1,323 @@
+use crate::config::Config;
+use crate::ipc;
+use crate::player::{Repeat, Shuffle};
+use crate::sync::{self, SyncOptions};
+use crate::tasks::Reporter;
+use crate::transcode::{self, TranscodeOptions};
+use crate::utils;
+use std::error::Error;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+pub enum Command {
+    // Files, folders and URLs given on the command line are added to the playlist
+    Play {
+        locations: Vec<String>,
+        start: StartOptions,
+    },
+    Transcode {
+        options: TranscodeOptions,
+        inputs: Vec<PathBuf>,
+    },
+    // Copies playlists and their tracks to a device
+    Sync {
+        options: SyncOptions,
+        playlists: Vec<PathBuf>,
+    },
+    // Asks the running player what it plays, for status bars, or sends it a
+    // request with `ctl`
+    Status {
+        request: String,
+    },
+    // Registers the player with file managers
+    InstallDesktop {
+        make_default: bool,
+    },
+}
+
+/// How playback starts, from flags for scripts and launchers.
+#[derive(Default)]
+pub struct StartOptions {
+    pub shuffle: Option<Shuffle>,
+    pub repeat: Option<Repeat>,
+    // Percent
+    pub volume: Option<u32>,
+    // Track number to start playing, counted from 1
+    pub play: Option<usize>,
+    // Load the first track, or the one from --play, without playing it
+    pub start_paused: bool,
+    // Start even when a player is already running, instead of handing over to it
+    pub new_instance: bool,
+    // Only add the locations, or start playing the first of them right away
+    pub enqueue: bool,
+    pub play_now: bool,
+    // Don't offer to resume the last session
+    pub no_resume: bool,
+    // Start locked in party mode
+    pub party: bool,
+    // Kiosk layout, resuming or starting playback without asking
+    pub kiosk: bool,
+}
+
+/// Takes `--profile NAME` out of the arguments, wherever it is, since the
+/// profile decides which config the rest is read with.
+pub fn take_profile(args: Vec<String>) -> Result<(Option<String>, Vec<String>), String> {
+    let mut profile = None;
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--profile" => profile = Some(value(&mut args, &arg)?),
+            // Locations after -- stay as they are
+            "--" => {
+                rest.push(arg);
+                rest.extend(args.by_ref());
+            }
+            _ => rest.push(arg),
+        }
+    }
+    Ok((profile, rest))
+}
+
+pub fn parse(mut args: impl Iterator<Item = String>, config: &Config) -> Result<Command, String> {
+    let Some(first) = args.next() else {
+        return parse_play(args);
+    };
+    match first.as_str() {
+        "transcode" => parse_transcode(args, config),
+        "sync" => parse_sync(args, config),
+        "status" => parse_status(args),
+        // ctl REQUEST..., e.g. `ctl next` or `ctl add ~/Music/new`
+        "ctl" => {
+            let request = args.collect::<Vec<_>>().join(" ");
+            if request.is_empty() {
+                return Err("ctl needs a request, e.g. 'music-cli ctl next'".to_string());
+            }
+            Ok(Command::Status { request })
+        }
+        "install-desktop" => match args.next().as_deref() {
+            None => Ok(Command::InstallDesktop { make_default: false }),
+            Some("--default") => Ok(Command::InstallDesktop { make_default: true }),
+            Some(other) => Err(format!("unknown option '{}'", other)),
+        },
+        _ => parse_play(std::iter::once(first).chain(args)),
+    }
+}
+
+// [--shuffle | --shuffle-albums | --shuffle-smart] [--repeat all|one|off] [--volume PERCENT] [--play N] [--start-paused] [--stdin]
+// [--new-instance] [--enqueue | --play-now] [--no-resume] [--party] [--kiosk] LOCATIONS...
+fn parse_play(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut start = StartOptions::default();
+    let mut locations = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--shuffle" => start.shuffle = Some(Shuffle::Tracks),
+            "--shuffle-albums" => start.shuffle = Some(Shuffle::Albums),
+            "--shuffle-smart" => start.shuffle = Some(Shuffle::Smart),
+            "--repeat" => start.repeat = Some(value(&mut args, &arg)?.parse()?),
+            "--volume" => {
+                let volume = value(&mut args, &arg)?;
+                start.volume = Some(
+                    volume
+                        .parse()
+                        .ok()
+                        .filter(|&volume| volume <= 100)
+                        .ok_or_else(|| format!("volume must be 0-100, not '{}'", volume))?,
+                );
+            }
+            "--play" => {
+                let number = value(&mut args, &arg)?;
+                start.play = Some(
+                    number
+                        .parse()
+                        .ok()
+                        .filter(|&number| number >= 1)
+                        .ok_or_else(|| format!("--play needs a track number, not '{}'", number))?,
+                );
+            }
+            "--start-paused" => start.start_paused = true,
+            "--new-instance" => start.new_instance = true,
+            "--no-resume" => start.no_resume = true,
+            "--party" => start.party = true,
+            "--kiosk" => start.kiosk = true,
+            "--enqueue" => (start.enqueue, start.play_now) = (true, false),
+            "--play-now" => (start.enqueue, start.play_now) = (false, true),
+            // One path or URL per line, e.g. from find
+            "--stdin" => {
+                for line in io::stdin().lock().lines() {
+                    let line = line.map_err(|e| format!("could not read stdin: {}", e))?;
+                    let line = line.trim();
+                    if !line.is_empty() {
+                        locations.push(line.to_string());
+                    }
+                }
+            }
+            // Everything after -- is a location, even if it starts with -
+            "--" => locations.extend(args.by_ref()),
+            other if other.starts_with('-') => return Err(format!("unknown option '{}'", other)),
+            _ => locations.push(arg),
+        }
+    }
+    Ok(Command::Play { locations, start })
+}
+
+// transcode [--format mp3|ogg|opus] [--bitrate KBPS] [--output DIR] FILES_OR_DIRS...
+fn parse_transcode(mut args: impl Iterator<Item = String>, config: &Config) -> Result<Command, String> {
+    let mut options = config.transcode_options();
+    let mut inputs = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" | "-f" => options.format = value(&mut args, &arg)?.parse()?,
+            "--bitrate" | "-b" => {
+                options.bitrate_kbps = value(&mut args, &arg)?
+                    .parse()
+                    .map_err(|_| "bitrate must be a number of kbit/s".to_string())?
+            }
+            "--output" | "-o" => options.output_dir = PathBuf::from(value(&mut args, &arg)?),
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+    if inputs.is_empty() {
+        return Err("transcode needs at least one file or folder".to_string());
+    }
+    Ok(Command::Transcode { options, inputs })
+}
+
+// sync [--to DIR] [--format copy|mp3|ogg|opus] [--bitrate KBPS] [PLAYLISTS...]
+fn parse_sync(mut args: impl Iterator<Item = String>, config: &Config) -> Result<Command, String> {
+    let mut options = config.sync_options();
+    let mut names = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--to" | "-t" => options.target = PathBuf::from(value(&mut args, &arg)?),
+            "--format" | "-f" => {
+                let format = value(&mut args, &arg)?;
+                options.format = if format.eq_ignore_ascii_case("copy") { None } else { Some(format.parse()?) };
+            }
+            "--bitrate" | "-b" => {
+                options.bitrate_kbps = value(&mut args, &arg)?
+                    .parse()
+                    .map_err(|_| "bitrate must be a number of kbit/s".to_string())?
+            }
+            _ => names.push(arg),
+        }
+    }
+    if options.target.as_os_str().is_empty() {
+        return Err("sync needs a device folder, with --to or sync_dir in clap.conf".to_string());
+    }
+    if names.is_empty() {
+        names = config.sync_playlists.clone();
+    }
+    if names.is_empty() {
+        return Err("sync needs playlists, on the command line or as sync_playlists in clap.conf".to_string());
+    }
+    let playlists = find_playlists(&names, config)?;
+    Ok(Command::Sync { options, playlists })
+}
+
+/// Playlists to sync, given as files or names of the ones in playlists_dir.
+pub fn find_playlists(names: &[String], config: &Config) -> Result<Vec<PathBuf>, String> {
+    let saved = utils::list_playlists(&config.playlists_dir);
+    names
+        .iter()
+        .map(|name| {
+            let path = PathBuf::from(name);
+            if path.is_file() {
+                return Ok(path);
+            }
+            saved
+                .iter()
+                .find(|playlist| playlist.file_stem().is_some_and(|stem| stem.eq_ignore_ascii_case(name.as_str())))
+                .cloned()
+                .ok_or_else(|| format!("no playlist named '{}'", name))
+        })
+        .collect()
+}
+
+// status [--json | --format TEMPLATE]
+fn parse_status(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut request = "text".to_string();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" | "-j" => request = "status".to_string(),
+            "--format" | "-f" => request = format!("format {}", value(&mut args, &arg)?),
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+    Ok(Command::Status { request })
+}
+
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{} needs a value", flag))
+}
+
+/// Passes locations to the player that is already running: the first one is
+/// played, unless `enqueue` is set, and the rest are added after it.
+pub fn hand_over(locations: &[String], enqueue: bool) -> Result<(), Box<dyn Error>> {
+    if locations.is_empty() {
+        return Err("the player is already running, use --new-instance to start another".into());
+    }
+    for (i, location) in locations.iter().enumerate() {
+        let request = if i == 0 && !enqueue { "open" } else { "add" };
+        ipc::send(&format!("{} {}", request, absolute(location)))?;
+    }
+    Ok(())
+}
+
+// The running player has its own working directory
+fn absolute(location: &str) -> String {
+    let path = Path::new(location);
+    if location.contains("://") || path.is_absolute() {
+        return location.to_string();
+    }
+    match std::env::current_dir() {
+        Ok(dir) => dir.join(path).to_string_lossy().into_owned(),
+        Err(_) => location.to_string(),
+    }
+}
+
+pub fn run_transcode(options: &TranscodeOptions, inputs: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    // Tracks from a folder keep their place under it, such as `CD1/01 Intro.opus`
+    let mut tracks = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            for track in utils::scan_music_directory(input) {
+                let relative = track.strip_prefix(input).ok().and_then(Path::parent).map(Path::to_path_buf);
+                tracks.push((track, relative.unwrap_or_default()));
+            }
+        } else {
+            tracks.push((input.clone(), PathBuf::new()));
+        }
+    }
+
+    let mut failed = 0;
+    for (track, relative) in &tracks {
+        let output = transcode::output_path(track, &options.output_dir.join(relative), options.format);
+        match transcode::transcode(track, &output, options, &Reporter::none()) {
+            Ok(()) => println!("{} -> {}", track.display(), output.display()),
+            Err(e) => {
+                eprintln!("{}: {}", track.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} tracks failed", failed, tracks.len()).into());
+    }
+    Ok(())
+}
+
+pub fn run_sync(options: &SyncOptions, playlists: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    let summary = sync::sync(playlists, options, &Reporter::none(), &mut |line| match line {
+        sync::Line::Done(text) => println!("{}", text),
+        sync::Line::Failed(text) => eprintln!("{}", text),
+    })?;
+    println!(
+        "{} copied, {} already there, {} removed",
+        summary.copied, summary.unchanged, summary.removed
+    );
+    if summary.failed > 0 {
+        return Err(format!("{} tracks failed", summary.failed).into());
+    }
+    Ok(())
+}
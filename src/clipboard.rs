@@ -0,0 +1,31 @@
+// The system clipboard, for copying what is playing and pasting locations
+// into prompts. On X11 and Wayland copied text only stays available while
+// the program that copied it holds on to it, so one connection is kept for
+// the whole session, opened the first time it is needed.
+
+#[derive(Default)]
+pub struct Clipboard {
+    connection: Option<arboard::Clipboard>,
+}
+
+impl Clipboard {
+    pub fn copy(&mut self, text: &str) -> Result<(), String> {
+        self.connection()?.set_text(text).map_err(|e| e.to_string())
+    }
+
+    pub fn paste(&mut self) -> Result<String, String> {
+        match self.connection()?.get_text() {
+            Ok(text) => Ok(text),
+            Err(arboard::Error::ContentNotAvailable) => Err("the clipboard holds no text".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    // Without a display, as over SSH, there is no clipboard to reach
+    fn connection(&mut self) -> Result<&mut arboard::Clipboard, String> {
+        if self.connection.is_none() {
+            self.connection = Some(arboard::Clipboard::new().map_err(|e| e.to_string())?);
+        }
+        Ok(self.connection.as_mut().unwrap())
+    }
+}
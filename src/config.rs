@@ -0,0 +1,621 @@
+use crate::announce;
+use crate::ducking::DuckFor;
+use crate::hooks::{self, Hooks};
+use crate::keymap::Preset;
+use crate::logging::LogLevels;
+use crate::night::NightHours;
+use crate::player::dsp::CompressorPreset;
+use crate::player::output::OutputBuffer;
+use crate::player::QueueEnd;
+use crate::player::resample::Resampling;
+use crate::replaygain::ReplayGainMode;
+use crate::sync::SyncOptions;
+use crate::transcode::{Format, TranscodeOptions};
+use crate::ui::{Column, Label, Theme};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Settings that can be changed from the settings popup, with their labels.
+pub const EDITABLE: &[(&str, &str)] = &[
+    ("volume_step", "Volume step (%)"),
+    ("crossfade_seconds", "Crossfade (seconds)"),
+    ("restart_seconds", "Previous restarts the track after (seconds)"),
+    ("resume_minutes", "Resume tracks longer than (minutes)"),
+    ("replay_seconds", "Jump back by (seconds)"),
+    ("forward_seconds", "Jump forward by (seconds)"),
+    ("focus_minutes", "Focus mode: work block (minutes)"),
+    ("break_minutes", "Focus mode: break (minutes)"),
+    ("party_max_volume", "Party mode: volume limit (%)"),
+    ("night_hours", "Night mode: hours, e.g. 22:00-07:00"),
+    ("night_max_volume", "Night mode: volume limit (%)"),
+    ("crossfeed", "Headphone crossfeed"),
+    ("crossfeed_strength", "Crossfeed strength (%)"),
+    ("balance", "Balance (%, -100 left to 100 right)"),
+    ("swap_channels", "Swap left and right"),
+    ("mono", "Mono"),
+    ("resampler", "Resampler (sinc, linear)"),
+    ("band_limit", "Band-limit when resampling down"),
+    ("compressor", "Compressor (off, podcast, night)"),
+    ("compressor_threshold", "Compressor threshold (dB, empty for the preset's)"),
+    ("compressor_ratio", "Compressor ratio (empty for the preset's)"),
+    ("announce", "Announce tracks (text to speech)"),
+    ("duck_for", "Turn down for other audio (off, calls, all)"),
+    ("duck_volume", "Turned down to (%)"),
+    ("auto_mix", "Auto-mix"),
+    ("auto_fill", "Endless play"),
+    ("queue_end", "At the end of the playlist (repeat, stop, fill, shutdown, hook)"),
+    ("skip_shorter_than", "Shuffle skips tracks shorter than (seconds, 0 for none)"),
+    ("skip_patterns", "Shuffle skips files named like (patterns separated by ;)"),
+    ("skip_silence", "Skip silence"),
+    ("silence_skip_seconds", "Skip silences longer than (seconds)"),
+    ("trim_silence", "Trim silence"),
+    ("replaygain", "ReplayGain (off, track, album)"),
+    ("output_device", "Output device"),
+    ("output_buffer", "Output buffer (auto, frames, or ms such as 50ms)"),
+    ("library_roots", "Library folders (separated by ;)"),
+    ("cache_limit_mb", "Offline copies of remote tracks: size limit (MB)"),
+    ("keymap", "Key bindings (default, vim, emacs)"),
+    ("theme", "Theme (default, high-contrast, deuteranopia, protanopia)"),
+    ("labels", "Track labels (name:colour, separated by ;)"),
+    ("reduced_motion", "Reduced motion"),
+    ("low_bandwidth", "Low-bandwidth mode (SSH)"),
+    ("split_percent", "Split layout: playlist width (%, 0 for one column)"),
+    ("pause_on_suspend", "Pause when suspended (Ctrl-Z)"),
+    ("smart_shuffle_rating", "Smart shuffle: weight of ratings"),
+    ("smart_shuffle_recency", "Smart shuffle: weight of time since last play"),
+];
+
+#[derive(Clone)]
+pub struct Config {
+    // Change in volume for each press of +/-, in percent
+    pub volume_step: f32,
+    pub skip_silence: bool,
+    pub silence_skip_seconds: f32,
+    pub trim_silence: bool,
+    // Headphone crossfeed, and how much of it in percent
+    pub crossfeed: bool,
+    pub crossfeed_strength: f32,
+    // Balance in percent, -100 all left to 100 all right, and the channel
+    // fixes for a dead earbud or a speaker wired the wrong way round
+    pub balance: f32,
+    pub swap_channels: bool,
+    pub mono: bool,
+    // How speed changes and rate mismatches with the device are resampled
+    pub resampler: Resampling,
+    pub band_limit: bool,
+    pub background_analysis: bool,
+    pub crossfade_seconds: f32,
+    // Previous restarts the current track once it has played this long
+    pub restart_seconds: f32,
+    pub resume_minutes: f32,
+    // Steps of the jump back and jump forward keys
+    pub replay_seconds: f32,
+    pub forward_seconds: f32,
+    // Focus mode blocks, and the playlists switched to at their start
+    pub focus_minutes: f32,
+    pub break_minutes: f32,
+    pub focus_playlist: Option<PathBuf>,
+    pub break_playlist: Option<PathBuf>,
+    // Unlocks party mode, and the volume guests can turn it up to, in percent
+    pub party_passphrase: Option<String>,
+    pub party_max_volume: f32,
+    // When night mode turns itself on, and the volume it limits to, in percent
+    pub night_hours: Option<NightHours>,
+    pub night_max_volume: f32,
+    // Compressor preset, and thresholds that replace the preset's
+    pub compressor: CompressorPreset,
+    pub compressor_threshold: Option<f32>,
+    pub compressor_ratio: Option<f32>,
+    // Speak each new track with `announce_command`
+    pub announce: bool,
+    pub announce_command: String,
+    // Turn the music down to `duck_volume` percent for calls, or any other
+    // program playing sound
+    pub duck_for: DuckFor,
+    pub duck_volume: f32,
+    pub auto_mix: bool,
+    // Keep playing tracks from the library when the playlist runs out
+    pub auto_fill: bool,
+    // What happens once the playlist has played through with repeat on
+    pub queue_end: QueueEnd,
+    // Run a minute after the playlist ends with `queue_end = shutdown`
+    pub shutdown_command: String,
+    // Files shuffle passes over: shorter than this many seconds, or with
+    // names matching one of the patterns
+    pub skip_shorter_than: f32,
+    pub skip_patterns: Vec<String>,
+    // Tracks kept in the list of recently played ones
+    pub history_size: usize,
+    pub record_dir: PathBuf,
+    pub transcode_format: Format,
+    pub transcode_bitrate: u32,
+    pub transcode_dir: PathBuf,
+    // Where `music-cli sync` mirrors the playlists to, and which ones
+    pub sync_dir: Option<PathBuf>,
+    pub sync_playlists: Vec<String>,
+    // Lossless tracks are encoded to this on the device, None copies them
+    pub sync_format: Option<Format>,
+    pub sync_bitrate: u32,
+    // File on a WebDAV server where resume positions and the history are
+    // shared with other machines, and the login for it
+    pub roaming_url: Option<String>,
+    pub roaming_user: Option<String>,
+    pub roaming_password: Option<String>,
+    // Copies of remote tracks beyond this are removed, played longest ago first
+    pub cache_limit_mb: u64,
+    pub replaygain: ReplayGainMode,
+    // Name of the main output device, empty for the system default
+    pub output_device: String,
+    // How much audio the device holds; bigger survives a busy system, smaller reacts sooner
+    pub output_buffer: OutputBuffer,
+    pub bit_perfect: bool,
+    // SoundFont used to play MIDI files
+    pub soundfont: PathBuf,
+    // Where the playlist switcher looks for .m3u, .m3u8 and .pls files
+    pub playlists_dir: PathBuf,
+    // Folders, smb:// and sftp:// URLs scanned instead of the default music folder
+    pub library_roots: Vec<String>,
+    pub keymap: Preset,
+    pub playlist_columns: Vec<Column>,
+    pub theme: Theme,
+    // Offered in the label popup, with the colours of their chips
+    pub labels: Vec<Label>,
+    // Redraw playback progress once a second rather than several times
+    pub reduced_motion: bool,
+    // ASCII only, fewer redraws and ticks, for slow SSH sessions
+    pub low_bandwidth: bool,
+    // Large now-playing layout that starts playing by itself, for a music box
+    pub kiosk: bool,
+    // Width of the playlist beside the queue on wide terminals, in percent;
+    // 0 stacks everything in one column
+    pub split_percent: u16,
+    // Template for the status bar, e.g. `{artist} - {title} [{elapsed}/{total}]`
+    pub status_format: Option<String>,
+    // Show the current track in the terminal's title bar
+    pub terminal_title: bool,
+    // Pause playback while suspended with Ctrl-Z
+    pub pause_on_suspend: bool,
+    // How much smart shuffle favours highly rated tracks and ones not played
+    // for a while, 0 to ignore either
+    pub smart_shuffle_rating: f32,
+    pub smart_shuffle_recency: f32,
+    // Commands run on player events
+    pub hooks: Hooks,
+    // Keys and the commands bound to them with `bind KEYS = COMMAND`
+    pub commands: Vec<(String, String)>,
+    // Names and comma-separated steps of `macro NAME = STEPS`
+    pub macros: Vec<(String, String)>,
+    // What is written to clap.log, overall and per area
+    pub log_level: LogLevels,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            volume_step: 10.0,
+            skip_silence: false,
+            crossfeed: false,
+            crossfeed_strength: 75.0,
+            balance: 0.0,
+            swap_channels: false,
+            mono: false,
+            resampler: Resampling::Sinc,
+            band_limit: true,
+            silence_skip_seconds: 2.0,
+            trim_silence: false,
+            background_analysis: false,
+            crossfade_seconds: 0.0,
+            restart_seconds: 3.0,
+            resume_minutes: 20.0,
+            replay_seconds: 10.0,
+            forward_seconds: 30.0,
+            auto_mix: false,
+            auto_fill: false,
+            queue_end: QueueEnd::Repeat,
+            shutdown_command: hooks::SHUTDOWN_COMMAND.to_string(),
+            skip_shorter_than: 0.0,
+            skip_patterns: Vec::new(),
+            history_size: 100,
+            announce: false,
+            announce_command: announce::DEFAULT_COMMAND.to_string(),
+            duck_for: DuckFor::Off,
+            duck_volume: 20.0,
+            focus_minutes: 25.0,
+            break_minutes: 5.0,
+            focus_playlist: None,
+            break_playlist: None,
+            party_passphrase: None,
+            party_max_volume: 80.0,
+            night_hours: None,
+            night_max_volume: 40.0,
+            compressor: CompressorPreset::Off,
+            compressor_threshold: None,
+            compressor_ratio: None,
+            record_dir: config_dir().join("recordings"),
+            transcode_format: Format::Opus,
+            transcode_bitrate: 128,
+            transcode_dir: config_dir().join("transcoded"),
+            sync_dir: None,
+            sync_playlists: Vec::new(),
+            sync_format: None,
+            sync_bitrate: 128,
+            roaming_url: None,
+            roaming_user: None,
+            roaming_password: None,
+            cache_limit_mb: 2048,
+            replaygain: ReplayGainMode::Off,
+            output_device: String::new(),
+            output_buffer: OutputBuffer::Auto,
+            bit_perfect: false,
+            soundfont: config_dir().join("default.sf2"),
+            playlists_dir: config_dir().join("playlists"),
+            library_roots: Vec::new(),
+            keymap: Preset::Default,
+            playlist_columns: Column::defaults(),
+            theme: Theme::Default,
+            labels: Label::parse_list("workout:red; focus:blue; party:magenta").unwrap_or_default(),
+            reduced_motion: false,
+            low_bandwidth: false,
+            kiosk: false,
+            split_percent: 60,
+            status_format: None,
+            terminal_title: true,
+            pause_on_suspend: false,
+            smart_shuffle_rating: 2.0,
+            smart_shuffle_recency: 2.0,
+            hooks: Hooks::default(),
+            commands: Vec::new(),
+            macros: Vec::new(),
+            log_level: LogLevels::default(),
+        }
+    }
+}
+
+impl Config {
+    // Missing files and unknown or malformed entries fall back to the defaults
+    pub fn load() -> Config {
+        let mut config = Config::default();
+        if let Ok(contents) = fs::read_to_string(config_path()) {
+            for line in contents.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                if let Some((key, value)) = line.split_once('=') {
+                    config.set(key.trim(), value.trim());
+                }
+            }
+        }
+        config
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "volume_step" => parse_into(value, &mut self.volume_step),
+            "skip_silence" => parse_into(value, &mut self.skip_silence),
+            "crossfeed" => parse_into(value, &mut self.crossfeed),
+            "crossfeed_strength" => parse_into(value, &mut self.crossfeed_strength),
+            "balance" => parse_into(value, &mut self.balance),
+            "swap_channels" => parse_into(value, &mut self.swap_channels),
+            "mono" => parse_into(value, &mut self.mono),
+            "resampler" => parse_into(value, &mut self.resampler),
+            "band_limit" => parse_into(value, &mut self.band_limit),
+            "silence_skip_seconds" => parse_into(value, &mut self.silence_skip_seconds),
+            "trim_silence" => parse_into(value, &mut self.trim_silence),
+            "background_analysis" => parse_into(value, &mut self.background_analysis),
+            "crossfade_seconds" => parse_into(value, &mut self.crossfade_seconds),
+            "restart_seconds" => parse_into(value, &mut self.restart_seconds),
+            "resume_minutes" => parse_into(value, &mut self.resume_minutes),
+            "replay_seconds" => parse_into(value, &mut self.replay_seconds),
+            "forward_seconds" => parse_into(value, &mut self.forward_seconds),
+            "auto_mix" => parse_into(value, &mut self.auto_mix),
+            "auto_fill" => parse_into(value, &mut self.auto_fill),
+            "queue_end" => parse_into(value, &mut self.queue_end),
+            "skip_shorter_than" => parse_into(value, &mut self.skip_shorter_than),
+            "skip_patterns" => {
+                self.skip_patterns = value
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|pattern| !pattern.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }
+            "shutdown_command" => self.shutdown_command = command(value).unwrap_or_else(|| hooks::SHUTDOWN_COMMAND.to_string()),
+            "history_size" => parse_into(value, &mut self.history_size),
+            "announce" => parse_into(value, &mut self.announce),
+            "announce_command" => {
+                self.announce_command = command(value).unwrap_or_else(|| announce::DEFAULT_COMMAND.to_string())
+            }
+            "focus_minutes" => parse_into(value, &mut self.focus_minutes),
+            "break_minutes" => parse_into(value, &mut self.break_minutes),
+            "focus_playlist" => self.focus_playlist = Some(PathBuf::from(value)).filter(|_| !value.is_empty()),
+            "break_playlist" => self.break_playlist = Some(PathBuf::from(value)).filter(|_| !value.is_empty()),
+            "party_passphrase" => self.party_passphrase = command(value),
+            "party_max_volume" => parse_into(value, &mut self.party_max_volume),
+            "night_hours" => self.night_hours = value.parse().ok(),
+            "night_max_volume" => parse_into(value, &mut self.night_max_volume),
+            "duck_for" => parse_into(value, &mut self.duck_for),
+            "duck_volume" => parse_into(value, &mut self.duck_volume),
+            "compressor" => parse_into(value, &mut self.compressor),
+            "compressor_threshold" => self.compressor_threshold = value.parse().ok(),
+            "compressor_ratio" => self.compressor_ratio = value.parse().ok(),
+            "record_dir" => self.record_dir = PathBuf::from(value),
+            "transcode_format" => parse_into(value, &mut self.transcode_format),
+            "transcode_bitrate" => parse_into(value, &mut self.transcode_bitrate),
+            "transcode_dir" => self.transcode_dir = PathBuf::from(value),
+            "sync_dir" => self.sync_dir = Some(PathBuf::from(value)).filter(|_| !value.is_empty()),
+            "sync_playlists" => {
+                self.sync_playlists = value
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }
+            "sync_format" => {
+                if value.eq_ignore_ascii_case("copy") {
+                    self.sync_format = None;
+                } else if let Ok(format) = value.parse() {
+                    self.sync_format = Some(format);
+                }
+            }
+            "sync_bitrate" => parse_into(value, &mut self.sync_bitrate),
+            "roaming_url" => self.roaming_url = command(value),
+            "roaming_user" => self.roaming_user = command(value),
+            "roaming_password" => self.roaming_password = command(value),
+            "cache_limit_mb" => parse_into(value, &mut self.cache_limit_mb),
+            "replaygain" => parse_into(value, &mut self.replaygain),
+            "output_device" => self.output_device = value.to_string(),
+            "output_buffer" => parse_into(value, &mut self.output_buffer),
+            "bit_perfect" => parse_into(value, &mut self.bit_perfect),
+            "soundfont" => self.soundfont = PathBuf::from(value),
+            "playlists_dir" => self.playlists_dir = PathBuf::from(value),
+            "keymap" => parse_into(value, &mut self.keymap),
+            "theme" => parse_into(value, &mut self.theme),
+            "labels" => {
+                if let Ok(labels) = Label::parse_list(value) {
+                    self.labels = labels;
+                }
+            }
+            "reduced_motion" => parse_into(value, &mut self.reduced_motion),
+            "low_bandwidth" => parse_into(value, &mut self.low_bandwidth),
+            "kiosk" => parse_into(value, &mut self.kiosk),
+            "split_percent" => parse_into(value, &mut self.split_percent),
+            "library_roots" => {
+                self.library_roots = value
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|root| !root.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }
+            "playlist_columns" => {
+                if let Ok(columns) = value.split(',').map(str::parse).collect::<Result<Vec<Column>, _>>() {
+                    if !columns.is_empty() {
+                        self.playlist_columns = columns;
+                    }
+                }
+            }
+            "status_format" => self.status_format = Some(value.to_string()).filter(|format| !format.is_empty()),
+            "terminal_title" => parse_into(value, &mut self.terminal_title),
+            "pause_on_suspend" => parse_into(value, &mut self.pause_on_suspend),
+            "smart_shuffle_rating" => parse_into(value, &mut self.smart_shuffle_rating),
+            "smart_shuffle_recency" => parse_into(value, &mut self.smart_shuffle_recency),
+            "on_track_change" => self.hooks.on_track_change = command(value),
+            "on_pause" => self.hooks.on_pause = command(value),
+            "on_stop" => self.hooks.on_stop = command(value),
+            "on_queue_end" => self.hooks.on_queue_end = command(value),
+            "on_focus" => self.hooks.on_focus = command(value),
+            "on_break" => self.hooks.on_break = command(value),
+            "log_level" => parse_into(value, &mut self.log_level),
+            _ => {
+                if let (Some(keys), Some(command)) = (key.strip_prefix("bind "), command(value)) {
+                    self.commands.push((keys.trim().to_string(), command));
+                } else if let (Some(name), Some(steps)) = (key.strip_prefix("macro "), command(value)) {
+                    // Saving a macro again under its name replaces it
+                    let name = name.trim().to_string();
+                    self.macros.retain(|(existing, _)| *existing != name);
+                    self.macros.push((name, steps));
+                }
+            }
+        }
+    }
+
+    /// A setting's value as it is written in clap.conf.
+    pub fn get(&self, key: &str) -> Option<String> {
+        Some(match key {
+            "volume_step" => self.volume_step.to_string(),
+            "skip_silence" => self.skip_silence.to_string(),
+            "crossfeed" => self.crossfeed.to_string(),
+            "crossfeed_strength" => self.crossfeed_strength.to_string(),
+            "balance" => self.balance.to_string(),
+            "swap_channels" => self.swap_channels.to_string(),
+            "mono" => self.mono.to_string(),
+            "resampler" => self.resampler.name().to_string(),
+            "band_limit" => self.band_limit.to_string(),
+            "silence_skip_seconds" => self.silence_skip_seconds.to_string(),
+            "trim_silence" => self.trim_silence.to_string(),
+            "crossfade_seconds" => self.crossfade_seconds.to_string(),
+            "restart_seconds" => self.restart_seconds.to_string(),
+            "resume_minutes" => self.resume_minutes.to_string(),
+            "replay_seconds" => self.replay_seconds.to_string(),
+            "forward_seconds" => self.forward_seconds.to_string(),
+            "auto_mix" => self.auto_mix.to_string(),
+            "auto_fill" => self.auto_fill.to_string(),
+            "queue_end" => self.queue_end.name().to_string(),
+            "skip_shorter_than" => self.skip_shorter_than.to_string(),
+            "skip_patterns" => self.skip_patterns.join("; "),
+            "announce" => self.announce.to_string(),
+            "focus_minutes" => self.focus_minutes.to_string(),
+            "break_minutes" => self.break_minutes.to_string(),
+            "party_max_volume" => self.party_max_volume.to_string(),
+            "night_hours" => self.night_hours.map(|hours| hours.to_string()).unwrap_or_default(),
+            "night_max_volume" => self.night_max_volume.to_string(),
+            "duck_for" => self.duck_for.name().to_string(),
+            "duck_volume" => self.duck_volume.to_string(),
+            "compressor" => self.compressor.name().to_string(),
+            "compressor_threshold" => self.compressor_threshold.map(|db| db.to_string()).unwrap_or_default(),
+            "compressor_ratio" => self.compressor_ratio.map(|ratio| ratio.to_string()).unwrap_or_default(),
+            "replaygain" => format!("{:?}", self.replaygain).to_lowercase(),
+            "output_device" => self.output_device.clone(),
+            "output_buffer" => self.output_buffer.to_string(),
+            "library_roots" => self.library_roots.join("; "),
+            "cache_limit_mb" => self.cache_limit_mb.to_string(),
+            "keymap" => format!("{:?}", self.keymap).to_lowercase(),
+            "theme" => self.theme.name().to_string(),
+            "labels" => Label::list_to_string(&self.labels),
+            "reduced_motion" => self.reduced_motion.to_string(),
+            "low_bandwidth" => self.low_bandwidth.to_string(),
+            "split_percent" => self.split_percent.to_string(),
+            "pause_on_suspend" => self.pause_on_suspend.to_string(),
+            "smart_shuffle_rating" => self.smart_shuffle_rating.to_string(),
+            "smart_shuffle_recency" => self.smart_shuffle_recency.to_string(),
+            _ => return None,
+        })
+    }
+
+    /// Changes a setting and writes it to clap.conf, keeping the rest of the
+    /// file as it is. Values that don't parse are refused.
+    pub fn update(&mut self, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        let valid = match key {
+            "volume_step" | "crossfade_seconds" | "silence_skip_seconds" | "restart_seconds" => {
+                value.parse::<f32>().is_ok()
+            }
+            "resume_minutes" | "skip_shorter_than" => value.parse::<f32>().is_ok_and(|length| length >= 0.0),
+            "focus_minutes" | "break_minutes" => value.parse::<f32>().is_ok_and(|minutes| minutes > 0.0),
+            "night_hours" => value.is_empty() || value.parse::<NightHours>().is_ok(),
+            "compressor" => value.parse::<CompressorPreset>().is_ok(),
+            "resampler" => value.parse::<Resampling>().is_ok(),
+            "duck_for" => value.parse::<DuckFor>().is_ok(),
+            "queue_end" => value.parse::<QueueEnd>().is_ok(),
+            "output_buffer" => value.parse::<OutputBuffer>().is_ok(),
+            "compressor_threshold" => value.is_empty() || value.parse::<f32>().is_ok_and(|db| db <= 0.0),
+            "compressor_ratio" => value.is_empty() || value.parse::<f32>().is_ok_and(|ratio| ratio >= 1.0),
+            "party_max_volume" | "night_max_volume" | "duck_volume" | "crossfeed_strength" => value.parse::<f32>().is_ok_and(|volume| (0.0..=100.0).contains(&volume)),
+            "balance" => value.parse::<f32>().is_ok_and(|balance| (-100.0..=100.0).contains(&balance)),
+            "replay_seconds" | "forward_seconds" => value.parse::<f32>().is_ok_and(|step| step > 0.0),
+            "smart_shuffle_rating" | "smart_shuffle_recency" => value.parse::<f32>().is_ok_and(|weight| weight >= 0.0),
+            "skip_silence" | "crossfeed" | "swap_channels" | "mono" | "band_limit" | "trim_silence" | "auto_mix" | "auto_fill" | "announce" | "pause_on_suspend"
+            | "reduced_motion" | "low_bandwidth" => value.parse::<bool>().is_ok(),
+            "replaygain" => value.parse::<ReplayGainMode>().is_ok(),
+            "cache_limit_mb" => value.parse::<u64>().is_ok(),
+            "split_percent" => value.parse::<u16>().is_ok_and(|percent| percent == 0 || (20..=80).contains(&percent)),
+            "keymap" => value.parse::<Preset>().is_ok(),
+            "theme" => value.parse::<Theme>().is_ok(),
+            "labels" => Label::parse_list(value).is_ok(),
+            _ => true,
+        };
+        if !valid {
+            return Err(format!("'{}' is not a valid value", value).into());
+        }
+        self.set(key, value);
+
+        let path = config_path();
+        let contents = fs::read_to_string(&path).unwrap_or_default();
+        let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+        let entry = format!("{} = {}", key, value);
+        // The last entry is the one that counts when a key appears twice
+        let existing = lines.iter().rposition(|line| {
+            let line = line.trim();
+            !line.starts_with('#') && line.split_once('=').is_some_and(|(name, _)| name.trim() == key)
+        });
+        match existing {
+            Some(i) => lines[i] = entry,
+            None => lines.push(entry),
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, lines.join("\n") + "\n")?;
+        Ok(())
+    }
+
+    pub fn transcode_options(&self) -> TranscodeOptions {
+        TranscodeOptions {
+            format: self.transcode_format,
+            bitrate_kbps: self.transcode_bitrate,
+            output_dir: self.transcode_dir.clone(),
+        }
+    }
+
+    pub fn sync_options(&self) -> SyncOptions {
+        SyncOptions {
+            target: self.sync_dir.clone().unwrap_or_default(),
+            format: self.sync_format,
+            bitrate_kbps: self.sync_bitrate,
+        }
+    }
+}
+
+fn command(value: &str) -> Option<String> {
+    Some(value.to_string()).filter(|command| !command.is_empty())
+}
+
+fn parse_into<T: std::str::FromStr>(value: &str, target: &mut T) {
+    if let Ok(parsed) = value.parse() {
+        *target = parsed;
+    }
+}
+
+// Profile picked with --profile for this run, None for the default one
+static PROFILE: OnceLock<Option<String>> = OnceLock::new();
+
+/// Picks the profile for this run, before anything reads the config. Each
+/// profile keeps its settings, library, history and session in its own
+/// folder under `profiles`.
+pub fn set_profile(name: Option<String>) -> Result<(), String> {
+    if let Some(name) = name.as_deref().filter(|name| !is_profile_name(name)) {
+        return Err(format!("'{}' can't be a profile name", name));
+    }
+    PROFILE.set(name).map_err(|_| "the profile is already set".to_string())
+}
+
+// Names become folder names
+pub fn is_profile_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', ':'])
+}
+
+pub fn profile() -> Option<&'static str> {
+    PROFILE.get().and_then(|name| name.as_deref())
+}
+
+/// Profiles made so far, by name, besides the default one.
+pub fn profiles() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(base_dir().join("profiles"))
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+/// The folder of the profile in use.
+pub fn config_dir() -> PathBuf {
+    match profile() {
+        Some(name) => base_dir().join("profiles").join(name),
+        None => base_dir(),
+    }
+}
+
+fn base_dir() -> PathBuf {
+    if let Ok(appdata) = std::env::var("APPDATA") {
+        PathBuf::from(appdata).join("clap")
+    } else if let Ok(config_home) = std::env::var("XDG_CONFIG_HOME") {
+        PathBuf::from(config_home).join("clap")
+    } else if let Ok(home) = std::env::var("HOME") {
+        PathBuf::from(home).join(".config").join("clap")
+    } else {
+        PathBuf::from(".")
+    }
+}
+
+pub fn config_path() -> PathBuf {
+    config_dir().join("clap.conf")
+}
@@ -0,0 +1,318 @@
+// Opens any supported file as a stream of f32 samples
+
+use crate::{archive, remote};
+use rodio::{Decoder, Source};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::time::Duration;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::formats::{FormatOptions, FormatReader, Track};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+pub type BoxedSource = Box<dyn Source<Item = f32> + Send>;
+
+pub fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+// Containers only symphonia can read
+fn needs_symphonia(extension: &str) -> bool {
+    matches!(extension, "aif" | "aiff" | "aifc" | "mka" | "mkv" | "webm" | "m4a" | "m4b")
+}
+
+// Codecs neither rodio nor symphonia decode, played through ffmpeg
+pub fn needs_ffmpeg(extension: &str) -> bool {
+    matches!(extension, "wv" | "ape" | "opus")
+}
+
+// Network streams such as internet radio, which ffmpeg reads directly
+pub fn is_stream(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.contains("://") && !remote::is_sftp(Path::new(path)) && !remote::is_webdav(Path::new(path)))
+}
+
+/// Opens one audio track of a file with whichever decoder handles it.
+pub fn open(path: &Path, audio_track: usize) -> Result<BoxedSource, Box<dyn Error>> {
+    let extension = extension(path);
+    if is_stream(path) || needs_ffmpeg(&extension) {
+        log::debug!(target: "decoder", "decoding {} with ffmpeg", path.display());
+        return Ok(Box::new(FfmpegSource::open(path, audio_track)?));
+    }
+    if audio_track > 0 || needs_symphonia(&extension) {
+        log::debug!(target: "decoder", "decoding {} with symphonia", path.display());
+        return match SymphoniaSource::open(path, audio_track) {
+            Ok(source) => Ok(Box::new(source)),
+            // Matroska and WebM can carry codecs symphonia lacks, such as Opus
+            Err(e) => {
+                log::debug!(target: "decoder", "symphonia failed on {} ({}), trying ffmpeg", path.display(), e);
+                FfmpegSource::open(path, audio_track)
+                    .map(|source| Box::new(source) as BoxedSource)
+                    .map_err(|_| e)
+            }
+        };
+    }
+    log::debug!(target: "decoder", "decoding {} with rodio", path.display());
+    let reader = BufReader::new(open_media(path)?);
+    Ok(Box::new(Decoder::new(reader)?.convert_samples::<f32>()))
+}
+
+/// Opens a file for decoding, decompressing it first when it sits inside an
+/// archive.
+pub fn open_media(path: &Path) -> Result<Box<dyn MediaSource>, Box<dyn Error>> {
+    if remote::is_remote(path) {
+        return Ok(Box::new(remote::open(path)?));
+    }
+    match archive::split(path) {
+        Some((archive, name)) => Ok(Box::new(archive::open(&archive, &name)?)),
+        None => Ok(Box::new(File::open(path)?)),
+    }
+}
+
+/// Reads a whole file wherever it is stored.
+pub fn read_all(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut data = Vec::new();
+    open_media(path)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+// ffmpeg needs a real file or stream URL, so archived and remote tracks are copied to a temp file
+fn ffmpeg_input(path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    if remote::is_jellyfin(path) {
+        return Ok(PathBuf::from(remote::jellyfin::stream_url(path)?));
+    }
+    if is_stream(path) || (path.is_file() && !remote::is_remote(path)) {
+        return Ok(path.to_path_buf());
+    }
+    let file_name = path.file_name().ok_or("invalid track path")?;
+    let temp = std::env::temp_dir().join(format!("music-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&temp)?;
+    let copy = temp.join(file_name);
+    std::io::copy(&mut open_media(path)?, &mut File::create(&copy)?)?;
+    Ok(copy)
+}
+
+// Number of audio tracks in the file, e.g. several languages in a Matroska file
+pub fn audio_track_count(path: &Path) -> usize {
+    if needs_ffmpeg(&extension(path)) {
+        return 1;
+    }
+    probe(path)
+        .map(|format| format.tracks().iter().filter(|track| is_audio(track)).count())
+        .unwrap_or(1)
+        .max(1)
+}
+
+fn is_audio(track: &Track) -> bool {
+    track.codec_params.codec != CODEC_TYPE_NULL && track.codec_params.sample_rate.is_some()
+}
+
+fn probe(path: &Path) -> Result<Box<dyn FormatReader>, Box<dyn Error>> {
+    let stream = MediaSourceStream::new(open_media(path)?, Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(&extension(path));
+    Ok(symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())?
+        .format)
+}
+
+/// Decodes through symphonia straight to `f32`, keeping the full resolution of
+/// 24-bit sources that rodio's decoders reduce to 16 bits.
+pub struct SymphoniaSource {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn symphonia::core::codecs::Decoder>,
+    track_id: u32,
+    buffer: Option<SampleBuffer<f32>>,
+    pos: usize,
+    channels: u16,
+    sample_rate: u32,
+    pub bits_per_sample: Option<u32>,
+    duration: Option<Duration>,
+}
+
+impl SymphoniaSource {
+    pub fn open(path: &Path, audio_track: usize) -> Result<Self, Box<dyn Error>> {
+        let format = probe(path)?;
+        let track = format
+            .tracks()
+            .iter()
+            .filter(|track| is_audio(track))
+            .nth(audio_track)
+            .ok_or("no such audio track")?;
+        let params = &track.codec_params;
+        let decoder = symphonia::default::get_codecs().make(params, &DecoderOptions::default())?;
+        let sample_rate = params.sample_rate.ok_or("unknown sample rate")?;
+        let duration = params
+            .time_base
+            .zip(params.n_frames)
+            .map(|(base, frames)| Duration::from_secs_f64(frames as f64 * base.numer as f64 / base.denom as f64));
+
+        Ok(SymphoniaSource {
+            track_id: track.id,
+            channels: params.channels.map_or(2, |channels| channels.count() as u16),
+            sample_rate,
+            bits_per_sample: params.bits_per_sample,
+            duration,
+            format,
+            decoder,
+            buffer: None,
+            pos: 0,
+        })
+    }
+
+    fn decode_packet(&mut self) -> bool {
+        loop {
+            let Ok(packet) = self.format.next_packet() else {
+                return false;
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // Corrupt packets are skipped like rodio does
+                Err(symphonia::core::errors::Error::DecodeError(_)) => continue,
+                Err(_) => return false,
+            };
+            let spec = *decoded.spec();
+            let needed = decoded.capacity() * spec.channels.count();
+            if self.buffer.as_ref().is_none_or(|buffer| buffer.capacity() < needed) {
+                self.buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+            }
+            let buffer = self.buffer.as_mut().unwrap();
+            buffer.copy_interleaved_ref(decoded);
+            self.pos = 0;
+            if !buffer.is_empty() {
+                return true;
+            }
+        }
+    }
+}
+
+impl Iterator for SymphoniaSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.buffer.as_ref().is_none_or(|buffer| self.pos >= buffer.len()) && !self.decode_packet() {
+            return None;
+        }
+        let sample = self.buffer.as_ref()?.samples()[self.pos];
+        self.pos += 1;
+        Some(sample)
+    }
+}
+
+impl Source for SymphoniaSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.duration
+    }
+}
+
+/// Streams raw samples decoded by an ffmpeg child process.
+pub struct FfmpegSource {
+    child: Child,
+    output: BufReader<ChildStdout>,
+    channels: u16,
+    sample_rate: u32,
+    duration: Option<Duration>,
+}
+
+impl FfmpegSource {
+    pub fn open(path: &Path, audio_track: usize) -> Result<Self, Box<dyn Error>> {
+        let path = &ffmpeg_input(path)?;
+        let probe = Command::new("ffprobe")
+            .args(["-v", "error", "-select_streams", &format!("a:{}", audio_track)])
+            .args(["-show_entries", "stream=sample_rate,channels:format=duration"])
+            .args(["-of", "default=noprint_wrappers=1"])
+            .arg(path)
+            .output()
+            .map_err(|e| format!("could not start ffprobe: {}", e))?;
+        let info = String::from_utf8_lossy(&probe.stdout);
+        let field = |name: &str| {
+            info.lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+                .map(str::to_string)
+        };
+        let sample_rate: u32 = field("sample_rate")
+            .and_then(|rate| rate.parse().ok())
+            .ok_or("ffprobe found no audio stream")?;
+        let channels: u16 = field("channels").and_then(|channels| channels.parse().ok()).unwrap_or(2);
+        let duration = field("duration")
+            .and_then(|duration| duration.parse::<f64>().ok())
+            .map(Duration::from_secs_f64);
+
+        let mut child = Command::new("ffmpeg")
+            .args(["-hide_banner", "-loglevel", "error", "-i"])
+            .arg(path)
+            .args(["-map", &format!("0:a:{}", audio_track), "-f", "f32le", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("could not start ffmpeg: {}", e))?;
+        let output = BufReader::new(child.stdout.take().ok_or("ffmpeg has no output")?);
+
+        Ok(FfmpegSource {
+            child,
+            output,
+            channels,
+            sample_rate,
+            duration,
+        })
+    }
+}
+
+impl Drop for FfmpegSource {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Iterator for FfmpegSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let mut bytes = [0; 4];
+        self.output.read_exact(&mut bytes).ok()?;
+        Some(f32::from_le_bytes(bytes))
+    }
+}
+
+impl Source for FfmpegSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.duration
+    }
+}
@@ -0,0 +1,138 @@
+// Registers the player with the desktop, so file managers can open audio
+// files with it through `music-cli --play-now` and `--enqueue`
+
+use std::error::Error;
+#[cfg(not(target_os = "macos"))]
+use std::{path::PathBuf, process::Command};
+
+#[cfg(not(target_os = "macos"))]
+fn executable() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(std::env::current_exe()?)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+const MIME_TYPES: &[&str] = &[
+    "audio/mpeg",
+    "audio/wav",
+    "audio/x-wav",
+    "audio/flac",
+    "audio/x-flac",
+    "audio/ogg",
+    "audio/x-vorbis+ogg",
+    "audio/midi",
+    "audio/x-midi",
+    "audio/aiff",
+    "audio/x-aiff",
+    "audio/x-matroska",
+    "audio/mp4",
+    "audio/x-m4a",
+    "audio/x-m4b",
+    "audio/x-wavpack",
+    "audio/x-ape",
+    "audio/x-mpegurl",
+    "audio/mpegurl",
+    "audio/x-scpls",
+];
+
+/// Writes desktop entries for opening and for queueing files. With
+/// `make_default` the player also becomes the default for audio files.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn install(make_default: bool) -> Result<(), Box<dyn Error>> {
+    let data_home = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME").ok_or("HOME is not set")?).join(".local/share"),
+    };
+    let applications = data_home.join("applications");
+    std::fs::create_dir_all(&applications)?;
+
+    // Exec arguments with spaces or quotes have to be quoted
+    let exec = executable()?.to_string_lossy().into_owned();
+    let exec = if exec.contains([' ', '"', '\\']) {
+        format!("\"{}\"", exec.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        exec
+    };
+    let mime_types = MIME_TYPES.iter().map(|mime| format!("{};", mime)).collect::<String>();
+    let entries = [
+        ("music-cli.desktop", "CLAP", "--play-now", false),
+        ("music-cli-enqueue.desktop", "Add to CLAP queue", "--enqueue", true),
+    ];
+    for (file, name, mode, hidden) in entries {
+        let entry = format!(
+            "[Desktop Entry]\nType=Application\nName={}\nComment=Terminal music player\nExec={} {} %F\nTerminal=true\nCategories=Audio;Player;\nMimeType={}\nNoDisplay={}\n",
+            name, exec, mode, mime_types, hidden
+        );
+        let path = applications.join(file);
+        std::fs::write(&path, entry)?;
+        println!("Wrote {}", path.display());
+    }
+
+    // Both are optional, menus pick up the entries eventually without them
+    let _ = Command::new("update-desktop-database").arg(&applications).status();
+    if make_default {
+        let status = Command::new("xdg-mime")
+            .args(["default", "music-cli.desktop"])
+            .args(MIME_TYPES)
+            .status()
+            .map_err(|e| format!("could not start xdg-mime: {}", e))?;
+        if !status.success() {
+            return Err("xdg-mime could not set the default player".into());
+        }
+        println!("CLAP is now the default for audio files");
+    }
+    Ok(())
+}
+
+// Extensions the library scanner picks up, plus playlists
+#[cfg(windows)]
+const EXTENSIONS: &[&str] = &[
+    "mp3", "wav", "flac", "ogg", "mid", "midi", "aif", "aiff", "aifc", "mka", "m4a", "m4b", "wv", "ape", "m3u", "m3u8",
+    "pls",
+];
+
+/// Registers the player under "Open with" for the audio extensions. Windows
+/// only lets the user pick the default, so `make_default` can't be honoured.
+#[cfg(windows)]
+pub fn install(make_default: bool) -> Result<(), Box<dyn Error>> {
+    let exe = executable()?;
+    let exe = exe.to_string_lossy();
+    let key = r"HKCU\Software\Classes\Applications\music-cli.exe";
+    let commands = [
+        ("open", "Play with CLAP", "--play-now"),
+        ("enqueue", "Add to CLAP queue", "--enqueue"),
+    ];
+    for (verb, label, mode) in commands {
+        reg(&[&format!(r"{}\shell\{}", key, verb), "/ve", "/d", label])?;
+        let command = format!("\"{}\" {} \"%1\"", exe, mode);
+        reg(&[&format!(r"{}\shell\{}\command", key, verb), "/ve", "/d", &command])?;
+    }
+    for extension in EXTENSIONS {
+        reg(&[&format!(r"{}\SupportedTypes", key), "/v", &format!(".{}", extension), "/d", ""])?;
+        reg(&[&format!(r"HKCU\Software\Classes\.{}\OpenWithList\music-cli.exe", extension), "/ve", "/d", ""])?;
+    }
+    println!("CLAP is listed under \"Open with\" for audio files");
+    if make_default {
+        println!("Windows only lets you choose the default player yourself: right-click a file, then Open with > Choose another app");
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn reg(args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let status = Command::new("reg")
+        .arg("add")
+        .args(args)
+        .arg("/f")
+        .stdout(std::process::Stdio::null())
+        .status()
+        .map_err(|e| format!("could not start reg: {}", e))?;
+    if !status.success() {
+        return Err(format!("could not write {}", args[0]).into());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+pub fn install(_make_default: bool) -> Result<(), Box<dyn Error>> {
+    Err("file associations need an app bundle on macOS, which music-cli doesn't have".into())
+}
@@ -0,0 +1,146 @@
+// Turns the music down while other programs play sound or a call is on. The
+// streams come from the audio server through pactl, which PulseAudio and
+// PipeWire both answer; elsewhere nothing is reported.
+
+use std::fmt;
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+// How often the audio server's streams are listed
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What turns the music down, from `duck_for`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuckFor {
+    Off,
+    Calls,
+    // Calls and any other program playing sound
+    All,
+}
+
+impl DuckFor {
+    pub fn name(self) -> &'static str {
+        match self {
+            DuckFor::Off => "off",
+            DuckFor::Calls => "calls",
+            DuckFor::All => "all",
+        }
+    }
+}
+
+impl FromStr for DuckFor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(DuckFor::Off),
+            "calls" => Ok(DuckFor::Calls),
+            "all" => Ok(DuckFor::All),
+            _ => Err(format!("unknown ducking mode '{}'", s)),
+        }
+    }
+}
+
+/// What else is playing on the system.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Activity {
+    Quiet,
+    Audio,
+    Call,
+}
+
+impl fmt::Display for Activity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Activity::Quiet => "quiet",
+            Activity::Audio => "other audio",
+            Activity::Call => "a call",
+        })
+    }
+}
+
+/// Watches the audio server in the background for what `duck_for` asks.
+pub struct Watcher {
+    changes: Option<Receiver<Activity>>,
+    current: Activity,
+}
+
+impl Watcher {
+    pub fn start(duck_for: DuckFor) -> Watcher {
+        let changes = (duck_for != DuckFor::Off).then(|| {
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                let own = std::process::id().to_string();
+                let mut last = Activity::Quiet;
+                loop {
+                    let activity = match streams(&own) {
+                        Ok(activity) if duck_for == DuckFor::Calls && activity == Activity::Audio => Activity::Quiet,
+                        Ok(activity) => activity,
+                        Err(e) => {
+                            log::warn!(target: "playback", "can't tell when other programs play sound: {}", e);
+                            return;
+                        }
+                    };
+                    // Stops once the watcher is dropped
+                    if activity != last && tx.send(activity).is_err() {
+                        return;
+                    }
+                    last = activity;
+                    thread::sleep(POLL_INTERVAL);
+                }
+            });
+            rx
+        });
+        Watcher { changes, current: Activity::Quiet }
+    }
+
+    /// The activity when it has changed since the last poll.
+    pub fn poll(&mut self) -> Option<Activity> {
+        let latest = self.changes.as_ref()?.try_iter().last()?;
+        self.current = latest;
+        Some(latest)
+    }
+
+    pub fn current(&self) -> Activity {
+        self.current
+    }
+}
+
+// Playing streams of other programs, from blocks such as
+//
+//   Sink Input #42
+//       Corked: no
+//       Properties:
+//           media.role = "phone"
+//           application.process.id = "1234"
+fn streams(own: &str) -> Result<Activity, String> {
+    let output = Command::new("pactl")
+        .args(["list", "sink-inputs"])
+        .output()
+        .map_err(|e| format!("pactl: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    let mut activity = Activity::Quiet;
+    for block in text.split("Sink Input #").skip(1) {
+        let property = |key: &str| {
+            block.lines().find_map(|line| {
+                let (name, value) = line.trim().split_once(" = ")?;
+                (name == key).then(|| value.trim_matches('"').to_string())
+            })
+        };
+        let corked = block.lines().any(|line| line.trim() == "Corked: yes");
+        if corked || property("application.process.id").as_deref() == Some(own) {
+            continue;
+        }
+        if property("media.role").as_deref() == Some("phone") {
+            return Ok(Activity::Call);
+        }
+        activity = Activity::Audio;
+    }
+    Ok(activity)
+}
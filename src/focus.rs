@@ -0,0 +1,56 @@
+// Focus mode: music plays through work blocks and pauses, or changes to
+// another playlist, during the breaks between them
+
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    Work,
+    Break,
+}
+
+pub struct FocusTimer {
+    work: Duration,
+    rest: Duration,
+    phase: Phase,
+    ends: Instant,
+    // Work blocks started, the current one included
+    round: u32,
+}
+
+impl FocusTimer {
+    /// Starts with a work block.
+    pub fn new(work: Duration, rest: Duration) -> FocusTimer {
+        FocusTimer { work, rest, phase: Phase::Work, ends: Instant::now() + work, round: 1 }
+    }
+
+    /// Moves on once the current block is over, returning the phase it moved to.
+    pub fn poll(&mut self) -> Option<Phase> {
+        if Instant::now() < self.ends {
+            return None;
+        }
+        let length = match self.phase {
+            Phase::Work => {
+                self.phase = Phase::Break;
+                self.rest
+            }
+            Phase::Break => {
+                self.phase = Phase::Work;
+                self.round += 1;
+                self.work
+            }
+        };
+        // Counted from now, so a suspended player doesn't race through blocks
+        self.ends = Instant::now() + length;
+        Some(self.phase)
+    }
+
+    /// The phase and time left for the status bar, e.g. `Focus #2 18:42`.
+    pub fn label(&self) -> String {
+        let left = self.ends.saturating_duration_since(Instant::now()).as_secs();
+        match self.phase {
+            Phase::Work => format!("Focus #{} {}:{:02}", self.round, left / 60, left % 60),
+            Phase::Break => format!("Break {}:{:02}", left / 60, left % 60),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// Tracks played lately, for finding out what that was after a shuffled track
+// has ended. Kept in `history` next to the configuration, oldest first, one
+// `SECONDS<TAB>PATH` line per track with the time it started since 1970.
+
+use crate::config::config_dir;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct Entry {
+    pub path: PathBuf,
+    pub started: u64,
+}
+
+pub struct History {
+    entries: VecDeque<Entry>,
+    limit: usize,
+}
+
+impl History {
+    /// The saved history, cut to the last `limit` tracks.
+    pub fn load(limit: usize) -> History {
+        let mut history = History { entries: VecDeque::new(), limit };
+        let contents = fs::read_to_string(config_dir().join("history")).unwrap_or_default();
+        for line in contents.lines() {
+            if let Some((started, path)) = line.split_once('\t') {
+                history.entries.push_back(Entry { path: PathBuf::from(path), started: started.parse().unwrap_or_default() });
+            }
+        }
+        history.trim();
+        history
+    }
+
+    /// Notes a track that started playing, and saves the history.
+    pub fn push(&mut self, path: PathBuf) -> io::Result<()> {
+        // Repeating one track adds it once
+        if self.entries.back().is_some_and(|last| last.path == path) {
+            return Ok(());
+        }
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        self.entries.push_back(Entry { path, started });
+        self.trim();
+        self.save()
+    }
+
+    /// Adds tracks played on other machines in the order they started, and
+    /// saves the history.
+    pub fn merge(&mut self, entries: Vec<Entry>) -> io::Result<()> {
+        let mut merged: Vec<Entry> = self.entries.drain(..).chain(entries).collect();
+        merged.sort_by(|a, b| (a.started, &a.path).cmp(&(b.started, &b.path)));
+        merged.dedup_by(|a, b| a.started == b.started && a.path == b.path);
+        self.entries = merged.into();
+        self.trim();
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let lines: String = self
+            .entries
+            .iter()
+            .map(|entry| format!("{}\t{}\n", entry.started, entry.path.display()))
+            .collect();
+        fs::create_dir_all(config_dir())?;
+        fs::write(config_dir().join("history"), lines)
+    }
+
+    /// Newest first.
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().rev()
+    }
+
+    fn trim(&mut self) {
+        while self.entries.len() > self.limit {
+            self.entries.pop_front();
+        }
+    }
+}
@@ -0,0 +1,168 @@
+// User commands run on player events, set with `on_track_change`, `on_pause`,
+// `on_stop`, `on_queue_end`, `on_focus` and `on_break` in the config, and
+// commands bound to keys with `bind`. Scripted plugins get the same events
+// through the plugins module.
+
+use crate::now_playing::NowPlaying;
+use crate::toast;
+use std::io;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+// Turns the machine off for `queue_end = shutdown`
+#[cfg(target_os = "macos")]
+pub const SHUTDOWN_COMMAND: &str = "osascript -e 'tell application \"System Events\" to shut down'";
+#[cfg(windows)]
+pub const SHUTDOWN_COMMAND: &str = "shutdown /s /t 0";
+#[cfg(not(any(target_os = "macos", windows)))]
+pub const SHUTDOWN_COMMAND: &str = "systemctl poweroff";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    TrackChange,
+    Pause,
+    Stop,
+    QueueEnd,
+    // Focus mode starting a work block or a break
+    Focus,
+    Break,
+}
+
+impl Event {
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::TrackChange => "track_change",
+            Event::Pause => "pause",
+            Event::Stop => "stop",
+            Event::QueueEnd => "queue_end",
+            Event::Focus => "focus",
+            Event::Break => "break",
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Hooks {
+    pub on_track_change: Option<String>,
+    pub on_pause: Option<String>,
+    pub on_stop: Option<String>,
+    pub on_queue_end: Option<String>,
+    pub on_focus: Option<String>,
+    pub on_break: Option<String>,
+}
+
+impl Hooks {
+    /// Events between two now-playing snapshots, in the order they happened.
+    pub fn events(before: &NowPlaying, after: &NowPlaying) -> Vec<Event> {
+        let mut events = Vec::new();
+        if before.state == "playing" && after.state == "paused" {
+            events.push(Event::Pause);
+        }
+        if before.state != "stopped" && after.state == "stopped" {
+            events.push(Event::Stop);
+        }
+        if after.state != "stopped" && after.file != before.file {
+            events.push(Event::TrackChange);
+        }
+        events
+    }
+
+    /// Runs the command for an event in the background. The track is
+    /// described in CLAP_* environment variables.
+    pub fn run(&self, event: Event, now_playing: &NowPlaying) {
+        let Some(command) = self.command(event) else {
+            return;
+        };
+
+        let mut shell = with_track(command, event.name(), now_playing);
+        // Output would scribble over the interface
+        shell.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+        let name = event.name();
+        match shell.spawn() {
+            // Reaped on its own thread so slow hooks don't hold up playback
+            Ok(mut child) => {
+                thread::spawn(move || {
+                    if let Some(status) = child.wait().ok().filter(|status| !status.success()) {
+                        toast::error(format!("The on_{} hook failed ({})", name, status));
+                    }
+                });
+            }
+            Err(e) => toast::error(format!("Could not run the on_{} hook: {}", name, e)),
+        }
+    }
+
+    pub fn command(&self, event: Event) -> Option<&str> {
+        match event {
+            Event::TrackChange => &self.on_track_change,
+            Event::Pause => &self.on_pause,
+            Event::Stop => &self.on_stop,
+            Event::QueueEnd => &self.on_queue_end,
+            Event::Focus => &self.on_focus,
+            Event::Break => &self.on_break,
+        }
+        .as_deref()
+    }
+}
+
+/// Starts a command such as `shutdown_command` and leaves it running.
+pub fn spawn(command: &str) -> io::Result<()> {
+    let mut shell = shell(command);
+    shell.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+    shell.spawn().map(drop)
+}
+
+/// Runs a command bound to a key in the background, sending the last line it
+/// prints, or why it failed, to `output` for the status bar.
+pub fn run_bound(command: &str, now_playing: &NowPlaying, output: Sender<String>) {
+    let mut shell = with_track(command, "key", now_playing);
+    shell.stdin(Stdio::null());
+    thread::spawn(move || {
+        let message = match shell.output() {
+            Ok(result) if result.status.success() => String::from_utf8_lossy(&result.stdout)
+                .lines()
+                .rev()
+                .find(|line| !line.trim().is_empty())
+                .unwrap_or_default()
+                .to_string(),
+            Ok(result) => {
+                let stderr = String::from_utf8_lossy(&result.stderr);
+                let reason = stderr.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or_default();
+                format!("Command failed ({}): {}", result.status, reason)
+            }
+            Err(e) => format!("Could not run command: {}", e),
+        };
+        let _ = output.send(message);
+    });
+}
+
+/// A shell running the command, with the track described in CLAP_* variables.
+pub fn with_track(command: &str, event: &str, now_playing: &NowPlaying) -> Command {
+    let mut shell = shell(command);
+    shell
+        .env("CLAP_EVENT", event)
+        .env("CLAP_STATE", now_playing.state)
+        .env("CLAP_TITLE", &now_playing.title)
+        .env("CLAP_ARTIST", &now_playing.artist)
+        .env("CLAP_ALBUM", &now_playing.album)
+        .env("CLAP_FILE", &now_playing.file)
+        .env("CLAP_NUMBER", now_playing.number.map(|number| number.to_string()).unwrap_or_default())
+        .env("CLAP_ELAPSED", now_playing.elapsed.as_secs().to_string())
+        .env("CLAP_DURATION", now_playing.duration.map(|duration| duration.as_secs().to_string()).unwrap_or_default())
+        .env("CLAP_VOLUME", now_playing.volume.to_string());
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.args(["/C", command]);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.args(["-c", command]);
+    shell
+}
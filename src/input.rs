@@ -0,0 +1,134 @@
+// The input thread, which turns terminal events into InputEvents for the main
+// loop and sends ticks in between. On Unix a second thread passes on job
+// control signals, so the terminal can be handed back before stopping.
+
+use crate::{ipc, plugins};
+use crossterm::event::{self, Event, KeyEvent, KeyEventKind, MouseButton, MouseEvent, MouseEventKind};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Longest wait before the thread notices it should stop
+const STOP_CHECK: Duration = Duration::from_millis(250);
+
+pub enum InputEvent<I> {
+    Input(I),
+    Paste(String),
+    // Left click at a column and row
+    Click(u16, u16),
+    // Request from the IPC socket
+    Remote(ipc::Command),
+    // A plugin asking for something, requests included
+    Plugin(plugins::Message),
+    // The terminal's new size, in columns and rows
+    Resize(u16, u16),
+    // SIGTSTP from outside, e.g. `kill -TSTP`
+    Suspend,
+    // SIGCONT, the screen may be garbled after running stopped
+    Continued,
+    Tick,
+}
+
+pub struct Input {
+    running: Arc<AtomicBool>,
+    // Milliseconds between ticks
+    tick_millis: Arc<AtomicU64>,
+    thread: JoinHandle<()>,
+    #[cfg(unix)]
+    signals: Option<signal_hook::iterator::Handle>,
+}
+
+impl Input {
+    pub fn spawn(tx: Sender<InputEvent<KeyEvent>>, tick_rate: Duration) -> Input {
+        let running = Arc::new(AtomicBool::new(true));
+        let tick_millis = Arc::new(AtomicU64::new(tick_rate.as_millis() as u64));
+        #[cfg(unix)]
+        let signals = watch_signals(tx.clone())
+            .inspect_err(|e| log::warn!(target: "playback", "Ctrl-Z from outside won't restore the terminal: {}", e))
+            .ok();
+        let thread = {
+            let running = running.clone();
+            let tick_millis = tick_millis.clone();
+            thread::spawn(move || read_events(&tx, &running, &tick_millis))
+        };
+        Input {
+            running,
+            tick_millis,
+            thread,
+            #[cfg(unix)]
+            signals,
+        }
+    }
+
+    pub fn set_tick_rate(&self, tick_rate: Duration) {
+        self.tick_millis.store(tick_rate.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Stops the thread and waits for it, so no event is read after the
+    /// terminal is handed back.
+    pub fn stop(self) {
+        #[cfg(unix)]
+        if let Some(signals) = &self.signals {
+            signals.close();
+        }
+        self.running.store(false, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+fn read_events(tx: &Sender<InputEvent<KeyEvent>>, running: &AtomicBool, tick_millis: &AtomicU64) {
+    let mut last_tick = Instant::now();
+    while running.load(Ordering::Relaxed) {
+        let tick_rate = Duration::from_millis(tick_millis.load(Ordering::Relaxed));
+        let timeout = tick_rate.saturating_sub(last_tick.elapsed()).min(STOP_CHECK);
+
+        let ready = match event::poll(timeout) {
+            Ok(ready) => ready,
+            Err(e) => {
+                log::error!(target: "playback", "could not read the terminal: {}", e);
+                return;
+            }
+        };
+        if ready && running.load(Ordering::Relaxed) {
+            let event = match event::read() {
+                Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => Some(InputEvent::Input(key)),
+                Ok(Event::Paste(text)) => Some(InputEvent::Paste(text)),
+                Ok(Event::Resize(columns, rows)) => Some(InputEvent::Resize(columns, rows)),
+                Ok(Event::Mouse(MouseEvent { kind: MouseEventKind::Down(MouseButton::Left), column, row, .. })) => {
+                    Some(InputEvent::Click(column, row))
+                }
+                _ => None,
+            };
+            // The main loop is gone once the receiver is
+            if event.is_some_and(|event| tx.send(event).is_err()) {
+                return;
+            }
+        }
+
+        if last_tick.elapsed() >= tick_rate {
+            if tx.send(InputEvent::Tick).is_err() {
+                return;
+            }
+            last_tick = Instant::now();
+        }
+    }
+}
+
+#[cfg(unix)]
+fn watch_signals(tx: Sender<InputEvent<KeyEvent>>) -> std::io::Result<signal_hook::iterator::Handle> {
+    use signal_hook::consts::{SIGCONT, SIGTSTP};
+
+    let mut signals = signal_hook::iterator::Signals::new([SIGTSTP, SIGCONT])?;
+    let handle = signals.handle();
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            let event = if signal == SIGTSTP { InputEvent::Suspend } else { InputEvent::Continued };
+            if tx.send(event).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(handle)
+}
@@ -0,0 +1,190 @@
+// Local socket through which other music-cli commands and plugins talk to
+// the running player. Each connection sends one request line and gets one
+// reply:
+//
+//   status             now playing as JSON
+//   text               `Artist - Title`
+//   format TEMPLATE    now playing filled into a template
+//
+// Other requests, such as `next` or `add PATH`, are passed to the main loop
+// as a Command and answered from there. Plugins send the same Commands
+// without going through the socket.
+
+use crate::config::config_dir;
+use crate::now_playing::NowPlaying;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+// Clients wait longer than the server waits for the main loop
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Snapshot the main loop keeps current and the socket answers from.
+pub type Shared = Arc<Mutex<NowPlaying>>;
+
+/// A request for the main loop, answered through `reply`.
+pub struct Command {
+    pub request: String,
+    reply: Sender<Result<String, String>>,
+}
+
+impl Command {
+    /// A request from inside the player, with where its answer arrives.
+    pub fn new(request: &str) -> (Command, Receiver<Result<String, String>>) {
+        let (reply, answer) = mpsc::channel();
+        (Command { request: request.to_string(), reply }, answer)
+    }
+
+    pub fn reply(self, result: Result<String, String>) {
+        let _ = self.reply.send(result);
+    }
+}
+
+type Forward = Arc<dyn Fn(Command) + Send + Sync>;
+
+/// The listening socket, removed again when dropped.
+pub struct Server {
+    path: PathBuf,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn answer(request: &str, now_playing: &Shared, forward: &Forward) -> String {
+    log::debug!(target: "ipc", "request: {}", request);
+    let now_playing = now_playing.lock().unwrap().clone();
+    match request.split_once(' ').unwrap_or((request, "")) {
+        ("status", _) => now_playing.to_json(),
+        ("text", _) => now_playing.text(),
+        ("format", template) => now_playing.format(template),
+        _ => {
+            let (command, answer) = Command::new(request);
+            forward(command);
+            answer
+                .recv_timeout(TIMEOUT)
+                .map(|result| result.unwrap_or_else(|e| format!("error: {}", e)))
+                .unwrap_or_else(|_| {
+                    log::warn!(target: "ipc", "no answer to '{}'", request);
+                    "error: the player did not answer".to_string()
+                })
+        }
+    }
+}
+
+fn handle(stream: impl Read + Write, now_playing: &Shared, forward: &Forward) {
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    if reader.read_line(&mut request).is_err() {
+        return;
+    }
+    let reply = answer(request.trim_end_matches(['\r', '\n']), now_playing, forward);
+    let _ = writeln!(reader.get_mut(), "{}", reply);
+}
+
+#[cfg(unix)]
+fn socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        // Players of different profiles run side by side
+        Some(dir) => match crate::config::profile() {
+            Some(profile) => PathBuf::from(dir).join(format!("clap-{}.sock", profile)),
+            None => PathBuf::from(dir).join("clap.sock"),
+        },
+        None => config_dir().join("clap.sock"),
+    }
+}
+
+/// Starts answering requests on a background thread. Fails when another
+/// player already owns the socket.
+#[cfg(unix)]
+pub fn serve(now_playing: Shared, forward: impl Fn(Command) + Send + Sync + 'static) -> Result<Server, Box<dyn Error>> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let path = socket_path();
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err("another player is running".into());
+        }
+        // Left behind by a player that didn't exit cleanly
+        std::fs::remove_file(&path)?;
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    log::info!(target: "ipc", "listening on {}", path.display());
+    let forward: Forward = Arc::new(forward);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = stream.set_read_timeout(Some(TIMEOUT));
+            handle(stream, &now_playing, &forward);
+        }
+    });
+    Ok(Server { path })
+}
+
+/// Sends one request to the running player and returns its reply.
+#[cfg(unix)]
+pub fn send(request: &str) -> Result<String, Box<dyn Error>> {
+    let stream = std::os::unix::net::UnixStream::connect(socket_path()).map_err(|_| "the player is not running")?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    exchange(stream, request)
+}
+
+// Windows has no Unix sockets in std, so the player listens on a local TCP
+// port written to a file next to the config
+#[cfg(not(unix))]
+fn port_path() -> PathBuf {
+    config_dir().join("clap.port")
+}
+
+#[cfg(not(unix))]
+pub fn serve(now_playing: Shared, forward: impl Fn(Command) + Send + Sync + 'static) -> Result<Server, Box<dyn Error>> {
+    use std::net::TcpListener;
+
+    let path = port_path();
+    if send("text").is_ok() {
+        return Err("another player is running".into());
+    }
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    std::fs::create_dir_all(config_dir())?;
+    std::fs::write(&path, listener.local_addr()?.port().to_string())?;
+    log::info!(target: "ipc", "listening on {}", path.display());
+    let forward: Forward = Arc::new(forward);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = stream.set_read_timeout(Some(TIMEOUT));
+            handle(stream, &now_playing, &forward);
+        }
+    });
+    Ok(Server { path })
+}
+
+#[cfg(not(unix))]
+pub fn send(request: &str) -> Result<String, Box<dyn Error>> {
+    let port: u16 = std::fs::read_to_string(port_path())
+        .ok()
+        .and_then(|port| port.trim().parse().ok())
+        .ok_or("the player is not running")?;
+    let stream = std::net::TcpStream::connect(("127.0.0.1", port)).map_err(|_| "the player is not running")?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    exchange(stream, request)
+}
+
+fn exchange(mut stream: impl Read + Write, request: &str) -> Result<String, Box<dyn Error>> {
+    writeln!(stream, "{}", request)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    let reply = reply.trim_end_matches(['\r', '\n']).to_string();
+    match reply.strip_prefix("error: ") {
+        Some(error) => Err(error.to_string().into()),
+        None => Ok(reply),
+    }
+}
@@ -0,0 +1,624 @@
+// Key bindings, chosen from a preset in the config
+//
+// Bindings are sequences of keys so presets can use vim's `gg` or emacs'
+// `C-x C-c`. A number typed before a binding repeats it. Keys can also be
+// bound to external commands with `bind KEYS = COMMAND` in the config, and to
+// plugin functions by the plugins.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Quit,
+    Up,
+    Down,
+    Top,
+    Bottom,
+    PageUp,
+    PageDown,
+    HalfPageUp,
+    HalfPageDown,
+    Play,
+    TogglePause,
+    Stop,
+    Next,
+    Previous,
+    // Jump back and forward by the configured steps
+    Replay,
+    FastForward,
+    // Move between the chapters of audiobooks and long mixes
+    PreviousChapter,
+    NextChapter,
+    Chapters,
+    // Passes over the track coming up next
+    VetoNext,
+    // Keeps the selected track, or its folder, out of shuffle and endless play
+    NeverPlay,
+    NeverPlayFolder,
+    // Turns skipping short and non-music files in shuffle off for the session, or back on
+    AutoSkip,
+    Shuffle,
+    Repeat,
+    VolumeUp,
+    VolumeDown,
+    // Gain remembered for the current track, on top of ReplayGain
+    TrackGainUp,
+    TrackGainDown,
+    BalanceLeft,
+    BalanceRight,
+    PitchDown,
+    PitchUp,
+    SpeedDown,
+    SpeedUp,
+    SwapChannels,
+    Mono,
+    SilenceSkipping,
+    AutoMix,
+    AutoFill,
+    Record,
+    Cast,
+    AudioTrack,
+    StreamInfo,
+    // Shrinks the interface to a four-line mini-player, or brings it back
+    MiniPlayer,
+    // Gives the keys to the other pane of the split layout
+    SwitchPane,
+    // Move the divider between the panes
+    SplitLeft,
+    SplitRight,
+    Outputs,
+    // Lists Bluetooth audio devices to connect or disconnect
+    Bluetooth,
+    // Lists the tracks played lately, to play one of them again next
+    History,
+    Settings,
+    // Lists the profiles to start over in one, or to make a new one
+    Profiles,
+    // Asks for a note on the selected track
+    Note,
+    // Lists the labels to put on the selected track or take off it
+    Labels,
+    // Shows the library's albums as a grid of covers in place of the playlist
+    Albums,
+    // Lists the copies of remote tracks kept for offline play
+    OfflineCache,
+    // Downloads the selected remote track to keep whatever the cache limit
+    KeepOffline,
+    Playlists,
+    // Starts or ends focus mode's work and break timer
+    Focus,
+    Open,
+    // Copy the playing track, or the selected one, to the system clipboard
+    CopyPath,
+    CopyTitle,
+    CopyArtistTitle,
+    ScanLoudness,
+    // Copies sync_playlists to sync_dir in the background
+    Sync,
+    // Lists the jobs running in the background, to pause or cancel them
+    Tasks,
+    Transcode,
+    Search,
+    SearchBackward,
+    SearchNext,
+    SearchPrevious,
+    GotoTrack,
+    GotoTime,
+    AbLoop,
+    Bookmark,
+    NextBookmark,
+    Log,
+    // Lists the actions to pick one by name
+    Palette,
+    // Records the actions that follow until pressed again, and replays them
+    RecordMacro,
+    PlayMacro,
+    // Locks the player for guests, or asks for the passphrase to unlock it
+    Party,
+    // Caps the volume and compresses loud passages
+    Night,
+    // Goes through the compressor presets
+    Compressor,
+    Crossfeed,
+    // Stops the player in the background, like Ctrl-Z in other programs
+    Suspend,
+    // Runs the command bound with `bind`, by its position in the config
+    RunCommand(usize),
+    // Runs a macro from the config, by its position there
+    RunMacro(usize),
+    // Calls the plugin function bound to the keys, by the order plugins bound them
+    RunPlugin(usize),
+}
+
+/// Actions listed in the command palette, with their names in macros and
+/// what they do.
+pub const PALETTE: &[(Action, &str, &str)] = &[
+    (Action::Play, "play", "Play the selected track"),
+    (Action::TogglePause, "toggle_pause", "Pause or resume"),
+    (Action::Stop, "stop", "Stop"),
+    (Action::Next, "next", "Next track"),
+    (Action::Previous, "previous", "Previous track"),
+    (Action::Replay, "replay", "Jump back"),
+    (Action::FastForward, "fast_forward", "Jump forward"),
+    (Action::PreviousChapter, "previous_chapter", "Previous chapter"),
+    (Action::NextChapter, "next_chapter", "Next chapter"),
+    (Action::Chapters, "chapters", "List chapters"),
+    (Action::VetoNext, "veto_next", "Skip the track up next"),
+    (Action::NeverPlay, "never_play", "Keep the selected track out of shuffle, or let it back"),
+    (Action::NeverPlayFolder, "never_play_folder", "Keep the selected track's folder out of shuffle, or let it back"),
+    (Action::Shuffle, "shuffle", "Change shuffle mode"),
+    (Action::AutoSkip, "auto_skip", "Skip short and non-music files in shuffle, or stop for now"),
+    (Action::Repeat, "repeat", "Change repeat mode"),
+    (Action::VolumeUp, "volume_up", "Volume up"),
+    (Action::VolumeDown, "volume_down", "Volume down"),
+    (Action::TrackGainUp, "track_gain_up", "Make this track louder, remembered"),
+    (Action::TrackGainDown, "track_gain_down", "Make this track quieter, remembered"),
+    (Action::BalanceLeft, "balance_left", "Balance to the left"),
+    (Action::BalanceRight, "balance_right", "Balance to the right"),
+    (Action::PitchDown, "pitch_down", "Pitch down a semitone"),
+    (Action::PitchUp, "pitch_up", "Pitch up a semitone"),
+    (Action::SpeedDown, "speed_down", "Slower"),
+    (Action::SpeedUp, "speed_up", "Faster"),
+    (Action::SwapChannels, "swap_channels", "Swap left and right"),
+    (Action::Mono, "mono", "Toggle mono"),
+    (Action::SilenceSkipping, "silence_skipping", "Toggle skipping silence"),
+    (Action::AutoMix, "auto_mix", "Toggle auto-mix"),
+    (Action::AutoFill, "auto_fill", "Toggle endless play"),
+    (Action::Record, "record", "Start or stop recording"),
+    (Action::Cast, "cast", "Cast to a renderer or stop casting"),
+    (Action::AudioTrack, "audio_track", "Next audio track of the file"),
+    (Action::StreamInfo, "stream_info", "Show or hide stream information"),
+    (Action::MiniPlayer, "mini_player", "Switch between the mini-player and the full view"),
+    (Action::SwitchPane, "switch_pane", "Switch between the playlist and up-next panes"),
+    (Action::SplitLeft, "split_left", "Move the divider between the panes left"),
+    (Action::SplitRight, "split_right", "Move the divider between the panes right"),
+    (Action::Outputs, "outputs", "Choose output zones"),
+    (Action::Bluetooth, "bluetooth", "Connect a Bluetooth speaker or headphones"),
+    (Action::History, "history", "Recently played tracks"),
+    (Action::Settings, "settings", "Settings"),
+    (Action::Profiles, "profiles", "Switch profile"),
+    (Action::Note, "note", "Write a note on the selected track"),
+    (Action::Labels, "labels", "Label the selected track"),
+    (Action::Albums, "albums", "Show or hide the albums"),
+    (Action::OfflineCache, "offline_cache", "Offline copies of remote tracks"),
+    (Action::KeepOffline, "keep_offline", "Keep the selected remote track offline, or stop keeping it"),
+    (Action::Playlists, "playlists", "Switch playlist"),
+    (Action::Focus, "focus", "Start or stop focus mode"),
+    (Action::Open, "open", "Open a file, folder, playlist or URL"),
+    (Action::CopyPath, "copy_path", "Copy the current track's path"),
+    (Action::CopyTitle, "copy_title", "Copy the current track's title"),
+    (Action::CopyArtistTitle, "copy_artist_title", "Copy the current track's artist and title"),
+    (Action::ScanLoudness, "scan_loudness", "Scan ReplayGain loudness"),
+    (Action::Sync, "sync", "Sync playlists to the device"),
+    (Action::Tasks, "tasks", "Pause or cancel background jobs"),
+    (Action::Transcode, "transcode", "Transcode the selected track or a playlist"),
+    (Action::Search, "search", "Search the library"),
+    (Action::SearchBackward, "search_backward", "Search the playlist backward"),
+    (Action::SearchNext, "search_next", "Next match"),
+    (Action::SearchPrevious, "search_previous", "Previous match"),
+    (Action::GotoTrack, "goto_track", "Go to track number"),
+    (Action::GotoTime, "goto_time", "Go to time"),
+    (Action::Up, "up", "Select the previous track"),
+    (Action::Down, "down", "Select the next track"),
+    (Action::Top, "top", "Select the first track"),
+    (Action::Bottom, "bottom", "Select the last track"),
+    (Action::PageUp, "page_up", "Page up"),
+    (Action::PageDown, "page_down", "Page down"),
+    (Action::HalfPageUp, "half_page_up", "Half a page up"),
+    (Action::HalfPageDown, "half_page_down", "Half a page down"),
+    (Action::AbLoop, "ab_loop", "Set or clear the A-B loop"),
+    (Action::Bookmark, "bookmark", "Toggle a bookmark here"),
+    (Action::NextBookmark, "next_bookmark", "Jump to the next bookmark"),
+    (Action::Log, "log", "Show or hide the log"),
+    (Action::Party, "party", "Lock or unlock party mode"),
+    (Action::Night, "night", "Turn night mode on or off"),
+    (Action::Compressor, "compressor", "Change compressor preset"),
+    (Action::Crossfeed, "crossfeed", "Turn headphone crossfeed on or off"),
+    (Action::RecordMacro, "record_macro", "Start or stop recording a macro"),
+    (Action::PlayMacro, "play_macro", "Replay the last macro"),
+    (Action::Suspend, "suspend", "Suspend to the shell"),
+    (Action::Quit, "quit", "Quit"),
+];
+
+/// The action a macro step names.
+pub fn action_named(name: &str) -> Option<Action> {
+    PALETTE.iter().find(|(_, named, _)| *named == name).map(|&(action, _, _)| action)
+}
+
+/// An action's name in macros.
+pub fn action_name(action: Action) -> Option<&'static str> {
+    PALETTE.iter().find(|(listed, _, _)| *listed == action).map(|&(_, name, _)| name)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Preset {
+    Default,
+    Vim,
+    Emacs,
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "default" => Ok(Preset::Default),
+            "vim" => Ok(Preset::Vim),
+            "emacs" => Ok(Preset::Emacs),
+            _ => Err(format!("unknown keymap '{}'", s)),
+        }
+    }
+}
+
+type Key = (KeyCode, KeyModifiers);
+
+fn key(c: char) -> Key {
+    (KeyCode::Char(c), KeyModifiers::NONE)
+}
+
+fn ctrl(c: char) -> Key {
+    (KeyCode::Char(c), KeyModifiers::CONTROL)
+}
+
+fn alt(c: char) -> Key {
+    (KeyCode::Char(c), KeyModifiers::ALT)
+}
+
+fn plain(code: KeyCode) -> Key {
+    (code, KeyModifiers::NONE)
+}
+
+// Bindings shared by all presets
+fn default_bindings() -> Vec<(Vec<Key>, Action)> {
+    vec![
+        (vec![key('q')], Action::Quit),
+        (vec![plain(KeyCode::Up)], Action::Up),
+        (vec![plain(KeyCode::Down)], Action::Down),
+        (vec![plain(KeyCode::Home)], Action::Top),
+        (vec![plain(KeyCode::End)], Action::Bottom),
+        (vec![plain(KeyCode::PageUp)], Action::PageUp),
+        (vec![plain(KeyCode::PageDown)], Action::PageDown),
+        (vec![ctrl('u')], Action::HalfPageUp),
+        (vec![ctrl('d')], Action::HalfPageDown),
+        (vec![plain(KeyCode::Enter)], Action::Play),
+        (vec![key(' ')], Action::TogglePause),
+        (vec![key('s')], Action::Stop),
+        (vec![plain(KeyCode::Right)], Action::Next),
+        (vec![plain(KeyCode::Left)], Action::Previous),
+        (vec![key('(')], Action::PreviousChapter),
+        (vec![key(')')], Action::NextChapter),
+        (vec![key('c')], Action::Chapters),
+        (vec![key('v')], Action::VetoNext),
+        (vec![key('V')], Action::NeverPlay),
+        (vec![key(',')], Action::Replay),
+        (vec![key('.')], Action::FastForward),
+        (vec![key('z')], Action::Shuffle),
+        (vec![key('K')], Action::AutoSkip),
+        (vec![key('r')], Action::Repeat),
+        (vec![key('+')], Action::VolumeUp),
+        (vec![key('=')], Action::VolumeUp),
+        (vec![key('-')], Action::VolumeDown),
+        (vec![alt('+')], Action::TrackGainUp),
+        (vec![alt('=')], Action::TrackGainUp),
+        (vec![alt('-')], Action::TrackGainDown),
+        (vec![key('[')], Action::BalanceLeft),
+        (vec![key(']')], Action::BalanceRight),
+        (vec![key('{')], Action::PitchDown),
+        (vec![key('}')], Action::PitchUp),
+        (vec![key('<')], Action::SpeedDown),
+        (vec![key('>')], Action::SpeedUp),
+        (vec![key('x')], Action::SwapChannels),
+        (vec![key('m')], Action::Mono),
+        (vec![key('Z')], Action::SilenceSkipping),
+        (vec![key('a')], Action::AutoMix),
+        (vec![key('E')], Action::AutoFill),
+        (vec![key('R')], Action::Record),
+        (vec![key('C')], Action::Cast),
+        (vec![key('A')], Action::AudioTrack),
+        (vec![key('i')], Action::StreamInfo),
+        (vec![key('_')], Action::MiniPlayer),
+        (vec![plain(KeyCode::Tab)], Action::SwitchPane),
+        (vec![(KeyCode::Left, KeyModifiers::ALT)], Action::SplitLeft),
+        (vec![(KeyCode::Right, KeyModifiers::ALT)], Action::SplitRight),
+        (vec![key('O')], Action::Outputs),
+        (vec![key('B')], Action::Bluetooth),
+        (vec![key('W')], Action::History),
+        (vec![key('S')], Action::Settings),
+        (vec![key('U')], Action::Profiles),
+        (vec![key('Y')], Action::OfflineCache),
+        (vec![key('J')], Action::Tasks),
+        (vec![key('e')], Action::Note),
+        (vec![key('#')], Action::Labels),
+        (vec![key('I')], Action::Albums),
+        (vec![key('p')], Action::Playlists),
+        (vec![key('F')], Action::Focus),
+        (vec![key('o')], Action::Open),
+        (vec![key('y'), key('p')], Action::CopyPath),
+        (vec![key('y'), key('t')], Action::CopyTitle),
+        (vec![key('y'), key('y')], Action::CopyArtistTitle),
+        (vec![key('L')], Action::ScanLoudness),
+        (vec![key('T')], Action::Transcode),
+        (vec![key('/')], Action::Search),
+        (vec![key('n')], Action::SearchNext),
+        (vec![key('N')], Action::SearchPrevious),
+        (vec![key('g')], Action::GotoTrack),
+        (vec![key('G')], Action::GotoTime),
+        (vec![key('b')], Action::AbLoop),
+        (vec![key('M')], Action::Bookmark),
+        (vec![key('\'')], Action::NextBookmark),
+        (vec![plain(KeyCode::F(12))], Action::Log),
+        (vec![ctrl('p')], Action::Palette),
+        (vec![key('Q')], Action::RecordMacro),
+        (vec![key('@')], Action::PlayMacro),
+        (vec![key('P')], Action::Party),
+        (vec![key('H')], Action::Night),
+        (vec![key('D')], Action::Compressor),
+        (vec![key('X')], Action::Crossfeed),
+        (vec![ctrl('z')], Action::Suspend),
+    ]
+}
+
+fn vim_bindings() -> Vec<(Vec<Key>, Action)> {
+    vec![
+        (vec![key('j')], Action::Down),
+        (vec![key('k')], Action::Up),
+        (vec![key('h')], Action::Previous),
+        (vec![key('l')], Action::Next),
+        (vec![key('g'), key('g')], Action::Top),
+        (vec![key('G')], Action::Bottom),
+        (vec![ctrl('b')], Action::PageUp),
+        (vec![ctrl('f')], Action::PageDown),
+        (vec![key('?')], Action::SearchBackward),
+        (vec![key(':')], Action::GotoTrack),
+        (vec![key('g'), key('t')], Action::GotoTime),
+    ]
+}
+
+fn emacs_bindings() -> Vec<(Vec<Key>, Action)> {
+    vec![
+        (vec![ctrl('n')], Action::Down),
+        (vec![ctrl('p')], Action::Up),
+        (vec![ctrl('f')], Action::Next),
+        (vec![ctrl('b')], Action::Previous),
+        (vec![alt('<')], Action::Top),
+        (vec![alt('>')], Action::Bottom),
+        (vec![alt('v')], Action::PageUp),
+        (vec![ctrl('v')], Action::PageDown),
+        (vec![ctrl('s')], Action::Search),
+        (vec![ctrl('r')], Action::SearchBackward),
+        (vec![ctrl('x'), ctrl('c')], Action::Quit),
+        (vec![alt('g'), key('g')], Action::GotoTrack),
+        (vec![alt('g'), alt('g')], Action::GotoTrack),
+        (vec![alt('g'), key('t')], Action::GotoTime),
+        (vec![alt('x')], Action::Palette),
+    ]
+}
+
+/// Parses keys written as in the help, e.g. `C-x l`, `M-g`, `F5` or `Enter`.
+pub fn parse_keys(text: &str) -> Option<Vec<Key>> {
+    let keys: Option<Vec<Key>> = text.split_whitespace().map(parse_key).collect();
+    keys.filter(|keys| !keys.is_empty())
+}
+
+fn parse_key(mut text: &str) -> Option<Key> {
+    let mut modifiers = KeyModifiers::NONE;
+    loop {
+        if let Some(rest) = text.strip_prefix("C-").filter(|rest| !rest.is_empty()) {
+            modifiers |= KeyModifiers::CONTROL;
+            text = rest;
+        } else if let Some(rest) = text.strip_prefix("M-").filter(|rest| !rest.is_empty()) {
+            modifiers |= KeyModifiers::ALT;
+            text = rest;
+        } else {
+            break;
+        }
+    }
+    let mut chars = text.chars();
+    let code = match (chars.next()?, chars.next()) {
+        (c, None) => KeyCode::Char(c),
+        _ => match text.to_lowercase().as_str() {
+            "enter" | "ret" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" | "ins" => KeyCode::Insert,
+            "space" | "spc" => KeyCode::Char(' '),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            name => KeyCode::F(name.strip_prefix('f')?.parse().ok().filter(|n| (1..=24).contains(n))?),
+        },
+    };
+    Some((code, modifiers))
+}
+
+/// Writes keys as `parse_keys` reads them.
+pub fn format_keys(keys: &[Key]) -> String {
+    let names: Vec<String> = keys
+        .iter()
+        .map(|(code, modifiers)| {
+            let mut name = String::new();
+            if modifiers.contains(KeyModifiers::CONTROL) {
+                name.push_str("C-");
+            }
+            if modifiers.contains(KeyModifiers::ALT) {
+                name.push_str("M-");
+            }
+            match code {
+                KeyCode::Char(' ') => name.push_str("Space"),
+                KeyCode::Char(c) => name.push(*c),
+                KeyCode::F(n) => name.push_str(&format!("F{}", n)),
+                KeyCode::PageUp => name.push_str("PageUp"),
+                KeyCode::PageDown => name.push_str("PageDown"),
+                KeyCode::Backspace => name.push_str("Backspace"),
+                other => name.push_str(&format!("{:?}", other)),
+            }
+            name
+        })
+        .collect();
+    names.join(" ")
+}
+
+/// Turns key presses into actions, collecting count prefixes and multi-key
+/// sequences along the way.
+pub struct Keymap {
+    preset: Preset,
+    bindings: Vec<(Vec<Key>, Action)>,
+    pending: Vec<Key>,
+    count: Option<usize>,
+    // Emacs' C-u was pressed and digits now form the count
+    universal_argument: bool,
+}
+
+impl Keymap {
+    /// `commands` are the keys of the `bind` entries in the config, in order.
+    /// Ones that don't parse are left unbound.
+    pub fn new(preset: Preset, commands: &[String]) -> Keymap {
+        let extra = match preset {
+            Preset::Default => Vec::new(),
+            Preset::Vim => vim_bindings(),
+            Preset::Emacs => emacs_bindings(),
+        };
+        // Preset bindings replace default ones that would clash, like vim's gg
+        // with g, and the user's commands replace both
+        let mut bindings = overlay(default_bindings(), extra);
+        let commands = commands
+            .iter()
+            .enumerate()
+            .filter_map(|(i, keys)| Some((parse_keys(keys)?, Action::RunCommand(i))))
+            .collect();
+        bindings = overlay(bindings, commands);
+        Keymap {
+            preset,
+            bindings,
+            pending: Vec::new(),
+            count: None,
+            universal_argument: false,
+        }
+    }
+
+    /// Binds keys on top of the preset's, as plugins do. Keys the config
+    /// binds with `bind` stay the config's, and ones that don't parse are
+    /// left unbound.
+    pub fn bind(&mut self, keys: &str, action: Action) {
+        let Some(keys) = parse_keys(keys) else {
+            return;
+        };
+        let taken = self.bindings.iter().any(|(other, bound)| {
+            matches!(bound, Action::RunCommand(_)) && (other.starts_with(&keys) || keys.starts_with(other))
+        });
+        if !taken {
+            self.bindings = overlay(std::mem::take(&mut self.bindings), vec![(keys, action)]);
+        }
+    }
+
+    /// The keys bound to an action, the shortest first.
+    pub fn keys_for(&self, action: Action) -> Vec<String> {
+        let mut keys: Vec<&Vec<Key>> = self.bindings.iter().filter(|(_, bound)| *bound == action).map(|(keys, _)| keys).collect();
+        keys.sort_by_key(|keys| keys.len());
+        keys.into_iter().map(|keys| format_keys(keys)).collect()
+    }
+
+    /// Whether a binding starts with the key.
+    pub fn binds(&self, key: Key) -> bool {
+        self.bindings.iter().any(|(keys, _)| keys.first() == Some(&key))
+    }
+
+    /// Keys typed so far towards a binding, shown in the status bar.
+    pub fn pending(&self) -> Option<String> {
+        let mut text = self.count.map(|count| count.to_string()).unwrap_or_default();
+        if self.universal_argument && self.count.is_none() {
+            text.push_str("C-u");
+        }
+        for (code, modifiers) in &self.pending {
+            if modifiers.contains(KeyModifiers::CONTROL) {
+                text.push_str("C-");
+            }
+            if modifiers.contains(KeyModifiers::ALT) {
+                text.push_str("M-");
+            }
+            if let KeyCode::Char(c) = code {
+                text.push(*c);
+            }
+        }
+        (!text.is_empty()).then_some(text)
+    }
+
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.count = None;
+        self.universal_argument = false;
+    }
+
+    /// Feeds one key press, returning the bound action and the count typed
+    /// before it once a binding is complete.
+    pub fn press(&mut self, event: KeyEvent) -> Option<(Action, Option<usize>)> {
+        // Shift is already part of the character
+        let modifiers = event.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT);
+        let pressed = (event.code, modifiers);
+        if pressed == plain(KeyCode::Esc) || pressed == ctrl('g') {
+            self.reset();
+            return None;
+        }
+        if self.preset == Preset::Emacs && pressed == ctrl('u') {
+            self.universal_argument = true;
+            return None;
+        }
+        if let (KeyCode::Char(digit @ '0'..='9'), true) = (event.code, modifiers.is_empty()) {
+            if self.pending.is_empty() && self.accepts_count(digit) {
+                let digit = digit.to_digit(10).unwrap() as usize;
+                self.count = Some(self.count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                return None;
+            }
+        }
+
+        self.pending.push(pressed);
+        let mut matches = self
+            .bindings
+            .iter()
+            .filter(|(keys, _)| keys.starts_with(&self.pending));
+        let Some((keys, action)) = matches.next() else {
+            // An unknown sequence is dropped, the last key may still start a new one
+            let retry = self.pending.len() > 1;
+            self.pending.clear();
+            return if retry { self.press(event) } else { self.finish(None) };
+        };
+        if keys.len() > self.pending.len() {
+            return None;
+        }
+        let action = *action;
+        self.finish(Some(action))
+    }
+
+    fn accepts_count(&self, digit: char) -> bool {
+        match self.preset {
+            // A leading 0 isn't a count in vim
+            Preset::Vim => self.count.is_some() || digit != '0',
+            Preset::Emacs => self.universal_argument,
+            Preset::Default => false,
+        }
+    }
+
+    fn finish(&mut self, action: Option<Action>) -> Option<(Action, Option<usize>)> {
+        // C-u alone counts four, as in emacs
+        let count = self.count.or(self.universal_argument.then_some(4));
+        self.reset();
+        action.map(|action| (action, count))
+    }
+}
+
+fn overlay(mut bindings: Vec<(Vec<Key>, Action)>, extra: Vec<(Vec<Key>, Action)>) -> Vec<(Vec<Key>, Action)> {
+    bindings.retain(|(keys, _)| {
+        !extra
+            .iter()
+            .any(|(other, _)| other.starts_with(keys) || keys.starts_with(other))
+    });
+    bindings.extend(extra);
+    bindings
+}
@@ -0,0 +1,322 @@
+use crate::config::config_dir;
+use crate::utils::{text, write_atomically};
+use regex::{Regex, RegexBuilder};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Bumped when the analysis worker learns to measure something new, so tracks
+// analyzed before are analyzed again: 1 found the silence and BPM, 2 the loudness
+pub const ANALYSIS_VERSION: u32 = 2;
+
+// Per-track data gathered while using the player, keyed by file path
+#[derive(Clone, Default)]
+pub struct TrackRecord {
+    // Start and end of the audible part of the track
+    pub silence: Option<(Duration, Duration)>,
+    pub bpm: Option<f32>,
+    pub loudness: Option<f32>,
+    // ANALYSIS_VERSION of the analysis that filled the fields above, 0 if none
+    pub analysis: u32,
+    // ReplayGain adjustments in dB and the sample peak, from the loudness scan
+    pub track_gain: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub peak: Option<f32>,
+    // Set by hand for a track mastered too quiet or loud, in dB on top of ReplayGain
+    pub gain_offset: Option<f32>,
+    // From the tags, or the page title of tracks fetched from the web, whose
+    // file names are only IDs
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    // Free text about the track, such as "drop at 1:12"
+    pub note: Option<String>,
+    // Names of labels from the config, such as workout
+    pub labels: Vec<String>,
+    pub duration: Option<Duration>,
+    pub size: Option<u64>,
+    // Modification time of the file when its tags were read, in seconds
+    pub modified: Option<u64>,
+    pub bookmarks: Vec<Duration>,
+    // Where a long track was left, to pick up from there
+    pub position: Option<Duration>,
+    // When the position was last set or cleared, in seconds since 1970, so
+    // the newest one wins when positions are shared between machines
+    pub position_saved: Option<u64>,
+    // Stars from 1 to 5
+    pub rating: Option<u8>,
+    // Times the track was started from the beginning, the last time in
+    // seconds since 1970
+    pub plays: u32,
+    pub last_played: Option<u64>,
+    // Left out of shuffle and endless play; on a folder's path, for all of its tracks
+    pub never_play: bool,
+}
+
+impl TrackRecord {
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "silence" => {
+                if let Some((start, end)) = value.split_once(',') {
+                    if let (Ok(start), Ok(end)) = (start.parse(), end.parse()) {
+                        self.silence = Some((Duration::from_secs_f64(start), Duration::from_secs_f64(end)));
+                    }
+                }
+            }
+            "bpm" => self.bpm = value.parse().ok(),
+            "loudness" => self.loudness = value.parse().ok(),
+            "analysis" => self.analysis = value.parse().unwrap_or_default(),
+            "track_gain" => self.track_gain = value.parse().ok(),
+            "album_gain" => self.album_gain = value.parse().ok(),
+            "peak" => self.peak = value.parse().ok(),
+            "gain_offset" => self.gain_offset = value.parse().ok(),
+            "title" => self.title = Some(value.to_string()),
+            "artist" => self.artist = Some(value.to_string()),
+            "album" => self.album = Some(value.to_string()),
+            "genre" => self.genre = Some(value.to_string()),
+            "note" => self.note = Some(value.to_string()),
+            "labels" => self.labels = value.split(',').filter(|label| !label.is_empty()).map(str::to_string).collect(),
+            "duration" => self.duration = value.parse().ok().map(Duration::from_secs_f64),
+            "size" => self.size = value.parse().ok(),
+            "modified" => self.modified = value.parse().ok(),
+            "bookmarks" => {
+                self.bookmarks = value
+                    .split(',')
+                    .filter_map(|mark| mark.parse().ok())
+                    .map(Duration::from_secs_f64)
+                    .collect()
+            }
+            "position" => self.position = value.parse().ok().map(Duration::from_secs_f64),
+            "position_saved" => self.position_saved = value.parse().ok(),
+            "rating" => self.rating = value.parse().ok().filter(|stars| (1..=5).contains(stars)),
+            "plays" => self.plays = value.parse().unwrap_or_default(),
+            "last_played" => self.last_played = value.parse().ok(),
+            "never_play" => self.never_play = value == "1",
+            _ => {}
+        }
+    }
+
+    // Tracks are analyzed once per analysis version, even when nothing could be read
+    pub fn is_analyzed(&self) -> bool {
+        self.analysis >= ANALYSIS_VERSION
+    }
+
+    fn fields(&self) -> Vec<String> {
+        let mut fields = Vec::new();
+        if let Some((start, end)) = self.silence {
+            fields.push(format!("silence={:.3},{:.3}", start.as_secs_f64(), end.as_secs_f64()));
+        }
+        if let Some(bpm) = self.bpm {
+            fields.push(format!("bpm={:.1}", bpm));
+        }
+        if let Some(loudness) = self.loudness {
+            fields.push(format!("loudness={:.1}", loudness));
+        }
+        if self.analysis > 0 {
+            fields.push(format!("analysis={}", self.analysis));
+        }
+        if let Some(gain) = self.track_gain {
+            fields.push(format!("track_gain={:.2}", gain));
+        }
+        if let Some(gain) = self.album_gain {
+            fields.push(format!("album_gain={:.2}", gain));
+        }
+        if let Some(peak) = self.peak {
+            fields.push(format!("peak={:.4}", peak));
+        }
+        if let Some(offset) = self.gain_offset {
+            fields.push(format!("gain_offset={:.1}", offset));
+        }
+        let texts = [
+            ("title", &self.title),
+            ("artist", &self.artist),
+            ("album", &self.album),
+            ("genre", &self.genre),
+            ("note", &self.note),
+        ];
+        for (key, text) in texts {
+            if let Some(text) = text {
+                // Tabs and line breaks would split the record
+                fields.push(format!("{}={}", key, text.replace(['\t', '\n', '\r'], " ")));
+            }
+        }
+        if !self.labels.is_empty() {
+            fields.push(format!("labels={}", self.labels.join(",")));
+        }
+        if let Some(duration) = self.duration {
+            fields.push(format!("duration={:.3}", duration.as_secs_f64()));
+        }
+        if let Some(size) = self.size {
+            fields.push(format!("size={}", size));
+        }
+        if let Some(modified) = self.modified {
+            fields.push(format!("modified={}", modified));
+        }
+        if !self.bookmarks.is_empty() {
+            let marks: Vec<String> = self.bookmarks.iter().map(|mark| format!("{:.3}", mark.as_secs_f64())).collect();
+            fields.push(format!("bookmarks={}", marks.join(",")));
+        }
+        if let Some(position) = self.position {
+            fields.push(format!("position={:.3}", position.as_secs_f64()));
+        }
+        if let Some(saved) = self.position_saved {
+            fields.push(format!("position_saved={}", saved));
+        }
+        if let Some(rating) = self.rating {
+            fields.push(format!("rating={}", rating));
+        }
+        if self.plays > 0 {
+            fields.push(format!("plays={}", self.plays));
+        }
+        if let Some(last_played) = self.last_played {
+            fields.push(format!("last_played={}", last_played));
+        }
+        if self.never_play {
+            fields.push("never_play=1".to_string());
+        }
+        fields
+    }
+}
+
+/// What a search looks for: words that must all appear somewhere in a
+/// track's fields, or a pattern one of them must match.
+#[derive(Clone)]
+pub enum Matcher {
+    // The words, and a pattern finding any of them for highlighting
+    Words(Vec<String>, Regex),
+    Pattern(Regex),
+}
+
+impl Matcher {
+    /// Reads the query as a regular expression when asked to, giving the
+    /// reason when it isn't one.
+    pub fn new(query: &str, regex: bool) -> Result<Matcher, String> {
+        // Patterns keep their case, `\D` isn't `\d`
+        let query = text::fold(query).0;
+        if !regex {
+            let words: Vec<String> = query.to_lowercase().split_whitespace().map(str::to_string).collect();
+            let any = words.iter().map(|word| regex::escape(word)).collect::<Vec<_>>().join("|");
+            let any = RegexBuilder::new(&any).case_insensitive(true).build().map_err(|e| e.to_string())?;
+            return Ok(Matcher::Words(words, any));
+        }
+        RegexBuilder::new(&query).case_insensitive(true).build().map(Matcher::Pattern).map_err(|e| {
+            // The last line says what is wrong, those before point at where
+            let message = e.to_string();
+            let reason = message.lines().last().unwrap_or_default();
+            reason.strip_prefix("error: ").unwrap_or(reason).to_string()
+        })
+    }
+
+    /// Whether a track's title, artist, album, genre, note or file name match,
+    /// ignoring case, accents and the Arabic or Persian form of letters.
+    /// Labels match written with `#`, as in `#workout`.
+    pub fn matches(&self, track: &Path, record: Option<&TrackRecord>) -> bool {
+        let name = track.file_name().unwrap_or_default().to_string_lossy();
+        let mut fields = vec![text::fold(&name).0];
+        if let Some(record) = record {
+            fields.extend(
+                [&record.title, &record.artist, &record.album, &record.genre, &record.note]
+                    .into_iter()
+                    .flatten()
+                    .map(|field| text::fold(field).0),
+            );
+            fields.extend(record.labels.iter().map(|label| format!("#{}", text::fold(label).0)));
+        }
+        match self {
+            Matcher::Words(words, _) => {
+                let text = fields.join("\n").to_lowercase();
+                words.iter().all(|word| text.contains(word.as_str()))
+            }
+            Matcher::Pattern(pattern) => fields.iter().any(|field| pattern.is_match(field)),
+        }
+    }
+
+    /// The byte ranges of a text the search matches, for highlighting.
+    pub fn find(&self, text: &str) -> Vec<Range<usize>> {
+        let (Matcher::Words(_, pattern) | Matcher::Pattern(pattern)) = self;
+        let (folded, origins) = text::fold(text);
+        pattern
+            .find_iter(&folded)
+            .filter(|found| !found.is_empty())
+            .map(|found| origins[found.start()]..origins[found.end()])
+            .collect()
+    }
+}
+
+pub struct Library {
+    path: PathBuf,
+    records: HashMap<PathBuf, TrackRecord>,
+}
+
+impl Library {
+    // The database is a text file with one track per line: the path followed by
+    // tab-separated `key=value` fields
+    pub fn load() -> Library {
+        let path = config_dir().join("library.db");
+        let mut records = HashMap::new();
+        if let Ok(contents) = fs::read_to_string(&path) {
+            for line in contents.lines() {
+                let mut parts = line.split('\t');
+                let Some(track) = parts.next().filter(|track| !track.is_empty()) else {
+                    continue;
+                };
+                let mut record = TrackRecord::default();
+                for field in parts {
+                    if let Some((key, value)) = field.split_once('=') {
+                        record.set(key, value);
+                    }
+                }
+                records.insert(PathBuf::from(track), record);
+            }
+        }
+        Library { path, records }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut contents = String::new();
+        for (track, record) in &self.records {
+            let fields = record.fields();
+            if fields.is_empty() {
+                continue;
+            }
+            // Read back, a name that isn't UTF-8 would become another track's
+            // and a tab or line break would split the line
+            let Some(name) = track.to_str().filter(|name| !name.contains(['\t', '\n', '\r'])) else {
+                log::warn!(target: "scanner", "library.db can't hold the name of {}, what is known about it isn't saved", track.display());
+                continue;
+            };
+            contents.push_str(name);
+            for field in fields {
+                contents.push('\t');
+                contents.push_str(&field);
+            }
+            contents.push('\n');
+        }
+        write_atomically(&self.path, &contents)
+    }
+
+    /// Every track the player has seen, with what is known about it.
+    pub fn records(&self) -> impl Iterator<Item = (&PathBuf, &TrackRecord)> {
+        self.records.iter()
+    }
+
+    pub fn get(&self, track: &Path) -> Option<&TrackRecord> {
+        self.records.get(track)
+    }
+
+    /// Whether a track, or a folder it is in, is flagged never to play.
+    pub fn never_play(&self, track: &Path) -> bool {
+        track.ancestors().any(|path| self.records.get(path).is_some_and(|record| record.never_play))
+    }
+
+    pub fn record_mut(&mut self, track: &Path) -> &mut TrackRecord {
+        self.records.entry(track.to_path_buf()).or_default()
+    }
+}
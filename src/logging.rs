@@ -0,0 +1,195 @@
+// Log of what the scanner, decoders, playback and the IPC socket do, written
+// to clap.log next to the config and kept in memory for the log panel (F12)
+//
+// Messages use short targets, `scanner`, `decoder`, `playback` and `ipc`,
+// which `log_level` can set separately, e.g. `log_level = warn, decoder=debug`.
+
+use crate::config::config_dir;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// clap.log is moved to clap.log.1 once it grows past this, and so on
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+const KEPT_FILES: usize = 3;
+// Lines the log panel can show
+const RECENT_LINES: usize = 500;
+
+/// The level for each target, set with `log_level` in the config.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogLevels {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        LogLevels {
+            default: LevelFilter::Info,
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for LogLevels {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut levels = LogLevels::default();
+        for part in s.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let parse = |level: &str| level.trim().parse().map_err(|_| format!("unknown log level '{}'", level.trim()));
+            match part.split_once('=') {
+                Some((target, level)) => levels.targets.push((target.trim().to_string(), parse(level)?)),
+                None => levels.default = parse(part)?,
+            }
+        }
+        Ok(levels)
+    }
+}
+
+impl LogLevels {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(name, _)| name == target)
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.targets.iter().map(|(_, level)| *level).fold(self.default, Ord::max)
+    }
+}
+
+/// A logged line, as shown in the log panel.
+#[derive(Clone)]
+pub struct Line {
+    pub level: Level,
+    pub text: String,
+}
+
+struct Logger {
+    levels: LogLevels,
+    // The open log file and how many bytes it holds, None when it can't be written
+    file: Mutex<Option<(File, u64)>>,
+    recent: Mutex<VecDeque<Line>>,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.levels.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let text = format!("{} {:<5} {}: {}", timestamp(), record.level(), record.target(), record.args());
+
+        if let Some((file, size)) = self.file.lock().unwrap().as_mut() {
+            if writeln!(file, "{}", text).is_ok() {
+                *size += text.len() as u64 + 1;
+            }
+            if *size > MAX_FILE_SIZE {
+                if let Some(rotated) = rotate() {
+                    *file = rotated;
+                    *size = 0;
+                }
+            }
+        }
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_LINES {
+            recent.pop_front();
+        }
+        recent.push_back(Line {
+            level: record.level(),
+            text,
+        });
+    }
+
+    fn flush(&self) {
+        if let Some((file, _)) = self.file.lock().unwrap().as_mut() {
+            let _ = file.flush();
+        }
+    }
+}
+
+fn log_path(generation: usize) -> PathBuf {
+    match generation {
+        0 => config_dir().join("clap.log"),
+        n => config_dir().join(format!("clap.log.{}", n)),
+    }
+}
+
+fn open_log() -> Option<(File, u64)> {
+    fs::create_dir_all(config_dir()).ok()?;
+    let file = OpenOptions::new().create(true).append(true).open(log_path(0)).ok()?;
+    let size = file.metadata().map(|metadata| metadata.len()).unwrap_or_default();
+    Some((file, size))
+}
+
+// Shifts every log file one generation back, dropping the oldest
+fn rotate() -> Option<File> {
+    for generation in (1..KEPT_FILES).rev() {
+        let _ = fs::rename(log_path(generation - 1), log_path(generation));
+    }
+    File::create(log_path(0)).ok()
+}
+
+/// Starts logging. Logging stays in memory when the file can't be opened.
+pub fn init(levels: LogLevels) {
+    let max = levels.max();
+    let logger = LOGGER.get_or_init(|| Logger {
+        levels,
+        file: Mutex::new(open_log()),
+        recent: Mutex::new(VecDeque::new()),
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(max);
+    }
+}
+
+/// The latest lines logged, oldest first.
+pub fn recent() -> Vec<Line> {
+    LOGGER
+        .get()
+        .map(|logger| logger.recent.lock().unwrap().iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+// UTC, as `2024-05-01 13:45:07`
+fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default();
+    let (days, time) = (seconds / 86400, seconds % 86400);
+
+    // Civil date from days since 1970-01-01, after Howard Hinnant's algorithm
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
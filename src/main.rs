@@ -1,184 +1,3326 @@
+mod announce;
+mod archive;
+mod bluetooth;
+mod cast;
+mod chapters;
+mod cli;
+mod clipboard;
+mod config;
+mod decode;
+mod desktop;
+mod ducking;
+mod history;
+mod focus;
+mod hooks;
+mod input;
+mod ipc;
+mod keymap;
+mod library;
+mod logging;
+mod midi;
+mod night;
+mod now_playing;
 mod player;
+mod plugins;
+mod remote;
+mod replaygain;
+mod roaming;
+mod rules;
+mod session;
+mod sync;
+mod tags;
+mod tasks;
+mod toast;
+mod transcode;
 mod ui;
+mod utils;
+mod ytdlp;
 
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
+    event::{
+        DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture, KeyCode, KeyEvent,
+        KeyModifiers,
+    },
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen, SetTitle},
 };
-use std::{error::Error, io, time::Duration, path::PathBuf};
-use tui::{backend::CrosstermBackend, widgets::ListState, Terminal};
+use keymap::{Action, Keymap};
+use std::{collections::HashSet, error::Error, io::{self, Write}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}, path::{Path, PathBuf}};
+use tui::{backend::CrosstermBackend, layout::Rect, widgets::ListState, Terminal};
+use input::InputEvent;
 use std::thread;
 use std::sync::mpsc;
 
+// Ticks while the progress bar or some background work moves, and otherwise
+const BUSY_TICK_RATE: Duration = Duration::from_millis(200);
+const IDLE_TICK_RATE: Duration = Duration::from_secs(1);
+// Busy ticks in low-bandwidth mode, still often enough to start the next track on time
+const LOW_BANDWIDTH_TICK_RATE: Duration = Duration::from_millis(500);
+// Letters typed for a jump within this long of each other add up
+const JUMP_TIMEOUT: Duration = Duration::from_secs(1);
+// How long the Bluetooth picker scans for devices
+const BLUETOOTH_SCAN: Duration = Duration::from_secs(5);
+// Time to change one's mind before the machine shuts down at the end of the playlist
+const POWEROFF_DELAY: Duration = Duration::from_secs(60);
+
 struct App {
     music_player: player::MusicPlayer,
-    list_state: ListState,
+    // Settings as loaded, plus changes made in the settings popup
+    config: config::Config,
+    list_state: ui::PlaylistState,
+    transcoder: transcode::Transcoder,
+    transcode_options: transcode::TranscodeOptions,
+    // Outcome of the last finished transcode
+    transcode_status: Option<String>,
+    replaygain_scan: Option<replaygain::Scanner>,
+    // Progress of the jobs running in the background
+    tasks: tasks::Tasks,
+    // A sync to the device running in the background
+    syncing: Option<mpsc::Receiver<Result<sync::Summary, String>>>,
+    device_picker: Option<(PickerKind, ui::DevicePicker)>,
+    discovery: Option<mpsc::Receiver<Vec<cast::Renderer>>>,
+    view: ui::View,
+    fetcher: ytdlp::Fetcher,
+    // Outcome of the last failed fetch
+    fetch_status: Option<String>,
+    // Page opened from the prompt, played as soon as it is fetched
+    play_when_fetched: Option<String>,
+    prompt: Option<(PromptKind, ui::TextInput)>,
+    // A yes-or-no question over everything else
+    confirm: Option<(ConfirmKind, ui::Confirm)>,
+    keymap: Keymap,
+    // Last search and whether it ran backwards, repeated by n/N
+    search: Option<(String, bool)>,
+    // Searches take regular expressions, toggled with Tab while typing
+    search_regex: bool,
+    // Text left in the library search when it was closed with Esc
+    search_draft: String,
+    // Start of the titles typed with Alt to jump to, and when it was last typed
+    jump: Option<(String, Instant)>,
+    // Picked in the command palette, run once the palette has closed
+    picked_action: Option<Action>,
+    // Steps of the macro being recorded, and of the last one recorded or run
+    recording: Option<Vec<String>>,
+    last_macro: Option<Vec<String>>,
+    // The passphrase that unlocks party mode, while guests have the keyboard
+    party: Option<String>,
+    // Feedback from the last prompt, cleared by the next key press
+    status: Option<String>,
+    // Where the progress bar was drawn, for seeking with the mouse
+    seek_bar: Option<Rect>,
+    // Playback time last shown, in whole seconds, for reduced motion
+    shown_second: Option<u64>,
+    // Last text put in the terminal's title bar, None when titles are off
+    window_title: Option<String>,
+    // What the IPC socket reports, and what the now-playing files last said
+    now_playing: ipc::Shared,
+    published: Option<now_playing::NowPlaying>,
+    hooks: hooks::Hooks,
+    announcer: announce::Announcer,
+    focus: Option<focus::FocusTimer>,
+    night: night::NightSchedule,
+    // Other programs playing sound, which the music is turned down for
+    ducking: ducking::Watcher,
+    // When the machine shuts down, after the playlist ended with `queue_end = shutdown`
+    poweroff_at: Option<Instant>,
+    // Profile picked in the switcher, None inside for the default one; the
+    // player quits and starts over in it
+    switch_profile: Option<Option<String>>,
+    journal: session::Journal,
+    // Tracks played lately, newest last
+    history: history::History,
+    // Positions and history shared by other machines, while they are fetched
+    roaming: Option<mpsc::Receiver<Result<roaming::Shared, String>>>,
+    // Commands bound to keys, and the lines they printed on their way back
+    commands: Vec<String>,
+    command_output: (mpsc::Sender<String>, mpsc::Receiver<String>),
+    // Scripts in the plugins folder, started once the main loop can answer them
+    plugins: plugins::Plugins,
+    // Scans and connections running in the background report here
+    bluetooth: (mpsc::Sender<bluetooth::Event>, mpsc::Receiver<bluetooth::Event>),
+    // Remote tracks downloaded to keep offline
+    offline: (mpsc::Sender<remote::Downloaded>, mpsc::Receiver<remote::Downloaded>),
+    clipboard: clipboard::Clipboard,
+}
+
+// What the text prompt asks for
+enum PromptKind {
+    Open,
+    Search { backward: bool },
+    GotoTrack,
+    GotoTime,
+    // New value for a setting from config::EDITABLE
+    Setting(&'static str),
+    // Name to save the macro just recorded under
+    MacroName,
+    // Passphrase to lock party mode with, or to unlock it
+    PartyLock,
+    PartyUnlock,
+    // Name of a new profile to switch to
+    ProfileName,
+    // Note on the playlist's track at this index, emptied to remove it
+    Note(usize),
+    // A label to add to the config and put on the playlist's track at this index
+    LabelName(usize),
+}
+
+// What the yes-or-no dialog asks about
+enum ConfirmKind {
+    // The last session, offered at startup
+    Resume(session::Session),
+    // The offline copy of this remote track
+    RemoveOfflineCopy(PathBuf),
+    // Stopping the background job with this id
+    CancelTask(u64),
+}
+
+// What the transcode popup offers to convert
+enum TranscodeChoice {
+    Track(PathBuf),
+    // Every track of the playlist on screen
+    Playlist,
+    // A playlist in playlists_dir
+    Saved(PathBuf),
+}
+
+// What key presses go to, the topmost widget on screen first
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Widget {
+    Confirm,
+    Prompt,
+    Picker,
+    Albums,
+    // The up-next pane of the split layout
+    Queue,
+    Playlist,
+}
+
+// What the open device picker lists
+enum PickerKind {
+    Cast(Vec<cast::Renderer>),
+    Outputs(Vec<String>),
+    // Bluetooth audio devices, empty while searching
+    Bluetooth(Vec<bluetooth::Device>),
+    Settings,
+    // Playlists in playlists_dir, those matching the search in order
+    Playlists { all: Vec<PathBuf>, shown: Vec<PathBuf> },
+    // Chapters of the current track
+    Chapters,
+    // Tracks played lately, newest first
+    History(Vec<PathBuf>),
+    // The default profile as None, then the others; a last row makes a new one
+    Profiles(Vec<Option<String>>),
+    // Remote tracks with an offline copy, played most recently first
+    OfflineCache(Vec<PathBuf>),
+    // Labels for the playlist's track at `track`, those of the config first;
+    // a last row makes a new one
+    Labels { track: usize, names: Vec<String> },
+    // Library and playlist tracks matching the typed text
+    Library { shown: Vec<PathBuf> },
+    // Actions matching the typed text, best match first
+    Palette { shown: Vec<Action> },
+    // Jobs running in the background, by their id in `tasks`
+    Tasks(Vec<u64>),
+    // The selected track, the playlist and the saved playlists
+    Transcode(Vec<TranscodeChoice>),
+    // A plugin's panel, and its function to call with the item picked
+    Plugin { plugin: usize, function: Option<String> },
 }
 
 impl App {
-    fn new() -> App {
-        App {
-            music_player: player::MusicPlayer::new(),
-            list_state: ListState::default(),
+    fn new(config: &config::Config) -> App {
+        let mut tasks = tasks::Tasks::new();
+        let mut music_player = player::MusicPlayer::new(&mut tasks);
+        let transcoder = transcode::Transcoder::new(tasks.start("Transcoding", tasks::Measure::Count));
+        music_player.configure(config);
+        let mut app = App {
+            music_player,
+            config: config.clone(),
+            list_state: ui::PlaylistState::new(config.playlist_columns.clone()),
+            transcoder,
+            transcode_options: config.transcode_options(),
+            transcode_status: None,
+            replaygain_scan: None,
+            tasks,
+            syncing: None,
+            device_picker: None,
+            discovery: None,
+            view: ui::View {
+                show_stream_info: false,
+                show_log: false,
+                status_format: config.status_format.clone(),
+                theme: config.theme,
+                ascii: config.low_bandwidth,
+                highlight: None,
+                kiosk: config.kiosk,
+                mini: false,
+                split: ui::Split::new(config.split_percent),
+                labels: config.labels.clone(),
+                albums: None,
+            },
+            fetcher: ytdlp::Fetcher::new(),
+            fetch_status: None,
+            play_when_fetched: None,
+            prompt: None,
+            confirm: None,
+            keymap: Keymap::new(config.keymap, &[]),
+            search: None,
+            search_regex: false,
+            search_draft: String::new(),
+            jump: None,
+            picked_action: None,
+            recording: None,
+            last_macro: None,
+            party: None,
+            status: None,
+            seek_bar: None,
+            shown_second: None,
+            window_title: config.terminal_title.then(String::new),
+            now_playing: ipc::Shared::default(),
+            published: None,
+            hooks: config.hooks.clone(),
+            announcer: announce::Announcer::default(),
+            focus: None,
+            night: night::NightSchedule::new(config.night_hours),
+            ducking: ducking::Watcher::start(config.duck_for),
+            poweroff_at: None,
+            switch_profile: None,
+            journal: session::Journal::new(),
+            history: history::History::load(config.history_size),
+            roaming: roaming::Remote::new(config).map(roaming::Remote::fetch_in_background),
+            commands: config.commands.iter().map(|(_, command)| command.clone()).collect(),
+            command_output: mpsc::channel(),
+            plugins: plugins::Plugins::default(),
+            bluetooth: mpsc::channel(),
+            offline: mpsc::channel(),
+            clipboard: clipboard::Clipboard::default(),
+        };
+        app.keymap = app.build_keymap();
+        app
+    }
+
+    // Background work shown next to the playback modes in the status bar
+    fn activity(&self) -> Vec<String> {
+        // Jobs in the background come first, so they stay put as the rest changes
+        let mut activity = self.tasks.labels(!self.config.reduced_motion && !self.config.low_bandwidth);
+        if self.party.is_some() {
+            activity.push("Party mode".to_string());
+        }
+        if let Some(focus) = &self.focus {
+            activity.push(focus.label());
+        }
+        if let Some(profile) = config::profile() {
+            activity.push(format!("Profile: {}", profile));
+        }
+        if let Some(at) = self.poweroff_at {
+            let left = at.saturating_duration_since(Instant::now()).as_secs();
+            activity.push(format!("⏻ Shutting down in {} s, any key cancels", left));
+        }
+        if self.ducking.current() != ducking::Activity::Quiet {
+            activity.push(format!("Turned down for {}", self.ducking.current()));
+        }
+        if let Some(status) = self.transcode_status.as_ref().filter(|_| self.transcoder.pending() == 0) {
+            activity.push(status.clone());
+        }
+        if let Some(status) = &self.status {
+            activity.push(status.clone());
+        }
+        if let Some(keys) = self.keymap.pending() {
+            activity.push(keys);
+        }
+        if let Some((prefix, _)) = &self.jump {
+            activity.push(format!("Jump: {}", prefix));
+        }
+        if let Some(steps) = &self.recording {
+            activity.push(format!("Recording macro ({} steps)", steps.len()));
+        }
+        if self.fetcher.pending() > 0 {
+            activity.push(format!("Fetching {} URL(s)", self.fetcher.pending()));
+        } else if let Some(status) = &self.fetch_status {
+            activity.push(status.clone());
+        }
+        activity
+    }
+
+    // Whether the progress bar needs drawing again. With reduced motion it
+    // only moves when the clock reaches the next second, as in low-bandwidth mode.
+    fn progress_moved(&mut self) -> bool {
+        if !self.config.reduced_motion && !self.config.low_bandwidth {
+            return self.music_player.is_playing();
+        }
+        let second = self.music_player.position().map(|position| position.as_secs());
+        let moved = second != self.shown_second;
+        self.shown_second = second;
+        moved
+    }
+
+    // Ticks come less often when nothing moves on screen
+    fn tick_rate(&self) -> Duration {
+        let busy = self.music_player.is_playing()
+            || self.replaygain_scan.is_some()
+            || self.tasks.busy()
+            || self.transcoder.pending() > 0
+            || self.fetcher.pending() > 0
+            || self.discovery.is_some()
+            || self.jump.is_some()
+            || self.view.show_log;
+        if busy && self.config.low_bandwidth {
+            LOW_BANDWIDTH_TICK_RATE
+        } else if busy {
+            BUSY_TICK_RATE
+        } else {
+            IDLE_TICK_RATE
+        }
+    }
+
+    // Returns whether anything on screen may have changed
+    fn on_tick(&mut self) -> bool {
+        // The progress bar and the log panel move on their own
+        let mut changed = self.progress_moved() || self.view.show_log;
+        if self.jump.as_ref().is_some_and(|(_, typed)| typed.elapsed() >= JUMP_TIMEOUT) {
+            self.jump = None;
+            changed = true;
+        }
+        changed |= self.music_player.poll_analysis();
+        changed |= self.music_player.poll_stream_info();
+        let recording = self.music_player.poll_recording();
+        changed |= recording.is_err();
+        self.report_failure(recording);
+        self.music_player.request_tags(self.list_state.visible());
+        if !self.deciding_resume() {
+            if let Err(e) = self.journal.update(&self.music_player, &self.view_state()) {
+                log::warn!(target: "playback", "could not save the session: {}", e);
+            }
+        }
+        if let Some(line) = self.command_output.1.try_iter().last() {
+            self.status = Some(line).filter(|line| !line.is_empty());
+            changed = true;
+        }
+        changed |= self.music_player.poll_tags();
+        changed |= self.poll_replaygain();
+        changed |= self.poll_discovery();
+        changed |= self.poll_bluetooth();
+        for result in self.transcoder.poll() {
+            changed = true;
+            self.transcode_status = Some(match result {
+                Ok(output) => format!("Transcoded {}", output.file_name().unwrap_or_default().to_string_lossy()),
+                Err(e) => format!("Transcode failed: {}", e),
+            });
+        }
+        changed |= self.poll_fetches();
+        changed |= self.poll_focus();
+        changed |= self.poll_roaming();
+        changed |= self.poll_offline();
+        changed |= toast::poll();
+        changed |= self.poll_sync();
+        // The spinners turn while anything runs
+        changed |= self.tasks.poll() || (self.tasks.busy() && !self.config.reduced_motion && !self.config.low_bandwidth);
+        if matches!(self.device_picker, Some((PickerKind::Tasks(_), _))) {
+            self.open_tasks();
+            changed = true;
+        }
+        if let Some(night) = self.night.poll() {
+            // The schedule only acts when night starts or ends, so H overrides it until then
+            log::info!(target: "playback", "night mode {} by the schedule", if night { "starts" } else { "ends" });
+            self.music_player.set_night(night);
+            changed = true;
+        }
+        if !self.announcer.speaking() {
+            self.music_player.set_ducked(false);
         }
+        if let Some(activity) = self.ducking.poll() {
+            log::info!(target: "playback", "ducking: {}", activity);
+            let level = (activity != ducking::Activity::Quiet).then_some(self.config.duck_volume / 100.0);
+            self.music_player.set_system_duck(level);
+            changed = true;
+        }
+        self.music_player.fade_duck();
+        if let Err(e) = self.music_player.check_auto_advance() {
+            log::error!(target: "playback", "could not advance to the next track: {}", e);
+        }
+        changed
+    }
+
+    fn toggle_focus(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.focus.take().is_some() {
+            self.status = Some("Focus mode off".to_string());
+            return Ok(());
+        }
+        let minutes = |minutes: f32| Duration::from_secs_f32(minutes.max(0.0) * 60.0);
+        self.focus = Some(focus::FocusTimer::new(minutes(self.config.focus_minutes), minutes(self.config.break_minutes)));
+        self.start_focus_phase(focus::Phase::Work)
     }
 
-    fn on_tick(&mut self) {
-        if self.music_player.is_track_finished() {
-            if let Err(e) = self.music_player.next_track() {
-                eprintln!("Error advancing track: {}", e);
+    // Switches between work blocks and breaks when their time is up. The
+    // countdown in the status bar changes every second.
+    fn poll_focus(&mut self) -> bool {
+        let Some(focus) = &mut self.focus else {
+            return false;
+        };
+        if let Some(phase) = focus.poll() {
+            if let Err(e) = self.start_focus_phase(phase) {
+                self.status = Some(format!("Could not start the playlist: {}", e));
             }
         }
+        true
     }
-}
 
-enum InputEvent<I> {
-    Input(I),
-    Tick,
-}
+    // Plays the phase's playlist when one is set, otherwise resumes for work
+    // and pauses for breaks
+    fn start_focus_phase(&mut self, phase: focus::Phase) -> Result<(), Box<dyn Error>> {
+        log::info!(target: "playback", "focus mode: {:?} starts", phase);
+        let (playlist, event) = match phase {
+            focus::Phase::Work => (self.config.focus_playlist.clone(), hooks::Event::Focus),
+            focus::Phase::Break => (self.config.break_playlist.clone(), hooks::Event::Break),
+        };
+        let result = match playlist {
+            // Names are looked up among the saved playlists
+            Some(path) => self.switch_playlist(&self.config.playlists_dir.join(path)),
+            None if phase == focus::Phase::Break => {
+                self.music_player.pause();
+                Ok(())
+            }
+            None => self.resume_playback(),
+        };
+        self.hooks.run(event, &now_playing::NowPlaying::of(&self.music_player));
+        self.plugins.event(event);
+        result
+    }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    // Terminal initialization
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+    // Unpauses, or starts the selected track when nothing is loaded
+    fn resume_playback(&mut self) -> Result<(), Box<dyn Error>> {
+        let player = &mut self.music_player;
+        if player.position().is_some() {
+            player.play();
+            return Ok(());
+        }
+        match self.list_state.selected().or((!player.tracks.is_empty()).then_some(0)) {
+            Some(index) => player.play_track(index),
+            None => Ok(()),
+        }
+    }
 
-    let (tx, rx) = mpsc::channel();
-    let tick_rate = Duration::from_millis(200);
+    // Opens the cast picker and searches for renderers in the background
+    fn open_cast_picker(&mut self) {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = tx.send(cast::discover(Duration::from_secs(3)));
+        });
+        self.discovery = Some(rx);
+        self.device_picker = Some((
+            PickerKind::Cast(Vec::new()),
+            ui::DevicePicker {
+                title: "Cast to (Enter: Select | Esc: Close)".to_string(),
+                devices: None,
+                state: ListState::default(),
+                search: None,
+            },
+        ));
+    }
+
+    fn open_output_picker(&mut self) {
+        let names = self.music_player.output_devices();
+        let mut state = ListState::default();
+        state.select((!names.is_empty()).then_some(0));
+        let picker = ui::DevicePicker {
+            title: "Outputs (Enter: Toggle zone | +/-: Zone volume | Esc: Close)".to_string(),
+            devices: Some(self.output_labels(&names)),
+            state,
+            search: None,
+        };
+        self.device_picker = Some((PickerKind::Outputs(names), picker));
+    }
 
-    // Input handling thread
-    thread::spawn(move || {
-        let mut last_tick = std::time::Instant::now();
-        loop {
-            let timeout = tick_rate
-                .checked_sub(last_tick.elapsed())
-                .unwrap_or_else(|| Duration::from_secs(0));
+    // Opens the Bluetooth picker and scans for devices in the background
+    fn open_bluetooth_picker(&mut self) {
+        bluetooth::discover(BLUETOOTH_SCAN, self.bluetooth.0.clone());
+        self.device_picker = Some((
+            PickerKind::Bluetooth(Vec::new()),
+            ui::DevicePicker {
+                title: "Bluetooth (Enter: Connect or disconnect | Esc: Close)".to_string(),
+                devices: None,
+                state: ListState::default(),
+                search: None,
+            },
+        ));
+    }
 
-            if event::poll(timeout).unwrap() {
-                if let Ok(Event::Key(key)) = event::read() {
-                    if key.kind == KeyEventKind::Press {
-                        tx.send(InputEvent::Input(key)).unwrap();
+    fn poll_bluetooth(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok(event) = self.bluetooth.1.try_recv() {
+            changed = true;
+            match event {
+                bluetooth::Event::Found(found) => {
+                    let Some((PickerKind::Bluetooth(devices), picker)) = &mut self.device_picker else {
+                        continue;
+                    };
+                    match found {
+                        Ok(found) => {
+                            picker.state.select((!found.is_empty()).then_some(0));
+                            picker.devices = Some(found.iter().map(bluetooth_label).collect());
+                            *devices = found;
+                        }
+                        Err(e) => {
+                            self.device_picker = None;
+                            self.status = Some(format!("Bluetooth: {}", e));
+                        }
                     }
                 }
+                bluetooth::Event::Connected(device, result) => {
+                    self.status = Some(match result {
+                        Ok(()) => match self.retarget_output(Some(&device)) {
+                            Ok(()) => format!("Connected to {}", device.name),
+                            Err(e) => format!("Connected to {}, but could not play there: {}", device.name, e),
+                        },
+                        Err(e) => format!("Could not connect to {}: {}", device.name, e),
+                    });
+                }
+                bluetooth::Event::Disconnected(device, result) => {
+                    self.status = Some(match result {
+                        Ok(()) => {
+                            if let Err(e) = self.retarget_output(None) {
+                                log::warn!(target: "playback", "could not reopen the output: {}", e);
+                            }
+                            format!("Disconnected {}", device.name)
+                        }
+                        Err(e) => format!("Could not disconnect {}: {}", device.name, e),
+                    });
+                }
             }
+        }
+        changed
+    }
 
-            if last_tick.elapsed() >= tick_rate {
-                tx.send(InputEvent::Tick).unwrap();
-                last_tick = std::time::Instant::now();
-            }
+    // Moves playback to a device that just connected, or back to the
+    // configured output. Audio servers that switch to a new device by
+    // themselves list no device of its own, so the output is reopened on
+    // the same one to follow them.
+    fn retarget_output(&mut self, connected: Option<&bluetooth::Device>) -> Result<(), Box<dyn Error>> {
+        let own = connected.and_then(|device| {
+            self.music_player
+                .output_devices()
+                .into_iter()
+                .find(|name| name.contains(&device.address) || name.contains(&device.name))
+        });
+        let target = own.unwrap_or_else(|| self.config.output_device.clone());
+        if self.music_player.output_device() == target {
+            self.music_player.reopen_output()
+        } else {
+            self.music_player.set_output_device(&target)
         }
-    });
+    }
+
+    fn open_settings(&mut self) {
+        let mut state = ListState::default();
+        state.select(Some(0));
+        self.device_picker = Some((
+            PickerKind::Settings,
+            ui::DevicePicker {
+                title: "Settings (Enter: Change | Esc: Close)".to_string(),
+                devices: Some(self.setting_labels()),
+                state,
+                search: None,
+            },
+        ));
+    }
 
-    let mut app = App::new();
+    fn open_chapters(&mut self) {
+        let player = &self.music_player;
+        if player.chapters().is_empty() {
+            self.status = Some("No chapters in this track".to_string());
+            return;
+        }
+        let labels = player
+            .chapters()
+            .iter()
+            .enumerate()
+            .map(|(i, chapter)| format!("{}. {}  {}", i + 1, ui::format_duration(chapter.start), chapter.title))
+            .collect();
+        let mut state = ListState::default();
+        state.select(Some(player.current_chapter().unwrap_or(0)));
+        self.device_picker = Some((
+            PickerKind::Chapters,
+            ui::DevicePicker {
+                title: "Chapters (Enter: Jump | Esc: Close)".to_string(),
+                devices: Some(labels),
+                state,
+                search: None,
+            },
+        ));
+    }
 
-    // Scan music directory
-    let music_dir = if let Ok(home) = std::env::var("USERPROFILE") {
-        PathBuf::from(home).join("Music")
-    } else {
-        PathBuf::from(".")
-    };
+    fn open_history(&mut self) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        let (paths, labels): (Vec<PathBuf>, Vec<String>) = self
+            .history
+            .entries()
+            .map(|entry| {
+                let minutes = now.saturating_sub(entry.started) / 60;
+                let ago = match minutes {
+                    0 => "just now".to_string(),
+                    1..=59 => format!("{} min ago", minutes),
+                    60..=1439 => format!("{} h ago", minutes / 60),
+                    _ => format!("{} d ago", minutes / 1440),
+                };
+                (entry.path.clone(), format!("{:>10}  {}", ago, self.history_label(&entry.path)))
+            })
+            .unzip();
+        if paths.is_empty() {
+            self.status = Some("Nothing played yet".to_string());
+            return;
+        }
+        let mut state = ListState::default();
+        state.select(Some(0));
+        self.device_picker = Some((
+            PickerKind::History(paths),
+            ui::DevicePicker {
+                title: "Recently played (Enter: Play next | Esc: Close)".to_string(),
+                devices: Some(labels),
+                state,
+                search: None,
+            },
+        ));
+    }
+
+    // `Artist – Title` from the library, the file name when it has no tags
+    fn history_label(&self, path: &Path) -> String {
+        let record = self.music_player.library.get(path);
+        let title = record
+            .and_then(|record| record.title.clone())
+            .unwrap_or_else(|| path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned()));
+        match record.and_then(|record| record.artist.as_deref()) {
+            Some(artist) => format!("{} – {}", artist, title),
+            None => title,
+        }
+    }
+
+    // Plays a track from the history after the current one, adding it to
+    // the playlist when it has left it since
+    fn play_next(&mut self, path: PathBuf) {
+        let player = &mut self.music_player;
+        let index = match player.tracks.iter().position(|track| *track == path) {
+            Some(index) => index,
+            None => {
+                player.add_track(path.clone());
+                player.tracks.len() - 1
+            }
+        };
+        player.queue_next(index);
+        self.status = Some(format!("Plays next: {}", self.history_label(&path)));
+    }
+
+    fn open_transcode(&mut self) {
+        let mut choices = Vec::new();
+        let mut labels = Vec::new();
+        if let Some(i) = self.list_state.selected() {
+            labels.push(format!("Selected track: {}", self.music_player.track_title(i)));
+            choices.push(TranscodeChoice::Track(self.music_player.tracks[i].clone()));
+        }
+        if !self.music_player.tracks.is_empty() {
+            labels.push(format!("This playlist ({} tracks)", self.music_player.tracks.len()));
+            choices.push(TranscodeChoice::Playlist);
+        }
+        for path in utils::list_playlists(&self.config.playlists_dir) {
+            labels.push(format!("Playlist: {}", playlist_name(&path)));
+            choices.push(TranscodeChoice::Saved(path));
+        }
+        if choices.is_empty() {
+            self.status = Some("Nothing to transcode".to_string());
+            return;
+        }
+        let mut state = ListState::default();
+        state.select(Some(0));
+        self.device_picker = Some((
+            PickerKind::Transcode(choices),
+            ui::DevicePicker {
+                title: format!(
+                    "Transcode to {} into {} (Enter: Start | Esc: Close)",
+                    self.transcode_options.format.extension(),
+                    self.transcode_options.output_dir.display()
+                ),
+                devices: Some(labels),
+                state,
+                search: None,
+            },
+        ));
+    }
 
-    if music_dir.exists() {
-        for entry in std::fs::read_dir(music_dir)? {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if let Some(ext) = path.extension() {
-                    if ext == "mp3" || ext == "wav" || ext == "flac" {
-                        app.music_player.add_track(path);
+    // Queues the tracks of a choice from the transcode popup; streams have no end to encode to
+    fn transcode(&mut self, choice: TranscodeChoice) {
+        let tracks = match choice {
+            TranscodeChoice::Track(track) => vec![track],
+            TranscodeChoice::Playlist => self.music_player.tracks.clone(),
+            TranscodeChoice::Saved(path) => match utils::read_playlist(&path) {
+                Ok(playlist) => {
+                    let mut tracks: Vec<PathBuf> = playlist.entries.into_iter().map(PathBuf::from).collect();
+                    if !playlist.rules.is_empty() {
+                        tracks.extend(rules::select(&playlist.rules, &self.music_player.library));
                     }
+                    tracks
                 }
-            }
+                Err(e) => {
+                    self.status = Some(format!("Could not read {}: {}", playlist_name(&path), e));
+                    return;
+                }
+            },
+        };
+        let tracks: Vec<PathBuf> = tracks.into_iter().filter(|track| !decode::is_stream(track)).collect();
+        if tracks.is_empty() {
+            self.status = Some("No files to transcode".to_string());
+            return;
+        }
+        toast::info(format!("Transcoding {} track(s)", tracks.len()));
+        for track in tracks {
+            self.transcoder.queue(track, self.transcode_options.clone());
         }
     }
 
-    // Select first track by default
-    if !app.music_player.tracks.is_empty() {
-        app.list_state.select(Some(0));
+    fn open_profiles(&mut self) {
+        let profiles: Vec<Option<String>> = std::iter::once(None).chain(config::profiles().into_iter().map(Some)).collect();
+        let current = config::profile();
+        let mut labels: Vec<String> = profiles
+            .iter()
+            .map(|profile| {
+                let mark = if profile.as_deref() == current { "●" } else { " " };
+                format!("{} {}", mark, profile.as_deref().unwrap_or("default"))
+            })
+            .collect();
+        labels.push("  New profile...".to_string());
+        let mut state = ListState::default();
+        state.select(profiles.iter().position(|profile| profile.as_deref() == current));
+        self.device_picker = Some((
+            PickerKind::Profiles(profiles),
+            ui::DevicePicker {
+                title: "Profiles (Enter: Switch | Esc: Close)".to_string(),
+                devices: Some(labels),
+                state,
+                search: None,
+            },
+        ));
     }
 
-    // Main event loop
-    loop {
-        terminal.draw(|f| ui::draw(f, &app.music_player, &mut app.list_state))?;
-
-        match rx.recv()? {
-            InputEvent::Input(event) => match event.code {
-                KeyCode::Char('q') => break,
-                KeyCode::Up => {
-                    if !app.music_player.tracks.is_empty() {
-                        let i = match app.list_state.selected() {
-                            Some(i) => {
-                                if i == 0 {
-                                    app.music_player.tracks.len() - 1
-                                } else {
-                                    i - 1
-                                }
-                            }
-                            None => 0,
-                        };
-                        app.list_state.select(Some(i));
-                    }
+    fn open_offline_cache(&mut self) {
+        let entries = remote::cache::entries();
+        if entries.is_empty() {
+            self.status = Some("No remote tracks kept offline yet".to_string());
+            return;
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        let total: u64 = entries.iter().map(|entry| entry.size).sum();
+        let labels = entries
+            .iter()
+            .map(|entry| {
+                let pin = if entry.pinned { "📌" } else { "  " };
+                let days = now.saturating_sub(entry.used) / 86400;
+                let used = match days {
+                    0 => "today".to_string(),
+                    _ => format!("{} d ago", days),
+                };
+                format!("{} {:>9}  {:>8}  {}", pin, megabytes(entry.size), used, self.history_label(&entry.track))
+            })
+            .collect();
+        let selected = match &self.device_picker {
+            Some((PickerKind::OfflineCache(_), picker)) => picker.state.selected().map(|i| i.min(entries.len() - 1)),
+            _ => Some(0),
+        };
+        let mut state = ListState::default();
+        state.select(selected);
+        self.device_picker = Some((
+            PickerKind::OfflineCache(entries.into_iter().map(|entry| entry.track).collect()),
+            ui::DevicePicker {
+                title: format!(
+                    "Offline copies: {} of {} (Enter: Pin/unpin | d: Remove | Esc: Close)",
+                    megabytes(total),
+                    megabytes(remote::cache::limit())
+                ),
+                devices: Some(labels),
+                state,
+                search: None,
+            },
+        ));
+    }
+
+    fn open_labels(&mut self, track: usize) {
+        let on = self.music_player.track_record(track).map(|record| record.labels.clone()).unwrap_or_default();
+        let mut names: Vec<String> = self.config.labels.iter().map(|label| label.name.clone()).collect();
+        // Labels since removed from the config can still be taken off
+        names.extend(on.iter().filter(|name| !names.contains(name)).cloned().collect::<Vec<_>>());
+        let mut labels: Vec<String> = names
+            .iter()
+            .map(|name| format!("{} {}", if on.contains(name) { "✔" } else { " " }, name))
+            .collect();
+        labels.push("  New label...".to_string());
+        let selected = match &self.device_picker {
+            Some((PickerKind::Labels { .. }, picker)) => picker.state.selected(),
+            _ => Some(0),
+        };
+        let mut state = ListState::default();
+        state.select(selected);
+        self.device_picker = Some((
+            PickerKind::Labels { track, names },
+            ui::DevicePicker {
+                title: format!("Labels of {} (Enter: Put on/take off | Esc: Close)", self.music_player.track_title(track)),
+                devices: Some(labels),
+                state,
+                search: None,
+            },
+        ));
+    }
+
+    // Adds a label given as `name[:colour]` to the config unless it is there,
+    // and puts it on a track
+    fn add_label(&mut self, track: usize, entry: &str) {
+        let name = entry.split(':').next().unwrap_or_default().trim().to_string();
+        if !ui::Label::is_name(&name) || entry.contains(';') {
+            self.status = Some(format!("'{}' can't be a label", entry));
+            return;
+        }
+        if !self.config.labels.iter().any(|known| known.name == name) {
+            let label = match entry.contains(':') {
+                true => ui::Label::parse_list(entry).map(|labels| labels[0].clone()),
+                false => Ok(ui::Label::next(&name, &self.config.labels)),
+            };
+            let label = match label {
+                Ok(label) => label,
+                Err(e) => {
+                    self.status = Some(format!("Could not add the label: {}", e));
+                    return;
                 }
-                KeyCode::Down => {
-                    if !app.music_player.tracks.is_empty() {
-                        let i = match app.list_state.selected() {
-                            Some(i) => (i + 1) % app.music_player.tracks.len(),
-                            None => 0,
-                        };
-                        app.list_state.select(Some(i));
-                    }
+            };
+            let mut labels = self.config.labels.clone();
+            labels.push(label);
+            if let Err(e) = self.config.update("labels", &ui::Label::list_to_string(&labels)) {
+                self.status = Some(format!("Could not save the label: {}", e));
+                return;
+            }
+            self.view.labels = self.config.labels.clone();
+        }
+        let player = &mut self.music_player;
+        if track < player.tracks.len() && !player.track_record(track).is_some_and(|record| record.labels.contains(&name)) {
+            player.toggle_label(track, &name);
+        }
+    }
+
+    fn toggle_albums(&mut self) {
+        match &mut self.view.albums {
+            Some(albums) => albums.closed = true,
+            None => {
+                let albums = ui::AlbumGrid::new(&self.music_player.library, ui::Graphics::detect());
+                if albums.albums.is_empty() {
+                    self.status = Some("No albums in the library yet".to_string());
+                    return;
                 }
-                KeyCode::Enter => {
-                    if let Some(i) = app.list_state.selected() {
-                        app.music_player.play_track(i)?;
-                    }
+                self.view.mini = false;
+                self.view.albums = Some(albums);
+            }
+        }
+    }
+
+    // Keys the albums grid takes, the rest go to the key bindings
+    fn handle_album_key(&mut self, code: KeyCode) -> Result<bool, Box<dyn Error>> {
+        let Some(albums) = self.view.albums.as_mut().filter(|albums| !albums.closed) else {
+            return Ok(false);
+        };
+        match code {
+            KeyCode::Left => albums.move_by(-1, 0),
+            KeyCode::Right => albums.move_by(1, 0),
+            KeyCode::Up => albums.move_by(0, -1),
+            KeyCode::Down => albums.move_by(0, 1),
+            KeyCode::PageUp => albums.page(false),
+            KeyCode::PageDown => albums.page(true),
+            KeyCode::Home => albums.select(0),
+            KeyCode::End => albums.select(usize::MAX),
+            KeyCode::Esc => albums.closed = true,
+            KeyCode::Enter => {
+                if let Some(album) = albums.selected_album() {
+                    let (title, tracks) = (album.title.clone(), album.tracks.clone());
+                    self.queue_album(&title, tracks)?;
                 }
-                KeyCode::Char(' ') => {
-                    if app.music_player.is_playing() {
-                        app.music_player.pause();
-                    } else {
-                        app.music_player.play();
-                    }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // Plays an album's tracks after the current one, adding those not in the
+    // playlist, or right away when nothing is playing
+    fn queue_album(&mut self, title: &str, tracks: Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
+        let player = &mut self.music_player;
+        let mut first = None;
+        let count = tracks.len();
+        for track in tracks {
+            let index = match player.tracks.iter().position(|known| *known == track) {
+                Some(index) => index,
+                None => {
+                    player.add_track(track);
+                    player.tracks.len() - 1
                 }
-                KeyCode::Char('s') => {
-                    app.music_player.stop();
+            };
+            player.queue_next(index);
+            first.get_or_insert(index);
+        }
+        match first {
+            Some(first) if player.current_track.is_none() => match player.play_track(first) {
+                Ok(()) => self.status = Some(format!("Playing {}", title)),
+                Err(e) => self.status = Some(format!("Playback failed: {}", e)),
+            },
+            _ => self.status = Some(format!("Queued {} track(s) of {}", count, title)),
+        }
+        Ok(())
+    }
+
+    // Pins a remote track's copy, downloading it first when there is none
+    fn keep_offline(&mut self, track: PathBuf) {
+        if !remote::can_keep_offline(&track) {
+            self.status = Some("Only tracks from SFTP and WebDAV servers can be kept offline".to_string());
+            return;
+        }
+        let label = self.history_label(&track);
+        if remote::cache::is_pinned(&track) {
+            self.status = Some(match remote::cache::set_pinned(&track, false) {
+                Ok(()) => format!("No longer kept offline: {}", label),
+                Err(e) => format!("Could not unpin {}: {}", label, e),
+            });
+        } else if remote::cache::entries().iter().any(|entry| entry.track == track) {
+            self.status = Some(match remote::cache::set_pinned(&track, true) {
+                Ok(()) => format!("Kept offline: {}", label),
+                Err(e) => format!("Could not pin {}: {}", label, e),
+            });
+        } else {
+            self.status = Some(format!("Downloading {}...", label));
+            remote::keep_offline(track, self.offline.0.clone());
+        }
+    }
+
+    fn poll_offline(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok(result) = self.offline.1.try_recv() {
+            changed = true;
+            match result {
+                Ok(track) => toast::info(format!("Kept offline: {}", self.history_label(&track))),
+                Err(e) => toast::error(format!("Could not download the track: {}", e)),
+            }
+        }
+        changed
+    }
+
+    // Quits to start over in another profile
+    fn switch_profile(&mut self, profile: Option<String>) {
+        if let Some(name) = profile.as_deref().filter(|name| !config::is_profile_name(name)) {
+            self.status = Some(format!("'{}' can't be a profile name", name));
+            return;
+        }
+        if profile.as_deref() == config::profile() {
+            return;
+        }
+        self.switch_profile = Some(profile);
+    }
+
+    fn open_playlists(&mut self) {
+        let picker = ui::DevicePicker {
+            title: String::new(),
+            devices: None,
+            state: ListState::default(),
+            search: Some(ui::TextInput::new("Playlist")),
+        };
+        let all = utils::list_playlists(&self.config.playlists_dir);
+        self.device_picker = Some((PickerKind::Playlists { all, shown: Vec::new() }, picker));
+        self.filter_playlists();
+    }
+
+    // Starts with what was typed before the last Esc, if anything
+    fn open_library_search(&mut self) {
+        let mut search = ui::TextInput::new("Search");
+        search.insert(&std::mem::take(&mut self.search_draft));
+        let picker = ui::DevicePicker {
+            title: String::new(),
+            devices: None,
+            state: ListState::default(),
+            search: Some(search),
+        };
+        self.device_picker = Some((PickerKind::Library { shown: Vec::new() }, picker));
+        self.filter_library();
+    }
+
+    // Lists the playlist's matches in playlist order, then those of the
+    // library's other tracks by artist, album and title
+    fn filter_library(&mut self) {
+        let Some((PickerKind::Library { shown }, picker)) = &mut self.device_picker else {
+            return;
+        };
+        let query = picker.query().to_string();
+        let mode = if self.search_regex { "Regex" } else { "Library" };
+        let matcher = match library::Matcher::new(&query, self.search_regex) {
+            Ok(matcher) => matcher,
+            Err(e) => {
+                shown.clear();
+                picker.title = format!("{} (Invalid pattern: {} | Tab: Words | Esc: Close)", mode, e);
+                picker.state.select(None);
+                picker.devices = Some(Vec::new());
+                return;
+            }
+        };
+        let player = &self.music_player;
+        let library = &player.library;
+        let listed: HashSet<&PathBuf> = player.tracks.iter().collect();
+        let mut labels = Vec::new();
+        shown.clear();
+        for (i, track) in player.tracks.iter().enumerate() {
+            if matcher.matches(track, library.get(track)) {
+                labels.push(format!("{:>4}  {}{}", i + 1, track_label(track, library.get(track)), never_play_mark(library, track)));
+                shown.push(track.clone());
+            }
+        }
+        // Only files whose tags were read, which leaves out streams
+        let mut others: Vec<(&PathBuf, &library::TrackRecord)> = library
+            .records()
+            .filter(|(track, record)| !listed.contains(track) && record.modified.is_some())
+            .filter(|(track, record)| matcher.matches(track, Some(record)))
+            .collect();
+        others.sort_by_key(|(track, record)| (record.artist.clone(), record.album.clone(), record.title.clone(), *track));
+        for (track, record) in others {
+            labels.push(format!("   +  {}{}", track_label(track, Some(record)), never_play_mark(library, track)));
+            shown.push(track.clone());
+        }
+        picker.title = format!(
+            "{} ({} found | Enter: Select | Tab: {} | Esc: Close)",
+            mode,
+            shown.len(),
+            if self.search_regex { "Words" } else { "Regex" }
+        );
+        picker.state.select((!shown.is_empty()).then_some(0));
+        picker.devices = Some(labels);
+    }
+
+    // Selects a search result in the playlist, adding it when it is only in the library
+    fn go_to_result(&mut self, track: PathBuf) {
+        let player = &mut self.music_player;
+        let index = match player.tracks.iter().position(|listed| *listed == track) {
+            Some(index) => index,
+            None => {
+                player.add_track(track);
+                self.status = Some(format!("Added {}", player.track_title(player.tracks.len() - 1)));
+                player.tracks.len() - 1
+            }
+        };
+        self.select_track(index);
+    }
+
+    fn open_palette(&mut self) {
+        let picker = ui::DevicePicker {
+            title: String::new(),
+            devices: None,
+            state: ListState::default(),
+            search: Some(ui::TextInput::new("Command")),
+        };
+        self.device_picker = Some((PickerKind::Palette { shown: Vec::new() }, picker));
+        self.filter_palette();
+    }
+
+    // Every action, then the commands bound in the config, with their keys
+    fn filter_palette(&mut self) {
+        let Some((PickerKind::Palette { shown }, picker)) = &mut self.device_picker else {
+            return;
+        };
+        let query = picker.query().to_string();
+        let actions = keymap::PALETTE.iter().map(|&(action, _, label)| (action, label.to_string()));
+        let macros = self.config.macros.iter().enumerate().map(|(i, (name, _))| (Action::RunMacro(i), format!("Macro {}", name)));
+        // Macros bound to keys are listed with the macros
+        let commands = self
+            .commands
+            .iter()
+            .enumerate()
+            .filter(|(_, command)| !command.starts_with('@'))
+            .map(|(i, command)| (Action::RunCommand(i), format!("Run {}", command)));
+        let plugins = self.plugins.bindings().iter().enumerate().map(|(i, binding)| {
+            (Action::RunPlugin(i), format!("Plugin {}: {}", self.plugins.name(binding.plugin), binding.function))
+        });
+        let mut scored: Vec<(i32, Action, String)> = actions
+            .chain(macros)
+            .chain(commands)
+            .chain(plugins)
+            .filter_map(|(action, label)| Some((utils::fuzzy_score(&query, &label)?, action, label)))
+            .collect();
+        // Stable, so equal matches stay in the listed order
+        if !query.is_empty() {
+            scored.sort_by_key(|&(score, _, _)| std::cmp::Reverse(score));
+        }
+        let width = scored.iter().map(|(_, _, label)| label.chars().count()).max().unwrap_or(0);
+        picker.devices = Some(
+            scored
+                .iter()
+                .map(|(_, action, label)| format!("{:width$}  {}", label, palette_keys(&self.keymap, &self.commands, &self.config.macros, *action).join(", "), width = width))
+                .collect(),
+        );
+        *shown = scored.into_iter().map(|(_, action, _)| action).collect();
+        picker.state.select((!shown.is_empty()).then_some(0));
+        picker.title = "Commands (Type to search | Enter: Run | Esc: Close)".to_string();
+    }
+
+    // Locks with the configured passphrase, or one typed now. Unlocking
+    // always asks for it.
+    fn toggle_party(&mut self) {
+        let kind = match (&self.party, &self.config.party_passphrase) {
+            (Some(_), _) => PromptKind::PartyUnlock,
+            (None, Some(passphrase)) => {
+                self.party = Some(passphrase.clone());
+                return;
+            }
+            (None, None) => PromptKind::PartyLock,
+        };
+        let title = match kind {
+            PromptKind::PartyUnlock => "Passphrase to unlock (Enter: Unlock | Esc: Cancel)",
+            _ => "Passphrase to unlock with later (Enter: Lock | Esc: Cancel)",
+        };
+        let mut prompt = ui::TextInput::new(title);
+        prompt.secret = true;
+        self.prompt = Some((kind, prompt));
+    }
+
+    // Starts recording, or stops and asks what to save the recording as
+    fn toggle_macro_recording(&mut self) {
+        match self.recording.take() {
+            None => self.recording = Some(Vec::new()),
+            Some(steps) if steps.is_empty() => self.status = Some("Nothing recorded".to_string()),
+            Some(steps) => {
+                self.last_macro = Some(steps);
+                self.prompt = Some((PromptKind::MacroName, ui::TextInput::new("Macro name (Enter: Save | Esc: Keep for @ only)")));
+            }
+        }
+    }
+
+    fn save_macro(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let Some(steps) = &self.last_macro else {
+            return Ok(());
+        };
+        if name.contains(['=', '#']) {
+            self.status = Some(format!("Not a macro name: {}", name));
+            return Ok(());
+        }
+        match self.config.update(&format!("macro {}", name), &steps.join(", ")) {
+            Ok(()) => self.status = Some(format!("Saved macro {}", name)),
+            Err(e) => self.status = Some(format!("Could not save macro {}: {}", name, e)),
+        }
+        Ok(())
+    }
+
+    fn run_named_macro(&mut self, name: &str) -> Result<(), String> {
+        let steps = self
+            .config
+            .macros
+            .iter()
+            .find(|(macro_name, _)| macro_name == name)
+            .map(|(_, steps)| macro_steps(steps))
+            .ok_or_else(|| format!("no macro named {}", name))?;
+        self.run_macro(steps)
+    }
+
+    // Runs a macro's steps in order, stopping at the first that fails
+    fn run_macro(&mut self, steps: Vec<String>) -> Result<(), String> {
+        for step in &steps {
+            self.run_macro_step(step).map_err(|e| format!("{}: {}", step, e))?;
+        }
+        self.last_macro = Some(steps);
+        Ok(())
+    }
+
+    // A step names an action, with a count before it to repeat it, or is a
+    // request as sent by `music-cli ctl`
+    fn run_macro_step(&mut self, step: &str) -> Result<(), String> {
+        let (count, name) = match step.split_once(' ') {
+            Some((count, name)) if count.parse::<usize>().is_ok() => (count.parse().ok(), name.trim()),
+            _ => (None, step),
+        };
+        match keymap::action_named(name) {
+            Some(Action::Quit | Action::Suspend | Action::RecordMacro | Action::PlayMacro) => {
+                Err("not possible in a macro".to_string())
+            }
+            Some(action) => self.run_action(action, count).map_err(|e| e.to_string()),
+            None if step.split_whitespace().next() == Some("macro") => Err("macros can't run other macros".to_string()),
+            None => self.handle_remote(step).map(|_| ()),
+        }
+    }
+
+    // Lists the playlists matching the typed text, best match first
+    fn filter_playlists(&mut self) {
+        let Some((PickerKind::Playlists { all, shown }, picker)) = &mut self.device_picker else {
+            return;
+        };
+        let query = picker.query().to_string();
+        let mut scored: Vec<(i32, &PathBuf)> = all
+            .iter()
+            .filter_map(|path| Some((utils::fuzzy_score(&query, &playlist_name(path))?, path)))
+            .collect();
+        // Stable, so equal matches stay in name order
+        scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+        *shown = scored.into_iter().map(|(_, path)| path.clone()).collect();
+        picker.devices = Some(shown.iter().map(|path| playlist_name(path)).collect());
+        picker.state.select((!shown.is_empty()).then_some(0));
+        picker.title = "Playlists (Type to search | Enter: Open | Esc: Close)".to_string();
+    }
+
+    // Replaces the playlist with a saved one and starts playing it
+    fn switch_playlist(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        log::info!(target: "playback", "switching to playlist {}", path.display());
+        self.music_player.replace_tracks(Vec::new());
+        self.open_location(&path.to_string_lossy());
+        // The order is drawn again over the whole list, in the mode the
+        // playlist asked for or the one in use
+        let player = &mut self.music_player;
+        player.set_shuffle(player.shuffle());
+        self.list_state.select(None);
+        player.next_track()?;
+        if let Some(current) = player.current_track {
+            self.list_state.select(Some(current));
+        }
+        Ok(())
+    }
+
+    fn setting_labels(&mut self) -> Vec<String> {
+        // Keys can toggle these, the popup shows what is in effect
+        self.config.auto_mix = self.music_player.auto_mix;
+        self.config.auto_fill = self.music_player.auto_fills();
+        self.config.skip_silence = self.music_player.skips_silence();
+        self.config.compressor = self.music_player.compressor();
+        self.config.crossfeed = self.music_player.crossfeeds();
+        self.config.balance = self.music_player.balance() * 100.0;
+        self.config.swap_channels = self.music_player.channels_swapped();
+        self.config.mono = self.music_player.is_mono();
+        config::EDITABLE
+            .iter()
+            .map(|(key, label)| format!("{}: {}", label, self.config.get(key).unwrap_or_default()))
+            .collect()
+    }
+
+    // Applies a setting typed in the settings popup and saves it to clap.conf
+    fn change_setting(&mut self, key: &'static str, value: &str) -> Result<(), Box<dyn Error>> {
+        self.setting_labels();
+        if let Err(e) = self.config.update(key, value) {
+            self.status = Some(format!("Could not save {}: {}", key, e));
+            return Ok(());
+        }
+        match key {
+            "output_device" => self.music_player.set_output_device(&self.config.output_device)?,
+            "output_buffer" => self.music_player.set_output_buffer(self.config.output_buffer)?,
+            "keymap" => self.keymap = self.build_keymap(),
+            "theme" => self.view.theme = self.config.theme,
+            "labels" => self.view.labels = self.config.labels.clone(),
+            "low_bandwidth" => self.view.ascii = self.config.low_bandwidth,
+            "night_hours" => self.night = night::NightSchedule::new(self.config.night_hours),
+            "duck_for" | "duck_volume" => {
+                self.ducking = ducking::Watcher::start(self.config.duck_for);
+                self.music_player.set_system_duck(None);
+            }
+            "library_roots" => {
+                for root in self.config.library_roots.clone() {
+                    match utils::scan_library_root(&root) {
+                        Ok(found) => {
+                            for track in found {
+                                if !self.music_player.tracks.contains(&track) {
+                                    self.music_player.add_track(track);
+                                }
+                            }
+                        }
+                        Err(e) => self.status = Some(format!("Skipping {}: {}", root, e)),
+                    }
                 }
-                KeyCode::Right => {
-                    app.music_player.next_track()?;
-                    if let Some(current) = app.music_player.current_track {
-                        app.list_state.select(Some(current));
+            }
+            _ => self.music_player.configure(&self.config),
+        }
+        let labels = self.setting_labels();
+        if let Some((PickerKind::Settings, picker)) = &mut self.device_picker {
+            picker.devices = Some(labels);
+        }
+        Ok(())
+    }
+
+    // The preset with the commands bound in the config and the plugins' functions
+    fn build_keymap(&self) -> Keymap {
+        let commands: Vec<String> = self.config.commands.iter().map(|(keys, _)| keys.clone()).collect();
+        let mut keymap = Keymap::new(self.config.keymap, &commands);
+        for (i, binding) in self.plugins.bindings().iter().enumerate() {
+            keymap.bind(&binding.keys, Action::RunPlugin(i));
+        }
+        keymap
+    }
+
+    fn offer_resume(&mut self, session: session::Session) {
+        let question = format!("Resume {} ({} tracks)?", session.describe(), session.tracks.len());
+        let mut confirm = ui::Confirm::new("Last session (y: Resume | n: Start fresh)", &question);
+        confirm.yes = true;
+        self.confirm = Some((ConfirmKind::Resume(session), confirm));
+    }
+
+    // The saved session is kept until the user has answered the offer
+    fn deciding_resume(&self) -> bool {
+        matches!(self.confirm, Some((ConfirmKind::Resume(_), _)))
+    }
+
+    // Keys go to the question while it is open, and it closes once answered
+    fn handle_confirm_key(&mut self, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+        let Some((_, confirm)) = &mut self.confirm else {
+            return Ok(());
+        };
+        let Some(yes) = confirm.handle_key(key) else {
+            return Ok(());
+        };
+        let Some((kind, _)) = self.confirm.take() else {
+            return Ok(());
+        };
+        match kind {
+            ConfirmKind::Resume(session) => {
+                if yes {
+                    if let Err(e) = self.resume_session(session) {
+                        self.status = Some(format!("Could not resume: {}", e));
                     }
                 }
-                KeyCode::Left => {
-                    app.music_player.previous_track()?;
-                    if let Some(current) = app.music_player.current_track {
-                        app.list_state.select(Some(current));
+            }
+            ConfirmKind::RemoveOfflineCopy(track) => {
+                if yes {
+                    if let Err(e) = remote::cache::remove(&track) {
+                        self.status = Some(format!("Could not remove the copy: {}", e));
                     }
                 }
-                KeyCode::Char('+') | KeyCode::Char('=') => {
-                    app.music_player.increase_volume();
+                self.open_offline_cache();
+                // Closed by the last one going
+                if remote::cache::entries().is_empty() {
+                    self.device_picker = None;
                 }
-                KeyCode::Char('-') => {
-                    app.music_player.decrease_volume();
+            }
+            ConfirmKind::CancelTask(id) => {
+                if yes {
+                    self.tasks.cancel(id);
                 }
-                _ => {}
+                self.open_tasks();
+            }
+        }
+        Ok(())
+    }
+
+    // Lists the jobs running in the background, or refreshes the list while
+    // it is open
+    fn open_tasks(&mut self) {
+        let jobs = self.tasks.list();
+        let selected = match &self.device_picker {
+            Some((PickerKind::Tasks(_), picker)) => picker.state.selected(),
+            _ if jobs.is_empty() => {
+                self.status = Some("No jobs running in the background".to_string());
+                return;
+            }
+            _ => Some(0),
+        };
+        let mut state = ListState::default();
+        state.select(selected.map(|i| i.min(jobs.len().saturating_sub(1))).filter(|_| !jobs.is_empty()));
+        let (ids, labels): (Vec<u64>, Vec<String>) = jobs.into_iter().unzip();
+        self.device_picker = Some((
+            PickerKind::Tasks(ids),
+            ui::DevicePicker {
+                title: "Background jobs (Enter: Pause/resume | d: Cancel | Esc: Close)".to_string(),
+                devices: Some(labels),
+                state,
+                search: None,
             },
-            InputEvent::Tick => {
-                app.on_tick();
+        ));
+    }
+
+    // Saves what the next start needs, before the player goes away
+    fn shutdown(&mut self) {
+        self.music_player.remember_position();
+        if let Err(e) = self.music_player.library.save() {
+            log::error!(target: "playback", "could not save the library: {}", e);
+        }
+        if let Some(remote) = roaming::Remote::new(&self.config) {
+            if let Err(e) = remote.upload(&self.music_player.library, &self.history, self.config.history_size) {
+                log::warn!(target: "playback", "could not share positions: {}", e);
+            }
+        }
+        if !self.deciding_resume() {
+            if let Err(e) = self.journal.save(&self.music_player, &self.view_state()) {
+                log::warn!(target: "playback", "could not save the session: {}", e);
             }
         }
     }
 
-    // Cleanup
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+    // Puts back the playlist, track, position and modes of a saved session
+    fn resume_session(&mut self, session: session::Session) -> Result<(), Box<dyn Error>> {
+        let player = &mut self.music_player;
+        player.set_shuffle(player::Shuffle::Off);
+        player.replace_tracks(session.tracks);
+        player.set_volume(session.volume);
+        player.set_repeat(session.repeat);
+        player.play_track_at(session.current, session.position)?;
+        if !session.playing {
+            player.pause();
+        }
+        player.set_shuffle(session.shuffle);
+        let view = session.view;
+        self.list_state.restore(view.selected.or(Some(session.current)), view.offset);
+        self.view.show_stream_info = view.stream_info;
+        self.view.show_log = view.log;
+        self.search = view.search;
+        self.search_regex = view.search_regex;
+        Ok(())
+    }
 
-    Ok(())
-}
\ No newline at end of file
+    // What the screen shows, saved with the session
+    fn view_state(&self) -> session::ViewState {
+        session::ViewState {
+            selected: self.list_state.selected(),
+            offset: self.list_state.offset(),
+            stream_info: self.view.show_stream_info,
+            log: self.view.show_log,
+            search: self.search.clone(),
+            search_regex: self.search_regex,
+        }
+    }
+
+    // The main output is listed first, active zones show their volume
+    fn output_labels(&self, names: &[String]) -> Vec<String> {
+        let zones = self.music_player.zones();
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| match zones.iter().find(|zone| zone.name == *name) {
+                _ if i == 0 => format!("  {} (main)", name),
+                Some(zone) => format!("● {} {:.0}%", name, zone.volume * 100.0),
+                None => format!("  {}", name),
+            })
+            .collect()
+    }
+
+    fn poll_discovery(&mut self) -> bool {
+        let Some(renderers) = self.discovery.as_ref().and_then(|rx| rx.try_recv().ok()) else {
+            return false;
+        };
+        self.discovery = None;
+        if let Some((PickerKind::Cast(found), picker)) = &mut self.device_picker {
+            picker.state.select((!renderers.is_empty()).then_some(0));
+            picker.devices = Some(renderers.iter().map(|renderer| renderer.name.clone()).collect());
+            *found = renderers;
+        }
+        true
+    }
+
+    // The widget that has the keys: a prompt or popup over everything else,
+    // then the albums grid or up-next pane when they are open and focused
+    fn focused(&self) -> Widget {
+        if self.confirm.is_some() {
+            Widget::Confirm
+        } else if self.prompt.is_some() {
+            Widget::Prompt
+        } else if self.device_picker.is_some() {
+            Widget::Picker
+        } else if self.view.split.queue_focused() {
+            Widget::Queue
+        } else if self.view.albums.as_ref().is_some_and(|albums| !albums.closed) {
+            Widget::Albums
+        } else {
+            Widget::Playlist
+        }
+    }
+
+    // Pasted text goes into the prompt or the popup's search, and nowhere else
+    fn paste(&mut self, text: &str) {
+        let search = match (self.focused(), &mut self.prompt, &mut self.device_picker) {
+            (Widget::Prompt, Some((_, prompt)), _) => {
+                prompt.insert(text);
+                return;
+            }
+            (Widget::Picker, _, Some((_, picker))) => picker.search.as_mut(),
+            _ => None,
+        };
+        if let Some(search) = search {
+            search.insert(text);
+            self.refilter();
+        }
+    }
+
+    // C-v in a prompt or search box pastes what the system clipboard holds
+    fn paste_clipboard(&mut self) {
+        match self.clipboard.paste() {
+            // A location copied with its line break would end the prompt early
+            Ok(text) => self.paste(text.trim_end_matches(['\r', '\n'])),
+            Err(e) => self.status = Some(format!("Could not paste: {}", e)),
+        }
+    }
+
+    // Puts the playing track, or the selected one when nothing plays, on the
+    // system clipboard
+    fn copy_track(&mut self, action: Action) {
+        let player = &self.music_player;
+        let Some(i) = player.current_track.or(self.list_state.selected()).filter(|&i| i < player.tracks.len()) else {
+            self.status = Some("No track to copy".to_string());
+            return;
+        };
+        let text = match action {
+            Action::CopyPath => player.tracks[i].display().to_string(),
+            Action::CopyTitle => player.track_title(i),
+            _ => match player.track_record(i).and_then(|record| record.artist.as_deref()) {
+                Some(artist) => format!("{} – {}", artist, player.track_title(i)),
+                None => player.track_title(i),
+            },
+        };
+        self.status = Some(match self.clipboard.copy(&text) {
+            Ok(()) => format!("Copied: {}", text),
+            Err(e) => format!("Could not copy: {}", e),
+        });
+    }
+
+    // Lists what matches the search box of whichever popup has one
+    fn refilter(&mut self) {
+        self.filter_playlists();
+        self.filter_library();
+        self.filter_palette();
+    }
+
+    // Keys go to the picker while it is open
+    fn handle_picker_key(&mut self, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+        let code = key.code;
+        // The search box takes the keys that edit text, the list the rest
+        if let Some((_, ui::DevicePicker { search: Some(search), .. })) = &mut self.device_picker {
+            if !matches!(code, KeyCode::Enter | KeyCode::Esc | KeyCode::Up | KeyCode::Down | KeyCode::Tab) {
+                let before = search.value().to_string();
+                search.handle_key(key);
+                if search.value() != before {
+                    self.refilter();
+                }
+                return Ok(());
+            }
+        }
+        if code == KeyCode::Tab && matches!(self.device_picker, Some((PickerKind::Library { .. }, _))) {
+            self.search_regex = !self.search_regex;
+            self.filter_library();
+            return Ok(());
+        }
+        let Some((kind, picker)) = &mut self.device_picker else {
+            return Ok(());
+        };
+        let count = picker.devices.as_ref().map_or(0, Vec::len);
+        let selected = picker.state.selected().filter(|&i| i < count);
+        match code {
+            KeyCode::Esc | KeyCode::Char('#' | 'B' | 'C' | 'J' | 'O' | 'S' | 'T' | 'U' | 'W' | 'Y' | 'c' | 'q') => {
+                // What was typed comes back with the next search
+                if let PickerKind::Library { .. } = kind {
+                    self.search_draft = picker.query().to_string();
+                }
+                self.device_picker = None;
+                self.discovery = None;
+            }
+            KeyCode::Up if count > 0 => {
+                let i = selected.unwrap_or(0);
+                picker.state.select(Some((i + count - 1) % count));
+            }
+            KeyCode::Down if count > 0 => {
+                let i = selected.unwrap_or(0);
+                picker.state.select(Some((i + 1) % count));
+            }
+            KeyCode::Enter => match kind {
+                PickerKind::Cast(renderers) => {
+                    if let Some(renderer) = selected.and_then(|i| renderers.get(i).cloned()) {
+                        self.device_picker = None;
+                        self.music_player.start_casting(renderer)?;
+                    }
+                }
+                PickerKind::Settings => {
+                    if let Some(&(key, label)) = selected.and_then(|i| config::EDITABLE.get(i)) {
+                        let mut prompt = ui::TextInput::new(&format!("{} (Enter: Save | Esc: Cancel)", label));
+                        prompt.insert(&self.config.get(key).unwrap_or_default());
+                        self.prompt = Some((PromptKind::Setting(key), prompt));
+                    }
+                }
+                PickerKind::Playlists { shown, .. } => {
+                    if let Some(path) = selected.map(|i| shown[i].clone()) {
+                        self.device_picker = None;
+                        self.switch_playlist(&path)?;
+                    }
+                }
+                PickerKind::Library { shown } => {
+                    if let Some(track) = selected.map(|i| shown[i].clone()) {
+                        // n/N go on through the playlist's matches
+                        let query = picker.query().to_string();
+                        if !query.is_empty() {
+                            self.view.highlight = library::Matcher::new(&query, self.search_regex).ok();
+                            self.search = Some((query, false));
+                        }
+                        self.device_picker = None;
+                        self.go_to_result(track);
+                    }
+                }
+                PickerKind::Palette { shown, .. } => {
+                    if let Some(&action) = selected.map(|i| &shown[i]) {
+                        self.device_picker = None;
+                        self.picked_action = Some(action);
+                    }
+                }
+                PickerKind::Chapters => {
+                    if let Some(i) = selected {
+                        self.device_picker = None;
+                        self.music_player.jump_to_chapter(i)?;
+                    }
+                }
+                PickerKind::Profiles(profiles) => {
+                    let profile = selected.map(|i| profiles.get(i).cloned());
+                    self.device_picker = None;
+                    match profile {
+                        Some(Some(profile)) => self.switch_profile(profile),
+                        Some(None) => self.open_prompt(PromptKind::ProfileName),
+                        None => {}
+                    }
+                }
+                PickerKind::History(paths) => {
+                    if let Some(path) = selected.map(|i| paths[i].clone()) {
+                        self.device_picker = None;
+                        self.play_next(path);
+                    }
+                }
+                PickerKind::Labels { track, names } => {
+                    let track = *track;
+                    match selected.map(|i| names.get(i).cloned()) {
+                        Some(Some(name)) => {
+                            self.music_player.toggle_label(track, &name);
+                            self.open_labels(track);
+                        }
+                        Some(None) => {
+                            self.device_picker = None;
+                            self.open_prompt(PromptKind::LabelName(track));
+                        }
+                        None => {}
+                    }
+                }
+                PickerKind::Tasks(ids) => {
+                    if let Some(&id) = selected.map(|i| &ids[i]) {
+                        self.tasks.toggle_pause(id);
+                        self.open_tasks();
+                    }
+                }
+                PickerKind::Transcode(choices) => {
+                    if let Some(choice) = selected.map(|i| choices.swap_remove(i)) {
+                        self.device_picker = None;
+                        self.transcode(choice);
+                    }
+                }
+                PickerKind::Plugin { plugin, function } => {
+                    let (plugin, function) = (*plugin, function.take());
+                    let item = selected.and_then(|i| Some((i, picker.devices.as_ref()?[i].clone())));
+                    self.device_picker = None;
+                    if let (Some(function), Some((i, item))) = (function, item) {
+                        self.plugins.pick(plugin, &function, i, &item);
+                    }
+                }
+                PickerKind::OfflineCache(tracks) => {
+                    if let Some(track) = selected.map(|i| tracks[i].clone()) {
+                        let pinned = remote::cache::is_pinned(&track);
+                        if let Err(e) = remote::cache::set_pinned(&track, !pinned) {
+                            self.status = Some(format!("Could not change the pin: {}", e));
+                        }
+                        self.open_offline_cache();
+                    }
+                }
+                PickerKind::Bluetooth(devices) => {
+                    if let Some(device) = selected.and_then(|i| devices.get(i).cloned()) {
+                        self.device_picker = None;
+                        if device.connected {
+                            self.status = Some(format!("Disconnecting {}...", device.name));
+                            bluetooth::disconnect(device, self.bluetooth.0.clone());
+                        } else {
+                            self.status = Some(format!("Connecting to {}...", device.name));
+                            bluetooth::connect(device, self.bluetooth.0.clone());
+                        }
+                    }
+                }
+                PickerKind::Outputs(names) => {
+                    // The main output can't be a zone of itself
+                    if let Some(name) = selected.filter(|&i| i > 0).map(|i| names[i].clone()) {
+                        self.music_player.toggle_zone(&name)?;
+                        self.refresh_output_labels();
+                    }
+                }
+            },
+            KeyCode::Char('d') | KeyCode::Delete => match (kind, selected) {
+                (PickerKind::OfflineCache(tracks), Some(i)) => {
+                    let track = tracks[i].clone();
+                    let question = format!("Remove the offline copy of {}?", self.history_label(&track));
+                    let confirm = ui::Confirm::new("Offline copies (y: Remove | n: Keep)", &question);
+                    self.confirm = Some((ConfirmKind::RemoveOfflineCopy(track), confirm));
+                }
+                (PickerKind::Tasks(ids), Some(i)) => {
+                    let id = ids[i];
+                    let question = format!("Cancel {}?", self.tasks.describe(id).unwrap_or_default());
+                    let confirm = ui::Confirm::new("Background jobs (y: Cancel | n: Let it run)", &question);
+                    self.confirm = Some((ConfirmKind::CancelTask(id), confirm));
+                }
+                _ => {}
+            },
+            KeyCode::Char(key @ ('+' | '=' | '-')) => {
+                if let (PickerKind::Outputs(names), Some(i)) = (kind, selected) {
+                    let name = names[i].clone();
+                    let delta = if key == '-' { -0.1 } else { 0.1 };
+                    self.music_player.adjust_zone_volume(&name, delta);
+                    self.refresh_output_labels();
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn refresh_output_labels(&mut self) {
+        if let Some((PickerKind::Outputs(names), _)) = &self.device_picker {
+            let labels = self.output_labels(names);
+            if let Some((_, picker)) = &mut self.device_picker {
+                picker.devices = Some(labels);
+            }
+        }
+    }
+
+    // Adds a file, folder, playlist, stream or web page to the playlist and
+    // returns the index of the first track added. Pages are fetched in the
+    // background and added once downloaded.
+    fn open_location(&mut self, location: &str) -> Option<usize> {
+        let first = self.music_player.tracks.len();
+        if ytdlp::is_page(location) {
+            self.fetcher.queue(location.to_string());
+            return None;
+        }
+        let path = PathBuf::from(location);
+        if path.is_dir() {
+            for track in utils::scan_music_directory(&path) {
+                self.music_player.add_track(track);
+            }
+        } else if utils::is_playlist(&path) {
+            match utils::read_playlist(&path) {
+                Ok(playlist) => {
+                    for entry in playlist.entries {
+                        self.open_location(&entry);
+                    }
+                    if !playlist.rules.is_empty() {
+                        for track in rules::select(&playlist.rules, &self.music_player.library) {
+                            self.music_player.add_track(track);
+                        }
+                    }
+                    self.apply_playlist_settings(&playlist.settings);
+                }
+                Err(e) => {
+                    log::warn!(target: "scanner", "could not read playlist {}: {}", location, e);
+                    self.fetch_status = Some(format!("Could not read {}: {}", location, e));
+                }
+            }
+        } else {
+            self.music_player.add_track(path);
+        }
+        (self.music_player.tracks.len() > first).then_some(first)
+    }
+
+    // Switches to the playback settings a playlist was saved with
+    fn apply_playlist_settings(&mut self, settings: &utils::PlaylistSettings) {
+        if settings.is_empty() {
+            return;
+        }
+        let player = &mut self.music_player;
+        if let Some(shuffle) = settings.shuffle {
+            player.set_shuffle(shuffle);
+        }
+        if let Some(repeat) = settings.repeat {
+            player.set_repeat(repeat);
+        }
+        if let Some(crossfade) = settings.crossfade {
+            player.set_crossfade(crossfade);
+        }
+        if let Some(speed) = settings.speed {
+            player.set_speed(speed);
+        }
+        if let Some(trim) = settings.trim_silence {
+            player.set_trim_silence(trim);
+        }
+        self.status = Some("Playlist settings applied".to_string());
+    }
+
+    // Sets up playback as asked for on the command line. `first_added` is the
+    // first track from the locations given there.
+    fn apply_start_options(&mut self, start: &cli::StartOptions, first_added: Option<usize>) {
+        let player = &mut self.music_player;
+        if let Some(volume) = start.volume {
+            player.set_volume(volume as f32 / 100.0);
+        }
+        if let Some(repeat) = start.repeat {
+            player.set_repeat(repeat);
+        }
+        let index = match start.play {
+            Some(number) => Some(number - 1),
+            None if start.play_now => first_added,
+            None if start.start_paused => Some(0),
+            None => None,
+        };
+        if let Some(index) = index {
+            if index >= player.tracks.len() {
+                self.status = Some(format!("No track {}", index + 1));
+            } else if let Err(e) = player.play_track(index) {
+                self.status = Some(format!("Playback failed: {}", e));
+            } else {
+                if start.start_paused {
+                    player.pause();
+                }
+                self.list_state.select(Some(index));
+            }
+        }
+        // Shuffled after starting, so the order begins with that track
+        if let Some(shuffle) = start.shuffle {
+            self.music_player.set_shuffle(shuffle);
+        }
+    }
+
+    // A music box plays as soon as it's switched on
+    fn start_kiosk(&mut self, paused: bool) {
+        let player = &mut self.music_player;
+        if paused || player.current_track.is_some() || player.tracks.is_empty() {
+            return;
+        }
+        let index = self.list_state.selected().unwrap_or(0);
+        match player.play_track(index) {
+            Ok(()) => self.list_state.select(Some(index)),
+            Err(e) => self.status = Some(format!("Playback failed: {}", e)),
+        }
+    }
+
+    fn open_prompt(&mut self, kind: PromptKind) {
+        let title = match kind {
+            PromptKind::Open => "Open file, folder, playlist or URL (Enter: Open | Esc: Cancel)",
+            PromptKind::Search { backward } => {
+                self.prompt = Some((kind, ui::TextInput::new(&self.search_title(backward))));
+                return;
+            }
+            PromptKind::GotoTrack => "Go to track number (Enter: Go | Esc: Cancel)",
+            PromptKind::GotoTime => "Go to time, e.g. 1:30 (Enter: Seek | Esc: Cancel)",
+            PromptKind::Setting(_) => "New value (Enter: Save | Esc: Cancel)",
+            PromptKind::MacroName => "Macro name (Enter: Save | Esc: Cancel)",
+            PromptKind::PartyLock | PromptKind::PartyUnlock => "Passphrase (Enter: OK | Esc: Cancel)",
+            PromptKind::ProfileName => "New profile name (Enter: Switch | Esc: Cancel)",
+            PromptKind::Note(_) => "Note, empty to remove it (Enter: Save | Esc: Cancel)",
+            PromptKind::LabelName(_) => "New label, e.g. workout or workout:green (Enter: Add | Esc: Cancel)",
+        };
+        self.prompt = Some((kind, ui::TextInput::new(title)));
+    }
+
+    fn search_title(&self, backward: bool) -> String {
+        format!(
+            "{}{} (Enter: Find | Tab: {} | Esc: Cancel)",
+            if self.search_regex { "Regex search" } else { "Search" },
+            if backward { " backward" } else { "" },
+            if self.search_regex { "Words" } else { "Regex" }
+        )
+    }
+
+    fn handle_prompt_key(&mut self, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+        if let Some((PromptKind::Search { backward }, _)) = self.prompt {
+            if key.code == KeyCode::Tab {
+                self.search_regex = !self.search_regex;
+                let title = self.search_title(backward);
+                if let Some((_, prompt)) = self.prompt.as_mut() {
+                    prompt.title = title;
+                }
+                return Ok(());
+            }
+        }
+        let Some((_, prompt)) = self.prompt.as_mut() else {
+            return Ok(());
+        };
+        match prompt.handle_key(key) {
+            ui::InputAction::Submit => {
+                let Some((kind, prompt)) = self.prompt.take() else {
+                    return Ok(());
+                };
+                let value = prompt.value().trim().to_string();
+                // An empty setting falls back to the default, an empty note removes it
+                if value.is_empty() && !matches!(kind, PromptKind::Setting(_) | PromptKind::Note(_)) {
+                    return Ok(());
+                }
+                match kind {
+                    PromptKind::Open => self.open_and_play(&value)?,
+                    PromptKind::Search { backward } => {
+                        self.status = None;
+                        self.find_track(&value, backward, 1);
+                        self.search = Some((value, backward));
+                    }
+                    PromptKind::GotoTrack => match value.parse::<usize>() {
+                        Ok(number) if number >= 1 && number <= self.music_player.tracks.len() => {
+                            self.select_track(number - 1)
+                        }
+                        _ => self.status = Some(format!("No track {}", value)),
+                    },
+                    PromptKind::GotoTime => match utils::parse_time(&value) {
+                        Some(position) if self.music_player.current_track.is_some() => {
+                            self.music_player.seek_to(position)?
+                        }
+                        Some(_) => self.status = Some("Nothing is playing".to_string()),
+                        None => self.status = Some(format!("Not a time: {}", value)),
+                    },
+                    PromptKind::Setting(key) => self.change_setting(key, &value)?,
+                    PromptKind::MacroName => self.save_macro(&value)?,
+                    PromptKind::ProfileName => self.switch_profile(Some(value)),
+                    PromptKind::Note(index) if index < self.music_player.tracks.len() => {
+                        self.music_player.set_note(index, Some(value).filter(|note| !note.is_empty()));
+                    }
+                    PromptKind::Note(_) => {}
+                    PromptKind::LabelName(index) => self.add_label(index, &value),
+                    PromptKind::PartyLock if value.is_empty() => self.status = Some("No passphrase given".to_string()),
+                    PromptKind::PartyLock => self.party = Some(value),
+                    PromptKind::PartyUnlock => {
+                        if self.party.as_deref() == Some(value.as_str()) {
+                            self.party = None;
+                        } else {
+                            self.status = Some("Wrong passphrase".to_string());
+                        }
+                    }
+                }
+            }
+            ui::InputAction::Cancel => self.prompt = None,
+            ui::InputAction::Edited => {}
+        }
+        Ok(())
+    }
+
+    fn open_and_play(&mut self, location: &str) -> Result<(), Box<dyn Error>> {
+        if ytdlp::is_page(location) {
+            self.play_when_fetched = Some(location.to_string());
+        }
+        if let Some(index) = self.open_location(location) {
+            match self.music_player.tracks.len() - index {
+                1 => toast::info("Added 1 track"),
+                added => toast::info(format!("Added {} tracks", added)),
+            }
+            self.list_state.select(Some(index));
+            if let Err(e) = self.music_player.play_track(index) {
+                self.status = Some(format!("Playback failed: {}", e));
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_fetches(&mut self) -> bool {
+        let results = self.fetcher.poll();
+        let changed = !results.is_empty();
+        for result in results {
+            match result {
+                Ok(fetched) => {
+                    self.fetch_status = None;
+                    let player = &mut self.music_player;
+                    player.library.record_mut(&fetched.path).title = Some(fetched.title);
+                    if let Err(e) = player.library.save() {
+                        log::error!(target: "playback", "could not save the library: {}", e);
+                    }
+                    let index = player.tracks.iter().position(|track| *track == fetched.path).unwrap_or_else(|| {
+                        player.add_track(fetched.path);
+                        player.tracks.len() - 1
+                    });
+                    // Start right away unless something else is playing
+                    let requested = self.play_when_fetched.as_ref() == Some(&fetched.url);
+                    if requested {
+                        self.play_when_fetched = None;
+                    }
+                    if requested || !player.is_playing() {
+                        self.list_state.select(Some(index));
+                        if let Err(e) = player.play_track(index) {
+                            self.fetch_status = Some(format!("Playback failed: {}", e));
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::warn!(target: "playback", "fetch failed: {}", e);
+                    self.fetch_status = Some(format!("Fetch failed: {}", e));
+                }
+            }
+        }
+        changed
+    }
+
+    fn run_action(&mut self, action: Action, count: Option<usize>) -> Result<(), Box<dyn Error>> {
+        if let (Some(steps), Some(name)) = (&mut self.recording, keymap::action_name(action)) {
+            if !matches!(action, Action::RecordMacro | Action::PlayMacro) {
+                steps.push(count.map_or(name.to_string(), |count| format!("{} {}", count, name)));
+            }
+        }
+        let repeat = count.unwrap_or(1).max(1);
+        let player = &mut self.music_player;
+        match action {
+            Action::Quit | Action::Suspend => {}
+            Action::Up => self.move_selection(-(repeat as isize)),
+            Action::Down => self.move_selection(repeat as isize),
+            // With a count these go to that track, as vim's 10G does
+            Action::Top => self.select_track(count.map_or(0, |n| n.saturating_sub(1))),
+            Action::Bottom => self.select_track(count.map_or(usize::MAX, |n| n.saturating_sub(1))),
+            Action::PageUp | Action::PageDown | Action::HalfPageUp | Action::HalfPageDown => {
+                let page = self.list_state.page_size() as isize;
+                let rows = match action {
+                    Action::PageUp => -page,
+                    Action::PageDown => page,
+                    Action::HalfPageUp => -(page / 2).max(1),
+                    _ => (page / 2).max(1),
+                };
+                self.list_state.scroll(rows * repeat as isize, player.tracks.len());
+            }
+            Action::Play => {
+                if let Some(i) = self.list_state.selected() {
+                    if let Err(e) = player.play_track(i) {
+                        self.status = Some(format!("Playback failed: {}", e));
+                    }
+                }
+            }
+            Action::TogglePause => {
+                if player.is_playing() {
+                    player.pause();
+                } else {
+                    player.play();
+                }
+            }
+            Action::Stop => player.stop(),
+            Action::Next | Action::Previous => {
+                for _ in 0..repeat {
+                    let result = if action == Action::Next { player.next_track() } else { player.previous_track() };
+                    if let Err(e) = result {
+                        self.status = Some(format!("Playback failed: {}", e));
+                        break;
+                    }
+                }
+                if let Some(current) = player.current_track {
+                    self.list_state.select(Some(current));
+                }
+            }
+            Action::Replay => player.seek_by(-f64::from(self.config.replay_seconds) * repeat as f64)?,
+            Action::FastForward => player.seek_by(f64::from(self.config.forward_seconds) * repeat as f64)?,
+            Action::PreviousChapter => player.previous_chapter()?,
+            Action::NextChapter => player.next_chapter()?,
+            Action::Chapters => self.open_chapters(),
+            Action::VetoNext => (0..repeat).for_each(|_| player.veto_up_next()),
+            Action::Shuffle => player.cycle_shuffle(),
+            Action::AutoSkip => {
+                player.toggle_auto_skip();
+                self.status = Some(match player.auto_skips() {
+                    Some(true) => "Shuffle skips short and non-music files again".to_string(),
+                    Some(false) => "Shuffle plays every file until restarted".to_string(),
+                    None => "No skip rules; set skip_shorter_than or skip_patterns".to_string(),
+                });
+            }
+            Action::Repeat => player.cycle_repeat(),
+            Action::VolumeUp => {
+                // Guests can't go past the party limit, or back up to a louder volume the host set
+                let limit = match self.party {
+                    Some(_) => (self.config.party_max_volume / 100.0).max(player.volume),
+                    None => 1.0,
+                };
+                (0..repeat).for_each(|_| player.increase_volume());
+                if player.volume > limit {
+                    player.set_volume(limit);
+                }
+                toast::update("volume", format!("Volume {:.0}%", player.volume * 100.0));
+            }
+            Action::VolumeDown => {
+                (0..repeat).for_each(|_| player.decrease_volume());
+                toast::update("volume", format!("Volume {:.0}%", player.volume * 100.0));
+            }
+            Action::TrackGainUp | Action::TrackGainDown => {
+                let up = action == Action::TrackGainUp;
+                let gain = (0..repeat).filter_map(|_| player.adjust_track_gain(up)).last();
+                self.status = Some(match gain {
+                    Some(gain) => format!("Track gain {:+.0} dB", gain),
+                    None => "Nothing is playing".to_string(),
+                });
+            }
+            Action::BalanceLeft | Action::BalanceRight => {
+                let step = if action == Action::BalanceLeft { -0.1 } else { 0.1 };
+                player.adjust_balance(step * repeat as f32);
+                // Adding 0 turns -0 into 0
+                let balance = (player.balance() * 100.0).round() + 0.0;
+                if let Err(e) = self.config.update("balance", &balance.to_string()) {
+                    self.status = Some(format!("Could not save the balance: {}", e));
+                }
+            }
+            Action::PitchDown => player.adjust_pitch(-(repeat as i32)),
+            Action::PitchUp => player.adjust_pitch(repeat as i32),
+            Action::SpeedDown => player.adjust_speed(-0.1 * repeat as f32),
+            Action::SpeedUp => player.adjust_speed(0.1 * repeat as f32),
+            Action::SwapChannels => {
+                player.toggle_channel_swap();
+                if let Err(e) = self.config.update("swap_channels", &player.channels_swapped().to_string()) {
+                    self.status = Some(format!("Could not save the channel swap: {}", e));
+                }
+            }
+            Action::Mono => {
+                player.toggle_mono();
+                if let Err(e) = self.config.update("mono", &player.is_mono().to_string()) {
+                    self.status = Some(format!("Could not save mono: {}", e));
+                }
+            }
+            Action::SilenceSkipping => player.toggle_silence_skipping(),
+            Action::AutoMix => player.toggle_auto_mix(),
+            Action::Compressor => player.cycle_compressor(),
+            Action::Crossfeed => {
+                // Kept for next time, it depends on the headphones more than the music
+                player.toggle_crossfeed();
+                if let Err(e) = self.config.update("crossfeed", &player.crossfeeds().to_string()) {
+                    self.status = Some(format!("Could not save crossfeed: {}", e));
+                }
+            }
+            Action::AutoFill => player.toggle_auto_fill(),
+            Action::Record => {
+                if let Err(e) = player.toggle_recording() {
+                    self.status = Some(format!("Could not record: {}", e));
+                }
+            }
+            Action::Cast => {
+                if player.casting_to().is_some() {
+                    player.stop_casting();
+                } else {
+                    self.open_cast_picker();
+                }
+            }
+            Action::AudioTrack => {
+                if let Err(e) = player.cycle_audio_track() {
+                    self.status = Some(format!("Could not switch the audio track: {}", e));
+                }
+            }
+            Action::StreamInfo => self.view.show_stream_info = !self.view.show_stream_info,
+            Action::SwitchPane => {
+                if !self.view.split.switch_focus() {
+                    self.status = Some("The terminal is too narrow for two panes".to_string());
+                }
+            }
+            Action::SplitLeft | Action::SplitRight => {
+                let step = if action == Action::SplitLeft { -5 } else { 5 };
+                if !self.view.split.resize(step * repeat as i16) {
+                    self.status = Some("The terminal is too narrow for two panes".to_string());
+                } else if let Err(e) = self.config.update("split_percent", &self.view.split.percent.to_string()) {
+                    // Kept for next time, like the other layout settings
+                    self.status = Some(format!("Could not save split_percent: {}", e));
+                }
+            }
+            Action::MiniPlayer => {
+                self.view.mini = !self.view.mini;
+                // The grid's pictures would be drawn over the mini-player
+                if let Some(albums) = &mut self.view.albums {
+                    albums.closed = true;
+                }
+            }
+            Action::Log => self.view.show_log = !self.view.show_log,
+            Action::Outputs => self.open_output_picker(),
+            Action::Bluetooth => self.open_bluetooth_picker(),
+            Action::History => self.open_history(),
+            Action::Albums => self.toggle_albums(),
+            Action::Profiles => self.open_profiles(),
+            Action::OfflineCache => self.open_offline_cache(),
+            Action::Labels => {
+                if let Some(i) = self.list_state.selected() {
+                    self.open_labels(i);
+                }
+            }
+            Action::Note => {
+                if let Some(i) = self.list_state.selected() {
+                    let note = player.library.get(&player.tracks[i]).and_then(|record| record.note.clone());
+                    self.open_prompt(PromptKind::Note(i));
+                    if let (Some(note), Some((_, prompt))) = (note, &mut self.prompt) {
+                        prompt.insert(&note);
+                    }
+                }
+            }
+            Action::KeepOffline => {
+                if let Some(i) = self.list_state.selected() {
+                    let track = player.tracks[i].clone();
+                    self.keep_offline(track);
+                }
+            }
+            Action::Settings => self.open_settings(),
+            Action::Playlists => self.open_playlists(),
+            Action::Focus => self.toggle_focus()?,
+            Action::Open => self.open_prompt(PromptKind::Open),
+            Action::CopyPath | Action::CopyTitle | Action::CopyArtistTitle => self.copy_track(action),
+            Action::ScanLoudness => self.start_replaygain_scan(),
+            Action::Sync => self.start_sync(),
+            Action::Tasks => self.open_tasks(),
+            Action::NeverPlay | Action::NeverPlayFolder => {
+                if let Some(i) = self.list_state.selected() {
+                    let (path, flagged) = player.toggle_never_play(i, action == Action::NeverPlayFolder);
+                    let name = if action == Action::NeverPlay {
+                        player.track_title(i)
+                    } else {
+                        path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned())
+                    };
+                    self.status = Some(if flagged {
+                        format!("Kept out of shuffle and endless play: {}", name)
+                    } else {
+                        format!("Back in shuffle and endless play: {}", name)
+                    });
+                }
+            }
+            Action::Transcode => self.open_transcode(),
+            Action::Search => self.open_library_search(),
+            Action::SearchBackward => self.open_prompt(PromptKind::Search { backward: true }),
+            Action::GotoTrack => self.open_prompt(PromptKind::GotoTrack),
+            Action::GotoTime => self.open_prompt(PromptKind::GotoTime),
+            Action::AbLoop => player.cycle_ab_loop(),
+            Action::Bookmark => {
+                if let Err(e) = player.toggle_bookmark() {
+                    self.status = Some(format!("Could not save the bookmark: {}", e));
+                }
+            }
+            Action::NextBookmark => player.next_bookmark()?,
+            Action::Palette => self.open_palette(),
+            Action::Party => self.toggle_party(),
+            Action::Night => {
+                let night = !self.music_player.is_night();
+                self.music_player.set_night(night);
+                self.status = Some(format!("Night mode {}", if night { "on" } else { "off" }));
+            }
+            Action::RecordMacro => self.toggle_macro_recording(),
+            Action::PlayMacro => match self.last_macro.clone() {
+                Some(steps) => {
+                    if let Err(e) = self.run_macro(steps) {
+                        self.status = Some(format!("Macro stopped at {}", e));
+                    }
+                }
+                None => self.status = Some("No macro recorded yet".to_string()),
+            },
+            Action::RunMacro(i) => {
+                let name = self.config.macros[i].0.clone();
+                if let Err(e) = self.run_named_macro(&name) {
+                    self.status = Some(format!("Macro {} stopped at {}", name, e));
+                }
+            }
+            Action::RunCommand(i) => match self.commands[i].strip_prefix('@') {
+                Some(name) => {
+                    let name = name.trim().to_string();
+                    if let Err(e) = self.run_named_macro(&name) {
+                        self.status = Some(format!("Macro {} stopped at {}", name, e));
+                    }
+                }
+                None => {
+                    let now_playing = now_playing::NowPlaying::of(player);
+                    hooks::run_bound(&self.commands[i], &now_playing, self.command_output.0.clone());
+                }
+            },
+            Action::RunPlugin(i) => self.plugins.press(i),
+            Action::SearchNext | Action::SearchPrevious => {
+                if let Some((query, backward)) = self.search.clone() {
+                    self.find_track(&query, backward != (action == Action::SearchPrevious), repeat);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Moving and Enter act on the up-next list while it has the keys. Returns
+    // whether the action was one of those.
+    fn run_queue_action(&mut self, action: Action, count: Option<usize>) -> Result<bool, Box<dyn Error>> {
+        let repeat = count.unwrap_or(1).max(1);
+        let split = &mut self.view.split;
+        let page = split.page_size() as isize;
+        let rows = match action {
+            Action::Up => -(repeat as isize),
+            Action::Down => repeat as isize,
+            Action::Top => isize::MIN / 2,
+            Action::Bottom => isize::MAX / 2,
+            Action::PageUp => -page * repeat as isize,
+            Action::PageDown => page * repeat as isize,
+            Action::HalfPageUp => -(page / 2).max(1) * repeat as isize,
+            Action::HalfPageDown => (page / 2).max(1) * repeat as isize,
+            Action::Play => {
+                if let Some(index) = split.selected_track() {
+                    match self.music_player.play_track(index) {
+                        Ok(()) => self.list_state.select(Some(index)),
+                        Err(e) => self.status = Some(format!("Playback failed: {}", e)),
+                    }
+                }
+                return Ok(true);
+            }
+            _ => return Ok(false),
+        };
+        split.move_selection(rows);
+        Ok(true)
+    }
+
+    // Now playing, for the terminal's title bar
+    // Answers a request that came in over the IPC socket, from `music-cli ctl`
+    // or a plugin
+    fn answer_remote(&mut self, command: ipc::Command) {
+        let result = self.handle_remote(&command.request);
+        if let Err(e) = &result {
+            log::warn!(target: "ipc", "request '{}' failed: {}", command.request, e);
+        }
+        command.reply(result);
+    }
+
+    // Requests, key bindings and panels from plugins
+    fn handle_plugin(&mut self, message: plugins::Message) {
+        match message {
+            plugins::Message::Request(command) => self.answer_remote(command),
+            plugins::Message::Bind { plugin, keys, function } => {
+                let i = self.plugins.bind(plugin, keys, function);
+                self.keymap.bind(&self.plugins.bindings()[i].keys, Action::RunPlugin(i));
+            }
+            plugins::Message::Panel { plugin, title, items, function } => {
+                // A panel shown again, as it updates, keeps its selection
+                let selected = match &self.device_picker {
+                    Some((PickerKind::Plugin { plugin: shown, .. }, picker)) if *shown == plugin => picker.state.selected(),
+                    _ => None,
+                };
+                let mut state = ListState::default();
+                state.select((!items.is_empty()).then(|| selected.unwrap_or(0).min(items.len() - 1)));
+                let keys = if function.is_some() { "Enter: Pick | Esc: Close" } else { "Esc: Close" };
+                self.device_picker = Some((
+                    PickerKind::Plugin { plugin, function },
+                    ui::DevicePicker {
+                        title: format!("{} ({})", title, keys),
+                        devices: Some(items),
+                        state,
+                        search: None,
+                    },
+                ));
+            }
+        }
+    }
+
+    fn handle_remote(&mut self, request: &str) -> Result<String, String> {
+        let (command, argument) = request.split_once(' ').unwrap_or((request, ""));
+        let argument = argument.trim();
+        let player = &mut self.music_player;
+        let track_line = |player: &player::MusicPlayer, i: usize| {
+            let artist = player.track_record(i).and_then(|record| record.artist.clone()).unwrap_or_default();
+            format!("{}\t{}\t{}\t{}", i + 1, artist, player.track_title(i), player.tracks[i].display())
+        };
+        match command {
+            "play" => {
+                let index = if argument.is_empty() {
+                    self.list_state.selected().ok_or("the playlist is empty")?
+                } else {
+                    match argument.parse::<usize>() {
+                        Ok(number) if number >= 1 && number <= player.tracks.len() => number - 1,
+                        _ => return Err(format!("no track {}", argument)),
+                    }
+                };
+                player.play_track(index).map_err(|e| e.to_string())?;
+                self.list_state.select(Some(index));
+            }
+            "pause" => player.pause(),
+            "resume" => player.play(),
+            "toggle" => {
+                if player.is_playing() {
+                    player.pause();
+                } else {
+                    player.play();
+                }
+            }
+            "stop" => player.stop(),
+            "next" | "previous" => {
+                let result = if command == "next" { player.next_track() } else { player.previous_track() };
+                result.map_err(|e| e.to_string())?;
+                if let Some(current) = player.current_track {
+                    self.list_state.select(Some(current));
+                }
+            }
+            "volume" if argument.is_empty() => return Ok(((player.volume * 100.0).round() as u32).to_string()),
+            "volume" => {
+                let volume: f32 = argument.parse().map_err(|_| format!("not a volume: {}", argument))?;
+                // +N and -N change it from where it is, for buttons and remotes
+                let volume = match argument.chars().next() {
+                    Some('+' | '-') => player.volume * 100.0 + volume,
+                    _ => volume,
+                };
+                player.set_volume(volume / 100.0);
+            }
+            "speed" if argument.is_empty() => return Ok(player.speed().to_string()),
+            "speed" => {
+                let speed: f32 = argument
+                    .trim_end_matches(['x', '×'])
+                    .parse()
+                    .ok()
+                    .filter(|speed: &f32| speed.is_finite())
+                    .ok_or_else(|| format!("not a speed: {}", argument))?;
+                player.set_speed(speed);
+            }
+            "seek" => {
+                // +TIME and -TIME jump from where playback is
+                let (sign, time) = match argument.chars().next() {
+                    Some('+') => (Some(1.0), &argument[1..]),
+                    Some('-') => (Some(-1.0), &argument[1..]),
+                    _ => (None, argument),
+                };
+                let position = utils::parse_time(time).ok_or_else(|| format!("not a time: {}", argument))?;
+                if player.current_track.is_none() {
+                    return Err("nothing is playing".to_string());
+                }
+                let result = match sign {
+                    Some(sign) => player.seek_by(sign * position.as_secs_f64()),
+                    None => player.seek_to(position),
+                };
+                result.map_err(|e| e.to_string())?;
+            }
+            "open" if !argument.is_empty() => {
+                self.open_and_play(argument).map_err(|e| e.to_string())?;
+            }
+            "add" if !argument.is_empty() => {
+                let first = self.open_location(argument);
+                if let Some(index) = first {
+                    if self.list_state.selected().is_none() {
+                        self.list_state.select(Some(index));
+                    }
+                }
+                let added = first.map_or(0, |first| self.music_player.tracks.len() - first);
+                return Ok(format!("added {} track(s)", added));
+            }
+            "tracks" => {
+                let lines: Vec<String> = (0..player.tracks.len()).map(|i| track_line(player, i)).collect();
+                return Ok(lines.join("\n"));
+            }
+            "find" if !argument.is_empty() => {
+                let query = argument.to_lowercase();
+                let lines: Vec<String> = (0..player.tracks.len())
+                    .filter(|&i| {
+                        let record = player.track_record(i);
+                        [
+                            Some(player.track_title(i)),
+                            record.and_then(|record| record.artist.clone()),
+                            record.and_then(|record| record.album.clone()),
+                        ]
+                        .into_iter()
+                        .flatten()
+                        .any(|text| text.to_lowercase().contains(&query))
+                    })
+                    .map(|i| track_line(player, i))
+                    .collect();
+                return Ok(lines.join("\n"));
+            }
+            "library" if !argument.is_empty() => {
+                let matcher = library::Matcher::new(argument, false)?;
+                let library = &player.library;
+                // Only files whose tags were read, as in the search popup
+                let mut found: Vec<(&PathBuf, &library::TrackRecord)> = library
+                    .records()
+                    .filter(|(track, record)| record.modified.is_some() && matcher.matches(track, Some(record)))
+                    .collect();
+                found.sort_by_key(|(track, record)| (record.artist.clone(), record.album.clone(), record.title.clone(), *track));
+                let lines: Vec<String> = found
+                    .into_iter()
+                    .map(|(track, record)| {
+                        let number = player.tracks.iter().position(|listed| listed == track).map(|i| (i + 1).to_string());
+                        let title = record.title.clone().unwrap_or_else(|| track.file_name().unwrap_or_default().to_string_lossy().into_owned());
+                        let artist = record.artist.as_deref().unwrap_or_default();
+                        format!("{}\t{}\t{}\t{}", number.unwrap_or_default(), artist, title, track.display())
+                    })
+                    .collect();
+                return Ok(lines.join("\n"));
+            }
+            "rate" => {
+                let stars: u8 = argument
+                    .parse()
+                    .ok()
+                    .filter(|&stars| stars <= 5)
+                    .ok_or_else(|| format!("not a rating from 0 to 5: {}", argument))?;
+                let current = player.current_track.ok_or("nothing is playing")?;
+                player.set_rating(current, (stars > 0).then_some(stars));
+            }
+            "show" => self.status = Some(argument.to_string()).filter(|text| !text.is_empty()),
+            "macro" if !argument.is_empty() => self.run_named_macro(argument)?,
+            _ => return Err(format!("unknown request '{}'", request)),
+        }
+        Ok("ok".to_string())
+    }
+
+    fn now_playing_title(&self) -> String {
+        let player = &self.music_player;
+        let Some(current) = player.current_track else {
+            return "CLAP".to_string();
+        };
+        let title = player.track_title(current);
+        let (state, dash) = match (player.is_playing(), self.config.low_bandwidth) {
+            (true, false) => ("▶", "–"),
+            (false, false) => ("⏸", "–"),
+            (true, true) => (">", "-"),
+            (false, true) => ("=", "-"),
+        };
+        let text = match player.track_record(current).and_then(|record| record.artist.as_deref()) {
+            Some(artist) => format!("{} {} {} {}", state, artist, dash, title),
+            None => format!("{} {}", state, title),
+        };
+        // Control characters from tags would end the escape sequence early
+        text.chars().filter(|c| !c.is_control()).collect()
+    }
+
+    // Updates what the IPC socket reports. When the track or its state
+    // changed, the now-playing files are rewritten and hooks run.
+    fn publish_now_playing(&mut self) {
+        let now_playing = now_playing::NowPlaying::of(&self.music_player);
+        // Plugins called for the events below find the new track
+        *self.now_playing.lock().unwrap() = now_playing.clone();
+        if self.music_player.take_queue_end() {
+            self.end_queue(&now_playing);
+        }
+        if self.published.as_ref().is_none_or(|published| now_playing.differs_from(published)) {
+            if let Err(e) = now_playing.write_files() {
+                self.status = Some(format!("Could not write now playing: {}", e));
+            }
+            let before = self.published.take().unwrap_or_default();
+            for event in hooks::Hooks::events(&before, &now_playing) {
+                self.hooks.run(event, &now_playing);
+                self.plugins.event(event);
+                if event == hooks::Event::TrackChange {
+                    self.note_history();
+                    if self.config.announce {
+                        self.announce(&now_playing);
+                    }
+                }
+            }
+            self.published = Some(now_playing);
+        }
+    }
+
+    // The playlist ran out: besides the hook, the machine may shut down or
+    // the hook may take over like a plugin, its last line in the status bar
+    fn end_queue(&mut self, now_playing: &now_playing::NowPlaying) {
+        self.plugins.event(hooks::Event::QueueEnd);
+        let stopped = !self.music_player.is_playing() && self.music_player.repeat() == player::Repeat::All;
+        match self.music_player.queue_end() {
+            player::QueueEnd::Shutdown if stopped => {
+                log::info!(target: "playback", "playlist ended, shutting down in {}s", POWEROFF_DELAY.as_secs());
+                self.poweroff_at = Some(Instant::now() + POWEROFF_DELAY);
+            }
+            player::QueueEnd::Hook if stopped => {
+                if let Some(command) = self.hooks.command(hooks::Event::QueueEnd) {
+                    hooks::run_bound(command, now_playing, self.command_output.0.clone());
+                    return;
+                }
+            }
+            _ => {}
+        }
+        self.hooks.run(hooks::Event::QueueEnd, now_playing);
+    }
+
+    // Runs `shutdown_command` once the delay is up, true when it did
+    fn poweroff_due(&mut self) -> bool {
+        if self.poweroff_at.is_none_or(|at| Instant::now() < at) {
+            return false;
+        }
+        self.poweroff_at = None;
+        match hooks::spawn(&self.config.shutdown_command) {
+            Ok(()) => true,
+            Err(e) => {
+                self.status = Some(format!("Could not shut down: {}", e));
+                false
+            }
+        }
+    }
+
+    fn note_history(&mut self) {
+        let player = &self.music_player;
+        let Some(path) = player.current_track.and_then(|current| player.tracks.get(current)).cloned() else {
+            return;
+        };
+        if let Err(e) = self.history.push(path) {
+            log::warn!(target: "playback", "could not save the history: {}", e);
+        }
+    }
+
+    // Speaks the new track over the music, which is turned down meanwhile
+    fn announce(&mut self, now_playing: &now_playing::NowPlaying) {
+        match self.announcer.announce(&self.config.announce_command, now_playing) {
+            Ok(()) => self.music_player.set_ducked(true),
+            Err(e) => {
+                log::warn!(target: "playback", "could not announce the track: {}", e);
+                self.status = Some(format!("Could not announce: {}", e));
+            }
+        }
+    }
+
+    // A click on the progress bar seeks to that point of the track
+    fn seek_to_click(&mut self, column: u16, row: u16) -> Result<(), Box<dyn Error>> {
+        let (Some(bar), Some(duration)) = (self.seek_bar, self.music_player.duration()) else {
+            return Ok(());
+        };
+        if row != bar.y || column < bar.x || column >= bar.right() {
+            return Ok(());
+        }
+        let fraction = (column - bar.x) as f64 / bar.width as f64;
+        self.music_player.seek_to(duration.mul_f64(fraction))
+    }
+
+    // What a dialog's answer fails to do, such as playing the playlist picked,
+    // is reported rather than ending the session
+    fn report_failure(&mut self, result: Result<(), Box<dyn Error>>) {
+        if let Err(e) = result {
+            log::warn!(target: "playback", "{}", e);
+            self.status = Some(format!("Failed: {}", e));
+        }
+    }
+
+    // Single steps wrap around the ends of the list, counted moves stop there
+    fn move_selection(&mut self, delta: isize) {
+        let len = self.music_player.tracks.len() as isize;
+        if len == 0 {
+            return;
+        }
+        let current = self.list_state.selected().map_or(-1, |i| i as isize);
+        let target = if delta.abs() == 1 {
+            (current + delta).rem_euclid(len)
+        } else {
+            (current + delta).clamp(0, len - 1)
+        };
+        self.list_state.select(Some(target as usize));
+    }
+
+    fn select_track(&mut self, index: usize) {
+        let len = self.music_player.tracks.len();
+        if len > 0 {
+            self.list_state.select(Some(index.min(len - 1)));
+        }
+    }
+
+    // Letters typed with Alt select the next track whose title starts with
+    // them, as in file managers: typed quickly they add up, and one letter
+    // again moves on to the next such track. Returns whether the key was one.
+    fn type_to_jump(&mut self, event: KeyEvent) -> bool {
+        let KeyCode::Char(c) = event.code else {
+            return false;
+        };
+        let key = (event.code, event.modifiers);
+        if event.modifiers != KeyModifiers::ALT || !c.is_alphanumeric() || self.keymap.pending().is_some() || self.keymap.binds(key) {
+            return false;
+        }
+        let mut prefix = match self.jump.take() {
+            Some((prefix, typed)) if typed.elapsed() < JUMP_TIMEOUT => prefix,
+            _ => String::new(),
+        };
+        // A longer prefix may still fit the selected track, a repeated letter moves on
+        let repeat = prefix.chars().all(|typed| typed == c);
+        if !repeat || prefix.is_empty() {
+            prefix.push(c);
+        }
+        let wanted = utils::text::fold(&prefix).0.to_lowercase();
+        let len = self.music_player.tracks.len();
+        let start = self.list_state.selected().map_or(0, |selected| if repeat { selected + 1 } else { selected });
+        let found = (0..len)
+            .map(|step| (start + step) % len)
+            .find(|&i| utils::text::fold(&self.music_player.track_title(i)).0.to_lowercase().starts_with(&wanted));
+        match found {
+            Some(i) => self.list_state.select(Some(i)),
+            None => self.status = Some(format!("No title starts with {}", prefix)),
+        }
+        self.jump = Some((prefix, Instant::now()));
+        true
+    }
+
+    // Selects the count-th track after the selection whose tags or file name
+    // match the query
+    fn find_track(&mut self, query: &str, backward: bool, count: usize) {
+        let len = self.music_player.tracks.len();
+        if len == 0 || query.is_empty() {
+            return;
+        }
+        let matcher = match library::Matcher::new(query, self.search_regex) {
+            Ok(matcher) => matcher,
+            Err(e) => {
+                self.status = Some(format!("Invalid pattern: {}", e));
+                return;
+            }
+        };
+        let start = self.list_state.selected().unwrap_or(0);
+        let player = &self.music_player;
+        let found = (1..=len)
+            .map(|step| if backward { (start + len * 2 - step) % len } else { (start + step) % len })
+            .filter(|&i| matcher.matches(&player.tracks[i], player.track_record(i)))
+            .nth(count - 1);
+        self.view.highlight = Some(matcher);
+        match found {
+            Some(i) => self.list_state.select(Some(i)),
+            None => self.status = Some(format!("Not found: {}", query)),
+        }
+    }
+
+    fn start_replaygain_scan(&mut self) {
+        if self.replaygain_scan.is_none() {
+            let progress = self.tasks.start("Computing ReplayGain", tasks::Measure::Percent);
+            self.replaygain_scan = Some(replaygain::Scanner::start(self.music_player.tracks.clone(), progress));
+        }
+    }
+
+    // Takes the positions and history other machines shared, once they arrive
+    fn poll_roaming(&mut self) -> bool {
+        let Some(result) = self.roaming.as_ref().and_then(|receiver| receiver.try_recv().ok()) else {
+            return false;
+        };
+        self.roaming = None;
+        let shared = match result {
+            Ok(shared) => shared,
+            Err(e) => {
+                log::warn!(target: "playback", "could not fetch shared positions: {}", e);
+                toast::error(format!("Could not fetch shared positions: {}", e));
+                return true;
+            }
+        };
+        let player = &mut self.music_player;
+        let changed = shared.apply(&mut player.library, &player.tracks, &mut self.history);
+        if changed > 0 {
+            if let Err(e) = player.library.save() {
+                log::error!(target: "playback", "could not save the library: {}", e);
+            }
+            toast::info(format!("Took {} resume position(s) from other machines", changed));
+        }
+        true
+    }
+
+    fn poll_replaygain(&mut self) -> bool {
+        let Some(scan) = self.replaygain_scan.as_mut() else {
+            return false;
+        };
+        let (gains, finished) = scan.poll();
+        let library = &mut self.music_player.library;
+        for gain in gains {
+            let record = library.record_mut(&gain.path);
+            record.track_gain = Some(gain.track_gain);
+            record.album_gain = Some(gain.album_gain);
+            record.peak = Some(gain.peak);
+        }
+        if finished {
+            self.replaygain_scan = None;
+            if let Err(e) = library.save() {
+                log::error!(target: "playback", "could not save the library: {}", e);
+            }
+        }
+        finished
+    }
+
+    // Copies sync_playlists to sync_dir in the background, as `music-cli sync` does
+    fn start_sync(&mut self) {
+        if self.syncing.is_some() {
+            self.status = Some("A sync is already running".to_string());
+            return;
+        }
+        let options = self.config.sync_options();
+        if options.target.as_os_str().is_empty() || self.config.sync_playlists.is_empty() {
+            self.status = Some("Set sync_dir and sync_playlists to sync".to_string());
+            return;
+        }
+        let playlists = match cli::find_playlists(&self.config.sync_playlists, &self.config) {
+            Ok(playlists) => playlists,
+            Err(e) => {
+                self.status = Some(format!("Could not sync: {}", e));
+                return;
+            }
+        };
+        let progress = self.tasks.start("Syncing", tasks::Measure::Count);
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let result = sync::sync(&playlists, &options, &progress, &mut |line| match line {
+                sync::Line::Done(text) => log::info!(target: "sync", "{}", text),
+                sync::Line::Failed(text) => log::warn!(target: "sync", "{}", text),
+            });
+            progress.finish();
+            let _ = tx.send(result.map_err(|e| e.to_string()));
+        });
+        self.syncing = Some(rx);
+    }
+
+    fn poll_sync(&mut self) -> bool {
+        let Some(result) = self.syncing.as_ref().and_then(|receiver| receiver.try_recv().ok()) else {
+            return false;
+        };
+        self.syncing = None;
+        match result {
+            Ok(summary) if summary.failed > 0 => {
+                toast::error(format!("Synced with {} track(s) failing, see the log", summary.failed))
+            }
+            Ok(summary) => toast::info(format!(
+                "Synced: {} copied, {} already there, {} removed",
+                summary.copied, summary.unchanged, summary.removed
+            )),
+            Err(e) if e == "canceled" => toast::info("Sync canceled, copies so far are kept"),
+            Err(e) => toast::error(format!("Could not sync: {}", e)),
+        }
+        true
+    }
+}
+
+// xterm-style terminals keep a stack of titles, so the one from before
+// starting can be put back on exit
+#[cfg(not(windows))]
+fn save_title(out: &mut impl Write) -> io::Result<()> {
+    write!(out, "\x1b[22;0t")?;
+    out.flush()
+}
+
+#[cfg(not(windows))]
+fn restore_title(out: &mut impl Write) -> io::Result<()> {
+    write!(out, "\x1b[23;0t")?;
+    out.flush()
+}
+
+// The Windows console has no title stack, the title is cleared instead
+#[cfg(windows)]
+fn save_title(_out: &mut impl Write) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(windows)]
+fn restore_title(out: &mut impl Write) -> io::Result<()> {
+    execute!(out, SetTitle(""))
+}
+
+fn enter_terminal(set_title: bool) -> Result<Terminal<CrosstermBackend<io::Stdout>>, Box<dyn Error>> {
+    let mut stdout = io::stdout();
+    take_terminal(&mut stdout, set_title)?;
+    Ok(Terminal::new(CrosstermBackend::new(stdout))?)
+}
+
+fn take_terminal(out: &mut impl Write, set_title: bool) -> io::Result<()> {
+    enable_raw_mode()?;
+    execute!(out, EnterAlternateScreen, EnableMouseCapture, EnableBracketedPaste)?;
+    if set_title {
+        save_title(out)?;
+    }
+    Ok(())
+}
+
+fn leave_terminal(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, set_title: bool) -> Result<(), Box<dyn Error>> {
+    disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        DisableBracketedPaste
+    )?;
+    terminal.show_cursor()?;
+    if set_title {
+        restore_title(terminal.backend_mut())?;
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let (profile, args) = cli::take_profile(std::env::args().skip(1).collect())?;
+    config::set_profile(profile)?;
+    let config = config::Config::load();
+    let (locations, start) = match cli::parse(args.into_iter(), &config)? {
+        cli::Command::Transcode { options, inputs } => return cli::run_transcode(&options, &inputs),
+        cli::Command::Sync { options, playlists } => return cli::run_sync(&options, &playlists),
+        cli::Command::Status { request } => {
+            println!("{}", ipc::send(&request)?);
+            return Ok(());
+        }
+        cli::Command::InstallDesktop { make_default } => return desktop::install(make_default),
+        cli::Command::Play { locations, start } => (locations, start),
+    };
+    if start.party && config.party_passphrase.is_none() {
+        return Err("--party needs party_passphrase in clap.conf".into());
+    }
+    // A second player would fight the first over the audio device
+    if !start.new_instance && ipc::send("text").is_ok() {
+        return cli::hand_over(&locations, start.enqueue);
+    }
+    logging::init(config.log_level.clone());
+
+    // Scan the library before the interface starts, so mounting a share or
+    // connecting to a server can ask for a password
+    let mut tracks = Vec::new();
+    if config.library_roots.is_empty() && locations.is_empty() {
+        let music_dir = if let Ok(home) = std::env::var("USERPROFILE") {
+            PathBuf::from(home).join("Music")
+        } else {
+            PathBuf::from(".")
+        };
+        tracks.extend(utils::scan_music_directory(&music_dir));
+    }
+    for root in &config.library_roots {
+        match utils::scan_library_root(root) {
+            Ok(found) => tracks.extend(found),
+            Err(e) => {
+                log::warn!(target: "scanner", "skipping library root {}: {}", root, e);
+                eprintln!("Skipping {}: {}", root, e);
+            }
+        }
+    }
+
+    // Startup
+    let mut terminal = enter_terminal(config.terminal_title)?;
+    let (tx, rx) = mpsc::channel();
+    let remote_tx = tx.clone();
+    let plugin_tx = tx.clone();
+    let input = input::Input::spawn(tx, BUSY_TICK_RATE);
+
+    let mut app = App::new(&config);
+    // Without the socket only the now-playing files are written
+    let _ipc = ipc::serve(app.now_playing.clone(), move |command| {
+        let _ = remote_tx.send(InputEvent::Remote(command));
+    })
+    .inspect_err(|e| log::warn!(target: "ipc", "not listening for requests: {}", e))
+    .ok();
+    app.plugins = plugins::Plugins::load(&config::config_dir().join("plugins"), app.now_playing.clone(), move |message| {
+        let _ = plugin_tx.send(InputEvent::Plugin(message));
+    });
+    for path in tracks {
+        app.music_player.add_track(path);
+    }
+    let mut first_added = None;
+    for location in &locations {
+        first_added = first_added.or(app.open_location(location));
+    }
+    if config.background_analysis {
+        app.music_player.analyze_library();
+    }
+    app.music_player.read_library_tags();
+
+    // Select first track by default
+    if !app.music_player.tracks.is_empty() {
+        app.list_state.select(Some(0));
+    }
+    app.apply_start_options(&start, first_added);
+    if start.party {
+        app.party = config.party_passphrase.clone();
+    }
+    let fresh_start = !locations.is_empty() || start.play.is_some() || start.start_paused;
+    let kiosk = config.kiosk || start.kiosk;
+    if !fresh_start && !start.no_resume {
+        if let Some(session) = session::Session::load() {
+            if kiosk {
+                // Nobody is at the keyboard to answer the offer
+                if let Err(e) = app.resume_session(session) {
+                    app.status = Some(format!("Could not resume: {}", e));
+                }
+            } else {
+                app.offer_resume(session);
+            }
+        }
+    }
+    if kiosk {
+        app.view.kiosk = true;
+        app.start_kiosk(start.start_paused);
+    }
+
+    let result = run(&mut app, &mut terminal, &rx, &input);
+
+    // Shutdown, also after an error so the terminal is usable again
+    input.stop();
+    app.shutdown();
+    leave_terminal(&mut terminal, config.terminal_title)?;
+    if let (Ok(()), Some(profile)) = (&result, app.switch_profile.take()) {
+        drop(_ipc);
+        drop(app);
+        return restart_in(profile.as_deref());
+    }
+    result
+}
+
+// Starts the player over in another profile, in place of this one
+fn restart_in(profile: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    if let Some(profile) = profile {
+        command.args(["--profile", profile]);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Only returns when it failed
+        Err(command.exec().into())
+    }
+    #[cfg(not(unix))]
+    {
+        let status = command.status()?;
+        std::process::exit(status.code().unwrap_or(1));
+    }
+}
+
+// Runs an action from a key or the palette, refusing what guests may not do
+// in party mode. An action that fails says why in the status bar rather than
+// ending the session. True when it was Quit.
+fn dispatch(
+    app: &mut App,
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    action: Action,
+    count: Option<usize>,
+) -> Result<bool, Box<dyn Error>> {
+    if app.party.is_some() && !party_allows(action) {
+        app.status = Some("Not available in party mode".to_string());
+        return Ok(false);
+    }
+    match action {
+        Action::Quit => return Ok(true),
+        Action::Suspend => suspend(app, terminal)?,
+        _ => {
+            if let Err(e) = app.run_action(action, count) {
+                let name = keymap::action_name(action).unwrap_or("action");
+                log::warn!(target: "playback", "{} failed: {}", name, e);
+                app.status = Some(format!("{} failed: {}", name, e));
+            }
+        }
+    }
+    Ok(false)
+}
+
+// Hands a key press to the focused widget. Prompts and popups keep every
+// key; the albums grid and the up-next pane take the keys they use and
+// leave the rest to the key bindings. True when it was Quit.
+fn handle_key(
+    app: &mut App,
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    event: KeyEvent,
+) -> Result<bool, Box<dyn Error>> {
+    let focused = app.focused();
+    if matches!(focused, Widget::Prompt | Widget::Picker) && (event.code, event.modifiers) == (KeyCode::Char('v'), KeyModifiers::CONTROL) {
+        app.paste_clipboard();
+        return Ok(false);
+    }
+    match focused {
+        Widget::Confirm => {
+            let answered = app.handle_confirm_key(event);
+            app.report_failure(answered);
+        }
+        Widget::Prompt => {
+            let answered = app.handle_prompt_key(event);
+            app.report_failure(answered);
+        }
+        Widget::Picker => {
+            let answered = app.handle_picker_key(event);
+            app.report_failure(answered);
+            // What the palette picked runs as if its keys were pressed
+            if let Some(action) = app.picked_action.take() {
+                return dispatch(app, terminal, action, None);
+            }
+        }
+        Widget::Albums | Widget::Queue | Widget::Playlist => {
+            app.status = None;
+            if focused == Widget::Albums && app.keymap.pending().is_none() && app.handle_album_key(event.code)? {
+                return Ok(false);
+            }
+            // Esc with nothing half-typed clears the search highlight
+            if event.code == KeyCode::Esc && app.keymap.pending().is_none() {
+                app.view.highlight = None;
+            }
+            if focused != Widget::Queue && app.type_to_jump(event) {
+                // The key only moved the selection
+            } else if let Some((action, count)) = app.keymap.press(event) {
+                if focused == Widget::Queue && app.run_queue_action(action, count)? {
+                    return Ok(false);
+                }
+                return dispatch(app, terminal, action, count);
+            }
+        }
+    }
+    Ok(false)
+}
+
+fn bluetooth_label(device: &bluetooth::Device) -> String {
+    match (device.connected, device.paired) {
+        (true, _) => format!("● {} (connected)", device.name),
+        (false, true) => format!("  {} (paired)", device.name),
+        (false, false) => format!("  {}", device.name),
+    }
+}
+
+// Guests can browse, enqueue and skip, and turn the volume within the limit
+fn party_allows(action: Action) -> bool {
+    matches!(
+        action,
+        Action::Up
+            | Action::Down
+            | Action::Top
+            | Action::Bottom
+            | Action::PageUp
+            | Action::PageDown
+            | Action::HalfPageUp
+            | Action::HalfPageDown
+            | Action::Play
+            | Action::Next
+            | Action::Previous
+            | Action::Replay
+            | Action::FastForward
+            | Action::PreviousChapter
+            | Action::NextChapter
+            | Action::Chapters
+            | Action::History
+            | Action::VetoNext
+            | Action::Shuffle
+            | Action::VolumeUp
+            | Action::VolumeDown
+            | Action::StreamInfo
+            | Action::CopyPath
+            | Action::CopyTitle
+            | Action::CopyArtistTitle
+            | Action::MiniPlayer
+            | Action::SwitchPane
+            | Action::Search
+            | Action::SearchBackward
+            | Action::SearchNext
+            | Action::SearchPrevious
+            | Action::GotoTrack
+            | Action::GotoTime
+            | Action::NextBookmark
+            | Action::Log
+            | Action::Palette
+            | Action::Party
+    )
+}
+
+// Runs until the user quits. Events redraw right away, ticks only when
+// something changed
+fn run(
+    app: &mut App,
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    events: &mpsc::Receiver<InputEvent<KeyEvent>>,
+    input: &input::Input,
+) -> Result<(), Box<dyn Error>> {
+    let mut dirty = true;
+    loop {
+        if let Some(mut albums) = app.view.albums.take_if(|albums| albums.closed) {
+            albums.hide(terminal.backend_mut())?;
+            // Pictures some terminals leave behind go with a full redraw
+            terminal.clear()?;
+            dirty = true;
+        }
+        if dirty {
+            let activity = app.activity();
+            terminal.draw(|f| {
+                app.seek_bar = ui::draw(
+                    f,
+                    &app.music_player,
+                    &mut app.list_state,
+                    &activity,
+                    ui::Dialogs {
+                        choice: app.device_picker.as_mut().map(|(_, picker)| picker),
+                        input: app.prompt.as_ref().map(|(_, prompt)| prompt),
+                        confirm: app.confirm.as_ref().map(|(_, confirm)| confirm),
+                    },
+                    &mut app.view,
+                )
+            })?;
+            let covered = app.device_picker.is_some() || app.prompt.is_some() || app.confirm.is_some();
+            if let Some(albums) = app.view.albums.as_mut() {
+                if albums.show_covers(terminal.backend_mut(), covered)? == ui::Covers::Stale {
+                    terminal.clear()?;
+                    continue;
+                }
+            }
+        }
+        input.set_tick_rate(app.tick_rate());
+
+        app.publish_now_playing();
+        if let Some(shown) = &app.window_title {
+            let title = app.now_playing_title();
+            if *shown != title {
+                execute!(terminal.backend_mut(), SetTitle(&title))?;
+                app.window_title = Some(title);
+            }
+        }
+
+        let event = events.recv()?;
+        dirty = !matches!(event, InputEvent::Tick);
+        match event {
+            InputEvent::Input(_) if app.poweroff_at.is_some() => {
+                app.poweroff_at = None;
+                app.status = Some("Shutdown cancelled".to_string());
+            }
+            InputEvent::Input(event) => {
+                if handle_key(app, terminal, event)? {
+                    return Ok(());
+                }
+            }
+            InputEvent::Paste(text) => app.paste(&text),
+            InputEvent::Click(column, row) => {
+                if let Err(e) = app.seek_to_click(column, row) {
+                    app.status = Some(format!("Could not seek: {}", e));
+                }
+            }
+            // The next draw lays everything out again and keeps the
+            // selection in view at the new height
+            InputEvent::Resize(columns, rows) => terminal.resize(Rect::new(0, 0, columns, rows))?,
+            InputEvent::Suspend => suspend(app, terminal)?,
+            InputEvent::Continued => {
+                terminal.clear()?;
+                if let Some(albums) = app.view.albums.as_mut() {
+                    albums.forget_shown();
+                }
+            }
+            InputEvent::Remote(command) => app.answer_remote(command),
+            InputEvent::Plugin(message) => app.handle_plugin(message),
+            InputEvent::Tick => {
+                if app.poweroff_due() {
+                    return Ok(());
+                }
+                dirty = app.on_tick() || app.poweroff_at.is_some();
+            }
+        }
+        if app.switch_profile.is_some() {
+            return Ok(());
+        }
+    }
+}
+
+// Gives the terminal back to the shell and stops until `fg`, as Ctrl-Z does
+// in other programs
+#[cfg(unix)]
+fn suspend(app: &mut App, terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Result<(), Box<dyn Error>> {
+    let pause = app.config.pause_on_suspend && app.music_player.is_playing();
+    if pause {
+        app.music_player.pause();
+    }
+    leave_terminal(terminal, app.config.terminal_title)?;
+    signal_hook::low_level::emulate_default_handler(signal_hook::consts::SIGTSTP)?;
+
+    take_terminal(terminal.backend_mut(), app.config.terminal_title)?;
+    terminal.clear()?;
+    // The title was put back on leaving, so it has to be set again
+    if app.window_title.is_some() {
+        app.window_title = Some(String::new());
+    }
+    if pause {
+        app.music_player.play();
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn suspend(app: &mut App, _terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Result<(), Box<dyn Error>> {
+    app.status = Some("Suspending needs a Unix shell".to_string());
+    Ok(())
+}
+
+// Macros are bound to keys through `bind KEYS = @NAME`
+fn palette_keys(keymap: &Keymap, commands: &[String], macros: &[(String, String)], action: Action) -> Vec<String> {
+    let Action::RunMacro(i) = action else {
+        return keymap.keys_for(action);
+    };
+    let bound = format!("@{}", macros[i].0);
+    (0..commands.len())
+        .filter(|&j| commands[j] == bound)
+        .flat_map(|j| keymap.keys_for(Action::RunCommand(j)))
+        .collect()
+}
+
+fn macro_steps(steps: &str) -> Vec<String> {
+    steps.split(',').map(str::trim).filter(|step| !step.is_empty()).map(str::to_string).collect()
+}
+
+// "Title – Artist (Album)" as far as the tags go
+fn track_label(track: &Path, record: Option<&library::TrackRecord>) -> String {
+    let record = record.cloned().unwrap_or_default();
+    let mut label = record.title.unwrap_or_else(|| track.file_name().unwrap_or_default().to_string_lossy().into_owned());
+    if let Some(artist) = record.artist {
+        label.push_str(&format!(" – {}", artist));
+    }
+    if let Some(album) = record.album {
+        label.push_str(&format!(" ({})", album));
+    }
+    label
+}
+
+// Marks tracks kept out of shuffle in lists that can't grey them out
+fn never_play_mark(library: &library::Library, track: &Path) -> &'static str {
+    if library.never_play(track) {
+        "  ⊘"
+    } else {
+        ""
+    }
+}
+
+// A playlist as listed in the switcher, its file name without the extension
+fn playlist_name(path: &Path) -> String {
+    path.file_stem().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1_048_576.0)
+}
@@ -0,0 +1,343 @@
+// MIDI playback through a small SoundFont sample player
+
+mod smf;
+mod soundfont;
+
+pub use soundfont::SoundFont;
+
+use crate::decode;
+use rodio::Source;
+use smf::{MidiEvent, MidiFile};
+use soundfont::Region;
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+const OUTPUT_RATE: u32 = 44_100;
+// Frames rendered between envelope updates
+const BLOCK_FRAMES: usize = 64;
+const MAX_VOICES: usize = 64;
+// Played after the last event so released notes can ring out
+const RELEASE_TAIL: Duration = Duration::from_secs(2);
+const PERCUSSION_CHANNEL: u8 = 9;
+const PERCUSSION_BANK: u16 = 128;
+
+pub fn is_midi(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| matches!(ext.to_lowercase().as_str(), "mid" | "midi"))
+}
+
+#[derive(Clone, Copy)]
+struct Channel {
+    bank: u16,
+    program: u8,
+    volume: f32,
+    expression: f32,
+    pan: f32,
+    sustain: bool,
+    // Current bend and the range of a full bend, in semitones
+    bend: f32,
+    bend_range: f32,
+    // Registered parameter selected by CC 101/100
+    rpn: (u8, u8),
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Channel {
+            bank: 0,
+            program: 0,
+            volume: 100.0 / 127.0,
+            expression: 1.0,
+            pan: 0.0,
+            sustain: false,
+            bend: 0.0,
+            bend_range: 2.0,
+            rpn: (127, 127),
+        }
+    }
+}
+
+struct Voice {
+    channel: u8,
+    key: u8,
+    region: Region,
+    position: f64,
+    step: f64,
+    // Seconds since the note started
+    time: f32,
+    // Time and level at the note's release
+    released: Option<(f32, f32)>,
+    // Note-off arrived while the sustain pedal was down
+    sustained: bool,
+    level: f32,
+}
+
+impl Voice {
+    fn release(&mut self) {
+        if self.released.is_none() {
+            self.released = Some((self.time, self.level));
+        }
+    }
+
+    // Volume envelope level, or None once the voice has died away
+    fn envelope(&self) -> Option<f32> {
+        let env = &self.region.envelope;
+        let t = self.time;
+        if let Some((at, level)) = self.released {
+            // SoundFont envelopes fall by 100 dB over the release time
+            let progress = (t - at) / env.release.max(0.001);
+            return (progress < 1.0).then(|| level * 10f32.powf(-5.0 * progress));
+        }
+        let attack_start = env.delay;
+        let hold_start = attack_start + env.attack;
+        let decay_start = hold_start + env.hold;
+        Some(if t < attack_start {
+            0.0
+        } else if t < hold_start {
+            (t - attack_start) / env.attack.max(0.001)
+        } else if t < decay_start {
+            1.0
+        } else {
+            let progress = (t - decay_start) / env.decay.max(0.001);
+            10f32.powf(-5.0 * progress).max(env.sustain)
+        })
+    }
+
+    // Linearly interpolated sample at the current position
+    fn sample(&self, samples: &[i16]) -> Option<f32> {
+        let index = self.position as usize;
+        let end = self.region.end.min(samples.len());
+        if index + 1 >= end {
+            return None;
+        }
+        let frac = (self.position - index as f64) as f32;
+        let a = samples[index] as f32;
+        let b = samples[index + 1] as f32;
+        Some((a + (b - a) * frac) / 32768.0)
+    }
+
+    fn advance(&mut self, bend_ratio: f64) {
+        self.position += self.step * bend_ratio;
+        let region = &self.region;
+        if region.looping && self.position >= region.loop_end as f64 {
+            self.position -= (region.loop_end - region.loop_start) as f64;
+        }
+    }
+}
+
+/// Renders a MIDI file to stereo audio with the given SoundFont.
+pub struct MidiSource {
+    soundfont: Arc<SoundFont>,
+    events: Vec<(Duration, MidiEvent)>,
+    next_event: usize,
+    length: Duration,
+    channels: [Channel; 16],
+    voices: Vec<Voice>,
+    frame: u64,
+    block: Vec<f32>,
+    pos: usize,
+}
+
+impl MidiSource {
+    pub fn open(path: &Path, soundfont: Arc<SoundFont>) -> Result<Self, Box<dyn Error>> {
+        let file = MidiFile::parse(&decode::read_all(path)?)?;
+        let mut channels = [Channel::default(); 16];
+        channels[PERCUSSION_CHANNEL as usize].bank = PERCUSSION_BANK;
+        Ok(MidiSource {
+            soundfont,
+            events: file.events,
+            next_event: 0,
+            length: file.length + RELEASE_TAIL,
+            channels,
+            voices: Vec::new(),
+            frame: 0,
+            block: Vec::with_capacity(BLOCK_FRAMES * 2),
+            pos: 0,
+        })
+    }
+
+    fn handle(&mut self, event: MidiEvent) {
+        match event {
+            MidiEvent::NoteOn { channel, key, velocity } => self.note_on(channel, key, velocity),
+            MidiEvent::NoteOff { channel, key } => {
+                let sustain = self.channels[channel as usize].sustain;
+                for voice in self.voices.iter_mut().filter(|v| v.channel == channel && v.key == key) {
+                    if sustain {
+                        voice.sustained = true;
+                    } else {
+                        voice.release();
+                    }
+                }
+            }
+            MidiEvent::ControlChange { channel, controller, value } => {
+                self.control_change(channel, controller, value)
+            }
+            MidiEvent::ProgramChange { channel, program } => self.channels[channel as usize].program = program,
+            MidiEvent::PitchBend { channel, value } => {
+                let state = &mut self.channels[channel as usize];
+                state.bend = value as f32 / 8192.0 * state.bend_range;
+            }
+        }
+    }
+
+    fn note_on(&mut self, channel: u8, key: u8, velocity: u8) {
+        let state = self.channels[channel as usize];
+        // A repeated key replaces the note still sounding
+        for voice in self.voices.iter_mut().filter(|v| v.channel == channel && v.key == key) {
+            voice.release();
+        }
+        for region in self.soundfont.regions(state.bank, state.program, key, velocity) {
+            if self.voices.len() >= MAX_VOICES {
+                // Steal the quietest voice, released ones first
+                let quietest = self
+                    .voices
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| {
+                        (a.released.is_none(), a.level)
+                            .partial_cmp(&(b.released.is_none(), b.level))
+                            .unwrap_or(std::cmp::Ordering::Equal)
+                    })
+                    .map(|(i, _)| i);
+                if let Some(i) = quietest {
+                    self.voices.swap_remove(i);
+                }
+            }
+            let mut region = region;
+            region.looping &= region.loop_end > region.loop_start + 1 && region.loop_end <= region.end;
+            let step = region.sample_rate as f64 / OUTPUT_RATE as f64 * 2f64.powf(region.pitch_cents as f64 / 1200.0);
+            self.voices.push(Voice {
+                channel,
+                key,
+                position: region.start as f64,
+                region,
+                step,
+                time: 0.0,
+                released: None,
+                sustained: false,
+                level: 0.0,
+            });
+        }
+    }
+
+    fn control_change(&mut self, channel: u8, controller: u8, value: u8) {
+        let state = &mut self.channels[channel as usize];
+        let amount = value as f32 / 127.0;
+        match controller {
+            0 if channel != PERCUSSION_CHANNEL => state.bank = value as u16,
+            6 if state.rpn == (0, 0) => state.bend_range = value as f32,
+            7 => state.volume = amount,
+            10 => state.pan = (value as f32 - 64.0) / 63.0,
+            11 => state.expression = amount,
+            64 => {
+                state.sustain = value >= 64;
+                if !state.sustain {
+                    for voice in self.voices.iter_mut().filter(|v| v.channel == channel && v.sustained) {
+                        voice.release();
+                    }
+                }
+            }
+            100 => state.rpn.1 = value,
+            101 => state.rpn.0 = value,
+            120 => self.voices.retain(|voice| voice.channel != channel),
+            121 => {
+                let bank = state.bank;
+                let program = state.program;
+                *state = Channel {
+                    bank,
+                    program,
+                    ..Channel::default()
+                };
+            }
+            123 => {
+                for voice in self.voices.iter_mut().filter(|v| v.channel == channel) {
+                    voice.release();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn render_block(&mut self) -> bool {
+        let start = Duration::from_secs_f64(self.frame as f64 / OUTPUT_RATE as f64);
+        if start >= self.length {
+            return false;
+        }
+        while let Some(&(time, event)) = self.events.get(self.next_event) {
+            if time > start {
+                break;
+            }
+            self.handle(event);
+            self.next_event += 1;
+        }
+
+        self.block.clear();
+        self.block.resize(BLOCK_FRAMES * 2, 0.0);
+        let block_seconds = BLOCK_FRAMES as f32 / OUTPUT_RATE as f32;
+        let samples = &self.soundfont.samples;
+        for voice in self.voices.iter_mut() {
+            let Some(level) = voice.envelope() else {
+                voice.level = -1.0;
+                continue;
+            };
+            voice.level = level;
+            let channel = &self.channels[voice.channel as usize];
+            let gain = voice.region.gain * level * (channel.volume * channel.expression).powi(2);
+            let pan = (voice.region.pan + channel.pan).clamp(-1.0, 1.0);
+            let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+            let (left, right) = (gain * angle.cos(), gain * angle.sin());
+            let bend_ratio = 2f64.powf(channel.bend as f64 / 12.0);
+
+            for frame in self.block.chunks_exact_mut(2) {
+                let Some(sample) = voice.sample(samples) else {
+                    voice.level = -1.0;
+                    break;
+                };
+                frame[0] += sample * left;
+                frame[1] += sample * right;
+                voice.advance(bend_ratio);
+            }
+            voice.time += block_seconds;
+        }
+        // Finished voices are marked with a negative level
+        self.voices.retain(|voice| voice.level >= 0.0);
+
+        self.frame += BLOCK_FRAMES as u64;
+        self.pos = 0;
+        true
+    }
+}
+
+impl Iterator for MidiSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.pos >= self.block.len() && !self.render_block() {
+            return None;
+        }
+        let sample = self.block[self.pos];
+        self.pos += 1;
+        Some(sample)
+    }
+}
+
+impl Source for MidiSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        OUTPUT_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(self.length)
+    }
+}
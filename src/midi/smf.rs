@@ -0,0 +1,203 @@
+// Standard MIDI File reader, flattening all tracks into one timeline
+
+use std::error::Error;
+use std::time::Duration;
+
+// Tempo until the file sets one, 120 BPM
+const DEFAULT_TEMPO_MICROS: u32 = 500_000;
+
+#[derive(Clone, Copy, Debug)]
+pub enum MidiEvent {
+    NoteOff { channel: u8, key: u8 },
+    NoteOn { channel: u8, key: u8, velocity: u8 },
+    ControlChange { channel: u8, controller: u8, value: u8 },
+    ProgramChange { channel: u8, program: u8 },
+    // Centered at 0, -8192..8191
+    PitchBend { channel: u8, value: i16 },
+}
+
+pub struct MidiFile {
+    // Events in playback order with their absolute time
+    pub events: Vec<(Duration, MidiEvent)>,
+    pub length: Duration,
+}
+
+enum Timed {
+    Event(MidiEvent),
+    Tempo(u32),
+}
+
+impl MidiFile {
+    pub fn parse(data: &[u8]) -> Result<MidiFile, Box<dyn Error>> {
+        let mut reader = Reader { data, pos: 0 };
+        if reader.bytes(4)? != b"MThd" {
+            return Err("not a MIDI file".into());
+        }
+        let header_len = reader.u32()? as usize;
+        let header = reader.bytes(header_len)?;
+        if header.len() < 6 {
+            return Err("truncated MIDI header".into());
+        }
+        let track_count = u16::from_be_bytes([header[2], header[3]]);
+        let division = u16::from_be_bytes([header[4], header[5]]);
+
+        // Tick timestamps from every track, tempo changes included
+        let mut timed: Vec<(u64, usize, Timed)> = Vec::new();
+        for _ in 0..track_count {
+            if reader.remaining() < 8 {
+                break;
+            }
+            let id = reader.bytes(4)?;
+            let len = reader.u32()? as usize;
+            let chunk = reader.bytes(len.min(reader.remaining()))?;
+            if id == b"MTrk" {
+                read_track(chunk, &mut timed);
+            }
+        }
+        // Stable order keeps simultaneous events in file order
+        timed.sort_by_key(|(tick, order, _)| (*tick, *order));
+
+        let mut events = Vec::new();
+        let mut tempo = DEFAULT_TEMPO_MICROS;
+        let mut last_tick = 0;
+        let mut time = 0.0;
+        for (tick, _, item) in timed {
+            time += tick_seconds(tick - last_tick, division, tempo);
+            last_tick = tick;
+            match item {
+                Timed::Tempo(micros) => tempo = micros,
+                Timed::Event(event) => events.push((Duration::from_secs_f64(time), event)),
+            }
+        }
+
+        Ok(MidiFile {
+            events,
+            length: Duration::from_secs_f64(time),
+        })
+    }
+}
+
+fn tick_seconds(ticks: u64, division: u16, tempo_micros: u32) -> f64 {
+    if division & 0x8000 != 0 {
+        // SMPTE time: frames per second and ticks per frame
+        let fps = -((division >> 8) as u8 as i8) as f64;
+        let per_frame = (division & 0xFF).max(1) as f64;
+        ticks as f64 / (fps * per_frame)
+    } else {
+        ticks as f64 * tempo_micros as f64 / 1_000_000.0 / division.max(1) as f64
+    }
+}
+
+fn read_track(chunk: &[u8], timed: &mut Vec<(u64, usize, Timed)>) {
+    let mut reader = Reader { data: chunk, pos: 0 };
+    let mut tick = 0u64;
+    let mut running_status = 0u8;
+    while reader.remaining() > 0 {
+        let Ok(delta) = reader.varlen() else {
+            return;
+        };
+        tick += delta as u64;
+        let Ok(mut status) = reader.u8() else {
+            return;
+        };
+        if status < 0x80 {
+            // Running status, the byte read was the first data byte
+            status = running_status;
+            reader.pos -= 1;
+        }
+
+        let order = timed.len();
+        match status {
+            0xFF => {
+                let (Ok(kind), Ok(len)) = (reader.u8(), reader.varlen()) else {
+                    return;
+                };
+                let Ok(data) = reader.bytes(len as usize) else {
+                    return;
+                };
+                match kind {
+                    0x2F => return,
+                    0x51 if data.len() == 3 => {
+                        let micros = u32::from_be_bytes([0, data[0], data[1], data[2]]);
+                        timed.push((tick, order, Timed::Tempo(micros)));
+                    }
+                    _ => {}
+                }
+            }
+            0xF0 | 0xF7 => {
+                let Ok(len) = reader.varlen() else {
+                    return;
+                };
+                if reader.bytes(len as usize).is_err() {
+                    return;
+                }
+            }
+            0x80..=0xEF => {
+                running_status = status;
+                let channel = status & 0x0F;
+                let data_len = if matches!(status & 0xF0, 0xC0 | 0xD0) { 1 } else { 2 };
+                let Ok(data) = reader.bytes(data_len) else {
+                    return;
+                };
+                let event = match status & 0xF0 {
+                    0x80 => Some(MidiEvent::NoteOff { channel, key: data[0] }),
+                    0x90 if data[1] == 0 => Some(MidiEvent::NoteOff { channel, key: data[0] }),
+                    0x90 => Some(MidiEvent::NoteOn { channel, key: data[0], velocity: data[1] }),
+                    0xB0 => Some(MidiEvent::ControlChange { channel, controller: data[0], value: data[1] }),
+                    0xC0 => Some(MidiEvent::ProgramChange { channel, program: data[0] }),
+                    0xE0 => {
+                        let value = ((data[1] as i16) << 7 | data[0] as i16) - 8192;
+                        Some(MidiEvent::PitchBend { channel, value })
+                    }
+                    _ => None,
+                };
+                if let Some(event) = event {
+                    timed.push((tick, order, Timed::Event(event)));
+                }
+            }
+            // Other system messages carry no data we need
+            _ => {}
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        if len > self.remaining() {
+            return Err("unexpected end of MIDI data");
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, &'static str> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn varlen(&mut self) -> Result<u32, &'static str> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = value << 7 | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("invalid variable-length number")
+    }
+}
@@ -0,0 +1,336 @@
+// SoundFont 2 reader, resolving presets down to the sample regions a note plays
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+const GENERATOR_COUNT: usize = 61;
+
+// Generator numbers from the SoundFont 2.04 specification
+const START_OFFSET: usize = 0;
+const END_OFFSET: usize = 1;
+const LOOP_START_OFFSET: usize = 2;
+const LOOP_END_OFFSET: usize = 3;
+const START_COARSE_OFFSET: usize = 4;
+const END_COARSE_OFFSET: usize = 12;
+const PAN: usize = 17;
+const DELAY_VOL_ENV: usize = 33;
+const ATTACK_VOL_ENV: usize = 34;
+const HOLD_VOL_ENV: usize = 35;
+const DECAY_VOL_ENV: usize = 36;
+const SUSTAIN_VOL_ENV: usize = 37;
+const RELEASE_VOL_ENV: usize = 38;
+const INSTRUMENT: usize = 41;
+const KEY_RANGE: usize = 43;
+const VEL_RANGE: usize = 44;
+const LOOP_START_COARSE_OFFSET: usize = 45;
+const KEYNUM: usize = 46;
+const VELOCITY: usize = 47;
+const INITIAL_ATTENUATION: usize = 48;
+const LOOP_END_COARSE_OFFSET: usize = 50;
+const COARSE_TUNE: usize = 51;
+const FINE_TUNE: usize = 52;
+const SAMPLE_ID: usize = 53;
+const SAMPLE_MODES: usize = 54;
+const SCALE_TUNING: usize = 56;
+const OVERRIDING_ROOT_KEY: usize = 58;
+
+type Generators = [Option<i16>; GENERATOR_COUNT];
+
+struct Zone {
+    generators: Generators,
+}
+
+impl Zone {
+    fn get(&self, generator: usize) -> Option<i16> {
+        self.generators[generator]
+    }
+
+    fn range(&self, generator: usize) -> (u8, u8) {
+        match self.generators[generator] {
+            Some(amount) => {
+                let [lo, hi] = amount.to_le_bytes();
+                (lo, hi)
+            }
+            None => (0, 127),
+        }
+    }
+
+    fn matches(&self, key: u8, velocity: u8) -> bool {
+        let (key_lo, key_hi) = self.range(KEY_RANGE);
+        let (vel_lo, vel_hi) = self.range(VEL_RANGE);
+        (key_lo..=key_hi).contains(&key) && (vel_lo..=vel_hi).contains(&velocity)
+    }
+}
+
+struct Preset {
+    bank: u16,
+    program: u16,
+    zones: Vec<Zone>,
+}
+
+struct SampleHeader {
+    start: u32,
+    end: u32,
+    loop_start: u32,
+    loop_end: u32,
+    sample_rate: u32,
+    original_pitch: u8,
+    pitch_correction: i8,
+}
+
+// Volume envelope stages in seconds, sustain as a linear gain
+#[derive(Clone, Copy, Debug)]
+pub struct Envelope {
+    pub delay: f32,
+    pub attack: f32,
+    pub hold: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+/// Everything a voice needs to play one sample for a note.
+#[derive(Clone, Debug)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+    pub loop_start: usize,
+    pub loop_end: usize,
+    pub looping: bool,
+    pub sample_rate: u32,
+    // Pitch of the note relative to the sample's recorded pitch, in cents
+    pub pitch_cents: f32,
+    pub gain: f32,
+    // -1.0 is fully left, 1.0 is fully right
+    pub pan: f32,
+    pub envelope: Envelope,
+}
+
+pub struct SoundFont {
+    pub samples: Vec<i16>,
+    sample_headers: Vec<SampleHeader>,
+    presets: Vec<Preset>,
+    instruments: Vec<Vec<Zone>>,
+}
+
+impl SoundFont {
+    pub fn load(path: &Path) -> Result<SoundFont, Box<dyn Error>> {
+        let data = fs::read(path)?;
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"sfbk" {
+            return Err("not a SoundFont 2 file".into());
+        }
+
+        let mut samples = Vec::new();
+        let mut hydra: Vec<(&[u8; 4], &[u8])> = Vec::new();
+        for (id, body) in chunks(&data[12..]) {
+            if id != b"LIST" || body.len() < 4 {
+                continue;
+            }
+            for (sub_id, sub_body) in chunks(&body[4..]) {
+                match (&body[0..4], sub_id) {
+                    (b"sdta", b"smpl") => {
+                        samples = sub_body
+                            .chunks_exact(2)
+                            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+                            .collect();
+                    }
+                    (b"pdta", _) => hydra.push((sub_id, sub_body)),
+                    _ => {}
+                }
+            }
+        }
+        let record = |name: &[u8; 4], size: usize| {
+            hydra
+                .iter()
+                .find(|(id, _)| *id == name)
+                .map(|(_, body)| body.chunks_exact(size).collect::<Vec<_>>())
+                .unwrap_or_default()
+        };
+
+        let phdr = record(b"phdr", 38);
+        let pbag = record(b"pbag", 4);
+        let pgen = record(b"pgen", 4);
+        let inst = record(b"inst", 22);
+        let ibag = record(b"ibag", 4);
+        let igen = record(b"igen", 4);
+        let shdr = record(b"shdr", 46);
+        if phdr.is_empty() || shdr.is_empty() {
+            return Err("SoundFont has no presets".into());
+        }
+
+        let instruments = (0..inst.len().saturating_sub(1))
+            .map(|i| read_zones(u16_at(inst[i], 20), u16_at(inst[i + 1], 20), &ibag, &igen, SAMPLE_ID))
+            .collect();
+        // The last record of each list only terminates the previous one
+        let presets = (0..phdr.len() - 1)
+            .map(|i| Preset {
+                program: u16_at(phdr[i], 20),
+                bank: u16_at(phdr[i], 22),
+                zones: read_zones(u16_at(phdr[i], 24), u16_at(phdr[i + 1], 24), &pbag, &pgen, INSTRUMENT),
+            })
+            .collect();
+        let sample_headers = shdr[..shdr.len() - 1]
+            .iter()
+            .map(|record| SampleHeader {
+                start: u32_at(record, 20),
+                end: u32_at(record, 24),
+                loop_start: u32_at(record, 28),
+                loop_end: u32_at(record, 32),
+                sample_rate: u32_at(record, 36),
+                original_pitch: record[40],
+                pitch_correction: record[41] as i8,
+            })
+            .collect();
+
+        Ok(SoundFont {
+            samples,
+            sample_headers,
+            presets,
+            instruments,
+        })
+    }
+
+    // Regions sounding for a note, falling back to bank 0 and then to any preset
+    pub fn regions(&self, bank: u16, program: u8, key: u8, velocity: u8) -> Vec<Region> {
+        let program = program as u16;
+        let preset = self
+            .presets
+            .iter()
+            .find(|preset| preset.bank == bank && preset.program == program)
+            .or_else(|| {
+                // Drum kits live in bank 128, melodic fallbacks in bank 0
+                let fallback = if bank == 128 { 128 } else { 0 };
+                self.presets
+                    .iter()
+                    .find(|preset| preset.bank == fallback && preset.program == program)
+            })
+            .or_else(|| self.presets.iter().find(|preset| preset.bank == bank.min(128)))
+            .or_else(|| self.presets.first());
+        let Some(preset) = preset else {
+            return Vec::new();
+        };
+
+        let mut regions = Vec::new();
+        for preset_zone in preset.zones.iter().filter(|zone| zone.matches(key, velocity)) {
+            let Some(instrument) = preset_zone
+                .get(INSTRUMENT)
+                .and_then(|i| self.instruments.get(i as u16 as usize))
+            else {
+                continue;
+            };
+            for zone in instrument.iter().filter(|zone| zone.matches(key, velocity)) {
+                if let Some(region) = self.region(zone, preset_zone, key, velocity) {
+                    regions.push(region);
+                }
+            }
+        }
+        regions
+    }
+
+    fn region(&self, zone: &Zone, preset_zone: &Zone, key: u8, velocity: u8) -> Option<Region> {
+        let header = self.sample_headers.get(zone.get(SAMPLE_ID)? as u16 as usize)?;
+        // Instrument values are absolute, preset values are added on top
+        let value = |generator: usize, default: i16| {
+            zone.get(generator).unwrap_or(default) as i32 + preset_zone.get(generator).unwrap_or(0) as i32
+        };
+        let offset = |fine: usize, coarse: usize| {
+            zone.get(fine).unwrap_or(0) as i64 + zone.get(coarse).unwrap_or(0) as i64 * 32768
+        };
+        let address = |base: u32, delta: i64| (base as i64 + delta).clamp(0, self.samples.len() as i64) as usize;
+
+        let root_key = match zone.get(OVERRIDING_ROOT_KEY) {
+            Some(root) if (0..128).contains(&root) => root as i32,
+            _ => header.original_pitch.min(127) as i32,
+        };
+        let key = zone.get(KEYNUM).filter(|k| (0..128).contains(k)).map_or(key as i32, |k| k as i32);
+        let velocity = zone.get(VELOCITY).filter(|v| (1..128).contains(v)).map_or(velocity as i32, |v| v as i32);
+        let scale_tuning = value(SCALE_TUNING, 100);
+        let pitch_cents = (key - root_key) * scale_tuning
+            + value(COARSE_TUNE, 0) * 100
+            + value(FINE_TUNE, 0)
+            + header.pitch_correction as i32;
+
+        // Attenuation in centibels plus a quadratic velocity curve
+        let attenuation = value(INITIAL_ATTENUATION, 0).clamp(0, 1440) as f32;
+        let velocity_gain = (velocity as f32 / 127.0).powi(2);
+        let sustain_attenuation = value(SUSTAIN_VOL_ENV, 0).clamp(0, 1440) as f32;
+
+        Some(Region {
+            start: address(header.start, offset(START_OFFSET, START_COARSE_OFFSET)),
+            end: address(header.end, offset(END_OFFSET, END_COARSE_OFFSET)),
+            loop_start: address(header.loop_start, offset(LOOP_START_OFFSET, LOOP_START_COARSE_OFFSET)),
+            loop_end: address(header.loop_end, offset(LOOP_END_OFFSET, LOOP_END_COARSE_OFFSET)),
+            looping: matches!(zone.get(SAMPLE_MODES).unwrap_or(0) & 3, 1 | 3),
+            sample_rate: header.sample_rate.max(1),
+            pitch_cents: pitch_cents as f32,
+            gain: 10f32.powf(-attenuation / 200.0) * velocity_gain,
+            pan: (value(PAN, 0).clamp(-500, 500) as f32) / 500.0,
+            envelope: Envelope {
+                delay: timecents(value(DELAY_VOL_ENV, -12000)),
+                attack: timecents(value(ATTACK_VOL_ENV, -12000)),
+                hold: timecents(value(HOLD_VOL_ENV, -12000)),
+                decay: timecents(value(DECAY_VOL_ENV, -12000)),
+                sustain: 10f32.powf(-sustain_attenuation / 200.0),
+                release: timecents(value(RELEASE_VOL_ENV, -12000)),
+            },
+        })
+    }
+}
+
+fn timecents(value: i32) -> f32 {
+    2f32.powf(value.clamp(-12000, 8000) as f32 / 1200.0)
+}
+
+// Zones of a preset or instrument, with its global zone merged into each of the others
+fn read_zones(first_bag: u16, end_bag: u16, bags: &[&[u8]], gens: &[&[u8]], terminal: usize) -> Vec<Zone> {
+    let mut global: Generators = [None; GENERATOR_COUNT];
+    let mut zones = Vec::new();
+    for bag in first_bag as usize..(end_bag as usize).min(bags.len().saturating_sub(1)) {
+        let first_gen = u16_at(bags[bag], 0) as usize;
+        let end_gen = (u16_at(bags[bag + 1], 0) as usize).min(gens.len());
+        let mut generators: Generators = [None; GENERATOR_COUNT];
+        for record in gens.get(first_gen..end_gen).unwrap_or_default() {
+            let generator = u16_at(record, 0) as usize;
+            if generator < GENERATOR_COUNT {
+                generators[generator] = Some(i16::from_le_bytes([record[2], record[3]]));
+            }
+        }
+
+        if generators[terminal].is_none() {
+            // Only the first zone may be global
+            if zones.is_empty() {
+                global = generators;
+            }
+            continue;
+        }
+        for (generator, global) in generators.iter_mut().zip(global) {
+            if generator.is_none() {
+                *generator = global;
+            }
+        }
+        zones.push(Zone { generators });
+    }
+    zones
+}
+
+fn chunks(mut data: &[u8]) -> Vec<(&[u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    while data.len() >= 8 {
+        let id: &[u8; 4] = data[0..4].try_into().unwrap();
+        let len = (u32_at(data, 4) as usize).min(data.len() - 8);
+        chunks.push((id, &data[8..8 + len]));
+        // Chunks are padded to an even length
+        let next = (8 + len + len % 2).min(data.len());
+        data = &data[next..];
+    }
+    chunks
+}
+
+fn u16_at(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
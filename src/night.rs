@@ -0,0 +1,116 @@
+// Night mode turns itself on during the hours set in `night_hours`, which
+// are in local time
+
+use std::fmt;
+use std::process::Command;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// How often the UTC offset is read again, so daylight saving time is noticed
+const OFFSET_REFRESH: Duration = Duration::from_secs(3600);
+
+/// A span of the day in minutes after midnight, e.g. `22:00-07:00`. It may
+/// run past midnight.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NightHours {
+    start: u32,
+    end: u32,
+}
+
+impl NightHours {
+    fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl FromStr for NightHours {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (start, end) = value.split_once('-').ok_or("expected a span such as 22:00-07:00")?;
+        Ok(NightHours { start: parse_clock(start)?, end: parse_clock(end)? })
+    }
+}
+
+impl fmt::Display for NightHours {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{:02}-{}:{:02}", self.start / 60, self.start % 60, self.end / 60, self.end % 60)
+    }
+}
+
+// `7`, `7:30` or `22:00`
+fn parse_clock(value: &str) -> Result<u32, String> {
+    let value = value.trim();
+    let (hours, minutes) = value.split_once(':').unwrap_or((value, "0"));
+    match (hours.parse::<u32>(), minutes.parse::<u32>()) {
+        (Ok(hours), Ok(minutes)) if hours < 24 && minutes < 60 => Ok(hours * 60 + minutes),
+        _ => Err(format!("not a time of day: {}", value)),
+    }
+}
+
+pub struct NightSchedule {
+    hours: Option<NightHours>,
+    // Seconds ahead of UTC, and when they were read
+    offset: Option<(i64, Instant)>,
+    // Whether it was night at the last poll
+    night: Option<bool>,
+}
+
+impl NightSchedule {
+    pub fn new(hours: Option<NightHours>) -> NightSchedule {
+        NightSchedule { hours, offset: None, night: None }
+    }
+
+    /// Whether night has started or ended since the last poll. The first poll
+    /// reports it as changed when it's night.
+    pub fn poll(&mut self) -> Option<bool> {
+        let hours = self.hours?;
+        let night = hours.contains(self.local_minute());
+        let changed = self.night.map_or(night, |was| was != night);
+        self.night = Some(night);
+        changed.then_some(night)
+    }
+
+    fn local_minute(&mut self) -> u32 {
+        if self.offset.is_none_or(|(_, read)| read.elapsed() >= OFFSET_REFRESH) {
+            let offset = utc_offset().unwrap_or_else(|| {
+                log::warn!(target: "playback", "could not read the time zone, night hours are in UTC");
+                0
+            });
+            self.offset = Some((offset, Instant::now()));
+        }
+        let offset = self.offset.map_or(0, |(offset, _)| offset);
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs() as i64)
+            .unwrap_or_default();
+        ((seconds + offset).rem_euclid(86400) / 60) as u32
+    }
+}
+
+// The system's offset from UTC in seconds, from `+0330` or `-05:00`
+fn utc_offset() -> Option<i64> {
+    #[cfg(windows)]
+    let output = Command::new("powershell")
+        .args(["-NoProfile", "-Command", "(Get-Date).ToString('zzz')"])
+        .output()
+        .ok()?;
+    #[cfg(not(windows))]
+    let output = Command::new("date").arg("+%z").output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout).trim().replace(':', "");
+    let (sign, digits) = match text.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 {
+        return None;
+    }
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
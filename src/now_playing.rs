@@ -0,0 +1,151 @@
+// What is playing, published for status bars through files next to the
+// config and through `music-cli status`
+
+use crate::config::config_dir;
+use crate::player::MusicPlayer;
+use crate::ui;
+use crate::utils;
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+#[derive(Clone, Default)]
+pub struct NowPlaying {
+    // playing, paused or stopped
+    pub state: &'static str,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub file: String,
+    // Position in the playlist, counted from 1
+    pub number: Option<usize>,
+    pub elapsed: Duration,
+    pub duration: Option<Duration>,
+    pub volume: u32,
+    // Title of the track coming up, empty when playback stops after this one
+    pub next: String,
+    // Length of the playlist and the time left until it ends
+    pub queue_total: Duration,
+    pub queue_left: Duration,
+}
+
+impl NowPlaying {
+    pub fn of(music_player: &MusicPlayer) -> NowPlaying {
+        let volume = (music_player.volume * 100.0).round() as u32;
+        let next = music_player.up_next().map(|next| music_player.track_title(next)).unwrap_or_default();
+        let (queue_total, queue_left) = music_player.queue_time();
+        let (Some(current), Some(elapsed)) = (music_player.current_track, music_player.position()) else {
+            return NowPlaying { state: "stopped", volume, queue_total, queue_left, ..Default::default() };
+        };
+        let record = music_player.track_record(current);
+        NowPlaying {
+            state: if music_player.is_playing() { "playing" } else { "paused" },
+            title: music_player.track_title(current),
+            artist: record.and_then(|record| record.artist.clone()).unwrap_or_default(),
+            album: record.and_then(|record| record.album.clone()).unwrap_or_default(),
+            file: music_player.tracks[current].to_string_lossy().into_owned(),
+            number: Some(current + 1),
+            elapsed,
+            duration: music_player.duration(),
+            volume,
+            next,
+            queue_total,
+            queue_left,
+        }
+    }
+
+    // Whether the files need writing again, which the clock alone doesn't cause
+    pub fn differs_from(&self, other: &NowPlaying) -> bool {
+        (self.state, &self.file, &self.title, &self.artist, &self.album, &self.next)
+            != (other.state, &other.file, &other.title, &other.artist, &other.album, &other.next)
+    }
+
+    /// `Artist - Title`, or just the title, empty when stopped.
+    pub fn text(&self) -> String {
+        match (self.state, self.artist.is_empty()) {
+            ("stopped", _) => String::new(),
+            (_, true) => self.title.clone(),
+            (_, false) => format!("{} - {}", self.artist, self.title),
+        }
+    }
+
+    /// Fills a template with the same fields as `status_format`.
+    pub fn format(&self, template: &str) -> String {
+        utils::fill_template(template, |field| {
+            Some(match field {
+                "title" => self.title.clone(),
+                "artist" => self.artist.clone(),
+                "album" => self.album.clone(),
+                "file" => self.file.rsplit(['/', '\\']).next().unwrap_or_default().to_string(),
+                "number" => self.number.map(|number| number.to_string()).unwrap_or_default(),
+                "elapsed" => clock(self.elapsed),
+                "total" => self.duration.map(clock).unwrap_or_default(),
+                "volume" => self.volume.to_string(),
+                "next" => self.next.clone(),
+                "queue_total" => ui::format_duration(self.queue_total),
+                "queue_left" => ui::format_duration(self.queue_left),
+                "state" => match self.state {
+                    "playing" => "▶",
+                    "paused" => "⏸",
+                    _ => "■",
+                }
+                .to_string(),
+                _ => return None,
+            })
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        let number = self.number.map_or("null".to_string(), |number| number.to_string());
+        let duration = self
+            .duration
+            .map_or("null".to_string(), |duration| format!("{:.3}", duration.as_secs_f64()));
+        format!(
+            "{{\"state\":{},\"title\":{},\"artist\":{},\"album\":{},\"file\":{},\"number\":{},\"elapsed\":{:.3},\"duration\":{},\"volume\":{},\"next\":{},\"queue_total\":{:.3},\"queue_left\":{:.3}}}",
+            json_string(self.state),
+            json_string(&self.title),
+            json_string(&self.artist),
+            json_string(&self.album),
+            json_string(&self.file),
+            number,
+            self.elapsed.as_secs_f64(),
+            duration,
+            self.volume,
+            json_string(&self.next),
+            self.queue_total.as_secs_f64(),
+            self.queue_left.as_secs_f64(),
+        )
+    }
+
+    /// Writes now_playing.txt and now_playing.json next to the config, for
+    /// status bars that watch files.
+    pub fn write_files(&self) -> io::Result<()> {
+        let dir = config_dir();
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("now_playing.txt"), self.text() + "\n")?;
+        fs::write(dir.join("now_playing.json"), self.to_json() + "\n")
+    }
+}
+
+fn clock(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{:02}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Writes text as a JSON string, quotes included.
+pub fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
@@ -0,0 +1,646 @@
+use super::recorder::RecorderMessage;
+use super::resample::{Resampler, Resampling};
+use rodio::Source;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// How many frames are processed before the shared settings are re-read
+const SETTINGS_REFRESH_FRAMES: usize = 512;
+// Peak level below which a frame counts as silent (about -50 dBFS)
+pub const SILENCE_THRESHOLD: f32 = 0.003;
+// Window of the pitch shifter's delay line, in frames
+const PITCH_WINDOW: usize = 2048;
+pub const MAX_PITCH_SEMITONES: i32 = 12;
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 2.0;
+// Crossfeed mixes each channel's low frequencies into the other, as heard
+// from speakers. At full strength the crossfed sound is 4.5 dB below the
+// direct one, Bauer's default.
+const CROSSFEED_CUTOFF: f32 = 700.0;
+const CROSSFEED_MAX_GAIN: f32 = 0.6;
+// The limiter after the compressor keeps peaks below this (-1 dBFS)
+const LIMITER_CEILING: f32 = 0.89;
+
+/// The compressor stage's settings. Levels above the threshold rise `ratio`
+/// times slower, and the makeup gain brings quiet passages up. It reacts to
+/// peaks at once and lets go over the release time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Compression {
+    pub threshold_db: f32,
+    pub ratio: f32,
+    pub makeup_db: f32,
+    // In seconds
+    pub release: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompressorPreset {
+    Off,
+    // Evens out speakers and brings quiet voices up
+    Podcast,
+    // Holds back loud passages and sudden peaks
+    Night,
+}
+
+impl CompressorPreset {
+    pub fn compression(self) -> Option<Compression> {
+        match self {
+            CompressorPreset::Off => None,
+            CompressorPreset::Podcast => Some(Compression { threshold_db: -30.0, ratio: 4.0, makeup_db: 10.0, release: 0.15 }),
+            CompressorPreset::Night => Some(Compression { threshold_db: -24.0, ratio: 3.0, makeup_db: 6.0, release: 0.25 }),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CompressorPreset::Off => "off",
+            CompressorPreset::Podcast => "podcast",
+            CompressorPreset::Night => "night",
+        }
+    }
+
+    pub fn next(self) -> CompressorPreset {
+        match self {
+            CompressorPreset::Off => CompressorPreset::Podcast,
+            CompressorPreset::Podcast => CompressorPreset::Night,
+            CompressorPreset::Night => CompressorPreset::Off,
+        }
+    }
+}
+
+impl FromStr for CompressorPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(CompressorPreset::Off),
+            "podcast" => Ok(CompressorPreset::Podcast),
+            "night" => Ok(CompressorPreset::Night),
+            _ => Err(format!("unknown compressor preset '{}'", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DspSettings {
+    // -1.0 is fully left, 1.0 is fully right
+    pub balance: f32,
+    pub swap_channels: bool,
+    pub mono: bool,
+    pub skip_silence: bool,
+    // Silences are played up to this length, anything longer is skipped
+    pub silence_skip_after: Duration,
+    pub pitch_semitones: i32,
+    // Playback speed, the pitch stays as it is
+    pub speed: f32,
+    pub compression: Option<Compression>,
+    pub crossfeed: bool,
+    // From 0 to 1
+    pub crossfeed_strength: f32,
+    pub resampler: Resampling,
+    pub band_limit: bool,
+}
+
+impl Default for DspSettings {
+    fn default() -> Self {
+        DspSettings {
+            balance: 0.0,
+            swap_channels: false,
+            mono: false,
+            skip_silence: false,
+            silence_skip_after: Duration::from_secs(2),
+            pitch_semitones: 0,
+            speed: 1.0,
+            compression: None,
+            crossfeed: false,
+            crossfeed_strength: 0.75,
+            resampler: Resampling::Sinc,
+            band_limit: true,
+        }
+    }
+}
+
+impl DspSettings {
+    // True when the chain passes samples through unchanged
+    pub fn is_neutral(&self) -> bool {
+        self.balance == 0.0
+            && !self.swap_channels
+            && !self.mono
+            && !self.skip_silence
+            && self.pitch_semitones == 0
+            && self.speed == 1.0
+            && self.compression.is_none()
+            && !self.crossfeed
+    }
+}
+
+#[derive(Default)]
+pub struct DspShared {
+    pub settings: Mutex<DspSettings>,
+    // Receives the processed output while recording
+    pub record_tap: Mutex<Option<Sender<RecorderMessage>>>,
+    skipped_nanos: AtomicI64,
+    // Most the compressor turned the sound down lately, in dB as f32 bits
+    reduction: AtomicU32,
+}
+
+impl DspShared {
+    // Playback time removed by the DSP chain, e.g. skipped silence, in
+    // seconds. Negative when slowed down.
+    pub fn skipped(&self) -> f64 {
+        self.skipped_nanos.load(Ordering::Relaxed) as f64 / 1e9
+    }
+
+    pub fn reset_skipped(&self) {
+        self.skipped_nanos.store(0, Ordering::Relaxed);
+    }
+
+    pub fn gain_reduction(&self) -> f32 {
+        f32::from_bits(self.reduction.load(Ordering::Relaxed))
+    }
+}
+
+pub type SharedDsp = Arc<DspShared>;
+
+// Lets the player fade a source out after it was handed to a sink
+#[derive(Clone, Default)]
+pub struct FadeHandle(Arc<AtomicU64>);
+
+impl FadeHandle {
+    pub fn fade_out(&self, length: Duration) {
+        self.0.store((length.as_millis() as u64).max(1), Ordering::Relaxed);
+    }
+
+    fn fade_out_millis(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Lets the player change a source's gain while it plays, in dB as f32 bits
+#[derive(Clone, Default)]
+pub struct GainHandle(Arc<AtomicU32>);
+
+impl GainHandle {
+    pub fn set_db(&self, gain_db: f32) {
+        self.0.store(gain_db.to_bits(), Ordering::Relaxed);
+    }
+
+    fn gain(&self) -> f32 {
+        10f32.powf(f32::from_bits(self.0.load(Ordering::Relaxed)) / 20.0)
+    }
+}
+
+/// Wraps a decoded source and runs every frame through the DSP chain.
+pub struct DspSource<S> {
+    input: S,
+    shared: SharedDsp,
+    settings: DspSettings,
+    frame: Vec<f32>,
+    pos: usize,
+    frames_until_refresh: usize,
+    silent_frames: u64,
+    gain: f32,
+    // Adjustable part of the gain, re-read with the settings
+    adjustment: GainHandle,
+    adjustment_gain: f32,
+    fade: FadeHandle,
+    // Total and remaining frames of a running fade-out
+    fade_out: Option<(u64, u64)>,
+    pitch: Option<PitchShifter>,
+    // Low frequencies of the left and right channel, for crossfeed
+    crossfeed_lowpass: [f32; 2],
+    // Level the compressor follows, and the most it reduced the gain since
+    // the settings were last re-read
+    envelope: f32,
+    reduction: f32,
+    // Converts to the device's rate, and changes speed
+    resampler: Option<Resampler>,
+    output_rate: Option<u32>,
+    // Time gained or lost by the speed change, not yet added to the total
+    speed_nanos: f64,
+    record_tap: Option<Sender<RecorderMessage>>,
+    record_buffer: Vec<f32>,
+    // Channels and sample rate of the last frame read
+    format: (u16, u32),
+}
+
+impl<S: Source<Item = f32>> DspSource<S> {
+    pub fn new(input: S, shared: SharedDsp) -> Self {
+        let settings = shared.settings.lock().unwrap().clone();
+        let record_tap = shared.record_tap.lock().unwrap().clone();
+        DspSource {
+            input,
+            shared,
+            settings,
+            frame: Vec::with_capacity(2),
+            pos: 0,
+            frames_until_refresh: SETTINGS_REFRESH_FRAMES,
+            silent_frames: 0,
+            gain: 1.0,
+            adjustment: GainHandle::default(),
+            adjustment_gain: 1.0,
+            fade: FadeHandle::default(),
+            fade_out: None,
+            pitch: None,
+            crossfeed_lowpass: [0.0; 2],
+            envelope: 0.0,
+            reduction: 0.0,
+            resampler: None,
+            output_rate: None,
+            speed_nanos: 0.0,
+            record_tap,
+            record_buffer: Vec::new(),
+            format: (0, 0),
+        }
+    }
+
+    // Fixed gain applied to this source only, in decibels
+    pub fn with_gain_db(mut self, gain_db: f32) -> Self {
+        self.gain = 10f32.powf(gain_db / 20.0);
+        self
+    }
+
+    pub fn with_gain_adjustment(mut self, adjustment: GainHandle) -> Self {
+        self.adjustment_gain = adjustment.gain();
+        self.adjustment = adjustment;
+        self
+    }
+
+    pub fn with_fade(mut self, fade: FadeHandle) -> Self {
+        self.fade = fade;
+        self
+    }
+
+    // Resamples to the rate the device runs at, when it isn't the track's
+    pub fn with_output_rate(mut self, rate: Option<u32>) -> Self {
+        self.output_rate = rate;
+        self
+    }
+
+    fn output_rate(&self) -> u32 {
+        self.output_rate.unwrap_or(self.input.sample_rate())
+    }
+
+    fn fill_frame(&mut self) -> bool {
+        let speed = self.settings.speed.clamp(MIN_SPEED, MAX_SPEED);
+        let filled = if speed == 1.0 && self.output_rate() == self.input.sample_rate() {
+            self.resampler = None;
+            self.next_input_frame()
+        } else {
+            self.resample(speed as f64)
+        };
+        if !filled {
+            return false;
+        }
+
+        if !self.apply_fade() {
+            return false;
+        }
+        self.process_pitch();
+        self.process_frame();
+        self.compress();
+
+        // A track fading out is no longer recorded, the incoming one is
+        if self.record_tap.is_some() && self.fade_out.is_none() {
+            self.record_buffer.extend_from_slice(&self.frame);
+        }
+        true
+    }
+
+
+    // Fade-out stage, returns false once the source has faded to silence
+    fn apply_fade(&mut self) -> bool {
+        if self.fade_out.is_none() {
+            let millis = self.fade.fade_out_millis();
+            if millis > 0 {
+                let frames = (millis * self.output_rate() as u64 / 1000).max(1);
+                self.fade_out = Some((frames, frames));
+            }
+        }
+
+        let mut gain = self.gain * self.adjustment_gain;
+        if let Some((total, remaining)) = self.fade_out.as_mut() {
+            if *remaining == 0 {
+                return false;
+            }
+            gain *= *remaining as f32 / *total as f32;
+            *remaining -= 1;
+        }
+
+        if gain != 1.0 {
+            for sample in self.frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+        true
+    }
+
+    // Reads the next frame that isn't skipped as silence
+    fn next_input_frame(&mut self) -> bool {
+        loop {
+            if !self.read_frame() {
+                return false;
+            }
+
+            self.frames_until_refresh -= 1;
+            if self.frames_until_refresh == 0 {
+                if let Ok(settings) = self.shared.settings.try_lock() {
+                    self.settings = settings.clone();
+                }
+                self.adjustment_gain = self.adjustment.gain();
+                self.flush_recording();
+                if let Ok(tap) = self.shared.record_tap.try_lock() {
+                    self.record_tap = tap.clone();
+                }
+                self.shared.reduction.store(self.reduction.to_bits(), Ordering::Relaxed);
+                self.reduction = 0.0;
+                let nanos = self.speed_nanos.trunc();
+                self.shared.skipped_nanos.fetch_add(nanos as i64, Ordering::Relaxed);
+                self.speed_nanos -= nanos;
+                self.frames_until_refresh = SETTINGS_REFRESH_FRAMES;
+            }
+
+            if !self.skip_silent_frame() {
+                return true;
+            }
+        }
+    }
+
+    // Speed and rate stage: steps through the input `speed` frames at a
+    // time, scaled to the output rate, interpolating between them. Changing
+    // speed raises or lowers the pitch too, which the pitch stage undoes.
+    fn resample(&mut self, speed: f64) -> bool {
+        let quality = self.settings.resampler;
+        while self.resampler.as_ref().is_none_or(Resampler::needs_input) {
+            if !self.next_input_frame() {
+                return false;
+            }
+            let channels = self.frame.len();
+            let resampler = self.resampler.get_or_insert_with(|| Resampler::new(quality, channels));
+            if resampler.channels() != channels || resampler.quality() != quality {
+                *resampler = Resampler::new(quality, channels);
+            }
+            resampler.push(&self.frame);
+        }
+
+        let output_rate = self.output_rate().max(1) as f64;
+        let step = speed * self.input.sample_rate() as f64 / output_rate;
+        if let Some(resampler) = &mut self.resampler {
+            resampler.output(&mut self.frame, step, self.settings.band_limit);
+        }
+        // Slowed down, a frame can come without reading any input
+        self.pos = 0;
+        self.speed_nanos += (speed - 1.0) * 1e9 / output_rate;
+        true
+    }
+
+    fn read_frame(&mut self) -> bool {
+        let channels = self.input.channels().max(1) as usize;
+        let format = (self.input.channels(), self.output_rate());
+        if format != self.format {
+            // Recorded chunks must not mix formats
+            self.flush_recording();
+            self.format = format;
+        }
+        self.frame.clear();
+        self.pos = 0;
+        for _ in 0..channels {
+            match self.input.next() {
+                Some(sample) => self.frame.push(sample),
+                None => break,
+            }
+        }
+        !self.frame.is_empty()
+    }
+
+    // Silence skipping stage, returns true when the frame should be dropped
+    fn skip_silent_frame(&mut self) -> bool {
+        let peak = self.frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        if peak >= SILENCE_THRESHOLD {
+            self.silent_frames = 0;
+            return false;
+        }
+
+        self.silent_frames += 1;
+        if !self.settings.skip_silence {
+            return false;
+        }
+
+        let rate = self.input.sample_rate().max(1) as u64;
+        let keep = (self.settings.silence_skip_after.as_secs_f64() * rate as f64) as u64;
+        if self.silent_frames <= keep {
+            return false;
+        }
+
+        self.shared
+            .skipped_nanos
+            .fetch_add(1_000_000_000 / rate as i64, Ordering::Relaxed);
+        true
+    }
+
+    fn process_frame(&mut self) {
+        if self.frame.len() != 2 {
+            return;
+        }
+
+        // Channel gain stage
+        if self.settings.swap_channels {
+            self.frame.swap(0, 1);
+        }
+        if self.settings.mono {
+            let mixed = (self.frame[0] + self.frame[1]) * 0.5;
+            self.frame[0] = mixed;
+            self.frame[1] = mixed;
+        }
+        self.crossfeed();
+        let (left, right) = balance_gains(self.settings.balance);
+        self.frame[0] *= left;
+        self.frame[1] *= right;
+    }
+
+    // Crossfeed stage: a low-passed copy of each channel goes to the other,
+    // so hard-panned recordings tire the ears less on headphones
+    fn crossfeed(&mut self) {
+        if !self.settings.crossfeed {
+            self.crossfeed_lowpass = [0.0; 2];
+            return;
+        }
+        let rate = self.output_rate().max(1) as f32;
+        let coefficient = 1.0 - (-2.0 * std::f32::consts::PI * CROSSFEED_CUTOFF / rate).exp();
+        for (lowpass, sample) in self.crossfeed_lowpass.iter_mut().zip(&self.frame) {
+            *lowpass += coefficient * (sample - *lowpass);
+        }
+        let gain = CROSSFEED_MAX_GAIN * self.settings.crossfeed_strength.clamp(0.0, 1.0);
+        let [left, right] = self.crossfeed_lowpass;
+        // Scaled down so centred sound doesn't get louder
+        self.frame[0] = (self.frame[0] + gain * right) / (1.0 + gain);
+        self.frame[1] = (self.frame[1] + gain * left) / (1.0 + gain);
+    }
+
+    // Compressor and limiter stage
+    fn compress(&mut self) {
+        let Some(compression) = self.settings.compression else {
+            self.envelope = 0.0;
+            return;
+        };
+        let peak = self.frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        if peak >= self.envelope {
+            self.envelope = peak;
+        } else {
+            let release = compression.release.max(0.001) * self.output_rate().max(1) as f32;
+            self.envelope = peak + (-1.0 / release).exp() * (self.envelope - peak);
+        }
+
+        let level_db = 20.0 * self.envelope.max(1e-6).log10();
+        let over = (level_db - compression.threshold_db).max(0.0);
+        let reduction_db = over * (1.0 - 1.0 / compression.ratio.max(1.0));
+        let mut gain = 10f32.powf((compression.makeup_db - reduction_db) / 20.0);
+        // What the makeup gain pushes past the ceiling is limited
+        if peak * gain > LIMITER_CEILING {
+            gain = LIMITER_CEILING / peak;
+        }
+        self.reduction = self.reduction.max(reduction_db.max(-20.0 * gain.log10()));
+        for sample in self.frame.iter_mut() {
+            *sample *= gain;
+        }
+    }
+
+    fn process_pitch(&mut self) {
+        let semitones = self.settings.pitch_semitones.clamp(-MAX_PITCH_SEMITONES, MAX_PITCH_SEMITONES);
+        let speed = self.settings.speed.clamp(MIN_SPEED, MAX_SPEED);
+        if semitones == 0 && speed == 1.0 {
+            self.pitch = None;
+            return;
+        }
+        let ratio = 2f32.powf(semitones as f32 / 12.0) / speed;
+        let channels = self.frame.len();
+        self.pitch
+            .get_or_insert_with(|| PitchShifter::new(channels))
+            .process(&mut self.frame, ratio);
+    }
+}
+
+// Delay-line pitch shifter: two read taps sweep through a short window at the
+// pitch ratio and are crossfaded so the jumps at the window edges stay inaudible
+struct PitchShifter {
+    // Interleaved frames
+    buffer: Vec<f32>,
+    channels: usize,
+    write: usize,
+    delay: f32,
+}
+
+impl PitchShifter {
+    fn new(channels: usize) -> Self {
+        PitchShifter {
+            buffer: vec![0.0; PITCH_WINDOW * channels],
+            channels,
+            write: 0,
+            delay: 0.0,
+        }
+    }
+
+    fn process(&mut self, frame: &mut [f32], ratio: f32) {
+        if frame.len() != self.channels {
+            *self = PitchShifter::new(frame.len());
+        }
+
+        let start = self.write * self.channels;
+        self.buffer[start..start + self.channels].copy_from_slice(frame);
+
+        let window = PITCH_WINDOW as f32;
+        let half = window / 2.0;
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            let mut out = 0.0;
+            for tap in 0..2 {
+                let delay = (self.delay + tap as f32 * half) % window;
+                let gain = 1.0 - (delay - half).abs() / half;
+                out += gain * self.read(channel, delay);
+            }
+            *sample = out;
+        }
+
+        self.delay = (self.delay + 1.0 - ratio).rem_euclid(window);
+        self.write = (self.write + 1) % PITCH_WINDOW;
+    }
+
+    fn read(&self, channel: usize, delay: f32) -> f32 {
+        let pos = (self.write as f32 - delay).rem_euclid(PITCH_WINDOW as f32);
+        let i = pos as usize % PITCH_WINDOW;
+        let j = (i + 1) % PITCH_WINDOW;
+        let frac = pos.fract();
+        self.buffer[i * self.channels + channel] * (1.0 - frac)
+            + self.buffer[j * self.channels + channel] * frac
+    }
+}
+
+fn balance_gains(balance: f32) -> (f32, f32) {
+    let balance = balance.clamp(-1.0, 1.0);
+    if balance > 0.0 {
+        (1.0 - balance, 1.0)
+    } else {
+        (1.0, 1.0 + balance)
+    }
+}
+
+impl<S> DspSource<S> {
+    fn flush_recording(&mut self) {
+        if self.record_buffer.is_empty() {
+            return;
+        }
+        let samples = std::mem::take(&mut self.record_buffer);
+        if let Some(tap) = &self.record_tap {
+            let _ = tap.send(RecorderMessage::Samples {
+                channels: self.format.0,
+                sample_rate: self.format.1,
+                samples,
+            });
+        }
+    }
+}
+
+impl<S> Drop for DspSource<S> {
+    fn drop(&mut self) {
+        self.shared.reduction.store(0, Ordering::Relaxed);
+        self.flush_recording();
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for DspSource<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.pos >= self.frame.len() && !self.fill_frame() {
+            return None;
+        }
+        let sample = self.frame[self.pos];
+        self.pos += 1;
+        Some(sample)
+    }
+}
+
+impl<S: Source<Item = f32>> Source for DspSource<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        // Resampled frames don't line up with the input's
+        if self.output_rate() != self.input.sample_rate() {
+            return None;
+        }
+        let buffered = self.frame.len() - self.pos;
+        self.input.current_frame_len().map(|len| len + buffered)
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.output_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
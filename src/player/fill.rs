@@ -0,0 +1,82 @@
+// Endless play: when the playlist runs out, a track from the library is added
+// and played instead of stopping or starting over
+
+use super::{MusicPlayer, QueueEnd, Repeat};
+use crate::library::TrackRecord;
+use crate::tags;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+impl MusicPlayer {
+    pub fn auto_fills(&self) -> bool {
+        self.auto_fill
+    }
+
+    pub fn toggle_auto_fill(&mut self) {
+        self.auto_fill = !self.auto_fill;
+    }
+
+    /// The track to go on with instead of wrapping around or stopping at the
+    /// end of the playlist, added to it first when it comes from the library.
+    pub(super) fn fill_queue(&mut self) -> Option<usize> {
+        let filling = self.auto_fill || (self.queue_end == QueueEnd::Fill && self.repeat == Repeat::All);
+        if !filling || self.repeat == Repeat::One {
+            return None;
+        }
+        let at_end = self.following().is_none_or(|next| self.wraps_to(next));
+        if !at_end {
+            return None;
+        }
+        let Some(path) = self.pick_fill() else {
+            log::info!(target: "playback", "nothing left in the library to add");
+            return None;
+        };
+        log::info!(target: "playback", "adding {} to keep playing", path.display());
+        self.tracks.push(path);
+        let index = self.tracks.len() - 1;
+        // The order ends here, so the added track goes last even in album shuffle
+        if let Some(order) = &mut self.order {
+            order.push(index);
+        }
+        Some(index)
+    }
+
+    // A library track not in the playlist, by the current track's artist or
+    // in its genre when there is one, any track otherwise
+    fn pick_fill(&self) -> Option<PathBuf> {
+        let listed: HashSet<&PathBuf> = self.tracks.iter().collect();
+        let current = self.current_track.and_then(|index| self.library.get(&self.tracks[index]));
+        // Only files whose tags were read, which leaves out streams
+        let candidates: Vec<(&PathBuf, &TrackRecord)> = self
+            .library
+            .records()
+            .filter(|(path, record)| !listed.contains(path) && record.modified.is_some() && !self.library.never_play(path))
+            .collect();
+        let similar: Vec<&PathBuf> = candidates
+            .iter()
+            .filter(|(_, record)| current.is_some_and(|current| similar(current, record)))
+            .map(|(path, _)| *path)
+            .collect();
+        let others = candidates.iter().map(|(path, _)| *path).collect();
+        [similar, others].into_iter().find_map(pick_existing)
+    }
+}
+
+fn similar(a: &TrackRecord, b: &TrackRecord) -> bool {
+    let same = |a: &Option<String>, b: &Option<String>| match (a, b) {
+        (Some(a), Some(b)) => a.trim().eq_ignore_ascii_case(b.trim()),
+        _ => false,
+    };
+    same(&a.artist, &b.artist) || same(&a.genre, &b.genre)
+}
+
+// Draws tracks at random until one whose file is still there turns up
+fn pick_existing(mut paths: Vec<&PathBuf>) -> Option<PathBuf> {
+    while !paths.is_empty() {
+        let path = paths.swap_remove(super::order::random_below(paths.len()));
+        if tags::modified(path).is_some() {
+            return Some(path.clone());
+        }
+    }
+    None
+}
@@ -0,0 +1,83 @@
+use super::MusicPlayer;
+use std::error::Error;
+use std::time::Duration;
+
+// Transition used by auto-mix when the tempos don't match
+const AUTO_MIX_FADE: Duration = Duration::from_secs(8);
+// Tempo-matched transitions last this many beats of the outgoing track
+const AUTO_MIX_BEATS: f32 = 16.0;
+// Largest relative tempo difference that still counts as compatible
+const BPM_TOLERANCE: f32 = 0.06;
+// Loudness matching pulls tracks halfway towards this level, in dBFS
+const AUTO_MIX_TARGET_LOUDNESS: f32 = -16.0;
+const AUTO_MIX_MAX_GAIN_DB: f32 = 4.0;
+
+impl MusicPlayer {
+    pub fn toggle_auto_mix(&mut self) {
+        self.auto_mix = !self.auto_mix;
+    }
+
+    pub fn set_crossfade(&mut self, length: Duration) {
+        self.crossfade = length;
+    }
+
+    // Length of the crossfade into the given track, if there should be one
+    fn transition_length(&self, next: usize) -> Option<Duration> {
+        if !self.auto_mix {
+            return (!self.crossfade.is_zero()).then_some(self.crossfade);
+        }
+
+        let current = self.current_track?;
+        match (self.bpm(current), self.bpm(next)) {
+            (Some(from), Some(to)) if tempos_compatible(from, to) => {
+                Some(Duration::from_secs_f32(AUTO_MIX_BEATS * 60.0 / from))
+            }
+            _ if self.crossfade.is_zero() => Some(AUTO_MIX_FADE),
+            _ => Some(self.crossfade),
+        }
+    }
+
+    // Starts the next track under the current one once it is close enough to its end
+    pub(super) fn check_transition(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.fading.is_some() {
+            return Ok(());
+        }
+        let (Some(next), Some(elapsed), Some(end)) = (self.following(), self.elapsed(), self.end) else {
+            return Ok(());
+        };
+        let Some(length) = self.transition_length(next) else {
+            return Ok(());
+        };
+
+        // Short tracks get shorter transitions instead of fading out right away
+        let length = length.min(end / 3);
+        if elapsed + length < end {
+            return Ok(());
+        }
+
+        self.note_wrap(next);
+        self.fade.fade_out(length);
+        self.remember_position();
+        self.fading = self.sink.take();
+        self.start_track(next, Some(length))
+    }
+
+    // Gain for the track, in decibels, applied on top of the volume
+    pub(super) fn mix_gain_db(&self, index: usize) -> f32 {
+        if !self.auto_mix {
+            return 0.0;
+        }
+        match self.library.get(&self.tracks[index]).and_then(|record| record.loudness) {
+            Some(loudness) => ((AUTO_MIX_TARGET_LOUDNESS - loudness) * 0.5)
+                .clamp(-AUTO_MIX_MAX_GAIN_DB, AUTO_MIX_MAX_GAIN_DB),
+            None => 0.0,
+        }
+    }
+}
+
+// Half and double time mix just as well as equal tempos
+fn tempos_compatible(from: f32, to: f32) -> bool {
+    [to, to * 2.0, to / 2.0]
+        .iter()
+        .any(|to| ((from - to) / from).abs() <= BPM_TOLERANCE)
+}
@@ -1,45 +1,367 @@
-use rodio::{Decoder, OutputStream, Sink};
-use std::time::{Duration, Instant};
-use std::{error::Error, fs::File, io::BufReader, path::PathBuf};
+pub mod dsp;
+mod fill;
+mod mix;
+mod order;
+pub mod output;
+mod recorder;
+pub mod resample;
+mod zones;
+
+use crate::cast::{CastCommand, CastSession, Renderer};
+use crate::chapters::{self, Chapter};
+use crate::config::Config;
+use crate::decode::{self, BoxedSource, SymphoniaSource};
+use crate::library::{self, Library, TrackRecord};
+use crate::midi::{self, MidiSource, SoundFont};
+use crate::remote;
+use crate::replaygain::ReplayGainMode;
+use crate::tags::TagReader;
+use crate::tasks::{Measure, Reporter, Tasks};
+use crate::utils::audio::{self, Analysis};
+use dsp::{Compression, CompressorPreset, DspSettings, DspSource, FadeHandle, GainHandle, SharedDsp};
+pub use order::{QueueEnd, Repeat, Shuffle};
+use recorder::Recorder;
+use zones::{Zone, ZoneTargets, ZoneTee};
+use rodio::{Sink, Source};
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{error::Error, path::{Path, PathBuf}};
+use symphonia::core::codecs::CODEC_TYPE_NULL;
 use symphonia::core::probe::Hint;
 use symphonia::core::formats::FormatOptions;
 use symphonia::core::io::MediaSourceStream;
 use symphonia::core::meta::MetadataOptions;
 
+// How often the library is saved while many tags are being read
+const LIBRARY_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+// A long track left less than this before its end doesn't resume
+const RESUME_END_MARGIN: Duration = Duration::from_secs(30);
+// Share of the volume kept while a track is announced
+const DUCKED_VOLUME: f32 = 0.3;
+// Steps and limit of the gain set by hand for a track, in dB
+const TRACK_GAIN_STEP: f32 = 1.0;
+const MAX_TRACK_GAIN: f32 = 12.0;
+// Seconds a fade for other audio on the system takes to turn the music down,
+// and to bring it back
+const DUCK_FADE_DOWN: f32 = 0.3;
+const DUCK_FADE_UP: f32 = 2.0;
+
+/// Technical details of a track as stored in its container.
+#[derive(Clone, Debug)]
+pub struct StreamInfo {
+    pub codec: String,
+    pub sample_rate: Option<u32>,
+    pub bits_per_sample: Option<u32>,
+    pub channels: Option<u16>,
+    // Average over the whole file, including tags and cover art
+    pub bitrate_kbps: Option<u32>,
+    pub duration: Option<Duration>,
+}
+
 pub struct MusicPlayer {
     pub tracks: Vec<PathBuf>,
     pub current_track: Option<usize>,
     sink: Option<Sink>,
-    stream_handle: Option<rodio::OutputStreamHandle>,
-    _stream: Option<OutputStream>,
+    fade: FadeHandle,
+    // The current track's own gain, changed while it plays
+    track_gain: GainHandle,
+    // Previous track while it fades out under the current one
+    fading: Option<Sink>,
+    output: Option<output::Output>,
+    output_buffer: output::OutputBuffer,
     pub volume: f32,
+    // Change in volume for each step up or down
+    volume_step: f32,
+    // Turned down while something is spoken over the music
+    ducked: bool,
+    // Share of the volume left while other programs play sound, and where
+    // the fade towards it has got to as of when
+    duck_target: f32,
+    duck_level: f32,
+    duck_faded: Instant,
+    // Night mode caps the volume, and compresses with its preset instead of
+    // the chosen one
+    night: bool,
+    night_volume: f32,
+    compressor: CompressorPreset,
+    // Thresholds from clap.conf that replace the preset's
+    compressor_threshold: Option<f32>,
+    compressor_ratio: Option<f32>,
     start_time: Option<Instant>,
     duration: Option<Duration>,
+    stream_info: Option<StreamInfo>,
     paused_duration: Option<Duration>,
+    // Position in the track where the current source started
+    start_offset: Duration,
+    // Where playback of the current track stops, if known
+    end: Option<Duration>,
+    dsp: SharedDsp,
+    pub library: Library,
+    trim_silence: bool,
+    analysis_jobs: Sender<PathBuf>,
+    analysis_results: Receiver<(PathBuf, Option<Analysis>)>,
+    analyzing: HashSet<PathBuf>,
+    // Analyses finished since the queue was last empty, out of those plus `analyzing`
+    analyzed: u64,
+    analysis_progress: Reporter,
+    // Stream details of starting tracks, read in the background
+    probe_jobs: Sender<(PathBuf, usize)>,
+    probe_results: Receiver<Probe>,
+    tags: TagReader,
+    // Read tags and plays not yet written to the library file
+    library_unsaved: bool,
+    library_saved: Instant,
+    crossfade: Duration,
+    // How far into a track Previous restarts it instead, zero to always go back
+    restart_threshold: Duration,
+    // Tracks at least this long start where they were left, zero for never
+    resume_after: Duration,
+    // How much smart shuffle favours rated and long-unplayed tracks
+    smart_weights: (f64, f64),
+    pub auto_mix: bool,
+    // Adds library tracks when the playlist runs out
+    auto_fill: bool,
+    queue_end: QueueEnd,
+    // Files shuffle passes over, and whether it does in this session
+    skip_shorter: Duration,
+    skip_patterns: Vec<String>,
+    auto_skip: bool,
+    recorder: Option<Recorder>,
+    record_dir: PathBuf,
+    replaygain: ReplayGainMode,
+    // Renderer the playback is mirrored to, the local output is muted meanwhile
+    cast: Option<CastSession>,
+    zones: Vec<Zone>,
+    // Zones fed by the current track and the format they are fed in
+    zone_targets: ZoneTargets,
+    zone_format: (u16, u32),
+    output_device: String,
+    // Reopens the output at each track's own format instead of resampling
+    bit_perfect: bool,
+    // Format the output was opened at for the current track, None when shared
+    output_format: Option<(u16, u32)>,
+    // Rate the output stream runs at, and the current track's before conversion
+    device_rate: Option<u32>,
+    source_rate: Option<u32>,
+    bits_per_sample: Option<u32>,
+    // Selected audio track of the current file and how many it has
+    audio_track: usize,
+    audio_tracks: usize,
+    // Chapters of the current track, read along with its stream details
+    chapters: Vec<Chapter>,
+    // Playback ran off the end of the playlist and wrapped to the start
+    queue_ended: bool,
+    repeat: Repeat,
+    shuffle: Shuffle,
+    // Shuffled play order, by index into `tracks`
+    order: Option<Vec<usize>>,
+    // Upcoming tracks passed over until the next track starts
+    vetoed: HashSet<usize>,
+    // Tracks asked to play next, ahead of the play order
+    queued: Vec<usize>,
+    // Start and end of the part of the current track played over and over
+    ab_loop: (Option<Duration>, Option<Duration>),
+    soundfont_path: PathBuf,
+    // Loaded with the first MIDI track
+    soundfont: Option<Arc<SoundFont>>,
+}
+
+// Stream details read for a starting track
+struct Probe {
+    path: PathBuf,
+    audio_track: usize,
+    info: Option<StreamInfo>,
+    audio_tracks: usize,
+    chapters: Vec<Chapter>,
 }
 
 impl MusicPlayer {
-    pub fn new() -> Self {
+    pub fn new(tasks: &mut Tasks) -> Self {
+        // Tracks are analyzed one at a time on a background thread. Once
+        // canceled, the queued ones come back without an analysis.
+        let (analysis_jobs, jobs) = mpsc::channel::<PathBuf>();
+        let (results, analysis_results) = mpsc::channel();
+        let analysis_progress = tasks.start("Analyzing", Measure::Count);
+        let progress = analysis_progress.clone();
+        thread::spawn(move || {
+            for path in jobs {
+                let analysis = (!progress.checkpoint()).then(|| audio::analyze_track(&path));
+                if results.send((path, analysis)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        // Only the latest track matters when skipping through several
+        let (probe_jobs, jobs) = mpsc::channel::<(PathBuf, usize)>();
+        let (results, probe_results) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(mut job) = jobs.recv() {
+                while let Ok(newer) = jobs.try_recv() {
+                    job = newer;
+                }
+                let (path, audio_track) = job;
+                let probe = Probe {
+                    info: Self::probe_stream(&path, audio_track),
+                    audio_tracks: decode::audio_track_count(&path),
+                    chapters: chapters::read(&path),
+                    path,
+                    audio_track,
+                };
+                if results.send(probe).is_err() {
+                    break;
+                }
+            }
+        });
+
         MusicPlayer {
             tracks: Vec::new(),
             current_track: None,
             sink: None,
-            stream_handle: None,
-            _stream: None,
+            fade: FadeHandle::default(),
+            track_gain: GainHandle::default(),
+            fading: None,
+            output: None,
+            output_buffer: output::OutputBuffer::Auto,
             volume: 1.0,
+            ducked: false,
+            duck_target: 1.0,
+            duck_level: 1.0,
+            duck_faded: Instant::now(),
+            night: false,
+            night_volume: 0.4,
+            compressor: CompressorPreset::Off,
+            compressor_threshold: None,
+            compressor_ratio: None,
+            volume_step: 0.1,
             start_time: None,
             duration: None,
+            stream_info: None,
             paused_duration: None,
+            start_offset: Duration::ZERO,
+            end: None,
+            dsp: Arc::default(),
+            library: Library::load(),
+            trim_silence: false,
+            analysis_jobs,
+            analysis_results,
+            analyzing: HashSet::new(),
+            analyzed: 0,
+            analysis_progress,
+            probe_jobs,
+            probe_results,
+            tags: TagReader::new(tasks.start("Scanning", Measure::Count)),
+            library_unsaved: false,
+            library_saved: Instant::now(),
+            crossfade: Duration::ZERO,
+            restart_threshold: Duration::from_secs(3),
+            resume_after: Duration::from_secs(20 * 60),
+            smart_weights: (2.0, 2.0),
+            auto_mix: false,
+            auto_fill: false,
+            queue_end: QueueEnd::Repeat,
+            skip_shorter: Duration::ZERO,
+            skip_patterns: Vec::new(),
+            auto_skip: true,
+            recorder: None,
+            record_dir: PathBuf::new(),
+            replaygain: ReplayGainMode::Off,
+            cast: None,
+            zones: Vec::new(),
+            zone_targets: ZoneTargets::default(),
+            zone_format: (2, 44_100),
+            output_device: String::new(),
+            bit_perfect: false,
+            output_format: None,
+            device_rate: None,
+            source_rate: None,
+            bits_per_sample: None,
+            audio_track: 0,
+            audio_tracks: 1,
+            chapters: Vec::new(),
+            queue_ended: false,
+            repeat: Repeat::All,
+            shuffle: Shuffle::Off,
+            vetoed: HashSet::new(),
+            queued: Vec::new(),
+            order: None,
+            ab_loop: (None, None),
+            soundfont_path: PathBuf::new(),
+            soundfont: None,
         }
     }
 
+    pub fn configure(&mut self, config: &Config) {
+        self.volume_step = (config.volume_step / 100.0).clamp(0.01, 1.0);
+        self.night_volume = (config.night_max_volume / 100.0).clamp(0.0, 1.0);
+        self.apply_volume();
+        self.compressor = config.compressor;
+        self.compressor_threshold = config.compressor_threshold;
+        self.compressor_ratio = config.compressor_ratio;
+        self.apply_compression();
+        self.trim_silence = config.trim_silence;
+        self.crossfade = Duration::from_secs_f32(config.crossfade_seconds.max(0.0));
+        self.restart_threshold = Duration::from_secs_f32(config.restart_seconds.max(0.0));
+        self.resume_after = Duration::from_secs_f32(config.resume_minutes.max(0.0) * 60.0);
+        self.auto_mix = config.auto_mix;
+        self.auto_fill = config.auto_fill;
+        self.queue_end = config.queue_end;
+        self.skip_shorter = Duration::from_secs_f32(config.skip_shorter_than.max(0.0));
+        self.skip_patterns = config.skip_patterns.clone();
+        self.smart_weights = (
+            f64::from(config.smart_shuffle_rating.max(0.0)),
+            f64::from(config.smart_shuffle_recency.max(0.0)),
+        );
+        self.record_dir = config.record_dir.clone();
+        self.replaygain = config.replaygain;
+        self.output_device = config.output_device.clone();
+        self.output_buffer = config.output_buffer;
+        self.bit_perfect = config.bit_perfect;
+        remote::cache::set_limit(config.cache_limit_mb);
+        if self.soundfont_path != config.soundfont {
+            self.soundfont_path = config.soundfont.clone();
+            self.soundfont = None;
+        }
+        let mut dsp = self.dsp_settings();
+        dsp.skip_silence = config.skip_silence;
+        dsp.silence_skip_after = Duration::from_secs_f32(config.silence_skip_seconds.max(0.0));
+        dsp.crossfeed = config.crossfeed;
+        dsp.crossfeed_strength = config.crossfeed_strength / 100.0;
+        dsp.balance = (config.balance / 100.0).clamp(-1.0, 1.0);
+        dsp.swap_channels = config.swap_channels;
+        dsp.mono = config.mono;
+        dsp.resampler = config.resampler;
+        dsp.band_limit = config.band_limit;
+    }
+
+    fn dsp_settings(&self) -> MutexGuard<'_, DspSettings> {
+        self.dsp.settings.lock().unwrap()
+    }
+
+    /// Swaps in another playlist, stopping playback.
+    pub fn replace_tracks(&mut self, tracks: Vec<PathBuf>) {
+        self.stop();
+        self.current_track = None;
+        self.tracks = tracks;
+        self.vetoed.clear();
+        self.queued.clear();
+        self.set_shuffle(self.shuffle);
+    }
+
     pub fn add_track(&mut self, path: PathBuf) {
         self.tracks.push(path);
+        self.shuffle_in(self.tracks.len() - 1);
     }
 
-    fn get_track_duration(path: &PathBuf) -> Option<Duration> {
-        let file = File::open(path).ok()?;
-        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    // Reads the stream details from the container without decoding
+    fn probe_stream(path: &Path, audio_track: usize) -> Option<StreamInfo> {
+        let media = decode::open_media(path).ok()?;
+        let size = media.byte_len()?;
+        let stream = MediaSourceStream::new(media, Default::default());
         let hint = Hint::new();
         let format_opts = FormatOptions::default();
         let metadata_opts = MetadataOptions::default();
@@ -49,11 +371,58 @@ impl MusicPlayer {
             .ok()?;
         
         let format = probed.format;
-        let track = format.tracks().get(0)?;
-        let time_base = track.codec_params.time_base?;
-        let n_frames = track.codec_params.n_frames?;
-        
-        Some(Duration::from_secs_f64(n_frames as f64 * time_base.numer as f64 / time_base.denom as f64))
+        let params = &format
+            .tracks()
+            .iter()
+            .filter(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .nth(audio_track)?
+            .codec_params;
+        let duration = params
+            .time_base
+            .zip(params.n_frames)
+            .map(|(base, frames)| Duration::from_secs_f64(frames as f64 * base.numer as f64 / base.denom as f64));
+        let codec = symphonia::default::get_codecs()
+            .get_codec(params.codec)
+            .map(|codec| codec.short_name.to_uppercase())
+            .unwrap_or_else(|| "Unknown".to_string());
+
+        Some(StreamInfo {
+            codec,
+            sample_rate: params.sample_rate,
+            bits_per_sample: params.bits_per_sample,
+            channels: params.channels.map(|channels| channels.count() as u16),
+            bitrate_kbps: duration
+                .filter(|duration| !duration.is_zero())
+                .map(|duration| (size as f64 * 8.0 / duration.as_secs_f64() / 1000.0).round() as u32),
+            duration,
+        })
+    }
+
+    pub fn stream_info(&self) -> Option<&StreamInfo> {
+        self.stream_info.as_ref()
+    }
+
+    // Takes in probed stream details, returning whether they were for the current track
+    pub fn poll_stream_info(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok(probe) = self.probe_results.try_recv() {
+            let current = self.current_track.map(|index| &self.tracks[index]);
+            if current != Some(&probe.path) || probe.audio_track != self.audio_track || self.sink.is_none() {
+                continue;
+            }
+            self.audio_tracks = probe.audio_tracks;
+            self.chapters = probe.chapters;
+            if let Some(duration) = probe.info.as_ref().and_then(|info| info.duration) {
+                // An end set from the estimate moves along, one set by trimming stays
+                if self.end == self.duration {
+                    self.end = Some(duration);
+                }
+                self.duration = Some(duration);
+            }
+            self.stream_info = probe.info;
+            changed = true;
+        }
+        changed
     }
 
     pub fn play_track(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
@@ -61,27 +430,209 @@ impl MusicPlayer {
             return Ok(());
         }
 
-        self.stop();
+        self.stop_sinks();
+        self.start_track(index, None)
+    }
+
+    /// Plays a track from a position in it.
+    pub fn play_track_at(&mut self, index: usize, position: Duration) -> Result<(), Box<dyn Error>> {
+        if index >= self.tracks.len() {
+            return Ok(());
+        }
+        self.stop_sinks();
+        self.start_track_from(index, None, position)
+    }
+
+    // Starts a track on a new sink, leaving any previous sink untouched
+    fn start_track(&mut self, index: usize, fade_in: Option<Duration>) -> Result<(), Box<dyn Error>> {
+        let resumed = self.resume_position(index);
+        if let Some(position) = resumed {
+            log::info!(target: "playback", "resuming {} at {:.0}s", self.tracks[index].display(), position.as_secs_f64());
+        }
+        self.start_track_from(index, fade_in, resumed.unwrap_or_default())?;
+        self.vetoed.clear();
+        if let Some(position) = self.queued.iter().position(|&queued| queued == index) {
+            self.queued.remove(position);
+        }
+        if resumed.is_none() {
+            self.note_play(index);
+        }
+        Ok(())
+    }
 
-        // Get track duration first
-        self.duration = Self::get_track_duration(&self.tracks[index]);
+    // Where a long track was left last time
+    fn resume_position(&self, index: usize) -> Option<Duration> {
+        let record = self.library.get(&self.tracks[index])?;
+        let long = record.duration.is_some_and(|duration| duration >= self.resume_after);
+        record.position.filter(|_| long && !self.resume_after.is_zero())
+    }
 
-        if self._stream.is_none() {
-            let (stream, handle) = OutputStream::try_default()?;
-            self._stream = Some(stream);
-            self.stream_handle = Some(handle);
+    /// Notes how far into the current track playback is when it is long, so
+    /// it starts from there next time.
+    pub fn remember_position(&mut self) {
+        let (Some(index), Some(elapsed), Some(duration)) = (self.current_track, self.elapsed(), self.duration) else {
+            return;
+        };
+        let Some(sink) = &self.sink else {
+            return;
+        };
+        if self.resume_after.is_zero() || duration < self.resume_after {
+            return;
+        }
+        // Played out or left close to the end counts as finished, to start over next time
+        let end = self.end.unwrap_or(duration);
+        let position = (!sink.empty() && elapsed + RESUME_END_MARGIN < end).then_some(elapsed);
+        let record = self.library.record_mut(&self.tracks[index]);
+        if record.position != position {
+            record.position = position;
+            record.position_saved = SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|time| time.as_secs());
+            self.library_unsaved = true;
         }
+    }
+
+    // Counts a play in the library, saved along with the next tags
+    fn note_play(&mut self, index: usize) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        let record = self.library.record_mut(&self.tracks[index]);
+        record.plays += 1;
+        record.last_played = Some(now);
+        self.library_unsaved = true;
+    }
+
+    /// The gain set by hand for a track, in dB.
+    pub fn track_gain_db(&self, index: usize) -> f32 {
+        self.track_record(index).and_then(|record| record.gain_offset).unwrap_or(0.0)
+    }
+
+    /// Raises or lowers the current track's own gain by a step, right away
+    /// and whenever it plays again. Returns the new gain in dB.
+    pub fn adjust_track_gain(&mut self, up: bool) -> Option<f32> {
+        let index = self.current_track?;
+        let step = if up { TRACK_GAIN_STEP } else { -TRACK_GAIN_STEP };
+        let gain = (self.track_gain_db(index) + step).clamp(-MAX_TRACK_GAIN, MAX_TRACK_GAIN);
+        self.library.record_mut(&self.tracks[index]).gain_offset = (gain != 0.0).then_some(gain);
+        self.library_unsaved = true;
+        self.track_gain.set_db(gain);
+        Some(gain)
+    }
+
+    /// Sets or, with None, clears the stars of a track.
+    pub fn set_rating(&mut self, index: usize, rating: Option<u8>) {
+        self.library.record_mut(&self.tracks[index]).rating = rating;
+        self.library_unsaved = true;
+    }
 
-        if let Some(handle) = &self.stream_handle {
-            let file = File::open(&self.tracks[index])?;
-            let reader = BufReader::new(file);
-            let source = Decoder::new(reader)?;
+    /// Sets or, given None, clears the note on a track.
+    pub fn set_note(&mut self, index: usize, note: Option<String>) {
+        self.library.record_mut(&self.tracks[index]).note = note;
+        self.library_unsaved = true;
+    }
+
+    /// Puts a label on a track or takes it off, returning whether it is on now.
+    pub fn toggle_label(&mut self, index: usize, label: &str) -> bool {
+        let labels = &mut self.library.record_mut(&self.tracks[index]).labels;
+        self.library_unsaved = true;
+        match labels.iter().position(|name| name == label) {
+            Some(i) => {
+                labels.remove(i);
+                false
+            }
+            None => {
+                labels.push(label.to_string());
+                true
+            }
+        }
+    }
+
+    pub fn current_note(&self) -> Option<&str> {
+        let track = &self.tracks[self.current_track?];
+        self.library.get(track)?.note.as_deref()
+    }
+
+    pub fn never_plays(&self, index: usize) -> bool {
+        self.library.never_play(&self.tracks[index])
+    }
+
+    /// Flags a track, or with `folder` the folder it is in, to be left out
+    /// of shuffle and endless play, or clears the flag. Returns the path and
+    /// whether it is flagged now.
+    pub fn toggle_never_play(&mut self, index: usize, folder: bool) -> (PathBuf, bool) {
+        let track = &self.tracks[index];
+        let path = if folder { track.parent().unwrap_or(track) } else { track }.to_path_buf();
+        let record = self.library.record_mut(&path);
+        record.never_play = !record.never_play;
+        let flagged = record.never_play;
+        self.library_unsaved = true;
+        (path, flagged)
+    }
+
+    fn start_track_from(&mut self, index: usize, fade_in: Option<Duration>, position: Duration) -> Result<(), Box<dyn Error>> {
+        if self.current_track != Some(index) {
+            self.audio_track = 0;
+            self.audio_tracks = 1;
+            self.chapters.clear();
+            self.ab_loop = (None, None);
+        }
+        // Probing can take a while on network shares, so until it is done the
+        // length comes from the tags or the decoder
+        let _ = self.probe_jobs.send((self.tracks[index].clone(), self.audio_track));
+        self.stream_info = None;
+        self.duration = self.library.get(&self.tracks[index]).and_then(|record| record.duration);
+
+        let trim = self.silence_bounds(index);
+
+        let mut source = self.open_source(index).inspect_err(|e| {
+            log::warn!(target: "decoder", "could not decode {}: {}", self.tracks[index].display(), e);
+        })?;
+        self.open_output(source.channels(), source.sample_rate()).inspect_err(|e| {
+            log::error!(target: "playback", "could not open the output: {}", e);
+        })?;
+        if self.duration.is_none() {
+            self.duration = source.total_duration();
+        }
+
+        if let Some(output) = &self.output {
+            self.start_offset = position;
+            self.end = self.duration;
+            if let Some((start, end)) = trim {
+                self.start_offset = position.max(start);
+                self.end = Some(end);
+            }
+            if !self.start_offset.is_zero() {
+                source = Box::new(source.skip_duration(self.start_offset));
+            }
+            if let (Some(_), Some(end)) = (trim, self.end) {
+                source = Box::new(source.take_duration(end.saturating_sub(self.start_offset)));
+            }
+            if let Some(length) = fade_in {
+                source = Box::new(source.fade_in(length));
+            }
             
-            let sink = Sink::try_new(handle)?;
-            sink.set_volume(self.volume);
-            sink.append(source);
+            let sink = output.sink();
+            sink.set_volume(self.output_volume());
+            self.dsp.reset_skipped();
+            self.fade = FadeHandle::default();
+            self.track_gain = GainHandle::default();
+            self.track_gain.set_db(self.track_gain_db(index));
+            let gain_db = self.mix_gain_db(index) + self.replaygain_db(index);
+            self.source_rate = Some(source.sample_rate());
+            let processed = DspSource::new(source, self.dsp.clone())
+                .with_gain_db(gain_db)
+                .with_gain_adjustment(self.track_gain.clone())
+                .with_fade(self.fade.clone())
+                .with_output_rate(self.device_rate);
+            let zone_targets = self.attach_zones((processed.channels(), processed.sample_rate()))?;
+            sink.append(ZoneTee::new(processed, zone_targets));
             sink.play();
             
+            if let Some(cast) = &self.cast {
+                cast.send(self.cast_load(index));
+                if !self.start_offset.is_zero() {
+                    cast.send(CastCommand::Seek(self.start_offset));
+                }
+            }
+
+            log::info!(target: "playback", "playing {} from {:?}", self.tracks[index].display(), self.start_offset);
             self.current_track = Some(index);
             self.sink = Some(sink);
             self.start_time = Some(Instant::now());
@@ -90,17 +641,457 @@ impl MusicPlayer {
         Ok(())
     }
 
+    fn open_source(&mut self, index: usize) -> Result<BoxedSource, Box<dyn Error>> {
+        self.bits_per_sample = None;
+        if midi::is_midi(&self.tracks[index]) {
+            let soundfont = self.soundfont()?;
+            return Ok(Box::new(MidiSource::open(&self.tracks[index], soundfont)?));
+        }
+        let path = &self.tracks[index];
+        if self.bit_perfect {
+            // Formats symphonia can't decode fall back to the other decoders
+            match SymphoniaSource::open(path, self.audio_track) {
+                Ok(decoder) => {
+                    self.bits_per_sample = decoder.bits_per_sample;
+                    return Ok(Box::new(decoder));
+                }
+                Err(e) => log::debug!(target: "decoder", "no bit-perfect decoding for {}: {}", path.display(), e),
+            }
+        }
+        decode::open(path, self.audio_track)
+    }
+
+    // Restarts the current track at the given position, keeping it paused if it was
+    fn restart_at(&mut self, position: Duration) -> Result<(), Box<dyn Error>> {
+        let Some(index) = self.current_track.filter(|_| self.sink.is_some()) else {
+            return Ok(());
+        };
+        let paused = self.paused_duration.is_some();
+        self.stop_sinks();
+        self.start_track_from(index, None, position)?;
+        if paused {
+            self.pause();
+        }
+        Ok(())
+    }
+
+    /// Moves playback to another output device, picking up where it was.
+    pub fn set_output_device(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        if self.output_device == name {
+            return Ok(());
+        }
+        self.output_device = name.to_string();
+        self.reopen_output()
+    }
+
+    /// Reopens the output with another buffer size, picking up where it was.
+    pub fn set_output_buffer(&mut self, buffer: output::OutputBuffer) -> Result<(), Box<dyn Error>> {
+        if self.output_buffer == buffer {
+            return Ok(());
+        }
+        self.output_buffer = buffer;
+        self.reopen_output()
+    }
+
+    pub fn output_device(&self) -> &str {
+        &self.output_device
+    }
+
+    /// Opens the output device again, e.g. when the system's default output
+    /// has moved, picking up where it was.
+    pub fn reopen_output(&mut self) -> Result<(), Box<dyn Error>> {
+        let position = self.position();
+        self.fading = None;
+        self.output = None;
+        match position {
+            Some(position) => self.restart_at(position),
+            None => Ok(()),
+        }
+    }
+
+    /// The output's buffer in frames and milliseconds, and how often it ran dry.
+    pub fn output_buffer(&self) -> Option<((u32, f32), u64)> {
+        let output = self.output.as_ref()?;
+        Some((output.buffer()?, output.underruns()))
+    }
+
+    /// Jumps to a position in the current track.
+    pub fn seek_to(&mut self, position: Duration) -> Result<(), Box<dyn Error>> {
+        let position = self.duration.map_or(position, |duration| position.min(duration));
+        self.restart_at(position)
+    }
+
+    /// Jumps forward, or back for negative seconds, in the current track.
+    pub fn seek_by(&mut self, seconds: f64) -> Result<(), Box<dyn Error>> {
+        let Some(position) = self.elapsed() else {
+            return Ok(());
+        };
+        self.seek_to(Duration::from_secs_f64((position.as_secs_f64() + seconds).max(0.0)))
+    }
+
+    /// Position in the current track, None when stopped.
+    pub fn position(&self) -> Option<Duration> {
+        self.elapsed()
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    pub fn chapters(&self) -> &[Chapter] {
+        &self.chapters
+    }
+
+    /// The chapter playing now, by index into `chapters`.
+    pub fn current_chapter(&self) -> Option<usize> {
+        chapters::at(&self.chapters, self.elapsed()?)
+    }
+
+    pub fn jump_to_chapter(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
+        match self.chapters.get(index) {
+            Some(chapter) => self.seek_to(chapter.start),
+            None => Ok(()),
+        }
+    }
+
+    /// Jumps to the next chapter, or the next track from the last chapter or
+    /// in a track without any.
+    pub fn next_chapter(&mut self) -> Result<(), Box<dyn Error>> {
+        let next = self.current_chapter().map_or(0, |current| current + 1);
+        if next < self.chapters.len() {
+            self.jump_to_chapter(next)
+        } else {
+            self.next_track()
+        }
+    }
+
+    /// Previous within the chapters of a track: restarts the chapter once it
+    /// has played past the threshold, goes to the one before otherwise.
+    pub fn previous_chapter(&mut self) -> Result<(), Box<dyn Error>> {
+        let (Some(current), Some(elapsed)) = (self.current_chapter(), self.elapsed()) else {
+            return self.previous_track();
+        };
+        let played = elapsed.saturating_sub(self.chapters[current].start);
+        if !self.restart_threshold.is_zero() && played > self.restart_threshold {
+            self.jump_to_chapter(current)
+        } else if current > 0 {
+            self.jump_to_chapter(current - 1)
+        } else {
+            self.previous_track()
+        }
+    }
+
+    pub fn ab_loop(&self) -> (Option<Duration>, Option<Duration>) {
+        self.ab_loop
+    }
+
+    // Sets the loop start, then its end, then clears the loop
+    pub fn cycle_ab_loop(&mut self) {
+        let Some(position) = self.elapsed() else {
+            return;
+        };
+        self.ab_loop = match self.ab_loop {
+            (None, _) => (Some(position), None),
+            (Some(start), None) if position > start => (Some(start), Some(position)),
+            (Some(_), None) => (Some(position), None),
+            (Some(_), Some(_)) => (None, None),
+        };
+    }
+
+    pub fn bookmarks(&self) -> Vec<Duration> {
+        self.current_track
+            .and_then(|index| self.library.get(&self.tracks[index]))
+            .map(|record| record.bookmarks.clone())
+            .unwrap_or_default()
+    }
+
+    // Bookmarks the current position, or removes a bookmark within a second of it
+    pub fn toggle_bookmark(&mut self) -> Result<(), Box<dyn Error>> {
+        let (Some(index), Some(position)) = (self.current_track, self.elapsed()) else {
+            return Ok(());
+        };
+        let bookmarks = &mut self.library.record_mut(&self.tracks[index]).bookmarks;
+        let near = |mark: &Duration| mark.abs_diff(position) < Duration::from_secs(1);
+        if bookmarks.iter().any(near) {
+            bookmarks.retain(|mark| !near(mark));
+        } else {
+            bookmarks.push(position);
+            bookmarks.sort();
+        }
+        self.library.save()?;
+        Ok(())
+    }
+
+    // Jumps to the next bookmark in the current track, wrapping to the first
+    pub fn next_bookmark(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(position) = self.elapsed() else {
+            return Ok(());
+        };
+        let bookmarks = self.bookmarks();
+        let after = position + Duration::from_secs(1);
+        match bookmarks.iter().find(|&&mark| mark > after).or(bookmarks.first()) {
+            Some(&mark) => self.seek_to(mark),
+            None => Ok(()),
+        }
+    }
+
+    pub fn audio_track(&self) -> (usize, usize) {
+        (self.audio_track, self.audio_tracks)
+    }
+
+    // Switches to the file's next audio track, e.g. another language in a Matroska file
+    pub fn cycle_audio_track(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.audio_tracks < 2 {
+            return Ok(());
+        }
+        let position = self.elapsed().unwrap_or_default();
+        self.audio_track = (self.audio_track + 1) % self.audio_tracks;
+        self.restart_at(position)
+    }
+
+    fn soundfont(&mut self) -> Result<Arc<SoundFont>, Box<dyn Error>> {
+        if let Some(soundfont) = &self.soundfont {
+            return Ok(soundfont.clone());
+        }
+        let soundfont = SoundFont::load(&self.soundfont_path)
+            .map_err(|e| format!("can't load SoundFont {}: {}", self.soundfont_path.display(), e))?;
+        Ok(self.soundfont.insert(Arc::new(soundfont)).clone())
+    }
+
+    // Opens the output stream, in bit-perfect mode at the track's own format
+    // whenever the device supports it
+    fn open_output(&mut self, channels: u16, sample_rate: u32) -> Result<(), Box<dyn Error>> {
+        let format = Some((channels, sample_rate));
+        if self.output.is_some() && (!self.bit_perfect || self.output_format == format) {
+            return Ok(());
+        }
+
+        let device = output::find_device(&self.output_device)?;
+        let exact = if self.bit_perfect {
+            output::exact_config(&device, channels, sample_rate, self.bits_per_sample)
+        } else {
+            None
+        };
+        if self.output.is_some() && exact.is_none() && self.output_format.is_none() {
+            return Ok(());
+        }
+
+        // A track still fading out can't move to the new stream
+        self.fading = None;
+        self.output = None;
+        self.output_format = exact.is_some().then_some((channels, sample_rate));
+        let config = match exact {
+            Some(config) => config,
+            None => output::default_config(&device)?,
+        };
+        self.device_rate = Some(config.sample_rate().0);
+        self.output = Some(output::Output::open(&device, config, self.output_buffer)?);
+        Ok(())
+    }
+
+    /// The sample rate conversion going on, if any, e.g.
+    /// `Resampled 44.1 → 48 kHz (sinc, band-limited)` or `Resampled for 1.5× (linear)`.
+    pub fn resampling(&self) -> Option<String> {
+        let from = self.source_rate.filter(|_| self.sink.is_some())?;
+        let to = self.device_rate.unwrap_or(from);
+        let dsp = self.dsp_settings();
+        let speed = dsp.speed.clamp(dsp::MIN_SPEED, dsp::MAX_SPEED);
+        if from == to && speed == 1.0 {
+            return None;
+        }
+        let band_limited = match dsp.resampler {
+            resample::Resampling::Sinc if dsp.band_limit => ", band-limited",
+            _ => "",
+        };
+        let rates = if from == to { String::new() } else { format!(" {} → {} kHz", from as f32 / 1000.0, to as f32 / 1000.0) };
+        let speed = if speed == 1.0 { String::new() } else { format!(" for {}×", speed) };
+        Some(format!("Resampled{}{} ({}{})", rates, speed, dsp.resampler.name(), band_limited))
+    }
+
+    // Output format while in bit-perfect mode, flagging anything that alters the samples
+    pub fn bit_perfect_status(&self) -> Option<String> {
+        if !self.bit_perfect || self.sink.is_none() {
+            return None;
+        }
+        let Some((_, rate)) = self.output_format else {
+            return Some("Resampled (format unsupported)".to_string());
+        };
+        let bits = self.bits_per_sample.map(|bits| format!("/{}", bits)).unwrap_or_default();
+        let altered = self.volume < 1.0
+            || self.auto_mix
+            || self.replaygain != ReplayGainMode::Off
+            || self.current_track.is_some_and(|index| self.track_gain_db(index) != 0.0)
+            || !self.dsp_settings().is_neutral();
+        Some(format!(
+            "Bit-perfect {}kHz{}{}",
+            rate as f32 / 1000.0,
+            bits,
+            if altered { " (altered by volume/DSP)" } else { "" }
+        ))
+    }
+
+    // Returns the cached trim points, scheduling an analysis when there are none yet
+    fn silence_bounds(&mut self, index: usize) -> Option<(Duration, Duration)> {
+        if !self.trim_silence {
+            return None;
+        }
+
+        let path = self.tracks[index].clone();
+        if let Some((start, end)) = self.library.get(&path).and_then(|record| record.silence) {
+            return if end > start { Some((start, end)) } else { None };
+        }
+        self.request_analysis(path);
+        None
+    }
+
+    fn request_analysis(&mut self, path: PathBuf) {
+        if self.analyzing.insert(path.clone()) {
+            let _ = self.analysis_jobs.send(path);
+            self.analysis_progress.update(self.analyzed, self.analyzed + self.analyzing.len() as u64);
+        }
+    }
+
+    // Queues every track the library has no analysis for yet
+    pub fn analyze_library(&mut self) {
+        let pending: Vec<PathBuf> = self
+            .tracks
+            .iter()
+            .filter(|track| !self.library.get(track).is_some_and(|record| record.is_analyzed()))
+            // Analyzing would download every track on the server
+            .filter(|track| !remote::is_sftp(track) && !remote::is_webdav(track) && !remote::is_jellyfin(track))
+            .cloned()
+            .collect();
+        for path in pending {
+            self.request_analysis(path);
+        }
+    }
+
+    // Normalization gain from the loudness scan, limited so the peak doesn't clip
+    fn replaygain_db(&self, index: usize) -> f32 {
+        let Some(record) = self.library.get(&self.tracks[index]) else {
+            return 0.0;
+        };
+        let gain = match self.replaygain {
+            ReplayGainMode::Off => None,
+            ReplayGainMode::Track => record.track_gain,
+            ReplayGainMode::Album => record.album_gain.or(record.track_gain),
+        };
+        match (gain, record.peak) {
+            (Some(gain), Some(peak)) if peak > 0.0 => gain.min(-20.0 * peak.log10()),
+            (Some(gain), _) => gain,
+            (None, _) => 0.0,
+        }
+    }
+
+    // Title from the tags or the web page, or the file name
+    pub fn track_title(&self, index: usize) -> String {
+        let track = &self.tracks[index];
+        match self.library.get(track).and_then(|record| record.title.clone()) {
+            Some(title) => title,
+            None => track.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        }
+    }
+
+    pub fn track_record(&self, index: usize) -> Option<&TrackRecord> {
+        self.library.get(&self.tracks[index])
+    }
+
+    // Reads the tags of the given tracks ahead of the rest, e.g. those on screen
+    pub fn request_tags(&mut self, range: Range<usize>) {
+        let end = range.end.min(self.tracks.len());
+        for track in &self.tracks[range.start.min(end)..end] {
+            let cached = self.library.get(track).and_then(|record| record.modified);
+            self.tags.request(track, cached, true);
+        }
+    }
+
+    // Queues every track for a check of its cached tags
+    pub fn read_library_tags(&mut self) {
+        for track in &self.tracks {
+            let cached = self.library.get(track).and_then(|record| record.modified);
+            self.tags.request(track, cached, false);
+        }
+    }
+
+    // Stores read tags in the library, saving once a burst of reads is over
+    // rather than after every track. Returns whether any tags came in.
+    pub fn poll_tags(&mut self) -> bool {
+        let reads = self.tags.poll();
+        let idle = reads.is_empty();
+        for read in reads {
+            let record = self.library.record_mut(&read.path);
+            // Tracks from the web keep their page title when the file has none
+            if read.tags.title.is_some() {
+                record.title = read.tags.title;
+            }
+            record.artist = read.tags.artist;
+            record.album = read.tags.album;
+            record.genre = read.tags.genre;
+            record.duration = read.tags.duration;
+            record.size = read.tags.size;
+            record.modified = Some(read.modified);
+            self.library_unsaved = true;
+        }
+        if self.library_unsaved && (idle || self.library_saved.elapsed() >= LIBRARY_SAVE_INTERVAL) {
+            if let Err(e) = self.library.save() {
+                log::error!(target: "playback", "could not save the library: {}", e);
+            }
+            self.library_unsaved = false;
+            self.library_saved = Instant::now();
+        }
+        !idle
+    }
+
+    pub fn bpm(&self, index: usize) -> Option<f32> {
+        self.library.get(&self.tracks[index])?.bpm
+    }
+
+    // Stores finished analyses in the library, returning whether any finished
+    pub fn poll_analysis(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok((path, analysis)) = self.analysis_results.try_recv() {
+            self.analyzing.remove(&path);
+            self.analyzed += 1;
+            changed = true;
+            // Left for next time
+            let Some(analysis) = analysis else {
+                continue;
+            };
+            let record = self.library.record_mut(&path);
+            // Unreadable tracks are stored with empty bounds so they aren't analyzed again
+            record.silence = Some(analysis.silence.unwrap_or_default());
+            record.bpm = analysis.bpm;
+            record.loudness = analysis.loudness;
+            record.analysis = library::ANALYSIS_VERSION;
+        }
+        if changed {
+            if let Err(e) = self.library.save() {
+                log::error!(target: "playback", "could not save the library: {}", e);
+            }
+            self.analysis_progress.update(self.analyzed, self.analyzed + self.analyzing.len() as u64);
+            if self.analyzing.is_empty() {
+                self.analyzed = 0;
+                self.analysis_progress.reset();
+            }
+        }
+        changed
+    }
+
+    // Position in the track, including time skipped by the DSP chain
+    fn elapsed(&self) -> Option<Duration> {
+        let start = self.start_time?;
+        if let Some(position) = self.cast.as_ref().and_then(|cast| cast.position(self.paused_duration.is_none())) {
+            return Some(position);
+        }
+        let played = self.paused_duration.unwrap_or_else(|| start.elapsed());
+        let position = (self.start_offset + played).as_secs_f64() + self.dsp.skipped();
+        Some(Duration::from_secs_f64(position.max(0.0)))
+    }
+
     pub fn get_progress(&self) -> Option<f32> {
-        if let (Some(start), Some(duration)) = (self.start_time, self.duration) {
-            if self.is_playing() {
-                let elapsed = if let Some(paused) = self.paused_duration {
-                    paused
-                } else {
-                    start.elapsed()
-                };
+        if let (Some(elapsed), Some(duration)) = (self.elapsed(), self.duration) {
+            if self.is_playing() || self.paused_duration.is_some() {
                 Some((elapsed.as_secs_f32() / duration.as_secs_f32()).min(1.0))
-            } else if let Some(paused) = self.paused_duration {
-                Some((paused.as_secs_f32() / duration.as_secs_f32()).min(1.0))
             } else {
                 None
             }
@@ -110,12 +1101,7 @@ impl MusicPlayer {
     }
 
     pub fn get_elapsed_time(&self) -> String {
-        if let Some(start) = self.start_time {
-            let elapsed = if let Some(paused) = self.paused_duration {
-                paused
-            } else {
-                start.elapsed()
-            };
+        if let Some(elapsed) = self.elapsed() {
             let seconds = elapsed.as_secs();
             let minutes = seconds / 60;
             let remaining_seconds = seconds % 60;
@@ -125,72 +1111,339 @@ impl MusicPlayer {
         }
     }
 
+    /// Whether the playlist ran out since the last call.
+    pub fn take_queue_end(&mut self) -> bool {
+        std::mem::take(&mut self.queue_ended)
+    }
+
     pub fn next_track(&mut self) -> Result<(), Box<dyn Error>> {
-        if let Some(current) = self.current_track {
-            let next = (current + 1) % self.tracks.len();
+        if let Some(next) = self.fill_queue().or_else(|| self.next_index()) {
             self.play_track(next)?;
-        } else if !self.tracks.is_empty() {
-            self.play_track(0)?;
         }
         Ok(())
     }
 
+    /// Restarts the current track once it has played past the threshold,
+    /// goes to the previous one before that.
     pub fn previous_track(&mut self) -> Result<(), Box<dyn Error>> {
-        if let Some(current) = self.current_track {
-            let previous = if current == 0 {
-                self.tracks.len() - 1
-            } else {
-                current - 1
-            };
+        // Counted from where a trimmed track starts
+        let start = self
+            .current_track
+            .filter(|_| self.trim_silence)
+            .and_then(|current| self.library.get(&self.tracks[current])?.silence)
+            .map_or(Duration::ZERO, |(start, _)| start);
+        let played = self.elapsed().filter(|_| self.sink.is_some()).map(|elapsed| elapsed.saturating_sub(start));
+        if !self.restart_threshold.is_zero() && played.is_some_and(|played| played > self.restart_threshold) {
+            return self.restart_at(Duration::ZERO);
+        }
+        if let Some(previous) = self.previous_index() {
             self.play_track(previous)?;
-        } else if !self.tracks.is_empty() {
-            self.play_track(self.tracks.len() - 1)?;
         }
         Ok(())
     }
 
     pub fn increase_volume(&mut self) {
-        self.volume = (self.volume + 0.1).min(1.0);
-        if let Some(sink) = &self.sink {
-            sink.set_volume(self.volume);
-        }
+        self.volume = (self.volume + self.volume_step).min(1.0);
+        self.apply_volume();
     }
 
     pub fn decrease_volume(&mut self) {
-        self.volume = (self.volume - 0.1).max(0.0);
-        if let Some(sink) = &self.sink {
-            sink.set_volume(self.volume);
+        self.volume = (self.volume - self.volume_step).max(0.0);
+        self.apply_volume();
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+        self.apply_volume();
+    }
+
+    /// Turns the music down while something is spoken over it, or back up.
+    pub fn set_ducked(&mut self, ducked: bool) {
+        if self.ducked != ducked {
+            self.ducked = ducked;
+            self.apply_volume();
+        }
+    }
+
+    /// Fades the music down to `level` of its volume while other audio plays,
+    /// or back up with None.
+    pub fn set_system_duck(&mut self, level: Option<f32>) {
+        self.duck_target = level.unwrap_or(1.0).clamp(0.0, 1.0);
+        self.duck_faded = Instant::now();
+    }
+
+    /// Moves the fade for other audio along. True while it's still going.
+    pub fn fade_duck(&mut self) -> bool {
+        if self.duck_level == self.duck_target {
+            return false;
+        }
+        let elapsed = self.duck_faded.elapsed().as_secs_f32();
+        self.duck_faded = Instant::now();
+        self.duck_level = if self.duck_level > self.duck_target {
+            (self.duck_level - elapsed / DUCK_FADE_DOWN).max(self.duck_target)
+        } else {
+            (self.duck_level + elapsed / DUCK_FADE_UP).min(self.duck_target)
+        };
+        self.apply_volume();
+        true
+    }
+
+    // Local outputs are muted while casting
+    fn output_volume(&self) -> f32 {
+        let volume = if self.is_night() { self.volume.min(self.night_volume) } else { self.volume };
+        let volume = volume * self.duck_level;
+        if self.cast.is_some() {
+            0.0
+        } else if self.ducked {
+            volume * DUCKED_VOLUME
+        } else {
+            volume
+        }
+    }
+
+    pub fn is_night(&self) -> bool {
+        self.night
+    }
+
+    /// Night mode caps the volume and compresses loud passages.
+    pub fn set_night(&mut self, night: bool) {
+        self.night = night;
+        self.apply_volume();
+        self.apply_compression();
+    }
+
+    pub fn compressor(&self) -> CompressorPreset {
+        self.compressor
+    }
+
+    pub fn cycle_compressor(&mut self) {
+        self.compressor = self.compressor.next();
+        self.apply_compression();
+    }
+
+    /// How far the compressor is turning the sound down, in dB.
+    pub fn gain_reduction(&self) -> f32 {
+        self.dsp.gain_reduction()
+    }
+
+    fn apply_compression(&self) {
+        let compression = if self.night {
+            CompressorPreset::Night.compression()
+        } else {
+            self.compressor.compression().map(|preset| Compression {
+                threshold_db: self.compressor_threshold.unwrap_or(preset.threshold_db),
+                ratio: self.compressor_ratio.unwrap_or(preset.ratio),
+                ..preset
+            })
+        };
+        self.dsp_settings().compression = compression;
+    }
+
+    pub fn night_volume(&self) -> f32 {
+        self.night_volume
+    }
+
+    fn apply_volume(&self) {
+        let volume = self.output_volume();
+        for sink in self.sink.iter().chain(&self.fading) {
+            sink.set_volume(volume);
+        }
+        self.set_zone_volumes(volume);
+    }
+
+    pub fn casting_to(&self) -> Option<&str> {
+        self.cast.as_ref().map(|cast| cast.renderer.name.as_str())
+    }
+
+    pub fn cast_error(&self) -> Option<String> {
+        self.cast.as_ref()?.error()
+    }
+
+    // Hands the current track over to a renderer, continuing where it is now
+    pub fn start_casting(&mut self, renderer: Renderer) -> Result<(), Box<dyn Error>> {
+        let cast = CastSession::start(renderer)?;
+        if let Some(index) = self.current_track.filter(|_| self.sink.is_some()) {
+            cast.send(self.cast_load(index));
+            if let Some(elapsed) = self.elapsed() {
+                cast.send(CastCommand::Seek(elapsed));
+            }
+            if self.paused_duration.is_some() {
+                cast.send(CastCommand::Pause);
+            }
+        }
+        self.cast = Some(cast);
+        self.apply_volume();
+        Ok(())
+    }
+
+    pub fn stop_casting(&mut self) {
+        // Dropping the session stops the renderer
+        self.cast = None;
+        self.apply_volume();
+    }
+
+    fn cast_load(&self, index: usize) -> CastCommand {
+        let path = self.tracks[index].clone();
+        let title = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        CastCommand::Load { path, title }
+    }
+
+    pub fn balance(&self) -> f32 {
+        self.dsp_settings().balance
+    }
+
+    pub fn channels_swapped(&self) -> bool {
+        self.dsp_settings().swap_channels
+    }
+
+    pub fn adjust_balance(&mut self, delta: f32) {
+        let mut dsp = self.dsp_settings();
+        // Round to avoid drifting away from exact center
+        dsp.balance = ((dsp.balance + delta).clamp(-1.0, 1.0) * 10.0).round() / 10.0;
+    }
+
+    pub fn toggle_channel_swap(&mut self) {
+        let mut dsp = self.dsp_settings();
+        dsp.swap_channels = !dsp.swap_channels;
+    }
+
+    pub fn is_mono(&self) -> bool {
+        self.dsp_settings().mono
+    }
+
+    pub fn toggle_mono(&mut self) {
+        let mut dsp = self.dsp_settings();
+        dsp.mono = !dsp.mono;
+    }
+
+    pub fn skips_silence(&self) -> bool {
+        self.dsp_settings().skip_silence
+    }
+
+    // Trimming for the tracks started from now on, as a playlist may ask for
+    pub fn set_trim_silence(&mut self, trim: bool) {
+        self.trim_silence = trim;
+    }
+
+    pub fn toggle_silence_skipping(&mut self) {
+        let mut dsp = self.dsp_settings();
+        dsp.skip_silence = !dsp.skip_silence;
+    }
+
+    pub fn crossfeeds(&self) -> bool {
+        self.dsp_settings().crossfeed
+    }
+
+    pub fn toggle_crossfeed(&mut self) {
+        let mut dsp = self.dsp_settings();
+        dsp.crossfeed = !dsp.crossfeed;
+    }
+
+    pub fn pitch(&self) -> i32 {
+        self.dsp_settings().pitch_semitones
+    }
+
+    pub fn adjust_pitch(&mut self, semitones: i32) {
+        let mut dsp = self.dsp_settings();
+        dsp.pitch_semitones = (dsp.pitch_semitones + semitones)
+            .clamp(-dsp::MAX_PITCH_SEMITONES, dsp::MAX_PITCH_SEMITONES);
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.dsp_settings().speed
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        // Rounded so repeated steps don't drift
+        self.dsp_settings().speed = ((speed * 100.0).round() / 100.0).clamp(dsp::MIN_SPEED, dsp::MAX_SPEED);
+    }
+
+    pub fn adjust_speed(&mut self, delta: f32) {
+        self.set_speed(self.speed() + delta);
+    }
+
+    pub fn recording_path(&self) -> Option<&PathBuf> {
+        self.recorder.as_ref().map(|recorder| &recorder.path)
+    }
+
+    // Starts or stops writing the processed output to a WAV file
+    pub fn toggle_recording(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.recorder.is_some() {
+            *self.dsp.record_tap.lock().unwrap() = None;
+            self.recorder = None;
+        } else {
+            let recorder = Recorder::start(&self.record_dir)?;
+            *self.dsp.record_tap.lock().unwrap() = Some(recorder.sender());
+            self.recorder = Some(recorder);
         }
+        Ok(())
+    }
+
+    // Stops a recording that could no longer be written, returning why
+    pub fn poll_recording(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(failure) = self.recorder.as_ref().and_then(Recorder::failure) else {
+            return Ok(());
+        };
+        *self.dsp.record_tap.lock().unwrap() = None;
+        self.recorder = None;
+        Err(format!("recording stopped, {}", failure).into())
     }
 
     pub fn play(&mut self) {
         if let Some(sink) = &self.sink {
             sink.play();
+            if let Some(fading) = &self.fading {
+                fading.play();
+            }
             if let Some(paused) = self.paused_duration {
                 self.start_time = Some(Instant::now() - paused);
                 self.paused_duration = None;
             } else if self.start_time.is_none() {
                 self.start_time = Some(Instant::now());
             }
+            self.pause_zones(false);
+            if let Some(cast) = &self.cast {
+                cast.send(CastCommand::Play);
+            }
         }
     }
 
     pub fn pause(&mut self) {
         if let Some(sink) = &self.sink {
             sink.pause();
+            if let Some(fading) = &self.fading {
+                fading.pause();
+            }
             if let Some(start) = self.start_time {
                 self.paused_duration = Some(start.elapsed());
             }
+            self.pause_zones(true);
+            if let Some(cast) = &self.cast {
+                cast.send(CastCommand::Pause);
+            }
         }
     }
 
     pub fn stop(&mut self) {
-        if let Some(sink) = &self.sink {
+        self.stop_sinks();
+        remote::forget_recent();
+    }
+
+    // Stops the sound, keeping the track's download for when it starts again
+    fn stop_sinks(&mut self) {
+        self.remember_position();
+        for sink in self.sink.iter().chain(&self.fading) {
             sink.stop();
         }
+        if let (Some(cast), Some(_)) = (&self.cast, &self.sink) {
+            cast.send(CastCommand::Stop);
+        }
         self.sink = None;
+        self.fading = None;
+        self.stop_zones();
         self.start_time = None;
         self.duration = None;
+        self.stream_info = None;
         self.paused_duration = None;
     }
 
@@ -221,17 +1474,45 @@ impl MusicPlayer {
     }
 
     pub fn check_auto_advance(&mut self) -> Result<(), Box<dyn Error>> {
-        if let (Some(sink), Some(start), Some(duration)) = (&self.sink, self.start_time, self.duration) {
-            if !sink.is_paused() && start.elapsed() >= duration {
-                return self.next_track();
+        if self.fading.as_ref().is_some_and(|sink| sink.empty()) {
+            self.fading = None;
+        }
+        self.prune_zones();
+
+        let Some(sink) = &self.sink else {
+            return Ok(());
+        };
+        if sink.is_paused() {
+            return Ok(());
+        }
+        if let (Some(start), Some(end)) = self.ab_loop {
+            if self.elapsed().is_some_and(|position| position >= end) {
+                return self.restart_at(start);
             }
         }
-        Ok(())
+        // The renderer decides when a cast track ends, the muted local sink may be ahead
+        let casting = self.cast.is_some() && self.duration.is_some();
+        if (sink.empty() && !casting) || self.is_track_finished() {
+            if let Some(added) = self.fill_queue() {
+                return self.play_track(added);
+            }
+            let Some(next) = self.following() else {
+                self.queue_ended = true;
+                self.stop();
+                return Ok(());
+            };
+            self.note_wrap(next);
+            return self.play_track(next);
+        }
+        if casting {
+            return Ok(());
+        }
+        self.check_transition()
     }
 
     pub fn is_track_finished(&self) -> bool {
-        if let (Some(start), Some(duration)) = (self.start_time, self.duration) {
-            start.elapsed() >= duration
+        if let (Some(elapsed), Some(duration)) = (self.elapsed(), self.duration) {
+            elapsed >= duration
         } else {
             false
         }
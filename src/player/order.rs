@@ -0,0 +1,460 @@
+// The order tracks play in: the playlist's own or shuffled, and what happens
+// when a track or the whole playlist ends
+
+use super::MusicPlayer;
+use crate::utils;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Repeat {
+    // Stop after the last track
+    Off,
+    All,
+    // Play the current track over and over
+    One,
+}
+
+impl FromStr for Repeat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" | "none" => Ok(Repeat::Off),
+            "all" | "playlist" => Ok(Repeat::All),
+            "one" | "track" => Ok(Repeat::One),
+            _ => Err(format!("unknown repeat mode '{}'", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shuffle {
+    Off,
+    Tracks,
+    // Albums in a random order, each album's tracks in playlist order
+    Albums,
+    // Rated and long-unplayed tracks are more likely to come up early
+    Smart,
+}
+
+impl FromStr for Shuffle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" | "false" => Ok(Shuffle::Off),
+            "tracks" | "on" | "true" => Ok(Shuffle::Tracks),
+            "albums" | "album" => Ok(Shuffle::Albums),
+            "smart" => Ok(Shuffle::Smart),
+            _ => Err(format!("unknown shuffle mode '{}'", s)),
+        }
+    }
+}
+
+/// What happens when the play order runs out and repeat is on, from
+/// `queue_end`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueueEnd {
+    Repeat,
+    Stop,
+    // Endless play from the library
+    Fill,
+    // Stop, then the app shuts the machine down
+    Shutdown,
+    // Stop, then the app runs `on_queue_end` as a plugin
+    Hook,
+}
+
+impl QueueEnd {
+    pub fn name(self) -> &'static str {
+        match self {
+            QueueEnd::Repeat => "repeat",
+            QueueEnd::Stop => "stop",
+            QueueEnd::Fill => "fill",
+            QueueEnd::Shutdown => "shutdown",
+            QueueEnd::Hook => "hook",
+        }
+    }
+
+    pub fn icon(self) -> &'static str {
+        match self {
+            QueueEnd::Repeat => "🔁",
+            QueueEnd::Stop => "⏹",
+            QueueEnd::Fill => "∞",
+            QueueEnd::Shutdown => "⏻",
+            QueueEnd::Hook => "⚙",
+        }
+    }
+}
+
+impl FromStr for QueueEnd {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "repeat" => Ok(QueueEnd::Repeat),
+            "stop" => Ok(QueueEnd::Stop),
+            "fill" | "endless" => Ok(QueueEnd::Fill),
+            "shutdown" | "poweroff" => Ok(QueueEnd::Shutdown),
+            "hook" => Ok(QueueEnd::Hook),
+            _ => Err(format!("unknown end of queue action '{}'", s)),
+        }
+    }
+}
+
+impl MusicPlayer {
+    pub fn repeat(&self) -> Repeat {
+        self.repeat
+    }
+
+    pub fn set_repeat(&mut self, repeat: Repeat) {
+        self.repeat = repeat;
+    }
+
+    pub fn queue_end(&self) -> QueueEnd {
+        self.queue_end
+    }
+
+    pub fn cycle_repeat(&mut self) {
+        self.repeat = match self.repeat {
+            Repeat::All => Repeat::One,
+            Repeat::One => Repeat::Off,
+            Repeat::Off => Repeat::All,
+        };
+    }
+
+    pub fn shuffle(&self) -> Shuffle {
+        self.shuffle
+    }
+
+    /// Sets how tracks are shuffled. Each shuffle is a new order in which
+    /// every track plays once, starting with the current one, or with the
+    /// current album when shuffling albums.
+    pub fn set_shuffle(&mut self, shuffle: Shuffle) {
+        self.shuffle = shuffle;
+        self.order = match shuffle {
+            Shuffle::Off => None,
+            Shuffle::Tracks => {
+                let mut order: Vec<usize> = (0..self.tracks.len()).collect();
+                shuffle_slice(&mut order);
+                if let Some(current) = self.current_track {
+                    order.retain(|&i| i != current);
+                    order.insert(0, current);
+                }
+                Some(order)
+            }
+            Shuffle::Albums => {
+                let mut albums: Vec<Vec<usize>> = Vec::new();
+                let mut positions = HashMap::new();
+                for index in 0..self.tracks.len() {
+                    let position = *positions.entry(self.album_of(index)).or_insert_with(|| {
+                        albums.push(Vec::new());
+                        albums.len() - 1
+                    });
+                    albums[position].push(index);
+                }
+                shuffle_slice(&mut albums);
+                if let Some(current) = self.current_track {
+                    if let Some(position) = albums.iter().position(|tracks| tracks.contains(&current)) {
+                        let album = albums.remove(position);
+                        albums.insert(0, album);
+                    }
+                }
+                Some(albums.concat())
+            }
+            Shuffle::Smart => {
+                // Weighted random order after Efraimidis and Spirakis: each
+                // track draws u^(1/weight) and the highest draws go first
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+                let mut draws: Vec<(f64, usize)> = (0..self.tracks.len())
+                    .map(|index| (random_unit().powf(1.0 / self.smart_weight(index, now)), index))
+                    .collect();
+                draws.sort_by(|a, b| b.0.total_cmp(&a.0));
+                let mut order: Vec<usize> = draws.into_iter().map(|(_, index)| index).collect();
+                if let Some(current) = self.current_track {
+                    order.retain(|&i| i != current);
+                    order.insert(0, current);
+                }
+                Some(order)
+            }
+        };
+    }
+
+    // Unrated tracks count as three stars, and tracks unplayed for a month or
+    // more, or never played, as fully due
+    fn smart_weight(&self, index: usize, now: u64) -> f64 {
+        let (rating_weight, recency_weight) = self.smart_weights;
+        let record = self.library.get(&self.tracks[index]);
+        let stars = f64::from(record.and_then(|record| record.rating).unwrap_or(3));
+        let days = record
+            .and_then(|record| record.last_played)
+            .map_or(f64::INFINITY, |played| now.saturating_sub(played) as f64 / 86400.0);
+        1.0 + rating_weight * (stars - 1.0) / 4.0 + recency_weight * (days / 30.0).min(1.0)
+    }
+
+    /// Goes from off to shuffling tracks, albums, then smart shuffle, and
+    /// off again.
+    pub fn cycle_shuffle(&mut self) {
+        self.set_shuffle(match self.shuffle {
+            Shuffle::Off => Shuffle::Tracks,
+            Shuffle::Tracks => Shuffle::Albums,
+            Shuffle::Albums => Shuffle::Smart,
+            Shuffle::Smart => Shuffle::Off,
+        });
+    }
+
+    // Tracks on an album are told apart by folder and album tag, so untagged
+    // tracks still group by folder
+    fn album_of(&self, index: usize) -> (Option<&Path>, Option<&str>) {
+        let track = &self.tracks[index];
+        let album = self.library.get(track).and_then(|record| record.album.as_deref());
+        (track.parent(), album)
+    }
+
+    // Tracks added while shuffling go somewhere in the part not yet played,
+    // or after the rest of their album when shuffling albums
+    pub(super) fn shuffle_in(&mut self, index: usize) {
+        let played = self.order_position(self.current_track).map_or(0, |position| position + 1);
+        let Some(order) = &self.order else {
+            return;
+        };
+        let played = played.min(order.len());
+        let position = match self.shuffle {
+            Shuffle::Albums => {
+                let album = self.album_of(index);
+                match order.iter().rposition(|&i| self.album_of(i) == album) {
+                    Some(last) => last + 1,
+                    // A new album starts where another one does
+                    None => {
+                        let starts: Vec<usize> = (played..=order.len())
+                            .filter(|&p| {
+                                p == played || p == order.len() || self.album_of(order[p - 1]) != self.album_of(order[p])
+                            })
+                            .collect();
+                        starts[random_below(starts.len())]
+                    }
+                }
+            }
+            _ => played + random_below(order.len() - played + 1),
+        };
+        if let Some(order) = &mut self.order {
+            order.insert(position, index);
+        }
+    }
+
+    /// Whether the rules for files that aren't music, set with
+    /// `skip_shorter_than` and `skip_patterns`, apply in this session; None
+    /// when there are none.
+    pub fn auto_skips(&self) -> Option<bool> {
+        let rules = !self.skip_shorter.is_zero() || !self.skip_patterns.is_empty();
+        rules.then_some(self.auto_skip)
+    }
+
+    /// Turns the rules off until the player restarts, or back on.
+    pub fn toggle_auto_skip(&mut self) {
+        self.auto_skip = !self.auto_skip;
+    }
+
+    // Never-play tracks, and while the rules apply short ones and those named
+    // like a pattern; tracks of unknown length aren't short
+    fn shuffle_skips(&self, index: usize) -> bool {
+        if self.never_plays(index) {
+            return true;
+        }
+        if self.auto_skips() != Some(true) {
+            return false;
+        }
+        let track = &self.tracks[index];
+        let length = self.library.get(track).and_then(|record| record.duration);
+        if length.is_some_and(|length| length < self.skip_shorter) {
+            return true;
+        }
+        let name = track.file_name().unwrap_or_default().to_string_lossy();
+        self.skip_patterns.iter().any(|pattern| utils::glob_match(pattern, &name))
+    }
+
+    fn order_position(&self, index: Option<usize>) -> Option<usize> {
+        let index = index?;
+        match &self.order {
+            Some(order) => order.iter().position(|&i| i == index),
+            None => Some(index),
+        }
+    }
+
+    fn at_position(&self, position: usize) -> usize {
+        match &self.order {
+            Some(order) => order[position],
+            None => position,
+        }
+    }
+
+    /// Plays a track after the current one, before the rest of the play
+    /// order. When shuffling, the shuffled order goes on from there.
+    pub fn queue_next(&mut self, index: usize) {
+        if index >= self.tracks.len() || self.queued.contains(&index) {
+            return;
+        }
+        self.queued.push(index);
+        let after = self.order_position(self.current_track).map_or(0, |position| position + 1);
+        if let Some(order) = &mut self.order {
+            if let Some(position) = order.iter().position(|&i| i == index) {
+                order.remove(position);
+                let after = if position < after { after - 1 } else { after };
+                order.insert(after.min(order.len()), index);
+            }
+        }
+    }
+
+    /// The track after the current one when skipping: one queued to play
+    /// next, or the next in order, wrapping around and passing over vetoed
+    /// tracks, and those shuffle skips when shuffling, unless nothing else
+    /// is left.
+    pub(super) fn next_index(&self) -> Option<usize> {
+        let len = self.tracks.len();
+        if len == 0 {
+            return None;
+        }
+        if let Some(&queued) = self.queued.first() {
+            return Some(queued);
+        }
+        let next = self.order_position(self.current_track).map_or(0, |position| position + 1);
+        let shuffling = self.shuffle != Shuffle::Off;
+        (0..len)
+            .map(|step| self.at_position((next + step) % len))
+            .find(|&index| !(self.vetoed.contains(&index) || shuffling && self.shuffle_skips(index)))
+            .or(Some(self.at_position(next % len)))
+    }
+
+    pub(super) fn previous_index(&self) -> Option<usize> {
+        let len = self.tracks.len();
+        if len == 0 {
+            return None;
+        }
+        let previous = self.order_position(self.current_track).map_or(len - 1, |position| (position + len - 1) % len);
+        Some(self.at_position(previous))
+    }
+
+    /// The track that plays after the current one, as far as can be told
+    /// before it ends: endless play may still add one.
+    pub fn up_next(&self) -> Option<usize> {
+        self.following()
+    }
+
+    /// Up to `limit` tracks after the current one, in the order they will
+    /// play as things stand: those queued to play next, then the rest of the
+    /// play order up to its end, without vetoed tracks and those shuffle
+    /// skips.
+    pub fn upcoming(&self, limit: usize) -> Vec<usize> {
+        let mut upcoming: Vec<usize> = self.queued.iter().copied().filter(|&index| Some(index) != self.current_track).collect();
+        let from = self.order_position(self.current_track).map_or(0, |position| position + 1);
+        let shuffling = self.shuffle != Shuffle::Off;
+        let later = (from..self.tracks.len())
+            .map(|position| self.at_position(position))
+            .filter(|index| !upcoming.contains(index) && !self.vetoed.contains(index))
+            .filter(|&index| !(shuffling && self.shuffle_skips(index)))
+            .take(limit.saturating_sub(upcoming.len()))
+            .collect::<Vec<_>>();
+        upcoming.extend(later);
+        upcoming.truncate(limit);
+        upcoming
+    }
+
+    /// Length of the whole playlist, and the listening time left until the
+    /// play order reaches its end: the rest of the current track, tracks
+    /// queued to play next and those after it in order, at the current
+    /// speed. Tracks whose length isn't known yet count as nothing.
+    pub fn queue_time(&self) -> (Duration, Duration) {
+        let length = |index: usize| self.library.get(&self.tracks[index]).and_then(|record| record.duration).unwrap_or_default();
+        let total = (0..self.tracks.len()).map(length).sum();
+        let Some(current) = self.current_track else {
+            return (total, total);
+        };
+        let end = self.end.or(self.duration).unwrap_or_default();
+        let mut left = end.saturating_sub(self.elapsed().unwrap_or_default());
+        let from = self.order_position(Some(current)).map_or(0, |position| position + 1);
+        let later: Vec<usize> = (from..self.tracks.len()).map(|position| self.at_position(position)).collect();
+        for &index in self.queued.iter().filter(|index| !later.contains(index) && **index != current) {
+            left += length(index);
+        }
+        for index in later.into_iter().filter(|index| !self.vetoed.contains(index)) {
+            left += length(index);
+        }
+        (total, left.div_f32(self.speed()))
+    }
+
+    /// Passes over the track coming up next, once. Vetoing again passes over
+    /// the one after it too.
+    pub fn veto_up_next(&mut self) {
+        if let Some(next) = self.following() {
+            self.vetoed.insert(next);
+        }
+    }
+
+    /// The track to play when the current one ends by itself, None when
+    /// playback should stop.
+    pub(super) fn following(&self) -> Option<usize> {
+        match self.repeat {
+            Repeat::One => self
+                .current_track
+                .filter(|current| self.queued.is_empty() && !self.vetoed.contains(current))
+                .or_else(|| self.next_index()),
+            Repeat::All if self.queue_end == QueueEnd::Repeat => self.next_index(),
+            Repeat::All | Repeat::Off => self.next_index().filter(|&next| !self.wraps_to(next)),
+        }
+    }
+
+    // Whether going on to a track starts the playlist over; a track queued
+    // to play next never does
+    pub(super) fn wraps_to(&self, next: usize) -> bool {
+        if self.queued.first() == Some(&next) {
+            return false;
+        }
+        match (self.order_position(self.current_track), self.order_position(Some(next))) {
+            (Some(current), Some(next)) => next <= current,
+            _ => false,
+        }
+    }
+
+    // Notes when automatic advancing wraps around to the first track
+    pub(super) fn note_wrap(&mut self, next: usize) {
+        if self.repeat != Repeat::One && self.wraps_to(next) {
+            self.queue_ended = true;
+        }
+    }
+}
+
+// Fisher-Yates
+fn shuffle_slice<T>(items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+        items.swap(i, random_below(i + 1));
+    }
+}
+
+// A pseudo-random number in (0, 1]
+fn random_unit() -> f64 {
+    const SCALE: usize = 1 << 30;
+    (random_below(SCALE) + 1) as f64 / SCALE as f64
+}
+
+// A pseudo-random number below `bound`, good enough for shuffling
+pub(super) fn random_below(bound: usize) -> usize {
+    static STATE: AtomicU64 = AtomicU64::new(0);
+    let mut x = STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        x = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0x2545_f491_4f6c_dd1d)
+            | 1;
+    }
+    // xorshift64
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    STATE.store(x, Ordering::Relaxed);
+    (x % bound.max(1) as u64) as usize
+}
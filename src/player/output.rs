@@ -0,0 +1,203 @@
+use rodio::cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use rodio::cpal::{
+    BufferSize, Device, FromSample, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig, SupportedBufferSize,
+    SupportedStreamConfig,
+};
+use rodio::dynamic_mixer::{self, DynamicMixer, DynamicMixerController};
+use rodio::Sink;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// How large a buffer to ask the device for. Larger ones add latency but
+/// leave more time to decode, which slow machines need.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputBuffer {
+    // Whatever the audio system picks
+    Auto,
+    Frames(u32),
+    Millis(u32),
+}
+
+impl FromStr for OutputBuffer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        let parsed = match s.strip_suffix("ms") {
+            _ if s == "auto" => Some(OutputBuffer::Auto),
+            Some(millis) => millis.trim().parse().ok().filter(|&millis| millis > 0).map(OutputBuffer::Millis),
+            None => s.parse().ok().filter(|&frames| frames > 0).map(OutputBuffer::Frames),
+        };
+        parsed.ok_or_else(|| format!("expected auto, a number of frames or milliseconds such as 50ms, not '{}'", s))
+    }
+}
+
+impl fmt::Display for OutputBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputBuffer::Auto => write!(f, "auto"),
+            OutputBuffer::Frames(frames) => write!(f, "{}", frames),
+            OutputBuffer::Millis(millis) => write!(f, "{}ms", millis),
+        }
+    }
+}
+
+#[derive(Default)]
+struct OutputStats {
+    // Frames asked for by the last callback
+    frames: AtomicU32,
+    // Callbacks that took longer to fill than the sound they held lasts, so
+    // the device ran dry
+    underruns: AtomicU64,
+}
+
+/// The main output: mixes the sinks played on it, like rodio's stream, but
+/// opened with the configured buffer size and counting underruns.
+pub struct Output {
+    _stream: Stream,
+    mixer: Arc<DynamicMixerController<f32>>,
+    stats: Arc<OutputStats>,
+    sample_rate: u32,
+}
+
+impl Output {
+    /// Opens the device at `config`, falling back to the device's own buffer
+    /// size when it refuses the configured one.
+    pub fn open(device: &Device, config: SupportedStreamConfig, buffer: OutputBuffer) -> Result<Output, Box<dyn Error>> {
+        let sample_rate = config.sample_rate().0;
+        let frames = match buffer {
+            OutputBuffer::Auto => None,
+            OutputBuffer::Frames(frames) => Some(frames),
+            OutputBuffer::Millis(millis) => Some((sample_rate as u64 * millis as u64 / 1000).max(1) as u32),
+        };
+        let frames = match (frames, config.buffer_size()) {
+            (Some(frames), SupportedBufferSize::Range { min, max }) => Some(frames.clamp(*min, (*max).max(*min))),
+            (frames, _) => frames,
+        };
+        let mut sizes = vec![BufferSize::Default];
+        if let Some(frames) = frames {
+            sizes.insert(0, BufferSize::Fixed(frames));
+        }
+
+        let mut error = None;
+        for buffer_size in sizes {
+            let (mixer, input) = dynamic_mixer::mixer::<f32>(config.channels(), sample_rate);
+            let stats = Arc::new(OutputStats::default());
+            let stream_config = StreamConfig { buffer_size, ..config.config() };
+            match build_stream(device, &stream_config, config.sample_format(), input, stats.clone()) {
+                Ok(stream) => {
+                    stream.play()?;
+                    return Ok(Output { _stream: stream, mixer, stats, sample_rate });
+                }
+                Err(e) => {
+                    log::warn!(target: "playback", "could not open the output with buffer size {:?}: {}", buffer_size, e);
+                    error = Some(e);
+                }
+            }
+        }
+        Err(error.unwrap_or_else(|| "no output stream".into()))
+    }
+
+    /// A sink playing on this output.
+    pub fn sink(&self) -> Sink {
+        let (sink, queue) = Sink::new_idle();
+        self.mixer.add(queue);
+        sink
+    }
+
+    /// Frames the device asks for at a time, once it has asked, and the
+    /// latency they add in milliseconds.
+    pub fn buffer(&self) -> Option<(u32, f32)> {
+        let frames = self.stats.frames.load(Ordering::Relaxed);
+        (frames > 0).then(|| (frames, frames as f32 * 1000.0 / self.sample_rate.max(1) as f32))
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.stats.underruns.load(Ordering::Relaxed)
+    }
+}
+
+fn build_stream(
+    device: &Device,
+    config: &StreamConfig,
+    format: SampleFormat,
+    mixer: DynamicMixer<f32>,
+    stats: Arc<OutputStats>,
+) -> Result<Stream, Box<dyn Error>> {
+    Ok(match format {
+        SampleFormat::F32 => build::<f32>(device, config, mixer, stats)?,
+        SampleFormat::F64 => build::<f64>(device, config, mixer, stats)?,
+        SampleFormat::I8 => build::<i8>(device, config, mixer, stats)?,
+        SampleFormat::I16 => build::<i16>(device, config, mixer, stats)?,
+        SampleFormat::I32 => build::<i32>(device, config, mixer, stats)?,
+        SampleFormat::I64 => build::<i64>(device, config, mixer, stats)?,
+        SampleFormat::U8 => build::<u8>(device, config, mixer, stats)?,
+        SampleFormat::U16 => build::<u16>(device, config, mixer, stats)?,
+        SampleFormat::U32 => build::<u32>(device, config, mixer, stats)?,
+        SampleFormat::U64 => build::<u64>(device, config, mixer, stats)?,
+        format => return Err(format!("unsupported sample format {}", format).into()),
+    })
+}
+
+fn build<T: SizedSample + FromSample<f32>>(
+    device: &Device,
+    config: &StreamConfig,
+    mut mixer: DynamicMixer<f32>,
+    stats: Arc<OutputStats>,
+) -> Result<Stream, rodio::cpal::BuildStreamError> {
+    let samples_per_second = config.channels as f64 * config.sample_rate.0 as f64;
+    let channels = config.channels.max(1) as u32;
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            let started = Instant::now();
+            for sample in data.iter_mut() {
+                *sample = T::from_sample(mixer.next().unwrap_or(0.0));
+            }
+            stats.frames.store(data.len() as u32 / channels, Ordering::Relaxed);
+            if started.elapsed().as_secs_f64() > data.len() as f64 / samples_per_second {
+                stats.underruns.fetch_add(1, Ordering::Relaxed);
+            }
+        },
+        |e| log::warn!(target: "playback", "output stream error: {}", e),
+        None,
+    )
+}
+
+// The configured output device, or the default one when no name is given
+pub fn find_device(name: &str) -> Result<Device, Box<dyn Error>> {
+    let host = rodio::cpal::default_host();
+    if name.is_empty() {
+        return Ok(host.default_output_device().ok_or("no output device")?);
+    }
+    host.output_devices()?
+        .find(|device| device.name().is_ok_and(|device_name| device_name == name))
+        .ok_or_else(|| format!("output device '{}' not found", name).into())
+}
+
+// The format the device runs at when nothing in particular is asked for
+pub fn default_config(device: &Device) -> Result<SupportedStreamConfig, Box<dyn Error>> {
+    Ok(device.default_output_config()?)
+}
+
+// A device configuration playing the track's samples unchanged, if the device
+// has one. Integer formats wide enough for the source are preferred.
+pub fn exact_config(device: &Device, channels: u16, sample_rate: u32, bits: Option<u32>) -> Option<SupportedStreamConfig> {
+    let rank = |format: SampleFormat| match format {
+        SampleFormat::I32 if bits.unwrap_or(24) > 16 => 0,
+        SampleFormat::I16 if bits.unwrap_or(16) <= 16 => 0,
+        SampleFormat::F32 => 1,
+        SampleFormat::I32 => 2,
+        _ => 3,
+    };
+    device
+        .supported_output_configs()
+        .ok()?
+        .filter(|range| range.channels() == channels)
+        .filter_map(|range| range.try_with_sample_rate(SampleRate(sample_rate)))
+        .min_by_key(|config| rank(config.sample_format()))
+}
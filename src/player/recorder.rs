@@ -0,0 +1,154 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// A WAV file holds at most 4 GiB; past this a new file is started, about
+// every 6 hours at 44.1 kHz stereo
+const MAX_DATA_LEN: u64 = (u32::MAX - 36) as u64;
+
+pub enum RecorderMessage {
+    Samples { channels: u16, sample_rate: u32, samples: Vec<f32> },
+    Finish,
+}
+
+/// Writes the processed output to 16-bit WAV files on a background thread.
+/// A new file is started whenever the channel count or sample rate changes,
+/// or the current file is full.
+pub struct Recorder {
+    tx: Sender<RecorderMessage>,
+    thread: Option<JoinHandle<()>>,
+    // Why the thread stopped writing, when it did
+    failure: Receiver<String>,
+    pub path: PathBuf,
+}
+
+impl Recorder {
+    pub fn start(dir: &Path) -> io::Result<Recorder> {
+        fs::create_dir_all(dir)?;
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+        let path = dir.join(format!("clap-recording-{}.wav", stamp));
+
+        let (tx, rx) = mpsc::channel();
+        let (failed, failure) = mpsc::channel();
+        let base = path.clone();
+        let thread = thread::spawn(move || {
+            let mut writer: Option<WavWriter> = None;
+            let mut files = 0;
+            for message in rx {
+                let RecorderMessage::Samples { channels, sample_rate, samples } = message else {
+                    break;
+                };
+                let full = writer
+                    .as_ref()
+                    .is_some_and(|w| w.data_len as u64 + samples.len() as u64 * 2 > MAX_DATA_LEN);
+                if full || writer.as_ref().map(|w| (w.channels, w.sample_rate)) != Some((channels, sample_rate)) {
+                    if let Some(writer) = writer.take() {
+                        let _ = writer.finish();
+                    }
+                    files += 1;
+                    let path = if files == 1 {
+                        base.clone()
+                    } else {
+                        base.with_extension(format!("{}.wav", files))
+                    };
+                    match WavWriter::create(&path, channels, sample_rate) {
+                        Ok(created) => writer = Some(created),
+                        Err(e) => {
+                            let _ = failed.send(format!("could not create {}: {}", path.display(), e));
+                            return;
+                        }
+                    }
+                }
+                if let Some(writer) = writer.as_mut() {
+                    if let Err(e) = writer.write(&samples) {
+                        let _ = failed.send(format!("could not write the recording: {}", e));
+                        break;
+                    }
+                }
+            }
+            if let Some(writer) = writer {
+                let _ = writer.finish();
+            }
+        });
+
+        Ok(Recorder { tx, thread: Some(thread), failure, path })
+    }
+
+    /// Why the recording stopped, once nothing more is being written.
+    pub fn failure(&self) -> Option<String> {
+        self.failure.try_recv().ok()
+    }
+
+    pub fn sender(&self) -> Sender<RecorderMessage> {
+        self.tx.clone()
+    }
+}
+
+// Dropping the recorder finishes the current file
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.tx.send(RecorderMessage::Finish);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct WavWriter {
+    file: BufWriter<File>,
+    channels: u16,
+    sample_rate: u32,
+    data_len: u32,
+}
+
+impl WavWriter {
+    fn create(path: &Path, channels: u16, sample_rate: u32) -> io::Result<WavWriter> {
+        let mut writer = WavWriter {
+            file: BufWriter::new(File::create(path)?),
+            channels,
+            sample_rate,
+            data_len: 0,
+        };
+        // The sizes are filled in once recording finishes
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let block_align = self.channels * 2;
+        let f = &mut self.file;
+        f.write_all(b"RIFF")?;
+        f.write_all(&self.data_len.saturating_add(36).to_le_bytes())?;
+        f.write_all(b"WAVEfmt ")?;
+        f.write_all(&16u32.to_le_bytes())?;
+        f.write_all(&1u16.to_le_bytes())?;
+        f.write_all(&self.channels.to_le_bytes())?;
+        f.write_all(&self.sample_rate.to_le_bytes())?;
+        f.write_all(&(self.sample_rate * block_align as u32).to_le_bytes())?;
+        f.write_all(&block_align.to_le_bytes())?;
+        f.write_all(&16u16.to_le_bytes())?;
+        f.write_all(b"data")?;
+        f.write_all(&self.data_len.to_le_bytes())
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+        self.data_len = self.data_len.saturating_add(samples.len() as u32 * 2);
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.file.flush()
+    }
+}
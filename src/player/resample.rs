@@ -0,0 +1,161 @@
+// Sample rate conversion for speed changes and for devices running at
+// another rate than the track. Converting here, rather than leaving it to the
+// output, lets the quality be chosen.
+
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+// Input frames on each side of the output point the sinc filter looks at
+const SINC_HALF_TAPS: usize = 16;
+// Filter phases worked out in advance, the ones between are interpolated
+const SINC_PHASES: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resampling {
+    // Straight lines between samples: cheap, but dulls the highs and lets
+    // some aliasing through
+    Linear,
+    // Windowed sinc interpolation
+    Sinc,
+}
+
+impl Resampling {
+    pub fn name(self) -> &'static str {
+        match self {
+            Resampling::Linear => "linear",
+            Resampling::Sinc => "sinc",
+        }
+    }
+
+    fn half_taps(self) -> usize {
+        match self {
+            Resampling::Linear => 1,
+            Resampling::Sinc => SINC_HALF_TAPS,
+        }
+    }
+}
+
+impl FromStr for Resampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "linear" => Ok(Resampling::Linear),
+            "sinc" => Ok(Resampling::Sinc),
+            _ => Err(format!("unknown resampler '{}'", s)),
+        }
+    }
+}
+
+/// Steps through input frames at any rate, interpolating between them.
+pub struct Resampler {
+    quality: Resampling,
+    channels: usize,
+    // The last input frames, interleaved. The output point lies `phase` of
+    // the way from the middle two to the next.
+    frames: VecDeque<f32>,
+    phase: f64,
+    // Sinc filter weights for each phase and the cutoff they were made for,
+    // as a share of the input's Nyquist frequency
+    table: Vec<f32>,
+    cutoff: f64,
+}
+
+impl Resampler {
+    pub fn new(quality: Resampling, channels: usize) -> Self {
+        let taps = 2 * quality.half_taps();
+        Resampler {
+            quality,
+            channels,
+            frames: std::iter::repeat_n(0.0, taps * channels).collect(),
+            phase: 1.0,
+            table: Vec::new(),
+            cutoff: 0.0,
+        }
+    }
+
+    pub fn quality(&self) -> Resampling {
+        self.quality
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// True until enough input is in for the next output frame.
+    pub fn needs_input(&self) -> bool {
+        self.phase >= 1.0
+    }
+
+    pub fn push(&mut self, frame: &[f32]) {
+        self.frames.drain(..self.channels);
+        self.frames.extend(frame.iter().copied().chain(std::iter::repeat(0.0)).take(self.channels));
+        self.phase -= 1.0;
+    }
+
+    /// Writes the next output frame and moves on by `step` input frames.
+    /// With `band_limit`, the sinc filter also cuts what the output rate can't
+    /// hold when it is lower.
+    pub fn output(&mut self, frame: &mut Vec<f32>, step: f64, band_limit: bool) {
+        frame.clear();
+        frame.resize(self.channels, 0.0);
+        let phase = self.phase.clamp(0.0, 1.0);
+        match self.quality {
+            Resampling::Linear => {
+                let t = phase as f32;
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    let (a, b) = (self.frames[channel], self.frames[self.channels + channel]);
+                    *sample = a * (1.0 - t) + b * t;
+                }
+            }
+            Resampling::Sinc => {
+                let cutoff = if band_limit { 0.95 * (1.0 / step).min(1.0) } else { 1.0 };
+                if cutoff != self.cutoff {
+                    self.table = sinc_table(cutoff);
+                    self.cutoff = cutoff;
+                }
+                let taps = 2 * SINC_HALF_TAPS;
+                let position = phase * SINC_PHASES as f64;
+                let index = (position as usize).min(SINC_PHASES - 1);
+                let t = (position - index as f64) as f32;
+                let (row, next) = (&self.table[index * taps..][..taps], &self.table[(index + 1) * taps..][..taps]);
+                for tap in 0..taps {
+                    let weight = row[tap] * (1.0 - t) + next[tap] * t;
+                    for (channel, sample) in frame.iter_mut().enumerate() {
+                        *sample += weight * self.frames[tap * self.channels + channel];
+                    }
+                }
+            }
+        }
+        self.phase += step;
+    }
+}
+
+// Blackman-windowed sinc weights for phases 0 to SINC_PHASES inclusive, each
+// row summing to one so levels stay as they are
+fn sinc_table(cutoff: f64) -> Vec<f32> {
+    let taps = 2 * SINC_HALF_TAPS;
+    let half = SINC_HALF_TAPS as f64;
+    let mut table = Vec::with_capacity((SINC_PHASES + 1) * taps);
+    for phase in 0..=SINC_PHASES {
+        let offset = phase as f64 / SINC_PHASES as f64;
+        let row: Vec<f64> = (0..taps)
+            .map(|tap| {
+                // Distance from the output point, in input frames
+                let x = tap as f64 - (half - 1.0) - offset;
+                let sinc = if x == 0.0 { 1.0 } else { (PI * cutoff * x).sin() / (PI * cutoff * x) };
+                let w = (x / half + 1.0) / 2.0;
+                let window = if (0.0..=1.0).contains(&w) {
+                    0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos()
+                } else {
+                    0.0
+                };
+                sinc * window
+            })
+            .collect();
+        let sum: f64 = row.iter().sum();
+        table.extend(row.iter().map(|weight| (weight / sum) as f32));
+    }
+    table
+}
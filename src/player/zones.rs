@@ -0,0 +1,341 @@
+use super::MusicPlayer;
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::{OutputStream, OutputStreamHandle, Sink, Source};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// A zone starts playing once this much audio is buffered
+const ZONE_LATENCY: Duration = Duration::from_millis(150);
+// Beyond this the oldest audio is dropped, e.g. while a device stalls
+const ZONE_MAX_BUFFER: Duration = Duration::from_secs(2);
+// Clock drift is corrected by dropping or repeating one frame this often
+const DRIFT_CORRECTION_FRAMES: u32 = 1000;
+// Frames collected before they are handed to the zones
+const TEE_CHUNK_FRAMES: usize = 256;
+
+/// A secondary output device playing along with the main one.
+pub struct Zone {
+    pub name: String,
+    pub volume: f32,
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    // One sink per track, so crossfades sound the same as on the main output
+    sinks: Vec<Sink>,
+}
+
+// Audio handed from the main output to one zone
+pub struct ZoneBuffer {
+    samples: Mutex<VecDeque<f32>>,
+    // Set once the track has been fully handed over
+    closed: AtomicBool,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl ZoneBuffer {
+    fn new(channels: u16, sample_rate: u32) -> Self {
+        ZoneBuffer {
+            samples: Mutex::new(VecDeque::new()),
+            closed: AtomicBool::new(false),
+            channels: channels.max(1),
+            sample_rate,
+        }
+    }
+
+    fn frames(&self, duration: Duration) -> usize {
+        (duration.as_secs_f64() * self.sample_rate as f64) as usize
+    }
+}
+
+// Zones fed by the track playing on a sink, shared with its tee
+pub type ZoneTargets = Arc<Mutex<Vec<Arc<ZoneBuffer>>>>;
+
+/// Passes a source through unchanged while copying it to every zone.
+pub struct ZoneTee<S> {
+    input: S,
+    targets: ZoneTargets,
+    buffers: Vec<Arc<ZoneBuffer>>,
+    pending: Vec<f32>,
+}
+
+impl<S: Source<Item = f32>> ZoneTee<S> {
+    pub fn new(input: S, targets: ZoneTargets) -> Self {
+        ZoneTee {
+            input,
+            targets,
+            buffers: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    fn flush(&mut self) {
+        for buffer in &self.buffers {
+            let mut samples = buffer.samples.lock().unwrap();
+            samples.extend(&self.pending);
+            let max = buffer.frames(ZONE_MAX_BUFFER) * buffer.channels as usize;
+            if samples.len() > max {
+                let excess = samples.len() - max;
+                samples.drain(..excess - excess % buffer.channels as usize);
+            }
+        }
+        self.pending.clear();
+        // Zones only join between chunks, which always end on a frame boundary
+        if let Ok(targets) = self.targets.try_lock() {
+            self.buffers.clone_from(&targets);
+        }
+    }
+}
+
+impl<S> Drop for ZoneTee<S> {
+    fn drop(&mut self) {
+        for buffer in &self.buffers {
+            buffer.samples.lock().unwrap().extend(&self.pending);
+            buffer.closed.store(true, Ordering::Relaxed);
+        }
+        // Zones that joined after the last chunk would otherwise wait forever
+        for buffer in self.targets.lock().unwrap().iter() {
+            buffer.closed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for ZoneTee<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.input.next()?;
+        self.pending.push(sample);
+        if self.pending.len() >= TEE_CHUNK_FRAMES * self.input.channels().max(1) as usize {
+            self.flush();
+        }
+        Some(sample)
+    }
+}
+
+impl<S: Source<Item = f32>> Source for ZoneTee<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+/// Plays a zone buffer on the zone's own device, keeping the buffer around
+/// its target latency to make up for the two devices' clocks drifting apart.
+struct ZoneSource {
+    buffer: Arc<ZoneBuffer>,
+    frame: Vec<f32>,
+    pos: usize,
+    buffering: bool,
+    frames_until_correction: u32,
+}
+
+impl ZoneSource {
+    fn new(buffer: Arc<ZoneBuffer>) -> Self {
+        let channels = buffer.channels as usize;
+        ZoneSource {
+            buffer,
+            frame: vec![0.0; channels],
+            pos: channels,
+            buffering: true,
+            frames_until_correction: DRIFT_CORRECTION_FRAMES,
+        }
+    }
+
+    fn fill_frame(&mut self) -> bool {
+        let channels = self.buffer.channels as usize;
+        let target = self.buffer.frames(ZONE_LATENCY);
+        let closed = self.buffer.closed.load(Ordering::Relaxed);
+        let mut samples = self.buffer.samples.lock().unwrap();
+        let frames = samples.len() / channels;
+        self.pos = 0;
+
+        if frames == 0 {
+            if closed {
+                return false;
+            }
+            // Underrun or paused main output, play silence until enough is buffered
+            self.buffering = true;
+            self.frame.fill(0.0);
+            return true;
+        }
+        if self.buffering {
+            if frames < target && !closed {
+                self.frame.fill(0.0);
+                return true;
+            }
+            self.buffering = false;
+        }
+
+        self.frames_until_correction -= 1;
+        if self.frames_until_correction == 0 {
+            self.frames_until_correction = DRIFT_CORRECTION_FRAMES;
+            if frames > target * 2 {
+                samples.drain(..channels);
+            } else if frames < target / 2 && !closed {
+                // Repeat the previous frame to let the buffer catch up
+                return true;
+            }
+        }
+
+        for sample in self.frame.iter_mut() {
+            *sample = samples.pop_front().unwrap_or(0.0);
+        }
+        true
+    }
+}
+
+impl Iterator for ZoneSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.pos >= self.frame.len() && !self.fill_frame() {
+            return None;
+        }
+        let sample = self.frame[self.pos];
+        self.pos += 1;
+        Some(sample)
+    }
+}
+
+impl Source for ZoneSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.buffer.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.buffer.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl MusicPlayer {
+    // Names of the output devices, the main one first
+    pub fn output_devices(&self) -> Vec<String> {
+        let main = super::output::find_device(&self.output_device).and_then(|device| Ok(device.name()?));
+        let mut names: Vec<String> = rodio::cpal::default_host()
+            .output_devices()
+            .map(|devices| devices.filter_map(|device| device.name().ok()).collect())
+            .unwrap_or_default();
+        if let Ok(main) = main {
+            names.retain(|name| *name != main);
+            names.insert(0, main);
+        }
+        names
+    }
+
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+
+    // Adds the device as a zone, or removes it if it already is one
+    pub fn toggle_zone(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        if let Some(i) = self.zones.iter().position(|zone| zone.name == name) {
+            self.zones.remove(i);
+            return Ok(());
+        }
+
+        let device = rodio::cpal::default_host()
+            .output_devices()?
+            .find(|device| device.name().is_ok_and(|device_name| device_name == name))
+            .ok_or_else(|| format!("output device '{}' not found", name))?;
+        let (stream, handle) = OutputStream::try_from_device(&device)?;
+        let mut zone = Zone {
+            name: name.to_string(),
+            volume: 1.0,
+            _stream: stream,
+            handle,
+            sinks: Vec::new(),
+        };
+        if self.sink.is_some() {
+            self.attach_zone(&mut zone)?;
+        }
+        self.zones.push(zone);
+        self.apply_volume();
+        Ok(())
+    }
+
+    pub fn adjust_zone_volume(&mut self, name: &str, delta: f32) {
+        if let Some(zone) = self.zones.iter_mut().find(|zone| zone.name == name) {
+            zone.volume = (zone.volume + delta).clamp(0.0, 1.0);
+        }
+        self.apply_volume();
+    }
+
+    // Starts the current track on the zone, from wherever the main output is
+    fn attach_zone(&self, zone: &mut Zone) -> Result<(), Box<dyn Error>> {
+        let (channels, sample_rate) = self.zone_format;
+        let buffer = Arc::new(ZoneBuffer::new(channels, sample_rate));
+        let sink = Sink::try_new(&zone.handle)?;
+        sink.set_volume(self.output_volume() * zone.volume);
+        sink.append(ZoneSource::new(buffer.clone()));
+        if self.sink.as_ref().is_some_and(|sink| sink.is_paused()) {
+            sink.pause();
+        }
+        self.zone_targets.lock().unwrap().push(buffer);
+        zone.sinks.push(sink);
+        Ok(())
+    }
+
+    // Feeds a new track to every zone through the returned targets
+    pub(super) fn attach_zones(&mut self, format: (u16, u32)) -> Result<ZoneTargets, Box<dyn Error>> {
+        self.zone_targets = ZoneTargets::default();
+        self.zone_format = format;
+        let mut zones = std::mem::take(&mut self.zones);
+        let result = zones.iter_mut().try_for_each(|zone| self.attach_zone(zone));
+        self.zones = zones;
+        result?;
+        Ok(self.zone_targets.clone())
+    }
+
+    pub(super) fn set_zone_volumes(&self, volume: f32) {
+        for zone in &self.zones {
+            for sink in &zone.sinks {
+                sink.set_volume(volume * zone.volume);
+            }
+        }
+    }
+
+    pub(super) fn pause_zones(&self, paused: bool) {
+        for sink in self.zones.iter().flat_map(|zone| &zone.sinks) {
+            if paused {
+                sink.pause();
+            } else {
+                sink.play();
+            }
+        }
+    }
+
+    pub(super) fn stop_zones(&mut self) {
+        for zone in &mut self.zones {
+            zone.sinks.clear();
+        }
+    }
+
+    // Drops the sinks of tracks that finished playing on the zones
+    pub(super) fn prune_zones(&mut self) {
+        for zone in &mut self.zones {
+            zone.sinks.retain(|sink| !sink.empty());
+        }
+    }
+}
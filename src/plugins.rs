@@ -0,0 +1,416 @@
+// Plugins written in Rhai (https://rhai.rs), the `.rhai` files in the
+// `plugins` folder next to the config. Each runs on a thread of its own, so a
+// slow one holds up neither playback nor the others. A plugin drives the
+// player through the requests `music-cli ctl` sends, answered by the main loop
+// as they come, and asks for key bindings and panels with messages of its own.
+// The player calls back into it on events, keys and picks in its panels.
+
+use crate::hooks::Event;
+use crate::ipc;
+use crate::keymap;
+use crate::toast;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST, FLOAT, INT};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+/// What a plugin asks of the main loop.
+pub enum Message {
+    // A request as `music-cli ctl` sends them
+    Request(ipc::Command),
+    // A function of the plugin to run on these keys
+    Bind { plugin: usize, keys: String, function: String },
+    // A list to show over the interface; Enter on an item calls the function
+    // with its index and text
+    Panel { plugin: usize, title: String, items: Vec<String>, function: Option<String> },
+}
+
+type Post = Arc<dyn Fn(Message) + Send + Sync>;
+
+// What the player asks of a plugin
+enum Call {
+    Event(Event),
+    Function(String),
+    Pick(String, usize, String),
+}
+
+struct Plugin {
+    name: String,
+    calls: Sender<Call>,
+}
+
+/// A plugin function bound to keys, `Action::RunPlugin` counting these.
+pub struct Binding {
+    pub keys: String,
+    pub plugin: usize,
+    pub function: String,
+}
+
+#[derive(Default)]
+pub struct Plugins {
+    running: Vec<Plugin>,
+    bindings: Vec<Binding>,
+}
+
+impl Plugins {
+    /// Starts every plugin in `dir`, by file name. Scripts that can't be read
+    /// or don't compile say why in a toast.
+    pub fn load(dir: &Path, now_playing: ipc::Shared, post: impl Fn(Message) + Send + Sync + 'static) -> Plugins {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+            .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+            .unwrap_or_default();
+        paths.retain(|path| path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("rhai")));
+        paths.sort();
+
+        let post: Post = Arc::new(post);
+        let mut running = Vec::new();
+        for path in paths {
+            let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            let script = match fs::read_to_string(&path) {
+                Ok(script) => script,
+                Err(e) => {
+                    toast::error(format!("Could not read the plugin {}: {}", name, e));
+                    continue;
+                }
+            };
+            let (calls, received) = mpsc::channel();
+            let host = Host {
+                plugin: running.len(),
+                name: name.clone(),
+                post: post.clone(),
+                now_playing: now_playing.clone(),
+            };
+            thread::spawn(move || host.run(&script, received));
+            running.push(Plugin { name, calls });
+        }
+        if !running.is_empty() {
+            log::info!(target: "plugins", "started {} plugin(s) from {}", running.len(), dir.display());
+        }
+        Plugins { running, bindings: Vec::new() }
+    }
+
+    pub fn name(&self, plugin: usize) -> &str {
+        &self.running[plugin].name
+    }
+
+    /// Calls `on_track_change` and the like in the plugins defining them.
+    pub fn event(&self, event: Event) {
+        for plugin in &self.running {
+            let _ = plugin.calls.send(Call::Event(event));
+        }
+    }
+
+    /// Records a binding asked for by a plugin, returning its index.
+    pub fn bind(&mut self, plugin: usize, keys: String, function: String) -> usize {
+        self.bindings.push(Binding { keys, plugin, function });
+        self.bindings.len() - 1
+    }
+
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    /// Runs the function bound to keys.
+    pub fn press(&self, binding: usize) {
+        let binding = &self.bindings[binding];
+        let _ = self.running[binding.plugin].calls.send(Call::Function(binding.function.clone()));
+    }
+
+    /// Tells a plugin which item of its panel was picked.
+    pub fn pick(&self, plugin: usize, function: &str, index: usize, item: &str) {
+        let _ = self.running[plugin].calls.send(Call::Pick(function.to_string(), index, item.to_string()));
+    }
+}
+
+// A plugin's end, on its thread
+#[derive(Clone)]
+struct Host {
+    plugin: usize,
+    name: String,
+    post: Post,
+    now_playing: ipc::Shared,
+}
+
+type Result<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+impl Host {
+    fn run(self, script: &str, calls: Receiver<Call>) {
+        let engine = self.engine();
+        let ast = match engine.compile(script) {
+            Ok(ast) => ast,
+            Err(e) => return self.report(&e.to_string()),
+        };
+        let mut scope = Scope::new();
+        if let Err(e) = engine.run_ast_with_scope(&mut scope, &ast) {
+            return self.report(&e.to_string());
+        }
+        // `this` in the plugin's functions, kept from one call to the next
+        let mut state = Dynamic::from_map(Map::new());
+        for received in calls {
+            let result = match received {
+                Call::Event(event) => {
+                    let function = format!("on_{}", event.name());
+                    if !ast.iter_functions().any(|defined| defined.name == function) {
+                        continue;
+                    }
+                    call_function(&engine, &mut scope, &ast, &mut state, &function, ())
+                }
+                Call::Function(function) => call_function(&engine, &mut scope, &ast, &mut state, &function, ()),
+                Call::Pick(function, index, item) => call_function(&engine, &mut scope, &ast, &mut state, &function, (index as INT, item)),
+            };
+            // What a function returns shows in the status bar, as a bound
+            // command's last line does
+            match result {
+                Ok(value) if value.is_string() => {
+                    let _ = self.request(&format!("show {}", value));
+                }
+                Ok(_) => {}
+                Err(e) => self.report(&e.to_string()),
+            }
+        }
+    }
+
+    fn report(&self, error: &str) {
+        log::warn!(target: "plugins", "{}: {}", self.name, error);
+        toast::error(format!("Plugin {}: {}", self.name, error));
+    }
+
+    // Waits for the main loop to answer
+    fn request(&self, request: &str) -> Result<String> {
+        let (command, answer) = ipc::Command::new(request);
+        (self.post)(Message::Request(command));
+        answer.recv().map_err(|_| "the player has quit")?.map_err(Into::into)
+    }
+
+    fn engine(&self) -> Engine {
+        let mut engine = Engine::new();
+        let name = self.name.clone();
+        engine.on_print(move |text| log::info!(target: "plugins", "{}: {}", name, text));
+        let name = self.name.clone();
+        engine.on_debug(move |text, _, _| log::debug!(target: "plugins", "{}: {}", name, text));
+
+        let host = Rc::new(self.clone());
+        // Requests that only say whether they worked
+        let simple = |engine: &mut Engine, name: &str, request: &'static str| {
+            let host = host.clone();
+            engine.register_fn(name, move || host.request(request).map(drop));
+        };
+        simple(&mut engine, "pause", "pause");
+        simple(&mut engine, "resume", "resume");
+        simple(&mut engine, "toggle", "toggle");
+        simple(&mut engine, "stop", "stop");
+        simple(&mut engine, "next", "next");
+        simple(&mut engine, "previous", "previous");
+        simple(&mut engine, "play", "play");
+
+        let h = host.clone();
+        engine.register_fn("request", move |request: &str| h.request(request));
+        let h = host.clone();
+        engine.register_fn("play", move |number: INT| h.request(&format!("play {}", number)).map(drop));
+        let h = host.clone();
+        engine.register_fn("volume", move || -> Result<INT> {
+            h.request("volume")?.parse().map_err(|_| "the player gave no volume".into())
+        });
+        let h = host.clone();
+        engine.register_fn("set_volume", move |volume: INT| h.request(&format!("volume {}", volume)).map(drop));
+        let h = host.clone();
+        engine.register_fn("speed", move || -> Result<FLOAT> {
+            h.request("speed")?.parse().map_err(|_| "the player gave no speed".into())
+        });
+        let h = host.clone();
+        engine.register_fn("set_speed", move |speed: FLOAT| h.request(&format!("speed {}", speed)).map(drop));
+        let h = host.clone();
+        engine.register_fn("seek", move |time: &str| h.request(&format!("seek {}", time)).map(drop));
+        let h = host.clone();
+        engine.register_fn("seek", move |seconds: INT| h.request(&format!("seek {}", seconds)).map(drop));
+        let h = host.clone();
+        engine.register_fn("add", move |location: &str| -> Result<INT> {
+            let reply = h.request(&format!("add {}", location))?;
+            Ok(reply.split_whitespace().nth(1).and_then(|added| added.parse().ok()).unwrap_or(0))
+        });
+        let h = host.clone();
+        engine.register_fn("rate", move |stars: INT| h.request(&format!("rate {}", stars)).map(drop));
+        let h = host.clone();
+        engine.register_fn("tracks", move || -> Result<Array> { Ok(track_maps(&h.request("tracks")?)) });
+        let h = host.clone();
+        engine.register_fn("find", move |text: &str| -> Result<Array> { Ok(track_maps(&h.request(&format!("find {}", text))?)) });
+        let h = host.clone();
+        engine.register_fn("library", move |query: &str| -> Result<Array> {
+            Ok(track_maps(&h.request(&format!("library {}", query))?))
+        });
+        let h = host.clone();
+        engine.register_fn("now_playing", move || now_playing_map(&h.now_playing.lock().unwrap()));
+
+        let h = host.clone();
+        engine.register_fn("show", move |text: &str| h.request(&format!("show {}", text)).map(drop));
+        engine.register_fn("toast", |text: &str| toast::info(text));
+        let h = host.clone();
+        engine.register_fn("bind", move |keys: &str, function: &str| -> Result<()> {
+            if keymap::parse_keys(keys).is_none() {
+                return Err(format!("can't read the keys '{}'", keys).into());
+            }
+            (h.post)(Message::Bind { plugin: h.plugin, keys: keys.to_string(), function: function.to_string() });
+            Ok(())
+        });
+        let h = host.clone();
+        engine.register_fn("panel", move |title: &str, items: Array| h.panel(title, items, None));
+        let h = host;
+        engine.register_fn("panel", move |title: &str, items: Array, function: &str| {
+            h.panel(title, items, Some(function.to_string()))
+        });
+        engine
+    }
+
+    fn panel(&self, title: &str, items: Array, function: Option<String>) {
+        (self.post)(Message::Panel {
+            plugin: self.plugin,
+            title: title.to_string(),
+            items: items.into_iter().map(|item| item.to_string()).collect(),
+            function,
+        });
+    }
+}
+
+fn call_function(engine: &Engine, scope: &mut Scope, ast: &AST, state: &mut Dynamic, function: &str, args: impl rhai::FuncArgs) -> Result<Dynamic> {
+    let options = CallFnOptions::new().eval_ast(false).rewind_scope(false).bind_this_ptr(state);
+    engine.call_fn_with_options(options, scope, ast, function, args)
+}
+
+// Track lines from `tracks`, `find` and `library`: number, artist, title and
+// path, the number empty for tracks not in the playlist
+fn track_maps(lines: &str) -> Array {
+    lines
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            let mut track = Map::new();
+            let number = fields.next().and_then(|number| number.parse::<INT>().ok());
+            track.insert("number".into(), number.map_or(Dynamic::UNIT, Dynamic::from));
+            for key in ["artist", "title", "path"] {
+                track.insert(key.into(), fields.next().unwrap_or_default().into());
+            }
+            Dynamic::from_map(track)
+        })
+        .collect()
+}
+
+fn now_playing_map(now_playing: &crate::now_playing::NowPlaying) -> Map {
+    let mut map = Map::new();
+    map.insert("state".into(), now_playing.state.into());
+    map.insert("title".into(), now_playing.title.clone().into());
+    map.insert("artist".into(), now_playing.artist.clone().into());
+    map.insert("album".into(), now_playing.album.clone().into());
+    map.insert("file".into(), now_playing.file.clone().into());
+    map.insert("number".into(), now_playing.number.map_or(Dynamic::UNIT, |number| (number as INT).into()));
+    map.insert("elapsed".into(), (now_playing.elapsed.as_secs() as INT).into());
+    map.insert("duration".into(), now_playing.duration.map_or(Dynamic::UNIT, |duration| (duration.as_secs() as INT).into()));
+    map.insert("volume".into(), (now_playing.volume as INT).into());
+    map.insert("next".into(), now_playing.next.clone().into());
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, Plugins};
+    use crate::hooks::Event;
+    use crate::ipc;
+    use std::fs;
+    use std::sync::mpsc::{self, Receiver};
+    use std::time::Duration;
+
+    const SCRIPT: &str = r#"
+        bind("F6", "louder");
+
+        fn louder() {
+            set_volume(volume() + 10);
+            this.presses = (this.presses ?? 0) + 1;
+            `Pressed ${this.presses} times`
+        }
+
+        fn on_track_change() {
+            let titles = tracks().map(|track| track.title);
+            panel("Playlist", titles, "picked");
+        }
+
+        fn picked(index, title) {
+            play(index + 1);
+        }
+    "#;
+
+    fn next(messages: &Receiver<Message>) -> Message {
+        messages.recv_timeout(Duration::from_secs(5)).expect("the plugin went quiet")
+    }
+
+    // Answers a request the way the main loop would
+    fn answer(messages: &Receiver<Message>, expected: &str, reply: &str) {
+        match next(messages) {
+            Message::Request(command) => {
+                assert_eq!(command.request, expected);
+                command.reply(Ok(reply.to_string()));
+            }
+            _ => panic!("expected the request '{}'", expected),
+        }
+    }
+
+    #[test]
+    fn script_drives_the_player() {
+        let dir = std::env::temp_dir().join(format!("clap-plugins-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("louder.rhai"), SCRIPT).unwrap();
+        let (sender, messages) = mpsc::channel();
+        let mut plugins = Plugins::load(&dir, ipc::Shared::default(), move |message| sender.send(message).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(plugins.name(0), "louder");
+
+        let Message::Bind { plugin, keys, function } = next(&messages) else {
+            panic!("expected the binding");
+        };
+        assert_eq!((plugin, keys.as_str(), function.as_str()), (0, "F6", "louder"));
+        let binding = plugins.bind(plugin, keys, function);
+
+        // `this` is kept between calls, and returned text goes to the status bar
+        for presses in 1..=2 {
+            plugins.press(binding);
+            answer(&messages, "volume", "40");
+            answer(&messages, "volume 50", "ok");
+            answer(&messages, &format!("show Pressed {} times", presses), "ok");
+        }
+
+        // Events the script has no function for are passed over
+        plugins.event(Event::Pause);
+        plugins.event(Event::TrackChange);
+        answer(&messages, "tracks", "1\tA\tIntro\t/music/a.flac\n2\tB\tOutro\t/music/b.flac");
+        let Message::Panel { plugin, title, items, function } = next(&messages) else {
+            panic!("expected the panel");
+        };
+        assert_eq!((plugin, title.as_str(), function.as_deref()), (0, "Playlist", Some("picked")));
+        assert_eq!(items, ["Intro", "Outro"]);
+
+        plugins.pick(0, "picked", 1, "Outro");
+        answer(&messages, "play 2", "ok");
+    }
+
+    #[test]
+    fn failed_requests_stop_the_function() {
+        let dir = std::env::temp_dir().join(format!("clap-plugins-failing-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("seek.rhai"), "fn on_stop() { seek(\"1:xx\"); show(\"never\"); }").unwrap();
+        let (sender, messages) = mpsc::channel();
+        let plugins = Plugins::load(&dir, ipc::Shared::default(), move |message| sender.send(message).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        plugins.event(Event::Stop);
+        match next(&messages) {
+            Message::Request(command) => {
+                assert_eq!(command.request, "seek 1:xx");
+                command.reply(Err("not a time: 1:xx".to_string()));
+            }
+            _ => panic!("expected the seek"),
+        }
+        assert!(messages.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+}
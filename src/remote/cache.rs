@@ -0,0 +1,166 @@
+// Copies of remote tracks kept in `cache/remote` next to the configuration,
+// so they play again without the network. `index` there has a line per copy,
+// `FILE<TAB>PINNED<TAB>USED<TAB>TRACK`, USED being when it was last played in
+// seconds since 1970, lines in the order they were used within a second.
+// Past the size limit the copies played longest ago go first; pinned ones are
+// never removed for space.
+
+use crate::config::config_dir;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static LIMIT: AtomicU64 = AtomicU64::new(2048 * 1024 * 1024);
+// Reading and rewriting the index happens on download threads too
+static INDEX: Mutex<()> = Mutex::new(());
+
+pub struct Entry {
+    pub track: PathBuf,
+    file: String,
+    pub size: u64,
+    pub pinned: bool,
+    pub used: u64,
+}
+
+pub fn set_limit(megabytes: u64) {
+    LIMIT.store(megabytes * 1024 * 1024, Ordering::Relaxed);
+}
+
+pub fn limit() -> u64 {
+    LIMIT.load(Ordering::Relaxed)
+}
+
+fn dir() -> PathBuf {
+    config_dir().join("cache").join("remote")
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+}
+
+fn file_name(track: &Path) -> String {
+    let mut hasher = DefaultHasher::new();
+    track.hash(&mut hasher);
+    let extension = track.extension().and_then(|extension| extension.to_str()).unwrap_or("bin");
+    format!("{:016x}.{}", hasher.finish(), extension)
+}
+
+// Copies whose file has gone are left out
+fn load() -> Vec<Entry> {
+    let contents = fs::read_to_string(dir().join("index")).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            let file = fields.next()?.to_string();
+            let pinned = fields.next()? == "1";
+            let used = fields.next()?.parse().ok()?;
+            let track = PathBuf::from(fields.next()?);
+            let size = fs::metadata(dir().join(&file)).ok()?.len();
+            Some(Entry { track, file, size, pinned, used })
+        })
+        .collect()
+}
+
+fn save(entries: &[Entry]) -> io::Result<()> {
+    let lines: String = entries
+        .iter()
+        .map(|entry| format!("{}\t{}\t{}\t{}\n", entry.file, u8::from(entry.pinned), entry.used, entry.track.display()))
+        .collect();
+    fs::create_dir_all(dir())?;
+    fs::write(dir().join("index"), lines)
+}
+
+/// The copy of a track, when there is one of the given size, or of any size
+/// when the server can't be asked.
+pub fn open(track: &Path, size: Option<u64>) -> Option<File> {
+    let _index = INDEX.lock().unwrap();
+    let mut entries = load();
+    let entry = entries
+        .iter_mut()
+        .find(|entry| entry.track == track && size.is_none_or(|size| size == entry.size))?;
+    let file = File::open(dir().join(&entry.file)).ok()?;
+    entry.used = now();
+    let track = entry.track.clone();
+    entries.sort_by_key(|entry| (entry.used, entry.track == track));
+    let _ = save(&entries);
+    Some(file)
+}
+
+/// Keeps a downloaded track, making room for it under the limit.
+pub fn store(track: &Path, data: &[u8], pin: bool) -> io::Result<()> {
+    let _index = INDEX.lock().unwrap();
+    let mut entries = load();
+    let pinned = pin || entries.iter().any(|entry| entry.track == track && entry.pinned);
+    entries.retain(|entry| entry.track != track);
+    if !pinned && data.len() as u64 > limit() {
+        return save(&entries);
+    }
+
+    // Written under another name first, so a half-written file is never taken for the track
+    let file = file_name(track);
+    fs::create_dir_all(dir())?;
+    let partial = dir().join(&file).with_extension("part");
+    fs::write(&partial, data)?;
+    fs::rename(partial, dir().join(&file))?;
+    entries.push(Entry { track: track.to_path_buf(), file, size: data.len() as u64, pinned, used: now() });
+
+    entries.sort_by_key(|entry| entry.used);
+    let mut total = 0;
+    let mut kept: Vec<Entry> = entries
+        .into_iter()
+        .rev()
+        .filter(|entry| {
+            total += entry.size;
+            let keep = entry.pinned || total <= limit();
+            if !keep {
+                total -= entry.size;
+                let _ = fs::remove_file(dir().join(&entry.file));
+            }
+            keep
+        })
+        .collect();
+    kept.reverse();
+    save(&kept)
+}
+
+/// The copies, played most recently first.
+pub fn entries() -> Vec<Entry> {
+    let _index = INDEX.lock().unwrap();
+    let mut entries = load();
+    entries.sort_by_key(|entry| entry.used);
+    entries.reverse();
+    entries
+}
+
+/// Whether a track has a copy that is kept whatever the limit.
+pub fn is_pinned(track: &Path) -> bool {
+    entries().iter().any(|entry| entry.track == track && entry.pinned)
+}
+
+pub fn set_pinned(track: &Path, pinned: bool) -> io::Result<()> {
+    let _index = INDEX.lock().unwrap();
+    let mut entries = load();
+    for entry in entries.iter_mut().filter(|entry| entry.track == track) {
+        entry.pinned = pinned;
+    }
+    save(&entries)
+}
+
+pub fn remove(track: &Path) -> io::Result<()> {
+    let _index = INDEX.lock().unwrap();
+    let mut entries = load();
+    entries.retain(|entry| {
+        let keep = entry.track != track;
+        if !keep {
+            let _ = fs::remove_file(dir().join(&entry.file));
+        }
+        keep
+    });
+    save(&entries)
+}
@@ -0,0 +1,258 @@
+// jellyfin://[user:password@]host[:port][?bitrate=KBPS] roots, or jellyfins://
+// for servers behind HTTPS, read through the server's API with curl
+//
+// Tracks are named jellyfin://host:port/ITEM/Artist/Album/01 Title.flac, so
+// they sort and show like files while the item ID finds them on the server.
+// They stream as they are, or transcoded to MP3 at the root's bitrate.
+// Logins are kept in `jellyfin` next to the configuration, one
+// `SERVER<TAB>USER ID<TAB>TOKEN<TAB>BITRATE` line per server, so tracks of
+// the last session play before the library is scanned again.
+
+use crate::config::config_dir;
+use crate::now_playing::json_string;
+use crate::tags::Tags;
+use crate::utils::{self, json::Json};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+static LOGINS: Mutex<Option<HashMap<String, Login>>> = Mutex::new(None);
+
+#[derive(Clone)]
+struct Login {
+    user_id: String,
+    token: String,
+    // Transcoded to MP3 at this many kbit/s, None streams the original file
+    bitrate: Option<u32>,
+}
+
+pub fn is_jellyfin(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("jellyfin://") || path.starts_with("jellyfins://"))
+}
+
+// `jellyfin://host:port` to `http://host:port`
+fn base_url(server: &str) -> String {
+    match server.strip_prefix("jellyfins://") {
+        Some(authority) => format!("https://{}", authority),
+        None => format!("http://{}", server.trim_start_matches("jellyfin://")),
+    }
+}
+
+// The server and item ID of a track
+fn split(path: &Path) -> Option<(String, String)> {
+    let path = path.to_str()?.replace('\\', "/");
+    let (scheme, rest) = path.split_once("://")?;
+    let mut parts = rest.split('/');
+    let authority = parts.next()?;
+    let item = parts.next().filter(|item| !item.is_empty())?;
+    Some((format!("{}://{}", scheme, authority), item.to_string()))
+}
+
+// Slashes in names would add folders
+fn file_name_part(name: &str) -> String {
+    name.replace(['/', '\\'], "-")
+}
+
+fn logins() -> std::sync::MutexGuard<'static, Option<HashMap<String, Login>>> {
+    let mut logins = LOGINS.lock().unwrap();
+    if logins.is_none() {
+        let contents = fs::read_to_string(config_dir().join("jellyfin")).unwrap_or_default();
+        let saved = contents
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                let server = fields.next()?.to_string();
+                let user_id = fields.next()?.to_string();
+                let token = fields.next()?.to_string();
+                let bitrate = fields.next().and_then(|bitrate| bitrate.parse().ok());
+                Some((server, Login { user_id, token, bitrate }))
+            })
+            .collect();
+        *logins = Some(saved);
+    }
+    logins
+}
+
+fn save_login(server: &str, login: Login) -> Result<(), Box<dyn Error>> {
+    let mut logins = logins();
+    let logins = logins.get_or_insert_with(HashMap::new);
+    logins.insert(server.to_string(), login);
+    let lines: String = logins
+        .iter()
+        .map(|(server, login)| {
+            let bitrate = login.bitrate.map(|bitrate| bitrate.to_string()).unwrap_or_default();
+            format!("{}\t{}\t{}\t{}\n", server, login.user_id, login.token, bitrate)
+        })
+        .collect();
+    fs::create_dir_all(config_dir())?;
+    fs::write(config_dir().join("jellyfin"), lines)?;
+    Ok(())
+}
+
+fn login_for(server: &str) -> Option<Login> {
+    logins().as_ref()?.get(server).cloned()
+}
+
+// Jellyfin wants to know which client and device is asking
+fn client_header(token: Option<&str>) -> String {
+    let device = std::env::var("HOSTNAME").unwrap_or_else(|_| "music-cli".to_string());
+    let mut header = format!(
+        "X-Emby-Authorization: MediaBrowser Client=\"CLAP\", Device=\"{}\", DeviceId=\"{}-clap\", Version=\"{}\"",
+        device,
+        device,
+        env!("CARGO_PKG_VERSION")
+    );
+    if let Some(token) = token {
+        header.push_str(&format!(", Token=\"{}\"", token));
+    }
+    header
+}
+
+fn request(url: &str, token: Option<&str>, body: Option<&str>) -> Result<Json, Box<dyn Error>> {
+    let header = client_header(token);
+    let mut options = vec![("url", url), ("header", header.as_str())];
+    if let Some(body) = body {
+        options.push(("header", "Content-Type: application/json"));
+        options.push(("data", body));
+    }
+    let response = utils::curl(&options, &["--max-time", "60"])?;
+    Ok(Json::parse(&response)?)
+}
+
+/// Logs in to the server of a root, or uses the saved login when the root
+/// has no password, and lists the server's music.
+pub fn list(root: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let (scheme, rest) = root.split_once("://").ok_or("not a jellyfin root")?;
+    let (rest, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let (credentials, authority) = match rest.trim_end_matches('/').rsplit_once('@') {
+        Some((credentials, authority)) => (Some(credentials), authority),
+        None => (None, rest.trim_end_matches('/')),
+    };
+    if authority.is_empty() {
+        return Err("jellyfin root has no host".into());
+    }
+    let server = format!("{}://{}", scheme, authority);
+    let base = base_url(&server);
+    let bitrate = query
+        .split('&')
+        .find_map(|setting| setting.strip_prefix("bitrate="))
+        .map(|bitrate| bitrate.parse().map_err(|_| "bitrate must be a number of kbit/s"))
+        .transpose()?;
+
+    let login = match credentials {
+        Some(credentials) => {
+            let (user, password) = credentials.split_once(':').unwrap_or((credentials, ""));
+            let body = format!("{{\"Username\":{},\"Pw\":{}}}", json_string(user), json_string(password));
+            let answer = request(&format!("{}/Users/AuthenticateByName", base), None, Some(&body))
+                .map_err(|e| format!("could not log in: {}", e))?;
+            let token = answer.get("AccessToken").and_then(Json::as_str).ok_or("the server sent no access token")?;
+            let user_id = answer
+                .get("User")
+                .and_then(|user| user.get("Id"))
+                .and_then(Json::as_str)
+                .ok_or("the server sent no user")?;
+            // Both go in the tab-separated logins file and in curl's headers
+            if ![token, user_id].iter().all(|field| !field.is_empty() && field.chars().all(|c| (' '..='~').contains(&c))) {
+                return Err("the server sent a malformed login".into());
+            }
+            let login = Login { user_id: user_id.to_string(), token: token.to_string(), bitrate };
+            save_login(&server, login.clone())?;
+            login
+        }
+        None => {
+            let mut login = login_for(&server).ok_or("no saved login, put user:password@ in the root once")?;
+            if login.bitrate != bitrate {
+                login.bitrate = bitrate;
+                save_login(&server, login.clone())?;
+            }
+            login
+        }
+    };
+
+    let url = format!(
+        "{}/Users/{}/Items?Recursive=true&IncludeItemTypes=Audio&SortBy=AlbumArtist,Album,ParentIndexNumber,IndexNumber,SortName",
+        base, login.user_id
+    );
+    let answer = request(&url, Some(&login.token), None)?;
+    let tracks: Vec<PathBuf> = answer
+        .get("Items")
+        .map(Json::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|item| {
+            let id = item.get("Id")?.as_str()?;
+            let title = item.get("Name").and_then(Json::as_str).unwrap_or(id);
+            let artist = artist(item).unwrap_or("Unknown artist");
+            let album = item.get("Album").and_then(Json::as_str).unwrap_or("Unknown album");
+            let number = item.get("IndexNumber").and_then(Json::as_f64);
+            let container = item.get("Container").and_then(Json::as_str).unwrap_or("mp3");
+            // Servers list several containers for one file, such as "mov,mp4,m4a"
+            let extension = container.split(',').next_back().unwrap_or(container);
+            let name = match number {
+                Some(number) => format!("{:02} {}.{}", number, title, extension),
+                None => format!("{}.{}", title, extension),
+            };
+            let path = format!(
+                "{}/{}/{}/{}/{}",
+                server,
+                id,
+                file_name_part(artist),
+                file_name_part(album),
+                file_name_part(&name)
+            );
+            Some(PathBuf::from(path))
+        })
+        .collect();
+    log::info!(target: "scanner", "found {} track(s) on {}", tracks.len(), server);
+    Ok(tracks)
+}
+
+fn artist(item: &Json) -> Option<&str> {
+    item.get("AlbumArtist")
+        .and_then(Json::as_str)
+        .or_else(|| item.get("Artists")?.as_array().first()?.as_str())
+}
+
+/// The address ffmpeg plays a track from.
+pub fn stream_url(path: &Path) -> Result<String, Box<dyn Error>> {
+    let (server, item) = split(path).ok_or("invalid jellyfin track")?;
+    let login = login_for(&server).ok_or_else(|| format!("not logged in to {}", server))?;
+    let base = base_url(&server);
+    Ok(match login.bitrate {
+        Some(bitrate) => format!(
+            "{}/Audio/{}/stream.mp3?audioCodec=mp3&audioBitRate={}&api_key={}",
+            base,
+            item,
+            bitrate * 1000,
+            login.token
+        ),
+        None => format!("{}/Audio/{}/stream?static=true&api_key={}", base, item, login.token),
+    })
+}
+
+/// A track's tags as the server knows them.
+pub fn tags(path: &Path) -> Option<Tags> {
+    let (server, item) = split(path)?;
+    let login = login_for(&server)?;
+    let url = format!("{}/Users/{}/Items/{}", base_url(&server), login.user_id, item);
+    let answer = request(&url, Some(&login.token), None)
+        .inspect_err(|e| log::debug!(target: "scanner", "no tags for {}: {}", path.display(), e))
+        .ok()?;
+    let text = |key: &str| answer.get(key).and_then(Json::as_str).map(str::to_string);
+    Some(Tags {
+        title: text("Name"),
+        artist: artist(&answer).map(str::to_string),
+        album: text("Album"),
+        genre: answer.get("Genres").and_then(|genres| genres.as_array().first()?.as_str()).map(str::to_string),
+        // Ticks are 100 ns
+        duration: answer
+            .get("RunTimeTicks")
+            .and_then(Json::as_f64)
+            .map(|ticks| Duration::from_secs_f64(ticks / 10_000_000.0)),
+        size: None,
+    })
+}
@@ -0,0 +1,253 @@
+// Library roots on other machines: SMB shares mounted through the system,
+// SFTP servers read over ssh, WebDAV folders and Jellyfin servers
+
+pub mod cache;
+pub mod jellyfin;
+mod sftp;
+mod smb;
+pub mod webdav;
+
+pub use jellyfin::is_jellyfin;
+pub use sftp::is_sftp;
+pub use webdav::is_webdav;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use symphonia::core::io::MediaSource;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+// Room set aside up front, as a server may report any size at all
+const MAX_RESERVED: u64 = CHUNK_SIZE as u64 * 64;
+
+// The file opened last, shared so probing, playing and seeking in a track
+// download it once
+static RECENT: Mutex<Option<(PathBuf, Arc<Shared>)>> = Mutex::new(None);
+
+/// Turns a configured library root into a path the scanner can read,
+/// mounting SMB shares as needed.
+pub fn resolve_root(root: &str) -> Result<PathBuf, Box<dyn Error>> {
+    if root.starts_with("smb://") {
+        smb::mount(root)
+    } else {
+        Ok(PathBuf::from(root))
+    }
+}
+
+pub fn is_remote(path: &Path) -> bool {
+    is_sftp(path) || is_webdav(path) || smb::is_mounted(path)
+}
+
+// Tracks downloaded from a server, which are worth keeping a copy of
+fn is_downloaded(path: &Path) -> bool {
+    is_sftp(path) || is_webdav(path)
+}
+
+type Stream = (u64, Box<dyn Read + Send>);
+
+fn open_stream(path: &Path) -> Result<Stream, Box<dyn Error>> {
+    if is_sftp(path) {
+        let (size, stream) = sftp::open(path)?;
+        Ok((size, Box::new(stream)))
+    } else {
+        let (size, stream) = webdav::open(path)?;
+        Ok((size, Box::new(stream)))
+    }
+}
+
+/// A track kept offline, or why it could not be downloaded.
+pub type Downloaded = Result<PathBuf, String>;
+
+/// Downloads a track in the background and keeps it in the cache whatever
+/// the size limit, reporting the track or what went wrong.
+pub fn keep_offline(track: PathBuf, done: Sender<Downloaded>) {
+    thread::spawn(move || {
+        let result = open_stream(&track)
+            .and_then(|(_, mut stream)| {
+                let mut data = Vec::new();
+                stream.read_to_end(&mut data)?;
+                cache::store(&track, &data, true)?;
+                Ok(())
+            })
+            .map(|_| track)
+            .map_err(|e| e.to_string());
+        let _ = done.send(result);
+    });
+}
+
+/// Whether a track can be kept offline.
+pub fn can_keep_offline(track: &Path) -> bool {
+    is_downloaded(track)
+}
+
+/// Lists the files in a remote directory and its subfolders.
+pub fn list(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    sftp::list(dir)
+}
+
+/// Lets go of the file opened last once it stops playing, so its download
+/// doesn't stay in memory.
+pub fn forget_recent() {
+    *RECENT.lock().unwrap() = None;
+}
+
+/// Opens a remote file, downloading it in the background ahead of playback.
+pub fn open(path: &Path) -> Result<ReadAhead, Box<dyn Error>> {
+    let mut recent = RECENT.lock().unwrap();
+    if let Some((recent_path, shared)) = recent.as_ref() {
+        if recent_path == path && shared.buffer.lock().unwrap().error.is_none() {
+            return Ok(ReadAhead::from_shared(shared.clone()));
+        }
+    }
+    let reader = if is_downloaded(path) {
+        match open_stream(path) {
+            Ok((size, stream)) => match cache::open(path, Some(size)) {
+                Some(file) => ReadAhead::spawn(file, size, None),
+                None => ReadAhead::spawn(stream, size, Some(path.to_path_buf())),
+            },
+            // Offline, the copy from last time still plays
+            Err(e) => {
+                let file = cache::open(path, None).ok_or(e)?;
+                let size = file.metadata()?.len();
+                ReadAhead::spawn(file, size, None)
+            }
+        }
+    } else {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        ReadAhead::spawn(file, size, None)
+    };
+    *recent = Some((path.to_path_buf(), reader.shared.clone()));
+    Ok(reader)
+}
+
+struct Buffer {
+    data: Vec<u8>,
+    done: bool,
+    error: Option<String>,
+}
+
+struct Shared {
+    buffer: Mutex<Buffer>,
+    ready: Condvar,
+    len: u64,
+}
+
+impl Shared {
+    // Waits until the byte at `pos` has arrived or the download has ended
+    fn wait_for(&self, pos: u64) -> MutexGuard<'_, Buffer> {
+        let mut buffer = self.buffer.lock().unwrap();
+        while buffer.data.len() as u64 <= pos && !buffer.done {
+            buffer = self.ready.wait(buffer).unwrap();
+        }
+        buffer
+    }
+}
+
+/// A file read from a slow link. A background thread keeps downloading while
+/// the decoder reads, blocking only when it catches up with the download.
+pub struct ReadAhead {
+    shared: Arc<Shared>,
+    pos: u64,
+}
+
+impl ReadAhead {
+    // A file downloaded in full is kept in the cache as `track` when given
+    fn spawn<R: Read + Send + 'static>(mut reader: R, len: u64, track: Option<PathBuf>) -> ReadAhead {
+        let shared = Arc::new(Shared {
+            buffer: Mutex::new(Buffer {
+                data: Vec::with_capacity(len.min(MAX_RESERVED) as usize),
+                done: false,
+                error: None,
+            }),
+            ready: Condvar::new(),
+            len,
+        });
+        let worker = shared.clone();
+        thread::spawn(move || {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            loop {
+                // Stop downloading once no reader or cache holds the file
+                if Arc::strong_count(&worker) == 1 {
+                    break;
+                }
+                let result = reader.read(&mut chunk);
+                let mut buffer = worker.buffer.lock().unwrap();
+                match result {
+                    Ok(0) => buffer.done = true,
+                    Ok(n) => buffer.data.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        buffer.error = Some(e.to_string());
+                        buffer.done = true;
+                    }
+                }
+                let done = buffer.done;
+                if done && buffer.error.is_none() {
+                    if let Some(track) = &track {
+                        if let Err(e) = cache::store(track, &buffer.data, false) {
+                            log::warn!(target: "decoder", "could not cache {}: {}", track.display(), e);
+                        }
+                    }
+                }
+                drop(buffer);
+                worker.ready.notify_all();
+                if done {
+                    break;
+                }
+            }
+        });
+        ReadAhead::from_shared(shared)
+    }
+
+    fn from_shared(shared: Arc<Shared>) -> ReadAhead {
+        ReadAhead { shared, pos: 0 }
+    }
+}
+
+impl Read for ReadAhead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let buffer = self.shared.wait_for(self.pos);
+        let available = buffer.data.len() as u64;
+        if self.pos >= available {
+            return match &buffer.error {
+                Some(error) => Err(io::Error::other(error.clone())),
+                None => Ok(0),
+            };
+        }
+        let start = self.pos as usize;
+        let n = buf.len().min(available as usize - start);
+        buf[..n].copy_from_slice(&buffer.data[start..start + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for ReadAhead {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+            SeekFrom::End(offset) => self.shared.len as i64 + offset,
+        };
+        if target < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file"));
+        }
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
+
+impl MediaSource for ReadAhead {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.shared.len)
+    }
+}
@@ -0,0 +1,133 @@
+// sftp://[user@]host[:port]/path roots, read through the system ssh client
+//
+// Files are listed with `find` and streamed with `cat` on the server, so any
+// host with a POSIX shell works, and keys, agents and ~/.ssh/config apply as
+// they do for ssh itself. Accounts limited to the SFTP subsystem, with no
+// shell, can't be read this way.
+
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+struct Location {
+    // user@host:port as written in the URL
+    authority: String,
+    // user@host as passed to ssh
+    destination: String,
+    port: Option<u16>,
+    path: String,
+}
+
+pub fn is_sftp(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with("sftp://"))
+}
+
+fn parse(path: &Path) -> Result<Location, Box<dyn Error>> {
+    let url = path.to_str().ok_or("invalid sftp path")?.replace('\\', "/");
+    let rest = url.strip_prefix("sftp://").ok_or("not an sftp path")?;
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let (destination, port) = match authority.rsplit_once(':') {
+        Some((destination, port)) => (destination, Some(port.parse().map_err(|_| "invalid sftp port")?)),
+        None => (authority, None),
+    };
+    if destination.is_empty() {
+        return Err("sftp path has no host".into());
+    }
+    Ok(Location {
+        authority: authority.to_string(),
+        destination: destination.to_string(),
+        port,
+        path: format!("/{}", path.trim_end_matches('/')),
+    })
+}
+
+// Runs a shell command on the server. Connections are shared between calls
+// where ssh supports it, so only the first one pays for the handshake.
+fn ssh(location: &Location, command: &str, interactive: bool) -> Command {
+    let mut ssh = Command::new("ssh");
+    if !interactive {
+        ssh.args(["-o", "BatchMode=yes"]);
+    }
+    #[cfg(unix)]
+    {
+        let control_path = crate::config::config_dir().join("ssh-%C");
+        ssh.args(["-o", "ControlMaster=auto", "-o", "ControlPersist=600", "-o"])
+            .arg(format!("ControlPath={}", control_path.display()));
+    }
+    if let Some(port) = location.port {
+        ssh.args(["-p", &port.to_string()]);
+    }
+    ssh.arg(&location.destination).arg(command);
+    ssh
+}
+
+fn quote(path: &str) -> String {
+    format!("'{}'", path.replace('\'', r"'\''"))
+}
+
+fn url(location: &Location, path: &str) -> PathBuf {
+    PathBuf::from(format!("sftp://{}{}", location.authority, path))
+}
+
+pub fn list(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let location = parse(dir)?;
+    // Listing happens before the interface starts, so ssh may ask for a password
+    // Subfolders too, as for local folders: Artist/Album/01.flac is found from Artist's parent
+    let output = ssh(&location, &format!("find {} -type f", quote(&location.path)), true)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("could not start ssh: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("could not list {}", dir.display()).into());
+    }
+    let mut files: Vec<PathBuf> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| url(&location, line))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Starts streaming a file, returning its size and the data.
+pub fn open(path: &Path) -> Result<(u64, SshStream), Box<dyn Error>> {
+    let location = parse(path)?;
+    let quoted = quote(&location.path);
+    // The size comes first on its own line, followed by the contents
+    let mut child = ssh(&location, &format!("wc -c < {0} && cat {0}", quoted), false)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("could not start ssh: {}", e))?;
+    let mut output = BufReader::new(child.stdout.take().ok_or("ssh has no output")?);
+    let mut size = String::new();
+    output.read_line(&mut size)?;
+    let size = size
+        .trim()
+        .parse()
+        .map_err(|_| format!("could not read {}", path.display()))?;
+    Ok((size, SshStream { child, output }))
+}
+
+/// File contents arriving from an ssh child process, which is stopped when
+/// the stream is dropped.
+pub struct SshStream {
+    child: Child,
+    output: BufReader<ChildStdout>,
+}
+
+impl Read for SshStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.output.read(buf)
+    }
+}
+
+impl Drop for SshStream {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
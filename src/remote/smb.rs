@@ -0,0 +1,115 @@
+// smb://[user@]server/share/path roots, mounted with the system's own tools
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+struct Share {
+    // user@server as written in the URL
+    #[cfg_attr(windows, allow(dead_code))]
+    authority: String,
+    server: String,
+    share: String,
+    subdir: String,
+}
+
+fn parse(url: &str) -> Result<Share, Box<dyn Error>> {
+    let rest = url.strip_prefix("smb://").ok_or("not an smb URL")?;
+    let (authority, rest) = rest.split_once('/').ok_or("smb URL has no share")?;
+    let (share, subdir) = rest.split_once('/').unwrap_or((rest, ""));
+    if share.is_empty() {
+        return Err("smb URL has no share".into());
+    }
+    let server = authority.rsplit_once('@').map_or(authority, |(_, server)| server);
+    Ok(Share {
+        authority: authority.to_string(),
+        server: server.to_string(),
+        share: share.to_string(),
+        subdir: subdir.trim_end_matches('/').to_string(),
+    })
+}
+
+/// Mounts the share if needed and returns the local path of the URL.
+pub fn mount(url: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let share = parse(url)?;
+    let root = mount_share(&share)?;
+    let path = if share.subdir.is_empty() { root } else { root.join(&share.subdir) };
+    if !path.is_dir() {
+        return Err(format!("could not open {}", url).into());
+    }
+    Ok(path)
+}
+
+// Windows reaches shares directly through UNC paths
+#[cfg(windows)]
+fn mount_share(share: &Share) -> Result<PathBuf, Box<dyn Error>> {
+    Ok(PathBuf::from(format!(r"\\{}\{}", share.server, share.share)))
+}
+
+#[cfg(target_os = "macos")]
+fn mount_share(share: &Share) -> Result<PathBuf, Box<dyn Error>> {
+    let mount_point = mounts_dir().join(format!("{}-{}", share.server, share.share));
+    std::fs::create_dir_all(&mount_point)?;
+    // An empty mount point means the share isn't mounted yet
+    if std::fs::read_dir(&mount_point)?.next().is_none() {
+        let status = std::process::Command::new("mount_smbfs")
+            .arg(format!("//{}/{}", share.authority, share.share))
+            .arg(&mount_point)
+            .status()
+            .map_err(|e| format!("could not start mount_smbfs: {}", e))?;
+        if !status.success() {
+            return Err(format!("could not mount //{}/{}", share.server, share.share).into());
+        }
+    }
+    Ok(mount_point)
+}
+
+// Other Unix systems mount through GVFS, which needs no root access
+#[cfg(all(unix, not(target_os = "macos")))]
+fn mount_share(share: &Share) -> Result<PathBuf, Box<dyn Error>> {
+    if let Some(path) = gvfs_path(share) {
+        return Ok(path);
+    }
+    let status = std::process::Command::new("gio")
+        .arg("mount")
+        .arg(format!("smb://{}/{}", share.authority, share.share))
+        .status()
+        .map_err(|e| format!("could not start gio: {}", e))?;
+    if !status.success() {
+        return Err(format!("could not mount //{}/{}", share.server, share.share).into());
+    }
+    gvfs_path(share).ok_or_else(|| format!("//{}/{} is not in the GVFS folder", share.server, share.share).into())
+}
+
+// GVFS mounts appear as smb-share:server=...,share=... in the runtime folder
+#[cfg(all(unix, not(target_os = "macos")))]
+fn gvfs_path(share: &Share) -> Option<PathBuf> {
+    let gvfs = PathBuf::from(std::env::var("XDG_RUNTIME_DIR").ok()?).join("gvfs");
+    let server = format!("server={}", share.server.to_lowercase());
+    let name = format!("share={}", share.share.to_lowercase());
+    std::fs::read_dir(gvfs).ok()?.flatten().map(|entry| entry.path()).find(|path| {
+        let mount = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+        mount.starts_with("smb-share:") && mount.split(&[':', ','][..]).any(|field| field == server)
+            && mount.split(&[':', ','][..]).any(|field| field == name)
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn mounts_dir() -> PathBuf {
+    crate::config::config_dir().join("mounts")
+}
+
+/// Whether the path lies on a mounted share, where reads may be slow.
+#[cfg(windows)]
+pub fn is_mounted(path: &Path) -> bool {
+    path.to_string_lossy().starts_with(r"\\")
+}
+
+#[cfg(target_os = "macos")]
+pub fn is_mounted(path: &Path) -> bool {
+    path.starts_with(mounts_dir())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn is_mounted(path: &Path) -> bool {
+    path.to_string_lossy().contains("/gvfs/smb-share:")
+}
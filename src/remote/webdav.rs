@@ -0,0 +1,337 @@
+// dav://[user:password@]host[:port]/path roots, or davs:// over HTTPS, such
+// as a Nextcloud music folder. Folders are listed with PROPFIND and files are
+// fetched in pieces with range requests, all through curl.
+//
+// Tracks are named like the root without the login, which is kept in memory
+// from the scan, so logins stay out of the library and the session.
+
+use crate::utils;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Bytes fetched by each range request
+const RANGE_SIZE: u64 = 4 * 1024 * 1024;
+
+const PROPFIND: &str = "<?xml version=\"1.0\"?>\
+    <d:propfind xmlns:d=\"DAV:\"><d:prop><d:resourcetype/><d:getcontentlength/></d:prop></d:propfind>";
+
+// Logins by server, and the sizes of the files listed
+static LOGINS: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+static SIZES: Mutex<Option<HashMap<PathBuf, u64>>> = Mutex::new(None);
+
+struct Location {
+    // dav://host:port
+    server: String,
+    login: Option<String>,
+    // Decoded, starting with a slash
+    path: String,
+}
+
+pub fn is_webdav(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("dav://") || path.starts_with("davs://"))
+}
+
+fn parse(url: &str) -> Result<Location, Box<dyn Error>> {
+    let url = url.replace('\\', "/");
+    let (scheme, rest) = url.split_once("://").ok_or("not a WebDAV URL")?;
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let (login, host) = match authority.rsplit_once('@') {
+        Some((login, host)) => (Some(login.to_string()), host),
+        None => (None, authority),
+    };
+    if host.is_empty() {
+        return Err("WebDAV URL has no host".into());
+    }
+    let server = format!("{}://{}", scheme, host);
+    let login = login.or_else(|| LOGINS.lock().unwrap().as_ref()?.get(&server).cloned());
+    Ok(Location { server, login, path: format!("/{}", path) })
+}
+
+impl Location {
+    fn url(&self, path: &str) -> String {
+        let base = match self.server.strip_prefix("davs://") {
+            Some(host) => format!("https://{}", host),
+            None => format!("http://{}", self.server.trim_start_matches("dav://")),
+        };
+        format!("{}{}", base, encode(path))
+    }
+
+    fn curl(&self, path: &str, options: &[(&str, &str)], args: &[&str]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let url = self.url(path);
+        let mut all = vec![("url", url.as_str())];
+        if let Some(login) = &self.login {
+            all.push(("user", login));
+        }
+        all.extend_from_slice(options);
+        utils::curl_bytes(&all, args)
+    }
+}
+
+// Percent-encodes everything but unreserved characters and slashes
+fn encode(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// The five named entities and numeric references such as `&#x20;`, others
+// left as they are
+fn unescape_xml(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..].find(';').map(|end| &rest[1..end + 1]);
+        let character = entity.and_then(|entity| match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "amp" => Some('&'),
+            _ => {
+                let code = match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#')?.parse().ok(),
+                };
+                char::from_u32(code?)
+            }
+        });
+        match (entity, character) {
+            (Some(entity), Some(character)) => {
+                unescaped.push(character);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+// The contents of the elements named `name` in any namespace, empty for
+// self-closing ones
+fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = rest.find('>').unwrap_or(rest.len());
+        let tag = &rest[..end];
+        let tag_name = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or_default();
+        let local = tag_name.rsplit(':').next().unwrap_or(tag_name);
+        rest = rest.get(end + 1..).unwrap_or_default();
+        if tag.starts_with('/') || !local.eq_ignore_ascii_case(name) {
+            continue;
+        }
+        if tag.ends_with('/') {
+            found.push("");
+            continue;
+        }
+        let close = format!("</{}>", tag_name);
+        let content_end = rest.find(&close).unwrap_or(rest.len());
+        found.push(&rest[..content_end]);
+        rest = rest.get(content_end + close.len()..).unwrap_or_default();
+    }
+    found
+}
+
+/// Lists the files under a root and its subfolders, remembering its login
+/// and the file sizes for playback.
+pub fn list(root: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let location = parse(root)?;
+    if let Some(login) = &location.login {
+        LOGINS
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .insert(location.server.clone(), login.clone());
+    }
+
+    let mut files = Vec::new();
+    let mut folders = VecDeque::from([location.path.trim_end_matches('/').to_string() + "/"]);
+    while let Some(folder) = folders.pop_front() {
+        let answer = location.curl(
+            &folder,
+            &[("request", "PROPFIND"), ("header", "Depth: 1"), ("header", "Content-Type: application/xml"), ("data", PROPFIND)],
+            &["--max-time", "60"],
+        )?;
+        let answer = String::from_utf8_lossy(&answer);
+        for response in elements(&answer, "response") {
+            let Some(href) = elements(response, "href").first().map(|href| decode(&unescape_xml(href.trim()))) else {
+                continue;
+            };
+            // Servers may answer with full URLs
+            let path = match href.split_once("://") {
+                Some((_, rest)) => rest.find('/').map_or("/".to_string(), |i| rest[i..].to_string()),
+                None => href,
+            };
+            if path.trim_end_matches('/') == folder.trim_end_matches('/') {
+                continue;
+            }
+            if !elements(response, "collection").is_empty() {
+                folders.push_back(path.trim_end_matches('/').to_string() + "/");
+                continue;
+            }
+            let track = PathBuf::from(format!("{}{}", location.server, path));
+            if let Some(size) = elements(response, "getcontentlength").first().and_then(|size| size.trim().parse().ok()) {
+                SIZES.lock().unwrap().get_or_insert_with(HashMap::new).insert(track.clone(), size);
+            }
+            files.push(track);
+        }
+    }
+    files.sort();
+    log::info!(target: "scanner", "found {} file(s) in {}", files.len(), location.server);
+    Ok(files)
+}
+
+// Asks the server when the file wasn't listed in this session
+fn size(location: &Location, path: &Path) -> Result<u64, Box<dyn Error>> {
+    if let Some(size) = SIZES.lock().unwrap().as_ref().and_then(|sizes| sizes.get(path)) {
+        return Ok(*size);
+    }
+    let head = location.curl(&location.path, &[], &["--head", "--max-time", "15"])?;
+    String::from_utf8_lossy(&head)
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("content-length").then(|| value.trim().parse().ok())?
+        })
+        .ok_or_else(|| format!("the server gave no size for {}", path.display()).into())
+}
+
+/// Opens a file, returning its size and the data, fetched a range at a time.
+pub fn open(path: &Path) -> Result<(u64, RangeReader), Box<dyn Error>> {
+    let location = parse(path.to_str().ok_or("invalid WebDAV path")?)?;
+    let len = size(&location, path)?;
+    Ok((len, RangeReader { location, len, pos: 0, chunk: Vec::new(), chunk_pos: 0 }))
+}
+
+pub struct RangeReader {
+    location: Location,
+    len: u64,
+    pos: u64,
+    chunk: Vec<u8>,
+    chunk_pos: usize,
+}
+
+impl Read for RangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.chunk_pos >= self.chunk.len() {
+            if self.pos >= self.len {
+                return Ok(0);
+            }
+            let last = (self.pos + RANGE_SIZE).min(self.len) - 1;
+            let range = format!("{}-{}", self.pos, last);
+            self.chunk = self
+                .location
+                .curl(&self.location.path, &[("range", &range)], &["--max-time", "120"])
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            self.chunk_pos = 0;
+            if self.chunk.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the server sent no data"));
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.chunk_pos);
+        buf[..n].copy_from_slice(&self.chunk[self.chunk_pos..self.chunk_pos + n]);
+        self.chunk_pos += n;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, elements, encode, unescape_xml};
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(decode("/music/Sigur%20R%C3%B3s/01%20-%20Hopp%C3%ADpolla.flac"), "/music/Sigur Rós/01 - Hoppípolla.flac");
+        assert_eq!(decode("/a%2fb%25c"), "/a/b%c");
+        // Stray percent signs stay as they are
+        assert_eq!(decode("/100%"), "/100%");
+        assert_eq!(decode("/50%zz%+1%4"), "/50%zz%+1%4");
+        assert_eq!(decode("/a+b"), "/a+b");
+        let path = "/Música/AC/DC & Friends #1?.mp3";
+        assert_eq!(decode(&encode(path)), path);
+    }
+
+    #[test]
+    fn xml_unescaping() {
+        assert_eq!(unescape_xml("Simon &amp; Garfunkel"), "Simon & Garfunkel");
+        assert_eq!(unescape_xml("&lt;a&gt; &quot;b&quot; &apos;c&apos;"), "<a> \"b\" 'c'");
+        // Unescaped only once
+        assert_eq!(unescape_xml("&amp;lt;"), "&lt;");
+        assert_eq!(unescape_xml("a&#x20;b&#32;c&#xE9;"), "a b cé");
+        assert_eq!(unescape_xml("R&B &nbsp; &#xD800; &"), "R&B &nbsp; &#xD800; &");
+    }
+
+    #[test]
+    fn finding_elements() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:multistatus xmlns:D="DAV:">
+  <D:response>
+    <D:href>/music/</D:href>
+    <D:propstat><D:prop><D:resourcetype><D:collection/></D:resourcetype></D:prop></D:propstat>
+  </D:response>
+  <D:response>
+    <D:href>/music/a%20b.flac</D:href>
+    <D:propstat><D:prop><D:resourcetype/><D:getcontentlength>1234</D:getcontentlength></D:prop></D:propstat>
+  </D:response>
+</D:multistatus>"#;
+        let responses = elements(xml, "response");
+        assert_eq!(responses.len(), 2);
+        assert_eq!(elements(responses[0], "href"), ["/music/"]);
+        assert_eq!(elements(responses[0], "collection"), [""]);
+        assert_eq!(elements(responses[1], "href"), ["/music/a%20b.flac"]);
+        assert!(elements(responses[1], "collection").is_empty());
+        assert_eq!(elements(responses[1], "resourcetype"), [""]);
+        assert_eq!(elements(responses[1], "getcontentlength"), ["1234"]);
+    }
+
+    #[test]
+    fn elements_in_other_namespaces() {
+        // No prefix, another prefix, and a differently cased name
+        let xml = r#"<multistatus xmlns="DAV:"><response><href>/x</href></response></multistatus>
+<lp1:Response><lp1:HREF>/y</lp1:HREF></lp1:Response>"#;
+        let hrefs: Vec<&str> = elements(xml, "response").into_iter().flat_map(|response| elements(response, "href")).collect();
+        assert_eq!(hrefs, ["/x", "/y"]);
+        // Names that only start the same don't count
+        assert!(elements("<D:responsedescription>x</D:responsedescription>", "response").is_empty());
+        // A cut-off answer still gives what's there
+        assert_eq!(elements("<D:href>/z", "href"), ["/z"]);
+    }
+}
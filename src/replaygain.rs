@@ -0,0 +1,115 @@
+use crate::tasks::Reporter;
+use crate::utils::loudness;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+// ReplayGain 2.0 reference level
+const REFERENCE_LUFS: f64 = -18.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplayGainMode {
+    Off,
+    Track,
+    Album,
+}
+
+impl FromStr for ReplayGainMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(ReplayGainMode::Off),
+            "track" => Ok(ReplayGainMode::Track),
+            "album" => Ok(ReplayGainMode::Album),
+            _ => Err(format!("unknown ReplayGain mode '{}'", s)),
+        }
+    }
+}
+
+pub struct TrackGain {
+    pub path: PathBuf,
+    pub track_gain: f32,
+    pub album_gain: f32,
+    pub peak: f32,
+}
+
+enum ScanEvent {
+    Album(Vec<TrackGain>),
+    Finished,
+}
+
+/// Measures tracks on a background thread, one album (folder) at a time so
+/// album gains can be computed from all of its tracks together.
+pub struct Scanner {
+    events: Receiver<ScanEvent>,
+}
+
+impl Scanner {
+    pub fn start(tracks: Vec<PathBuf>, progress: Reporter) -> Self {
+        let (tx, events) = mpsc::channel();
+        let total = tracks.len() as u64;
+        progress.update(0, total);
+        thread::spawn(move || {
+            let mut albums: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+            for track in tracks {
+                let album = track.parent().map(PathBuf::from).unwrap_or_default();
+                albums.entry(album).or_default().push(track);
+            }
+
+            let mut done = 0;
+            // Albums measured before a cancel keep their gains
+            'albums: for tracks in albums.into_values() {
+                let mut measured = Vec::new();
+                for path in tracks {
+                    if progress.checkpoint() {
+                        break 'albums;
+                    }
+                    if let Some(measurement) = loudness::measure(&path) {
+                        measured.push((path, measurement));
+                    }
+                    done += 1;
+                    progress.update(done, total);
+                }
+
+                let album_loudness = loudness::integrated_loudness(
+                    measured.iter().flat_map(|(_, measurement)| measurement.blocks.iter()),
+                );
+                let gains = measured
+                    .into_iter()
+                    .filter_map(|(path, measurement)| {
+                        let track_loudness = loudness::integrated_loudness(measurement.blocks.iter())?;
+                        Some(TrackGain {
+                            path,
+                            track_gain: (REFERENCE_LUFS - track_loudness) as f32,
+                            album_gain: (REFERENCE_LUFS - album_loudness.unwrap_or(track_loudness)) as f32,
+                            peak: measurement.peak,
+                        })
+                    })
+                    .collect();
+                if tx.send(ScanEvent::Album(gains)).is_err() {
+                    break 'albums;
+                }
+            }
+            progress.finish();
+            let _ = tx.send(ScanEvent::Finished);
+        });
+
+        Scanner { events }
+    }
+
+    // Returns the gains measured since the last poll and whether the scan is done
+    pub fn poll(&mut self) -> (Vec<TrackGain>, bool) {
+        let mut gains = Vec::new();
+        let mut finished = false;
+        for event in self.events.try_iter() {
+            match event {
+                ScanEvent::Album(album) => gains.extend(album),
+                ScanEvent::Finished => finished = true,
+            }
+        }
+        (gains, finished)
+    }
+}
@@ -0,0 +1,182 @@
+// Resume positions and recently played tracks shared between machines, through
+// a file on a WebDAV server such as Nextcloud, or anything else that takes
+// HTTP GET and PUT. Transfers go through the system curl.
+//
+// The file has a line per position, `P<TAB>SAVED<TAB>SECONDS<TAB>TRACK` with
+// SECONDS left empty once the track was finished, and a line per history
+// entry, `H<TAB>STARTED<TAB>TRACK`. Times are in seconds since 1970. Tracks
+// are named by their last two folders and file name, `Artist/Album/01.flac`,
+// since the music lives in a different place on each machine.
+
+use crate::config::{config_dir, Config};
+use crate::history::{Entry, History};
+use crate::library::Library;
+use crate::utils;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+pub struct Remote {
+    url: String,
+    // user:password
+    credentials: Option<String>,
+}
+
+#[derive(Default)]
+pub struct Shared {
+    positions: HashMap<String, (u64, Option<Duration>)>,
+    history: Vec<(u64, String)>,
+}
+
+/// How a track is known on every machine.
+fn track_key(path: &Path) -> String {
+    let names: Vec<_> = path.iter().rev().take(3).map(|name| name.to_string_lossy()).collect();
+    names.into_iter().rev().collect::<Vec<_>>().join("/")
+}
+
+impl Remote {
+    pub fn new(config: &Config) -> Option<Remote> {
+        let url = config.roaming_url.clone()?;
+        let credentials = config
+            .roaming_user
+            .as_ref()
+            .map(|user| format!("{}:{}", user, config.roaming_password.as_deref().unwrap_or_default()));
+        Some(Remote { url, credentials })
+    }
+
+    fn curl(&self, extra: &[&str]) -> Result<String, Box<dyn Error>> {
+        let mut options = vec![("url", self.url.as_str())];
+        if let Some(credentials) = &self.credentials {
+            options.push(("user", credentials));
+        }
+        utils::curl(&options, &[&["--max-time", "15"], extra].concat())
+    }
+
+    /// What the other machines shared, empty before the first upload.
+    pub fn fetch(&self) -> Result<Shared, Box<dyn Error>> {
+        match self.curl(&[]) {
+            Ok(contents) => Ok(Shared::parse(&contents)),
+            Err(e) if e.to_string().contains("404") => Ok(Shared::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn fetch_in_background(self) -> Receiver<Result<Shared, String>> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(self.fetch().map_err(|e| e.to_string()));
+        });
+        receiver
+    }
+
+    /// Adds this machine's positions and history to the shared file.
+    pub fn upload(&self, library: &Library, history: &History, history_limit: usize) -> Result<(), Box<dyn Error>> {
+        let mut shared = self.fetch()?;
+        shared.merge(Shared::local(library, history), history_limit);
+        let file = config_dir().join("roaming.upload");
+        fs::write(&file, shared.to_text())?;
+        let result = self.curl(&["--upload-file", &file.to_string_lossy()]);
+        let _ = fs::remove_file(&file);
+        result.map(|_| ())
+    }
+}
+
+impl Shared {
+    fn parse(contents: &str) -> Shared {
+        let mut shared = Shared::default();
+        for line in contents.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                ["P", saved, seconds, track] => {
+                    let Ok(saved) = saved.parse() else {
+                        continue;
+                    };
+                    let position = seconds.parse().ok().map(Duration::from_secs_f64);
+                    shared.positions.insert(track.to_string(), (saved, position));
+                }
+                ["H", started, track] => {
+                    if let Ok(started) = started.parse() {
+                        shared.history.push((started, track.to_string()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        shared
+    }
+
+    fn to_text(&self) -> String {
+        let mut lines: Vec<String> = self
+            .positions
+            .iter()
+            .map(|(track, (saved, position))| {
+                let seconds = position.map(|position| format!("{:.3}", position.as_secs_f64())).unwrap_or_default();
+                format!("P\t{}\t{}\t{}\n", saved, seconds, track)
+            })
+            .collect();
+        lines.sort();
+        lines.extend(self.history.iter().map(|(started, track)| format!("H\t{}\t{}\n", started, track)));
+        lines.concat()
+    }
+
+    fn local(library: &Library, history: &History) -> Shared {
+        let positions = library
+            .records()
+            .filter_map(|(path, record)| Some((track_key(path), (record.position_saved?, record.position))))
+            .collect();
+        let history = history.entries().map(|entry| (entry.started, track_key(&entry.path))).collect();
+        Shared { positions, history }
+    }
+
+    // Keeps the newest position of each track, and the latest history entries
+    fn merge(&mut self, other: Shared, history_limit: usize) {
+        for (track, (saved, position)) in other.positions {
+            if self.positions.get(&track).is_none_or(|(current, _)| saved > *current) {
+                self.positions.insert(track, (saved, position));
+            }
+        }
+        self.history.extend(other.history);
+        self.history.sort();
+        self.history.dedup();
+        let excess = self.history.len().saturating_sub(history_limit);
+        self.history.drain(..excess);
+    }
+
+    /// Takes the positions that are newer than the ones here, and the tracks
+    /// played elsewhere that are found here, returning how many positions changed.
+    pub fn apply(&self, library: &mut Library, tracks: &[PathBuf], history: &mut History) -> usize {
+        let local: HashMap<String, PathBuf> = library
+            .records()
+            .map(|(path, _)| path)
+            .chain(tracks)
+            .map(|path| (track_key(path), path.clone()))
+            .collect();
+
+        let mut changed = 0;
+        for (track, (saved, position)) in &self.positions {
+            let Some(path) = local.get(track) else {
+                continue;
+            };
+            let record = library.record_mut(path);
+            if record.position_saved.is_none_or(|current| *saved > current) {
+                record.position = *position;
+                record.position_saved = Some(*saved);
+                changed += 1;
+            }
+        }
+
+        let played = self
+            .history
+            .iter()
+            .filter_map(|(started, track)| Some(Entry { path: local.get(track)?.clone(), started: *started }))
+            .collect();
+        if let Err(e) = history.merge(played) {
+            log::warn!(target: "playback", "could not save the history: {}", e);
+        }
+        changed
+    }
+}
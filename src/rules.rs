@@ -0,0 +1,309 @@
+// Smart playlists: a playlist with `#CLAP-RULE:` lines gets, after its own
+// entries, every track in library.db that passes all of its rules, such as
+// `bpm between 120 and 130` for a workout mix or `label is party`. The list
+// is worked out each time the playlist is opened, so it follows the library
+// as tracks are analyzed, rated and tagged.
+
+use crate::library::{Library, TrackRecord};
+use crate::utils::{self, text};
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Bpm,
+    Rating,
+    Plays,
+    Duration,
+    Loudness,
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Note,
+    Label,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        match name.to_lowercase().as_str() {
+            "bpm" | "tempo" => Some(Field::Bpm),
+            "rating" | "stars" => Some(Field::Rating),
+            "plays" => Some(Field::Plays),
+            "duration" | "length" => Some(Field::Duration),
+            "loudness" => Some(Field::Loudness),
+            "title" => Some(Field::Title),
+            "artist" => Some(Field::Artist),
+            "album" => Some(Field::Album),
+            "genre" => Some(Field::Genre),
+            "note" => Some(Field::Note),
+            "label" | "labels" => Some(Field::Label),
+            _ => None,
+        }
+    }
+
+    fn is_text(self) -> bool {
+        matches!(self, Field::Title | Field::Artist | Field::Album | Field::Genre | Field::Note | Field::Label)
+    }
+
+    // Lengths in seconds
+    fn number(self, record: &TrackRecord) -> Option<f64> {
+        match self {
+            Field::Bpm => record.bpm.map(f64::from),
+            Field::Rating => record.rating.map(f64::from),
+            Field::Plays => Some(record.plays as f64),
+            Field::Duration => record.duration.map(|duration| duration.as_secs_f64()),
+            Field::Loudness => record.loudness.map(f64::from),
+            _ => None,
+        }
+    }
+
+    fn text(self, record: &TrackRecord) -> Option<&str> {
+        match self {
+            Field::Title => record.title.as_deref(),
+            Field::Artist => record.artist.as_deref(),
+            Field::Album => record.album.as_deref(),
+            Field::Genre => record.genre.as_deref(),
+            Field::Note => record.note.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operator {
+    Between,
+    Is,
+    Contains,
+    Above,
+    Below,
+    AtLeast,
+    AtMost,
+}
+
+// Longer ones first, so `is not` isn't read as `is` with `not` for a value
+const OPERATORS: [(&str, Operator, bool); 15] = [
+    ("is not", Operator::Is, true),
+    ("not between", Operator::Between, true),
+    ("does not contain", Operator::Contains, true),
+    ("between", Operator::Between, false),
+    ("contains", Operator::Contains, false),
+    ("is", Operator::Is, false),
+    ("above", Operator::Above, false),
+    ("below", Operator::Below, false),
+    ("at least", Operator::AtLeast, false),
+    ("at most", Operator::AtMost, false),
+    (">=", Operator::AtLeast, false),
+    ("<=", Operator::AtMost, false),
+    ("!=", Operator::Is, true),
+    (">", Operator::Above, false),
+    ("<", Operator::Below, false),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Test {
+    Between(f64, f64),
+    Equals(f64),
+    Above(f64),
+    Below(f64),
+    AtLeast(f64),
+    AtMost(f64),
+    // Folded and lowercased, as the field is before comparing
+    Is(String),
+    Contains(String),
+}
+
+/// A condition on one field of a track, such as `bpm between 120 and 130`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    field: Field,
+    test: Test,
+    negated: bool,
+}
+
+impl Rule {
+    /// Reads a rule as written after `#CLAP-RULE:`, giving the reason when it
+    /// isn't one.
+    pub fn parse(rule: &str) -> Result<Rule, String> {
+        let rule = rule.trim();
+        let (name, rest) = rule.split_at(rule.find(|c: char| !c.is_alphanumeric()).unwrap_or(rule.len()));
+        let field = Field::parse(name).ok_or_else(|| format!("unknown field '{}'", name))?;
+        let rest = rest.trim_start();
+        let (operator, negated, value) = OPERATORS
+            .iter()
+            .find_map(|&(word, operator, negated)| Some((operator, negated, strip_operator(rest, word)?)))
+            .or_else(|| Some((Operator::Is, false, rest.strip_prefix('=')?.trim_start())))
+            .ok_or_else(|| format!("'{}' needs a test such as is, contains or between", name))?;
+        if value.is_empty() {
+            return Err(format!("'{}' needs a value", rule));
+        }
+
+        let test = if field.is_text() {
+            let value = text::fold(value).0.to_lowercase();
+            match operator {
+                Operator::Is => Test::Is(value),
+                Operator::Contains => Test::Contains(value),
+                _ => return Err(format!("{} is text, test it with is or contains", name.to_lowercase())),
+            }
+        } else {
+            let number = |value: &str| {
+                let number = match field {
+                    Field::Duration => utils::parse_time(value).map(|duration| duration.as_secs_f64()),
+                    _ => value.parse().ok().filter(|number: &f64| number.is_finite()),
+                };
+                number.ok_or_else(|| format!("'{}' isn't a number", value))
+            };
+            match operator {
+                Operator::Between => {
+                    let words: Vec<&str> = value.split_whitespace().collect();
+                    let [low, and, high] = words[..] else {
+                        return Err(format!("'{}' needs two values, as in between 120 and 130", rule));
+                    };
+                    if !and.eq_ignore_ascii_case("and") {
+                        return Err(format!("'{}' needs two values, as in between 120 and 130", rule));
+                    }
+                    let (low, high) = (number(low)?, number(high)?);
+                    Test::Between(low.min(high), low.max(high))
+                }
+                Operator::Is => Test::Equals(number(value)?),
+                Operator::Above => Test::Above(number(value)?),
+                Operator::Below => Test::Below(number(value)?),
+                Operator::AtLeast => Test::AtLeast(number(value)?),
+                Operator::AtMost => Test::AtMost(number(value)?),
+                Operator::Contains => return Err(format!("{} is a number, compare it with is, above, below or between", name.to_lowercase())),
+            }
+        };
+        Ok(Rule { field, test, negated })
+    }
+
+    /// Whether a track passes. Tracks without the field, such as those not
+    /// analyzed yet for their BPM, never do.
+    pub fn matches(&self, record: &TrackRecord) -> bool {
+        let passes = match &self.test {
+            // A track has labels even when it has none, so `label is not party` takes it
+            Test::Is(value) | Test::Contains(value) if self.field == Field::Label => {
+                Some(record.labels.iter().any(|label| text::fold(label).0.to_lowercase() == *value))
+            }
+            Test::Is(value) => self.field.text(record).map(|text| text::fold(text).0.to_lowercase() == *value),
+            Test::Contains(value) => self.field.text(record).map(|text| text::fold(text).0.to_lowercase().contains(value.as_str())),
+            test => self.field.number(record).map(|number| match *test {
+                Test::Between(low, high) => (low..=high).contains(&number),
+                // Values are stored rounded, so `bpm is 128` takes 127.6 too
+                Test::Equals(value) => (number - value).abs() < 0.5,
+                Test::Above(value) => number > value,
+                Test::Below(value) => number < value,
+                Test::AtLeast(value) => number >= value,
+                Test::AtMost(value) => number <= value,
+                Test::Is(_) | Test::Contains(_) => unreachable!(),
+            }),
+        };
+        passes.is_some_and(|passes| passes != self.negated)
+    }
+}
+
+// What follows a test written at the start of `text`, ignoring case; words
+// must end there, `>` and the like needn't
+fn strip_operator<'a>(text: &'a str, operator: &str) -> Option<&'a str> {
+    let start = text.get(..operator.len()).filter(|start| start.eq_ignore_ascii_case(operator))?;
+    let rest = &text[start.len()..];
+    let ends = !operator.ends_with(char::is_alphabetic) || rest.is_empty() || rest.starts_with(char::is_whitespace);
+    ends.then(|| rest.trim_start())
+}
+
+/// The tracks in library.db passing every rule, by artist, album and title.
+/// Only files whose tags were read count, which leaves out streams.
+pub fn select(rules: &[Rule], library: &Library) -> Vec<PathBuf> {
+    let mut found: Vec<(&PathBuf, &TrackRecord)> = library
+        .records()
+        .filter(|(_, record)| record.modified.is_some() && rules.iter().all(|rule| rule.matches(record)))
+        .collect();
+    found.sort_by_key(|(track, record)| (record.artist.clone(), record.album.clone(), record.title.clone(), *track));
+    found.into_iter().map(|(track, _)| track.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rule;
+    use crate::library::TrackRecord;
+    use std::time::Duration;
+
+    fn track() -> TrackRecord {
+        TrackRecord {
+            bpm: Some(124.3),
+            rating: Some(4),
+            plays: 7,
+            duration: Some(Duration::from_secs(245)),
+            artist: Some("Röyksopp".to_string()),
+            genre: Some("Electronic".to_string()),
+            labels: vec!["workout".to_string(), "Party".to_string()],
+            ..TrackRecord::default()
+        }
+    }
+
+    fn passes(rule: &str) -> bool {
+        Rule::parse(rule).unwrap_or_else(|e| panic!("{}: {}", rule, e)).matches(&track())
+    }
+
+    #[test]
+    fn numbers() {
+        assert!(passes("bpm between 120 and 130"));
+        assert!(passes("BPM Between 130 And 120"));
+        assert!(!passes("bpm between 125 and 130"));
+        assert!(passes("bpm not between 125 and 130"));
+        assert!(passes("bpm is 124"));
+        assert!(!passes("bpm != 124"));
+        assert!(passes("rating at least 4"));
+        assert!(passes("rating >= 4"));
+        assert!(!passes("rating > 4"));
+        assert!(passes("plays below 10"));
+        assert!(passes("plays<10"));
+        assert!(passes("duration between 3:00 and 4:30"));
+        assert!(passes("length above 240"));
+    }
+
+    #[test]
+    fn text() {
+        assert!(passes("artist is royksopp"));
+        assert!(passes("genre = electronic"));
+        assert!(passes("genre contains TRON"));
+        assert!(passes("genre does not contain rock"));
+        assert!(!passes("genre is not Electronic"));
+    }
+
+    #[test]
+    fn labels() {
+        assert!(passes("label is workout"));
+        assert!(passes("labels contains party"));
+        assert!(!passes("label is work"));
+        assert!(passes("label is not focus"));
+        assert!(!passes("label is not party"));
+        assert!(Rule::parse("label is not party").unwrap().matches(&TrackRecord::default()));
+        assert!(Rule::parse("label above 2").is_err());
+    }
+
+    #[test]
+    fn missing_values_never_pass() {
+        assert!(!passes("loudness below 0"));
+        assert!(!passes("loudness not between -100 and 0"));
+        assert!(!passes("album is not Melody A.M."));
+    }
+
+    #[test]
+    fn malformed() {
+        for rule in [
+            "",
+            "bpm",
+            "bpm between 120",
+            "bpm between 120 to 130",
+            "bpm is fast",
+            "bpm contains 12",
+            "artist above 3",
+            "mood is happy",
+            "genre",
+            "genre is",
+            "bpm isnt 120",
+            "duration is 1:xx",
+        ] {
+            assert!(Rule::parse(rule).is_err(), "{:?} parsed", rule);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+// Journal of the playback session, written every few seconds so the next
+// launch can pick up where playback was, even after a crash
+//
+// `session` holds the current track, position, modes and what the screen
+// showed as key=value lines, `session.tracks` the playlist, one path per line.
+// The playlist is only rewritten when it changes.
+
+use crate::config::config_dir;
+use crate::player::{MusicPlayer, Repeat, Shuffle};
+use crate::utils::write_atomically;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A saved session, as offered for resuming at startup.
+pub struct Session {
+    pub tracks: Vec<PathBuf>,
+    pub current: usize,
+    pub position: Duration,
+    pub playing: bool,
+    pub volume: f32,
+    pub repeat: Repeat,
+    pub shuffle: Shuffle,
+    pub view: ViewState,
+}
+
+/// What was on screen, put back along with playback.
+#[derive(Clone, Default)]
+pub struct ViewState {
+    // Selected track and the first one in view
+    pub selected: Option<usize>,
+    pub offset: usize,
+    pub stream_info: bool,
+    pub log: bool,
+    // Last search and whether it ran backwards, for n/N
+    pub search: Option<(String, bool)>,
+    pub search_regex: bool,
+}
+
+impl Session {
+    /// The last session, if something was playing in it.
+    pub fn load() -> Option<Session> {
+        let state = fs::read_to_string(config_dir().join("session")).ok()?;
+        let tracks: Vec<PathBuf> = fs::read_to_string(config_dir().join("session.tracks"))
+            .ok()?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect();
+
+        let mut session = Session {
+            tracks,
+            current: usize::MAX,
+            position: Duration::ZERO,
+            playing: false,
+            volume: 1.0,
+            repeat: Repeat::All,
+            shuffle: Shuffle::Off,
+            view: ViewState::default(),
+        };
+        for line in state.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match key {
+                "current" => session.current = value.parse().unwrap_or(usize::MAX),
+                "position" => {
+                    session.position = value.parse().ok().map(Duration::from_secs_f64).unwrap_or_default()
+                }
+                "playing" => session.playing = value == "true",
+                "volume" => session.volume = value.parse().unwrap_or(1.0),
+                "repeat" => session.repeat = value.parse().unwrap_or(Repeat::All),
+                "shuffle" => session.shuffle = value.parse().unwrap_or(Shuffle::Off),
+                "selected" => session.view.selected = value.parse().ok(),
+                "offset" => session.view.offset = value.parse().unwrap_or_default(),
+                "stream_info" => session.view.stream_info = value == "true",
+                "log" => session.view.log = value == "true",
+                "search" => session.view.search = Some((value.to_string(), false)),
+                "search_regex" => session.view.search_regex = value == "true",
+                "search_backward" => {
+                    if let Some((_, backward)) = &mut session.view.search {
+                        *backward = value == "true";
+                    }
+                }
+                _ => {}
+            }
+        }
+        (session.current < session.tracks.len()).then_some(session)
+    }
+
+    /// What resuming would play, e.g. `Song.flac at 2:13`.
+    pub fn describe(&self) -> String {
+        let name = self.tracks[self.current].file_name().unwrap_or_default().to_string_lossy();
+        let seconds = self.position.as_secs();
+        format!("{} at {}:{:02}", name, seconds / 60, seconds % 60)
+    }
+}
+
+/// Writes the session journal as playback goes on.
+pub struct Journal {
+    saved: Option<Instant>,
+    // Length of the playlist last written
+    tracks_saved: Option<usize>,
+}
+
+impl Journal {
+    pub fn new() -> Journal {
+        Journal {
+            saved: None,
+            tracks_saved: None,
+        }
+    }
+
+    /// Saves the session when the last save is old enough.
+    pub fn update(&mut self, music_player: &MusicPlayer, view: &ViewState) -> io::Result<()> {
+        if self.saved.is_some_and(|saved| saved.elapsed() < SAVE_INTERVAL) {
+            return Ok(());
+        }
+        self.save(music_player, view)
+    }
+
+    pub fn save(&mut self, music_player: &MusicPlayer, view: &ViewState) -> io::Result<()> {
+        self.saved = Some(Instant::now());
+        let dir = config_dir();
+        fs::create_dir_all(&dir)?;
+        if self.tracks_saved != Some(music_player.tracks.len()) {
+            let mut tracks = String::new();
+            for track in &music_player.tracks {
+                tracks.push_str(&track.to_string_lossy());
+                tracks.push('\n');
+            }
+            write_atomically(&dir.join("session.tracks"), &tracks)?;
+            self.tracks_saved = Some(music_player.tracks.len());
+        }
+
+        // Stopped sessions keep no current track, there is nothing to resume
+        let current = music_player.current_track.filter(|_| music_player.position().is_some());
+        let mut state = format!(
+            "current={}\nposition={:.3}\nplaying={}\nvolume={}\nrepeat={}\nshuffle={}\n",
+            current.map(|current| current.to_string()).unwrap_or_default(),
+            music_player.position().unwrap_or_default().as_secs_f64(),
+            music_player.is_playing(),
+            music_player.volume,
+            format!("{:?}", music_player.repeat()).to_lowercase(),
+            format!("{:?}", music_player.shuffle()).to_lowercase(),
+        );
+        state.push_str(&format!(
+            "selected={}\noffset={}\nstream_info={}\nlog={}\nsearch_regex={}\n",
+            view.selected.map(|selected| selected.to_string()).unwrap_or_default(),
+            view.offset,
+            view.stream_info,
+            view.log,
+            view.search_regex,
+        ));
+        if let Some((search, backward)) = &view.search {
+            state.push_str(&format!("search={}\nsearch_backward={}\n", search, backward));
+        }
+        write_atomically(&dir.join("session"), &state)
+    }
+}
@@ -0,0 +1,272 @@
+// Mirrors playlists to a phone, USB stick or any other folder. What was copied
+// is kept in `.clap-sync` on the device, one `MTIME<TAB>SIZE<TAB>COPY<TAB>SOURCE`
+// line per track, so later runs only copy tracks that are new or changed and
+// remove the ones no longer in any of the playlists.
+
+use crate::library::Library;
+use crate::rules;
+use crate::tasks::Reporter;
+use crate::transcode::{self, Format, TranscodeOptions};
+use crate::utils;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const MANIFEST: &str = ".clap-sync";
+
+#[derive(Clone, Debug)]
+pub struct SyncOptions {
+    pub target: PathBuf,
+    // Lossless tracks are encoded to this format, everything else is copied as it is
+    pub format: Option<Format>,
+    pub bitrate_kbps: u32,
+}
+
+#[derive(Default)]
+pub struct Summary {
+    pub copied: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub failed: usize,
+}
+
+/// What a sync says about a track: printed by `music-cli sync`, logged by the player.
+pub enum Line {
+    Done(String),
+    Failed(String),
+}
+
+struct Stamp {
+    mtime: u64,
+    size: u64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Stamp> {
+        let metadata = fs::metadata(path).ok()?;
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        Some(Stamp { mtime, size: metadata.len() })
+    }
+}
+
+struct Synced {
+    stamp: Stamp,
+    // Relative to the target folder
+    path: PathBuf,
+}
+
+fn is_lossless(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| matches!(extension.to_lowercase().as_str(), "flac" | "wav" | "aiff" | "aif" | "ape" | "wv"))
+}
+
+/// Where a track goes on the device: under the last two folders it is in, so
+/// `Artist/Album/01 Track.flac` for a library sorted that way, with the
+/// extension of the format it's encoded to. Two tracks can end up with the
+/// same place, which `claim` sorts out.
+fn copy_path(track: &Path, format: Option<Format>) -> PathBuf {
+    let folders: Vec<_> = track
+        .parent()
+        .into_iter()
+        .flat_map(|parent| parent.iter().rev().take(2))
+        .filter(|folder| *folder != "/")
+        .collect();
+    let mut path: PathBuf = folders.into_iter().rev().collect();
+    match format.filter(|_| is_lossless(track)) {
+        Some(format) => path.push(Path::new(track.file_stem().unwrap_or_default()).with_extension(format.extension())),
+        None => path.push(track.file_name().unwrap_or_default()),
+    }
+    path
+}
+
+/// The place for a track's copy: `path`, or `NAME (2)` and so on past those
+/// held by other tracks in the manifest or by files the sync didn't copy.
+/// The first one free is kept on later runs, as long as the track holds it.
+fn claim(track: &Path, path: PathBuf, owners: &HashMap<PathBuf, PathBuf>, target: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+    let mut candidate = path.clone();
+    let mut n = 2;
+    loop {
+        match owners.get(&candidate) {
+            Some(owner) if owner == track => return candidate,
+            None if !target.join(&candidate).exists() => return candidate,
+            _ => {}
+        }
+        candidate = path.with_file_name(format!("{} ({}){}", stem, n, extension));
+        n += 1;
+    }
+}
+
+fn read_manifest(target: &Path) -> HashMap<PathBuf, Synced> {
+    let contents = fs::read_to_string(target.join(MANIFEST)).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            let mtime = fields.next()?.parse().ok()?;
+            let size = fields.next()?.parse().ok()?;
+            let path = PathBuf::from(fields.next()?);
+            let source = PathBuf::from(fields.next()?);
+            Some((source, Synced { stamp: Stamp { mtime, size }, path }))
+        })
+        .collect()
+}
+
+fn write_manifest(target: &Path, copies: &HashMap<PathBuf, Synced>) -> Result<(), Box<dyn Error>> {
+    let mut lines: Vec<String> = copies
+        .iter()
+        .map(|(source, copy)| {
+            format!("{}\t{}\t{}\t{}\n", copy.stamp.mtime, copy.stamp.size, copy.path.display(), source.display())
+        })
+        .collect();
+    lines.sort();
+    fs::write(target.join(MANIFEST), lines.concat())?;
+    Ok(())
+}
+
+fn copy_track(track: &Path, copy: &Path, options: &SyncOptions, progress: &Reporter) -> Result<(), Box<dyn Error>> {
+    let copy = options.target.join(copy);
+    let dir = copy.parent().unwrap_or(&options.target).to_path_buf();
+    fs::create_dir_all(&dir)?;
+    match options.format.filter(|_| is_lossless(track)) {
+        Some(format) => {
+            // The old copy of a changed track makes way, ffmpeg won't overwrite it
+            if copy.exists() {
+                fs::remove_file(&copy)?;
+            }
+            let transcode = TranscodeOptions { format, bitrate_kbps: options.bitrate_kbps, output_dir: dir };
+            transcode::transcode(track, &copy, &transcode, progress)?;
+        }
+        None => {
+            fs::copy(track, &copy)?;
+        }
+    }
+    Ok(())
+}
+
+// Removes a copy and the folders it leaves empty, up to the target
+fn remove_copy(target: &Path, copy: &Path) {
+    let path = target.join(copy);
+    if fs::remove_file(&path).is_err() {
+        return;
+    }
+    let mut dir = path.parent();
+    while let Some(folder) = dir.filter(|folder| *folder != target) {
+        if fs::remove_dir(folder).is_err() {
+            break;
+        }
+        dir = folder.parent();
+    }
+}
+
+/// Brings the target up to date with the playlists, telling `say` what it
+/// does, and writes each playlist next to the tracks as `NAME.m3u8`. A cancel
+/// keeps what was copied so far in the manifest and removes nothing.
+pub fn sync(
+    playlists: &[PathBuf],
+    options: &SyncOptions,
+    progress: &Reporter,
+    say: &mut dyn FnMut(Line),
+) -> Result<Summary, Box<dyn Error>> {
+    if !options.target.is_dir() {
+        return Err(format!("{} is not a folder, is the device mounted?", options.target.display()).into());
+    }
+    let mut copies = read_manifest(&options.target);
+    // Which track each copy belongs to, to tell places apart
+    let mut owners: HashMap<PathBuf, PathBuf> =
+        copies.iter().map(|(source, copy)| (copy.path.clone(), source.clone())).collect();
+    let mut summary = Summary::default();
+    let mut wanted = HashSet::new();
+
+    // Read up front so progress can be counted against every track. Smart
+    // playlists pick theirs from library.db, loaded for the first of them.
+    let mut library = None;
+    let playlists = playlists
+        .iter()
+        .map(|playlist| {
+            let read = utils::read_playlist(playlist).map_err(|e| format!("{}: {}", playlist.display(), e))?;
+            let mut tracks: Vec<PathBuf> = read.entries.into_iter().map(PathBuf::from).collect();
+            if !read.rules.is_empty() {
+                tracks.extend(rules::select(&read.rules, library.get_or_insert_with(Library::load)));
+            }
+            tracks.retain(|track| !track.to_string_lossy().contains("://"));
+            Ok((playlist, tracks))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let total = playlists.iter().map(|(_, tracks)| tracks.len() as u64).sum();
+    let mut done = 0;
+
+    for (playlist, tracks) in playlists {
+        let mut lines = vec!["#EXTM3U".to_string()];
+        for track in tracks {
+            progress.update(done, total);
+            done += 1;
+            if progress.checkpoint() {
+                write_manifest(&options.target, &copies)?;
+                return Err("canceled".into());
+            }
+            let Some(stamp) = Stamp::of(&track) else {
+                say(Line::Failed(format!("{}: not found", track.display())));
+                summary.failed += 1;
+                continue;
+            };
+            let path = claim(&track, copy_path(&track, options.format), &owners, &options.target);
+            // Tracks that failed to copy are left out of the playlist
+            let line = path.to_string_lossy().replace('\\', "/");
+            if !wanted.insert(track.clone()) {
+                if copies.get(&track).is_some_and(|copy| copy.path == path) {
+                    lines.push(line);
+                }
+                continue;
+            }
+
+            let current = copies.get(&track).is_some_and(|copy| {
+                copy.path == path
+                    && copy.stamp.mtime == stamp.mtime
+                    && copy.stamp.size == stamp.size
+                    && options.target.join(&copy.path).exists()
+            });
+            if current {
+                summary.unchanged += 1;
+                lines.push(line);
+                continue;
+            }
+            match copy_track(&track, &path, options, progress) {
+                Ok(()) => {
+                    say(Line::Done(format!("{} -> {}", track.display(), options.target.join(&path).display())));
+                    owners.insert(path.clone(), track.clone());
+                    if let Some(old) = copies.insert(track, Synced { stamp, path: path.clone() }) {
+                        if old.path != path {
+                            owners.remove(&old.path);
+                            remove_copy(&options.target, &old.path);
+                        }
+                    }
+                    summary.copied += 1;
+                    lines.push(line);
+                }
+                Err(e) => {
+                    say(Line::Failed(format!("{}: {}", track.display(), e)));
+                    summary.failed += 1;
+                }
+            }
+        }
+        let name = playlist.file_stem().unwrap_or_default();
+        fs::write(options.target.join(name).with_extension("m3u8"), lines.join("\n") + "\n")?;
+    }
+
+    copies.retain(|source, copy| {
+        if wanted.contains(source) {
+            return true;
+        }
+        say(Line::Done(format!("removed {}", options.target.join(&copy.path).display())));
+        remove_copy(&options.target, &copy.path);
+        summary.removed += 1;
+        false
+    });
+    write_manifest(&options.target, &copies)?;
+    Ok(summary)
+}
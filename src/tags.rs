@@ -0,0 +1,285 @@
+// Title, artist, album, genre and length of tracks, read from their tags on a
+// background thread and cached in the library
+
+use crate::decode::{self, extension, needs_ffmpeg};
+use crate::tasks::Reporter;
+use crate::{archive, remote};
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+
+#[derive(Clone, Default)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub duration: Option<Duration>,
+    pub size: Option<u64>,
+}
+
+impl Tags {
+    fn take(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let field = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => &mut self.title,
+                Some(StandardTagKey::Artist) => &mut self.artist,
+                Some(StandardTagKey::Album) => &mut self.album,
+                Some(StandardTagKey::Genre) => &mut self.genre,
+                _ => continue,
+            };
+            // RIFF INFO strings keep their terminating NUL
+            let value = tag.value.to_string();
+            let value = value.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+            if field.is_none() && !value.is_empty() {
+                *field = Some(value.to_string());
+            }
+        }
+    }
+}
+
+/// Reads a track's tags. Streams, SFTP and WebDAV tracks are skipped, reading them
+/// would mean downloading them, and Jellyfin tracks are asked about on the server.
+pub fn read(path: &Path) -> Tags {
+    if remote::is_jellyfin(path) {
+        return remote::jellyfin::tags(path).unwrap_or_default();
+    }
+    if decode::is_stream(path) || remote::is_sftp(path) || remote::is_webdav(path) {
+        return Tags::default();
+    }
+    let mut tags = if needs_ffmpeg(&extension(path)) {
+        read_ffprobe(path)
+    } else {
+        read_symphonia(path).unwrap_or_default()
+    };
+    tags.size = match archive::split(path) {
+        Some((archive, name)) => archive::entries(&archive)
+            .ok()
+            .and_then(|entries| entries.into_iter().find(|entry| entry.name == name))
+            .map(|entry| entry.size),
+        None => std::fs::metadata(path).ok().map(|metadata| metadata.len()),
+    };
+    tags
+}
+
+/// When a track's file last changed, in seconds, so cached tags can be
+/// checked. Archived tracks use the archive's time.
+pub fn modified(path: &Path) -> Option<u64> {
+    // Server tracks are asked about once
+    if remote::is_jellyfin(path) {
+        return Some(0);
+    }
+    if decode::is_stream(path) || remote::is_sftp(path) || remote::is_webdav(path) {
+        return None;
+    }
+    let file = archive::split(path).map_or(path.to_path_buf(), |(archive, _)| archive);
+    let modified = std::fs::metadata(file).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+// Names of cover images next to the tracks, most likely first
+const COVER_NAMES: [&str; 5] = ["cover", "folder", "front", "album", "albumart"];
+
+/// A track's cover: an image such as cover.jpg in its folder, or else the
+/// picture in its tags. Tracks on servers and streams have none.
+pub fn cover(path: &Path) -> Option<Vec<u8>> {
+    if decode::is_stream(path) || remote::is_sftp(path) || remote::is_webdav(path) || remote::is_jellyfin(path) {
+        return None;
+    }
+    folder_cover(path).or_else(|| embedded_cover(path))
+}
+
+fn folder_cover(path: &Path) -> Option<Vec<u8>> {
+    let images: Vec<PathBuf> = std::fs::read_dir(path.parent()?)
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|file| matches!(extension(file).as_str(), "jpg" | "jpeg" | "png"))
+        .collect();
+    let stem = |file: &PathBuf| file.file_stem().map(|stem| stem.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let image = images
+        .iter()
+        .filter_map(|file| Some((COVER_NAMES.iter().position(|name| stem(file) == *name)?, file)))
+        .min()
+        .map(|(_, file)| file)?;
+    std::fs::read(image).ok()
+}
+
+fn embedded_cover(path: &Path) -> Option<Vec<u8>> {
+    let stream = MediaSourceStream::new(decode::open_media(path).ok()?, Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(&extension(path));
+    let mut probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .ok()?;
+    if let Some(visual) = probed.format.metadata().current().and_then(|revision| revision.visuals().first()) {
+        return Some(visual.data.to_vec());
+    }
+    let metadata = probed.metadata.get()?;
+    let visual = metadata.current()?.visuals().first()?;
+    Some(visual.data.to_vec())
+}
+
+fn read_symphonia(path: &Path) -> Option<Tags> {
+    let stream = MediaSourceStream::new(decode::open_media(path).ok()?, Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(&extension(path));
+    let mut probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .ok()?;
+
+    let mut tags = Tags::default();
+    // Tags in the container come first, then ID3 tags found while probing
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.take(revision);
+    }
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|metadata| metadata.current()) {
+        tags.take(revision);
+    }
+    let params = &probed.format.default_track()?.codec_params;
+    tags.duration = params
+        .time_base
+        .zip(params.n_frames)
+        .map(|(base, frames)| Duration::from_secs_f64(frames as f64 * base.numer as f64 / base.denom as f64));
+    Some(tags)
+}
+
+fn read_ffprobe(path: &Path) -> Tags {
+    let Ok(output) = Command::new("ffprobe")
+        .args(["-v", "error", "-show_entries", "format=duration:format_tags=title,artist,album,genre"])
+        .args(["-of", "default=noprint_wrappers=1"])
+        .arg(path)
+        .output()
+    else {
+        return Tags::default();
+    };
+    let info = String::from_utf8_lossy(&output.stdout);
+    let field = |name: &str| {
+        info.lines()
+            .find_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                // Tag names keep the file's case, e.g. TAG:ARTIST in Vorbis comments
+                let key = key.strip_prefix("TAG:").unwrap_or(key);
+                key.eq_ignore_ascii_case(name).then(|| value.trim().to_string())
+            })
+            .filter(|value| !value.is_empty())
+    };
+    Tags {
+        title: field("title"),
+        artist: field("artist"),
+        album: field("album"),
+        genre: field("genre"),
+        duration: field("duration")
+            .and_then(|duration| duration.parse::<f64>().ok())
+            .map(Duration::from_secs_f64),
+        size: None,
+    }
+}
+
+/// A track whose tags were read, with the modification time they belong to.
+pub struct Read {
+    pub path: PathBuf,
+    pub tags: Tags,
+    pub modified: u64,
+}
+
+// Tracks waiting to be read, the ones on screen go to the front
+type Queue = Arc<(Mutex<VecDeque<(PathBuf, Option<u64>)>>, Condvar)>;
+
+/// Reads tags on a worker thread. Each track is checked once per session and
+/// only read again when its file changed since the cached read. Progress is
+/// counted from the start of each burst of requests until the queue is empty,
+/// and canceling drops what is queued, leaving later requests to be read.
+pub struct TagReader {
+    checked: HashSet<PathBuf>,
+    // Checked ahead of the rest, possibly while also waiting further back
+    urgent: HashSet<PathBuf>,
+    queue: Queue,
+    results: Receiver<Read>,
+}
+
+impl TagReader {
+    pub fn new(progress: Reporter) -> TagReader {
+        let queue: Queue = Arc::default();
+        let (result_tx, results) = mpsc::channel();
+        let jobs = queue.clone();
+        let mut done = HashSet::new();
+        let mut checked = 0;
+        thread::spawn(move || loop {
+            let (path, cached, left) = {
+                let (pending, ready) = &*jobs;
+                let mut pending = pending.lock().unwrap();
+                loop {
+                    match pending.pop_front() {
+                        Some((path, cached)) => break (path, cached, pending.len() as u64),
+                        None => pending = ready.wait(pending).unwrap(),
+                    }
+                }
+            };
+            if progress.checkpoint() {
+                jobs.0.lock().unwrap().clear();
+                progress.reset();
+                progress.update(0, 0);
+                checked = 0;
+                continue;
+            }
+            checked += 1;
+            // Tracks asked for twice count twice, the second time in passing
+            if done.insert(path.clone()) {
+                if let Some(read) = read_if_changed(path, cached) {
+                    if result_tx.send(read).is_err() {
+                        break;
+                    }
+                }
+            }
+            progress.update(checked, checked + left);
+            if left == 0 {
+                checked = 0;
+            }
+        });
+        TagReader {
+            checked: HashSet::new(),
+            urgent: HashSet::new(),
+            queue,
+            results,
+        }
+    }
+
+    /// Queues a track unless it was checked before. `cached` is the
+    /// modification time of the tags in the library.
+    pub fn request(&mut self, path: &Path, cached: Option<u64>, urgent: bool) {
+        let new = self.checked.insert(path.to_path_buf());
+        let first_urgent = urgent && self.urgent.insert(path.to_path_buf());
+        if !new && !first_urgent {
+            return;
+        }
+        let (pending, ready) = &*self.queue;
+        let mut pending = pending.lock().unwrap();
+        if urgent {
+            pending.push_front((path.to_path_buf(), cached));
+        } else {
+            pending.push_back((path.to_path_buf(), cached));
+        }
+        ready.notify_one();
+    }
+
+    pub fn poll(&mut self) -> Vec<Read> {
+        self.results.try_iter().collect()
+    }
+}
+
+fn read_if_changed(path: PathBuf, cached: Option<u64>) -> Option<Read> {
+    let modified = modified(&path)?;
+    if cached == Some(modified) {
+        return None;
+    }
+    let tags = read(&path);
+    Some(Read { path, tags, modified })
+}
@@ -0,0 +1,242 @@
+// Long jobs running in the background report how far they've got here:
+// reading tags, analyzing tracks, transcodes, ReplayGain scans and syncs to a
+// device. Each job holds a `Reporter` that sends what it has done out of how
+// much, and the status bar shows the jobs busy for more than a moment with a
+// spinner. The tasks popup pauses or cancels them through the same reporter,
+// which jobs check between pieces of work.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Bursts of work shorter than this never show, so the few tags read while
+// scrolling don't flicker by
+const SHOW_AFTER: Duration = Duration::from_millis(500);
+// Quarters of a circle, which the ASCII mode turns into | / - \
+const SPINNER: [&str; 4] = ["◐", "◓", "◑", "◒"];
+const SPINNER_STEP: Duration = Duration::from_millis(150);
+// How often a paused job looks whether it may go on
+const PAUSE_POLL: Duration = Duration::from_millis(100);
+
+/// How a job's progress reads: `Scanning 4 312/12 000` or `Computing ReplayGain 12%`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Measure {
+    Count,
+    Percent,
+}
+
+enum Update {
+    Progress { id: u64, done: u64, total: u64 },
+    Finished(u64),
+}
+
+// Set from the tasks popup, read by the job
+#[derive(Default)]
+struct Control {
+    cancelled: AtomicBool,
+    paused: AtomicBool,
+}
+
+/// A job's end of the progress channel, moved to the thread doing the work.
+#[derive(Clone)]
+pub struct Reporter {
+    id: u64,
+    updates: Option<Sender<Update>>,
+    control: Arc<Control>,
+}
+
+impl Reporter {
+    /// A reporter nobody listens to, for jobs run from the command line.
+    pub fn none() -> Reporter {
+        Reporter { id: 0, updates: None, control: Arc::default() }
+    }
+
+    /// Waits while the job is paused, then tells whether it was canceled.
+    pub fn checkpoint(&self) -> bool {
+        while self.control.paused.load(Ordering::Relaxed) && !self.control.cancelled.load(Ordering::Relaxed) {
+            thread::sleep(PAUSE_POLL);
+        }
+        self.control.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Clears a cancel once the work it was meant for is dropped, for jobs
+    /// that go on waiting for more.
+    pub fn reset(&self) {
+        self.control.cancelled.store(false, Ordering::Relaxed);
+    }
+
+    /// Reports `done` out of `total`, 0 out of 0 when the job is idle.
+    pub fn update(&self, done: u64, total: u64) {
+        if let Some(updates) = &self.updates {
+            let _ = updates.send(Update::Progress { id: self.id, done, total });
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Some(updates) = &self.updates {
+            let _ = updates.send(Update::Finished(self.id));
+        }
+    }
+}
+
+struct Task {
+    label: String,
+    measure: Measure,
+    done: u64,
+    total: u64,
+    // Since when the job has had work left, None while idle
+    busy_since: Option<Instant>,
+    control: Arc<Control>,
+}
+
+impl Task {
+    fn shown(&self) -> bool {
+        self.busy_since.is_some_and(|since| since.elapsed() >= SHOW_AFTER)
+    }
+
+    fn describe(&self) -> String {
+        let progress = match self.measure {
+            Measure::Count => format!("{} {}/{}", self.label, group_digits(self.done), group_digits(self.total)),
+            Measure::Percent => format!("{} {}%", self.label, self.done * 100 / self.total.max(1)),
+        };
+        if self.control.cancelled.load(Ordering::Relaxed) {
+            format!("{} (canceling)", progress)
+        } else if self.control.paused.load(Ordering::Relaxed) {
+            format!("{} (paused)", progress)
+        } else {
+            progress
+        }
+    }
+}
+
+/// The player's end of the progress channel, with every job that hasn't finished.
+pub struct Tasks {
+    updates: Sender<Update>,
+    received: Receiver<Update>,
+    running: BTreeMap<u64, Task>,
+    next_id: u64,
+    created: Instant,
+}
+
+impl Tasks {
+    pub fn new() -> Tasks {
+        let (updates, received) = mpsc::channel();
+        Tasks {
+            updates,
+            received,
+            running: BTreeMap::new(),
+            next_id: 1,
+            created: Instant::now(),
+        }
+    }
+
+    /// Registers a job under `label`, such as `Scanning`, returning its reporter.
+    pub fn start(&mut self, label: &str, measure: Measure) -> Reporter {
+        let id = self.next_id;
+        self.next_id += 1;
+        let control: Arc<Control> = Arc::default();
+        self.running.insert(id, Task {
+            label: label.to_string(),
+            measure,
+            done: 0,
+            total: 0,
+            busy_since: None,
+            control: control.clone(),
+        });
+        Reporter { id, updates: Some(self.updates.clone()), control }
+    }
+
+    /// Takes in the progress reported since the last poll, returning whether
+    /// any of it is on screen.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        for update in self.received.try_iter() {
+            match update {
+                Update::Progress { id, done, total } => {
+                    let Some(task) = self.running.get_mut(&id) else {
+                        continue;
+                    };
+                    changed |= task.shown();
+                    task.done = done;
+                    task.total = total;
+                    if done >= total {
+                        task.busy_since = None;
+                    } else if task.busy_since.is_none() {
+                        task.busy_since = Some(Instant::now());
+                    }
+                }
+                Update::Finished(id) => {
+                    changed |= self.running.remove(&id).is_some_and(|task| task.shown());
+                }
+            }
+        }
+        changed
+    }
+
+    /// Whether any job is on screen.
+    pub fn busy(&self) -> bool {
+        self.running.values().any(Task::shown)
+    }
+
+    /// Jobs with work left, by id, for the tasks popup.
+    pub fn list(&self) -> Vec<(u64, String)> {
+        self.running
+            .iter()
+            .filter(|(_, task)| task.busy_since.is_some())
+            .map(|(&id, task)| (id, task.describe()))
+            .collect()
+    }
+
+    /// Pauses a job, or lets it go on, returning whether it is paused now.
+    pub fn toggle_pause(&self, id: u64) -> bool {
+        let Some(task) = self.running.get(&id) else {
+            return false;
+        };
+        let paused = !task.control.paused.load(Ordering::Relaxed);
+        task.control.paused.store(paused, Ordering::Relaxed);
+        paused
+    }
+
+    /// Asks a job to stop, which it does at its next checkpoint. A paused job
+    /// wakes up to stop.
+    pub fn cancel(&self, id: u64) {
+        if let Some(task) = self.running.get(&id) {
+            task.control.paused.store(false, Ordering::Relaxed);
+            task.control.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn describe(&self, id: u64) -> Option<String> {
+        self.running.get(&id).map(Task::describe)
+    }
+
+    /// A line for each job on screen, the spinner standing still unless `animate`.
+    pub fn labels(&self, animate: bool) -> Vec<String> {
+        let frame = if animate {
+            (self.created.elapsed().as_millis() / SPINNER_STEP.as_millis()) as usize % SPINNER.len()
+        } else {
+            0
+        };
+        self.running
+            .values()
+            .filter(|task| task.shown())
+            .map(|task| format!("{} {}", SPINNER[frame], task.describe()))
+            .collect()
+    }
+}
+
+// 12000 as `12 000`
+fn group_digits(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(' ');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
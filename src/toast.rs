@@ -0,0 +1,90 @@
+// Short messages shown in the bottom-right corner for a few seconds, such as
+// "Added 12 tracks" or "Volume 70%". Any module or thread can push one; the
+// interface draws whatever hasn't expired yet.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How long a toast stays, errors a little longer to be read
+const SHOWN_FOR: Duration = Duration::from_secs(3);
+const ERROR_SHOWN_FOR: Duration = Duration::from_secs(6);
+// Older toasts make way for newer ones past this many
+const MAX_SHOWN: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Info,
+    Error,
+}
+
+/// A message on screen, newest last.
+#[derive(Clone)]
+pub struct Toast {
+    pub kind: Kind,
+    pub text: String,
+    // Toasts about the same thing replace each other, so holding + shows one volume
+    topic: Option<&'static str>,
+    until: Instant,
+}
+
+struct Queue {
+    toasts: VecDeque<Toast>,
+    // Set when a toast comes or goes, until the next redraw
+    changed: bool,
+}
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    toasts: VecDeque::new(),
+    changed: false,
+});
+
+fn push(kind: Kind, topic: Option<&'static str>, text: String) {
+    let shown_for = match kind {
+        Kind::Info => SHOWN_FOR,
+        Kind::Error => ERROR_SHOWN_FOR,
+    };
+    let mut queue = QUEUE.lock().unwrap();
+    if topic.is_some() {
+        queue.toasts.retain(|toast| toast.topic != topic);
+    }
+    if queue.toasts.len() == MAX_SHOWN {
+        queue.toasts.pop_front();
+    }
+    queue.toasts.push_back(Toast {
+        kind,
+        text,
+        topic,
+        until: Instant::now() + shown_for,
+    });
+    queue.changed = true;
+}
+
+pub fn info(text: impl Into<String>) {
+    push(Kind::Info, None, text.into());
+}
+
+pub fn error(text: impl Into<String>) {
+    push(Kind::Error, None, text.into());
+}
+
+/// Shows a toast in place of the last one about `topic`.
+pub fn update(topic: &'static str, text: impl Into<String>) {
+    push(Kind::Info, Some(topic), text.into());
+}
+
+/// Drops expired toasts, returning whether the screen needs redrawing.
+pub fn poll() -> bool {
+    let mut queue = QUEUE.lock().unwrap();
+    let now = Instant::now();
+    let before = queue.toasts.len();
+    queue.toasts.retain(|toast| toast.until > now);
+    let changed = queue.changed || queue.toasts.len() != before;
+    queue.changed = false;
+    changed
+}
+
+/// The toasts to draw, oldest first.
+pub fn shown() -> Vec<Toast> {
+    QUEUE.lock().unwrap().toasts.iter().cloned().collect()
+}
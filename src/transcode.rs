@@ -0,0 +1,203 @@
+use crate::decode;
+use crate::tasks::Reporter;
+use rodio::Source;
+use std::error::Error;
+use std::fs;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Format {
+    Mp3,
+    Ogg,
+    Opus,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Mp3 => "mp3",
+            Format::Ogg => "ogg",
+            Format::Opus => "opus",
+        }
+    }
+
+    fn codec(self) -> &'static str {
+        match self {
+            Format::Mp3 => "libmp3lame",
+            Format::Ogg => "libvorbis",
+            Format::Opus => "libopus",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mp3" => Ok(Format::Mp3),
+            "ogg" | "vorbis" => Ok(Format::Ogg),
+            "opus" => Ok(Format::Opus),
+            _ => Err(format!("unsupported format '{}' (expected mp3, ogg or opus)", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TranscodeOptions {
+    pub format: Format,
+    pub bitrate_kbps: u32,
+    pub output_dir: PathBuf,
+}
+
+/// Where a track is encoded to in `dir`: its name with the format's
+/// extension, or `NAME (2)` and so on when a file of that name is there, so
+/// tracks of the same name from different albums don't replace each other.
+pub fn output_path(track: &Path, dir: &Path, format: Format) -> PathBuf {
+    let stem = track.file_stem().unwrap_or_default().to_string_lossy();
+    let mut output = dir.join(format!("{}.{}", stem, format.extension()));
+    let mut n = 2;
+    while output.exists() {
+        output = dir.join(format!("{} ({}).{}", stem, n, format.extension()));
+        n += 1;
+    }
+    output
+}
+
+/// Decodes a track and encodes it to `output` through ffmpeg, which must
+/// not exist yet. A cancel through `progress` stops it within a second of
+/// audio, and like a failed encode removes the partly written file.
+pub fn transcode(path: &Path, output: &Path, options: &TranscodeOptions, progress: &Reporter) -> Result<(), Box<dyn Error>> {
+    if output.exists() {
+        return Err(format!("{} already exists", output.display()).into());
+    }
+    let source = decode::open(path, 0)?.convert_samples::<i16>();
+    let channels = source.channels();
+    let sample_rate = source.sample_rate();
+    if let Some(dir) = output.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // -n rather than -y: nothing already on disk is ever overwritten
+    let mut encoder = Command::new("ffmpeg")
+        .args(["-hide_banner", "-loglevel", "error", "-n", "-f", "s16le"])
+        .args(["-ar", &sample_rate.to_string(), "-ac", &channels.to_string()])
+        .args(["-i", "-", "-c:a", options.format.codec()])
+        .args(["-b:a", &format!("{}k", options.bitrate_kbps)])
+        .arg(output)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not start ffmpeg: {}", e))?;
+
+    // Read alongside, so ffmpeg never blocks on a full pipe while it's fed
+    let mut stderr = encoder.stderr.take();
+    let errors = thread::spawn(move || {
+        let mut text = String::new();
+        if let Some(stderr) = stderr.as_mut() {
+            let _ = stderr.read_to_string(&mut text);
+        }
+        text
+    });
+
+    let mut fed = Ok(());
+    if let Some(stdin) = encoder.stdin.take() {
+        let mut stdin = BufWriter::new(stdin);
+        let second = (sample_rate as usize * channels as usize).max(1);
+        for (i, sample) in source.enumerate() {
+            if i % second == 0 && progress.checkpoint() {
+                drop(stdin);
+                let _ = encoder.kill();
+                let _ = encoder.wait();
+                let _ = fs::remove_file(output);
+                return Err("canceled".into());
+            }
+            // A broken pipe means ffmpeg gave up; its own message says why
+            fed = stdin.write_all(&sample.to_le_bytes());
+            if fed.is_err() {
+                break;
+            }
+        }
+        if fed.is_ok() {
+            fed = stdin.flush();
+        }
+    }
+
+    let status = encoder.wait()?;
+    let errors = errors.join().unwrap_or_default();
+    if status.success() && fed.is_ok() {
+        return Ok(());
+    }
+    let _ = fs::remove_file(output);
+    match errors.lines().map(str::trim).rfind(|line| !line.is_empty()) {
+        Some(line) => Err(format!("ffmpeg failed: {}", line).into()),
+        None => match fed {
+            Err(e) => Err(format!("ffmpeg stopped reading: {}", e).into()),
+            Ok(()) => Err(format!("ffmpeg failed to encode {}", path.display()).into()),
+        },
+    }
+}
+
+/// Runs transcodes started from the UI on a background thread. Once
+/// canceled, the queued ones come back as canceled too.
+pub struct Transcoder {
+    jobs: Sender<(PathBuf, TranscodeOptions)>,
+    results: Receiver<Result<PathBuf, String>>,
+    pending: usize,
+    // Finished since the queue was last empty
+    finished: usize,
+    progress: Reporter,
+}
+
+impl Transcoder {
+    pub fn new(progress: Reporter) -> Self {
+        let (jobs, job_rx) = mpsc::channel::<(PathBuf, TranscodeOptions)>();
+        let (result_tx, results) = mpsc::channel();
+        let worker = progress.clone();
+        thread::spawn(move || {
+            for (path, options) in job_rx {
+                let output = output_path(&path, &options.output_dir, options.format);
+                let result = transcode(&path, &output, &options, &worker).map(|()| output).map_err(|e| e.to_string());
+                if result_tx.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+        Transcoder { jobs, results, pending: 0, finished: 0, progress }
+    }
+
+    pub fn queue(&mut self, path: PathBuf, options: TranscodeOptions) {
+        if self.jobs.send((path, options)).is_ok() {
+            self.pending += 1;
+            self.report();
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    pub fn poll(&mut self) -> Vec<Result<PathBuf, String>> {
+        let finished: Vec<_> = self.results.try_iter().collect();
+        if !finished.is_empty() {
+            self.pending -= finished.len();
+            self.finished += finished.len();
+            self.report();
+        }
+        if self.pending == 0 {
+            self.finished = 0;
+            self.progress.reset();
+        }
+        finished
+    }
+
+    fn report(&self) {
+        self.progress.update(self.finished as u64, (self.finished + self.pending) as u64);
+    }
+}
@@ -0,0 +1,337 @@
+// The albums of the library as a grid of covers, in place of the playlist.
+// Covers are pictures where the terminal can show them, through the kitty
+// graphics protocol or iTerm2's inline images, and coloured tiles with the
+// album's initials everywhere else.
+
+use super::theme::Palette;
+use super::truncate;
+use crate::library::Library;
+use crate::tags;
+use crossterm::{cursor::MoveTo, queue};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use tui::{
+    backend::Backend,
+    layout::{Alignment, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, Paragraph},
+    Frame,
+};
+
+// A tile is a cover, about square in most fonts, over the title and artist
+const TILE_WIDTH: u16 = 16;
+const COVER_WIDTH: u16 = 14;
+const COVER_HEIGHT: u16 = 7;
+const TILE_HEIGHT: u16 = COVER_HEIGHT + 3;
+
+// Backgrounds of the tiles without a picture, dark enough for white initials
+const TILE_COLORS: [u8; 12] = [24, 29, 53, 58, 88, 94, 130, 61, 67, 97, 131, 23];
+
+pub struct Album {
+    pub title: String,
+    pub artist: String,
+    pub tracks: Vec<PathBuf>,
+}
+
+/// How the terminal shows pictures, if it does.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Graphics {
+    None,
+    Kitty,
+    ITerm,
+}
+
+impl Graphics {
+    pub fn detect() -> Graphics {
+        let var = |name: &str| std::env::var(name).unwrap_or_default();
+        // tmux doesn't pass the pictures on
+        if !var("TMUX").is_empty() {
+            return Graphics::None;
+        }
+        if var("TERM").contains("kitty") || !var("KITTY_WINDOW_ID").is_empty() || var("TERM_PROGRAM") == "ghostty" {
+            Graphics::Kitty
+        } else if matches!(var("TERM_PROGRAM").as_str(), "iTerm.app" | "WezTerm") || var("LC_TERMINAL") == "iTerm2" {
+            Graphics::ITerm
+        } else {
+            Graphics::None
+        }
+    }
+}
+
+/// What the caller has to do after the covers were drawn.
+#[derive(PartialEq)]
+pub enum Covers {
+    Shown,
+    // iTerm2 pictures stay until their cells are written again, so the
+    // screen has to be cleared and drawn again before the new ones go up
+    Stale,
+}
+
+pub struct AlbumGrid {
+    pub albums: Vec<Album>,
+    pub selected: usize,
+    // First row of tiles in view, and the grid's size in the last frame
+    offset: usize,
+    columns: usize,
+    rows: usize,
+    graphics: Graphics,
+    // Pictures read so far by album, None for albums without one
+    covers: HashMap<usize, Option<Vec<u8>>>,
+    // Where the last frame wants pictures, and where they are on screen
+    placements: Vec<(Rect, usize)>,
+    shown: Vec<(Rect, usize)>,
+    // Pictures the kitty terminal has been sent
+    sent: HashSet<usize>,
+    // Set when the grid is closed, so its pictures are taken down
+    pub closed: bool,
+}
+
+impl AlbumGrid {
+    /// The library's albums, by artist and then title, with their tracks in
+    /// file order. Tracks without an album tag are left out.
+    pub fn new(library: &Library, graphics: Graphics) -> AlbumGrid {
+        let mut grouped: BTreeMap<(String, String), Album> = BTreeMap::new();
+        for (path, record) in library.records() {
+            let Some(title) = record.album.clone() else {
+                continue;
+            };
+            let artist = record.artist.clone().unwrap_or_default();
+            let key = (artist.to_lowercase(), title.to_lowercase());
+            grouped
+                .entry(key)
+                .or_insert_with(|| Album { title, artist, tracks: Vec::new() })
+                .tracks
+                .push(path.clone());
+        }
+        let albums: Vec<Album> = grouped
+            .into_values()
+            .map(|mut album| {
+                album.tracks.sort();
+                album
+            })
+            .collect();
+        AlbumGrid {
+            albums,
+            selected: 0,
+            offset: 0,
+            columns: 1,
+            rows: 1,
+            graphics,
+            covers: HashMap::new(),
+            placements: Vec::new(),
+            shown: Vec::new(),
+            sent: HashSet::new(),
+            closed: false,
+        }
+    }
+
+    pub fn selected_album(&self) -> Option<&Album> {
+        self.albums.get(self.selected)
+    }
+
+    /// Moves the selection by columns and rows of tiles, stopping at the ends.
+    pub fn move_by(&mut self, columns: isize, rows: isize) {
+        let step = columns + rows * self.columns as isize;
+        let last = self.albums.len().saturating_sub(1) as isize;
+        self.selected = (self.selected as isize + step).clamp(0, last) as usize;
+    }
+
+    pub fn page(&mut self, down: bool) {
+        let rows = self.rows.max(1) as isize;
+        self.move_by(0, if down { rows } else { -rows });
+    }
+
+    pub fn select(&mut self, index: usize) {
+        self.selected = index.min(self.albums.len().saturating_sub(1));
+    }
+
+    // The picture for an album, read from its first track that has one
+    fn cover(&mut self, index: usize) -> Option<&[u8]> {
+        if !self.covers.contains_key(&index) {
+            let cover = self.albums[index].tracks.iter().take(3).find_map(|track| tags::cover(track));
+            // kitty only takes PNG without another step
+            let cover = cover.filter(|data| self.graphics != Graphics::Kitty || data.starts_with(b"\x89PNG"));
+            self.covers.insert(index, cover);
+        }
+        self.covers[&index].as_deref()
+    }
+
+    /// Forgets what is on screen, after the terminal was cleared.
+    pub fn forget_shown(&mut self) {
+        self.shown.clear();
+    }
+
+    /// Puts the pictures of the last frame up, or takes them down while a
+    /// popup is over the grid.
+    pub fn show_covers(&mut self, out: &mut impl Write, covered: bool) -> io::Result<Covers> {
+        let wanted = if covered { Vec::new() } else { self.placements.clone() };
+        if wanted == self.shown {
+            return Ok(Covers::Shown);
+        }
+        match self.graphics {
+            Graphics::None => {}
+            Graphics::Kitty => {
+                // Lowercase d keeps the pictures for placing them again
+                write!(out, "\x1b_Ga=d,d=a,q=2\x1b\\")?;
+                for &(area, index) in &wanted {
+                    let id = index + 1;
+                    if self.sent.insert(index) {
+                        let data = self.covers.get(&index).cloned().flatten().unwrap_or_default();
+                        send_kitty(out, id, &data)?;
+                    }
+                    queue!(out, MoveTo(area.x, area.y))?;
+                    write!(out, "\x1b_Ga=p,i={},c={},r={},C=1,q=2\x1b\\", id, area.width, area.height)?;
+                }
+            }
+            Graphics::ITerm => {
+                if !self.shown.is_empty() {
+                    self.shown.clear();
+                    return Ok(Covers::Stale);
+                }
+                for &(area, index) in &wanted {
+                    let data = self.covers.get(&index).cloned().flatten().unwrap_or_default();
+                    queue!(out, MoveTo(area.x, area.y))?;
+                    write!(
+                        out,
+                        "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=0:{}\x07",
+                        data.len(),
+                        area.width,
+                        area.height,
+                        base64(&data)
+                    )?;
+                }
+            }
+        }
+        out.flush()?;
+        self.shown = wanted;
+        Ok(Covers::Shown)
+    }
+
+    /// Takes the pictures down as the grid closes.
+    pub fn hide(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if self.graphics == Graphics::Kitty {
+            write!(out, "\x1b_Ga=D,d=a,q=2\x1b\\")?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// Pictures go in pieces of 4096 base64 characters
+fn send_kitty(out: &mut impl Write, id: usize, data: &[u8]) -> io::Result<()> {
+    let encoded = base64(data);
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(4096).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        if i == 0 {
+            write!(out, "\x1b_Ga=t,f=100,i={},q=2,m={};", id, more)?;
+        } else {
+            write!(out, "\x1b_Gm={};", more)?;
+        }
+        out.write_all(chunk)?;
+        write!(out, "\x1b\\")?;
+    }
+    Ok(())
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let value = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(value >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// Up to two letters from the first words of the title
+fn initials(title: &str) -> String {
+    title
+        .split_whitespace()
+        .filter_map(|word| word.chars().find(|c| c.is_alphanumeric()))
+        .take(2)
+        .flat_map(char::to_uppercase)
+        .collect()
+}
+
+fn tile_color(album: &Album) -> Color {
+    let mut hasher = DefaultHasher::new();
+    (&album.artist, &album.title).hash(&mut hasher);
+    Color::Indexed(TILE_COLORS[(hasher.finish() % TILE_COLORS.len() as u64) as usize])
+}
+
+pub fn draw<B: Backend>(f: &mut Frame<B>, grid: &mut AlbumGrid, colors: &Palette, ascii: bool, area: Rect) {
+    let title = match grid.albums.len() {
+        0 => " Albums ".to_string(),
+        count => format!(" Albums {}/{} (Enter: Queue | Esc: Close) ", grid.selected + 1, count),
+    };
+    let block = Block::default()
+        .title(title)
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(colors.playlist));
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    grid.placements.clear();
+    if grid.albums.is_empty() {
+        let empty = Paragraph::new("No albums in the library yet")
+            .alignment(Alignment::Center)
+            .style(Style::default().fg(colors.text));
+        f.render_widget(empty, inner);
+        return;
+    }
+
+    grid.columns = (inner.width / TILE_WIDTH).max(1) as usize;
+    grid.rows = (inner.height / TILE_HEIGHT).max(1) as usize;
+    let row = grid.selected / grid.columns;
+    if row < grid.offset {
+        grid.offset = row;
+    } else if row >= grid.offset + grid.rows {
+        grid.offset = row + 1 - grid.rows;
+    }
+
+    let first = grid.offset * grid.columns;
+    let last = (first + grid.columns * grid.rows).min(grid.albums.len());
+    for index in first..last {
+        let position = index - first;
+        let x = inner.x + (position % grid.columns) as u16 * TILE_WIDTH + (TILE_WIDTH - COVER_WIDTH) / 2;
+        let y = inner.y + (position / grid.columns) as u16 * TILE_HEIGHT;
+        if y + TILE_HEIGHT - 1 > inner.bottom() || x + COVER_WIDTH > inner.right() {
+            continue;
+        }
+        let cover = Rect::new(x, y, COVER_WIDTH, COVER_HEIGHT);
+        let album = &grid.albums[index];
+        let selected = index == grid.selected;
+
+        // The tile stays under the picture, and shows where there is none
+        let lines = vec![String::new(); (COVER_HEIGHT / 2) as usize].join("\n") + initials(&album.title).as_str();
+        let tile = Paragraph::new(lines)
+            .alignment(Alignment::Center)
+            .style(Style::default().fg(Color::White).bg(tile_color(album)).add_modifier(Modifier::BOLD));
+        f.render_widget(tile, cover);
+
+        let text_style = if selected {
+            Style::default().fg(colors.selected).bg(colors.playlist).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(colors.text)
+        };
+        let width = COVER_WIDTH as usize;
+        let title = Paragraph::new(truncate(&album.title, width)).style(text_style);
+        f.render_widget(title, Rect::new(x, y + COVER_HEIGHT, COVER_WIDTH, 1));
+        let artist = Paragraph::new(truncate(&album.artist, width)).style(Style::default().fg(colors.excluded));
+        f.render_widget(artist, Rect::new(x, y + COVER_HEIGHT + 1, COVER_WIDTH, 1));
+
+        if grid.graphics != Graphics::None && !ascii && grid.cover(index).is_some() {
+            grid.placements.push((cover, index));
+        }
+    }
+}
@@ -0,0 +1,90 @@
+// Modal dialogs: a question answered yes or no, a text field, and a list to
+// pick one item from. Each covers the middle of the screen in the same kind
+// of box and takes every key until it is answered or dismissed.
+
+use super::centered_rect;
+use super::theme::Palette;
+use super::truncate;
+use super::{DevicePicker, TextInput};
+use crossterm::event::{KeyCode, KeyEvent};
+use tui::{
+    backend::Backend,
+    layout::{Alignment, Rect},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Clear, Paragraph},
+    Frame,
+};
+
+/// The dialogs open over the interface, drawn in this order so a question
+/// asked from a list shows on top of it.
+pub struct Dialogs<'a> {
+    pub choice: Option<&'a mut DevicePicker>,
+    pub input: Option<&'a TextInput>,
+    pub confirm: Option<&'a Confirm>,
+}
+
+/// A yes-or-no question, answered with y or n, or with Enter on the
+/// highlighted button.
+pub struct Confirm {
+    pub title: String,
+    pub question: String,
+    // The highlighted button, No unless the caller picks Yes
+    pub yes: bool,
+}
+
+impl Confirm {
+    pub fn new(title: &str, question: &str) -> Confirm {
+        Confirm {
+            title: title.to_string(),
+            question: question.to_string(),
+            yes: false,
+        }
+    }
+
+    /// The answer once the key gives one, Esc counting as no.
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<bool> {
+        match key.code {
+            KeyCode::Char('y' | 'Y') => Some(true),
+            KeyCode::Char('n' | 'N') | KeyCode::Esc => Some(false),
+            KeyCode::Enter => Some(self.yes),
+            KeyCode::Left | KeyCode::Right | KeyCode::Tab | KeyCode::BackTab | KeyCode::Char('h' | 'l') => {
+                self.yes = !self.yes;
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Clears a box in the middle of the screen and draws its border and title,
+/// returning the space inside for the dialog's contents.
+pub(super) fn draw_frame<B: Backend>(f: &mut Frame<B>, title: &str, percent_x: u16, rows: u16, color: Color) -> Rect {
+    let area = centered_rect(percent_x, rows.saturating_add(2), f.size());
+    let block = Block::default()
+        .title(format!(" {} ", title))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(color));
+    let inner = block.inner(area);
+    f.render_widget(Clear, area);
+    f.render_widget(block, area);
+    inner
+}
+
+pub(super) fn draw_confirm<B: Backend>(f: &mut Frame<B>, confirm: &Confirm, colors: &Palette) {
+    let inner = draw_frame(f, &confirm.title, 50, 3, colors.prompt);
+    let button = |label: &'static str, highlighted: bool| {
+        let style = if highlighted {
+            Style::default().fg(colors.selected).bg(colors.prompt).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(colors.text)
+        };
+        Span::styled(label, style)
+    };
+    let lines = vec![
+        Spans::from(Span::styled(truncate(&confirm.question, inner.width as usize), Style::default().fg(colors.text))),
+        Spans::default(),
+        Spans::from(vec![button("[ Yes ]", confirm.yes), Span::raw("   "), button("[ No ]", !confirm.yes)]),
+    ];
+    f.render_widget(Paragraph::new(lines).alignment(Alignment::Center), inner);
+}
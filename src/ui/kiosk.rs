@@ -0,0 +1,125 @@
+// The kiosk layout: what is playing in large print above the playlist, for a
+// small display read from across the room
+
+use super::theme::Palette;
+use super::{draw_playlist, mode_indicators, PlaylistState, View};
+use crate::player::MusicPlayer;
+use tui::{
+    backend::Backend,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
+    symbols,
+    text::{Span, Spans},
+    widgets::{Block, Borders, LineGauge, Paragraph},
+    Frame,
+};
+
+// Digits five rows high for the clock, each three cells wide
+const DIGITS: [[&str; 5]; 10] = [
+    ["███", "█ █", "█ █", "█ █", "███"],
+    [" █ ", "██ ", " █ ", " █ ", "███"],
+    ["███", "  █", "███", "█  ", "███"],
+    ["███", "  █", "███", "  █", "███"],
+    ["█ █", "█ █", "███", "  █", "  █"],
+    ["███", "█  ", "███", "  █", "███"],
+    ["███", "█  ", "███", "█ █", "███"],
+    ["███", "  █", "  █", "  █", "  █"],
+    ["███", "█ █", "███", "█ █", "███"],
+    ["███", "█ █", "███", "  █", "███"],
+];
+
+pub fn draw<B: Backend>(
+    f: &mut Frame<B>,
+    music_player: &MusicPlayer,
+    list_state: &mut PlaylistState,
+    activity: &[String],
+    view: &View,
+    colors: &Palette,
+) {
+    let area = f.size();
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Percentage(55), Constraint::Percentage(45)].as_ref())
+        .split(area);
+    draw_now_playing(f, music_player, activity, colors, chunks[0]);
+    draw_playlist(f, music_player, list_state, view, colors, chunks[1]);
+}
+
+fn draw_now_playing<B: Backend>(f: &mut Frame<B>, music_player: &MusicPlayer, activity: &[String], colors: &Palette, area: Rect) {
+    let block = Block::default()
+        .title(" Now playing ")
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(colors.status));
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let Some(current) = music_player.current_track else {
+        let idle = Paragraph::new("Nothing playing").alignment(Alignment::Center).style(Style::default().fg(colors.status));
+        f.render_widget(idle, inner);
+        return;
+    };
+    let record = music_player.track_record(current);
+    let artist = record.and_then(|record| record.artist.clone()).unwrap_or_default();
+    let album = record.and_then(|record| record.album.clone()).unwrap_or_default();
+    let state = if music_player.is_playing() { "▶ Playing" } else { "⏸ Paused" };
+    let modes: Vec<String> = std::iter::once(state.to_string())
+        .chain(std::iter::once(format!("Vol {:.0}%", music_player.volume * 100.0)))
+        .chain(mode_indicators(music_player))
+        .chain(activity.iter().cloned())
+        .collect();
+
+    let mut lines = vec![Spans::from(Span::styled(
+        music_player.track_title(current),
+        Style::default().fg(colors.status).add_modifier(Modifier::BOLD),
+    ))];
+    let by = [artist, album].into_iter().filter(|text| !text.is_empty()).collect::<Vec<_>>().join(" — ");
+    if !by.is_empty() {
+        lines.push(Spans::from(Span::styled(by, Style::default().fg(colors.status))));
+    }
+    lines.push(Spans::default());
+    lines.push(Spans::from(Span::styled(modes.join(" | "), Style::default().fg(colors.status))));
+    // The big clock only when it leaves room for the rest and the progress line
+    if inner.height as usize >= lines.len() + 8 {
+        let style = Style::default().fg(colors.progress).add_modifier(Modifier::BOLD);
+        let clock = big_text(&music_player.get_elapsed_time()).into_iter().map(|row| Spans::from(Span::styled(row, style)));
+        lines.splice(0..0, clock.chain(std::iter::once(Spans::default())));
+    }
+
+    let text_height = (lines.len() as u16).min(inner.height.saturating_sub(2));
+    let top = inner.y + inner.height.saturating_sub(text_height + 2) / 2;
+    let text_area = Rect::new(inner.x, top, inner.width, text_height);
+    f.render_widget(Paragraph::new(lines).alignment(Alignment::Center), text_area);
+
+    if let Some(progress) = music_player.get_progress() {
+        let (time, total) = music_player.get_time_info();
+        let gauge_area = Rect::new(inner.x + 2, top + text_height + 1, inner.width.saturating_sub(4), 1);
+        if gauge_area.bottom() <= inner.bottom() {
+            let gauge = LineGauge::default()
+                .label(format!("{} / {}", time, total))
+                .ratio(progress.clamp(0.0, 1.0) as f64)
+                .line_set(symbols::line::THICK)
+                .style(Style::default().fg(colors.progress))
+                .gauge_style(Style::default().fg(colors.progress).bg(colors.progress_rest));
+            f.render_widget(gauge, gauge_area);
+        }
+    }
+}
+
+// `03:07` in the five-row digits, other characters as narrow gaps
+fn big_text(text: &str) -> Vec<String> {
+    let mut rows = vec![String::new(); 5];
+    for c in text.chars() {
+        for (i, row) in rows.iter_mut().enumerate() {
+            match c.to_digit(10) {
+                Some(digit) => row.push_str(DIGITS[digit as usize][i]),
+                None if c == ':' => row.push(if i == 1 || i == 3 { '█' } else { ' ' }),
+                None => row.push(' '),
+            }
+            row.push(' ');
+        }
+    }
+    for row in &mut rows {
+        row.pop();
+    }
+    rows
+}
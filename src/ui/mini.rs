@@ -0,0 +1,87 @@
+// The mini-player: what is playing, where it is, the volume and what comes
+// next in four lines, for a small tmux pane or a corner of the screen
+
+use super::theme::Palette;
+use super::{format_duration, mode_indicators, truncate};
+use crate::player::MusicPlayer;
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Modifier, Style},
+    symbols,
+    text::{Span, Spans},
+    widgets::{LineGauge, Paragraph},
+    Frame,
+};
+
+// Rows the mini-player takes, the last one dropped in a three-row pane
+const HEIGHT: u16 = 4;
+
+// Draws from the top of the screen, returning the progress line for seeking
+// with the mouse
+pub fn draw<B: Backend>(f: &mut Frame<B>, music_player: &MusicPlayer, activity: &[String], colors: &Palette) -> Option<Rect> {
+    let area = f.size();
+    let width = area.width as usize;
+    let row = |i: u16| (i < area.height.min(HEIGHT)).then(|| Rect::new(area.x, area.y + i, area.width, 1));
+    let style = Style::default().fg(colors.status);
+
+    let Some(current) = music_player.current_track else {
+        if let Some(first) = row(0) {
+            f.render_widget(Paragraph::new("Nothing playing").style(style), first);
+        }
+        return None;
+    };
+    let state = if music_player.is_playing() { "▶" } else { "⏸" };
+    let title = match music_player.track_record(current).and_then(|record| record.artist.as_deref()) {
+        Some(artist) => format!("{} – {}", artist, music_player.track_title(current)),
+        None => music_player.track_title(current),
+    };
+    let line = Spans::from(vec![
+        Span::styled(format!("{} ", state), style),
+        Span::styled(truncate(&title, width.saturating_sub(2)), style.add_modifier(Modifier::BOLD)),
+    ]);
+    if let Some(first) = row(0) {
+        f.render_widget(Paragraph::new(line), first);
+    }
+
+    let seek_bar = row(1).and_then(|area| draw_progress(f, music_player, colors, area));
+
+    let modes: Vec<String> = std::iter::once(format!("Vol {:.0}%", music_player.volume * 100.0))
+        .chain(mode_indicators(music_player))
+        .chain(activity.iter().cloned())
+        .collect();
+    if let Some(third) = row(2) {
+        f.render_widget(Paragraph::new(truncate(&modes.join(" | "), width)).style(style), third);
+    }
+
+    let (_, left) = music_player.queue_time();
+    let next = match music_player.up_next() {
+        Some(next) => format!("Next: {} ({} left)", music_player.track_title(next), format_duration(left)),
+        None => "Nothing up next".to_string(),
+    };
+    if let Some(fourth) = row(3) {
+        f.render_widget(Paragraph::new(truncate(&next, width)).style(Style::default().fg(colors.text)), fourth);
+    }
+    seek_bar
+}
+
+fn draw_progress<B: Backend>(f: &mut Frame<B>, music_player: &MusicPlayer, colors: &Palette, area: Rect) -> Option<Rect> {
+    let style = Style::default().fg(colors.progress);
+    let Some(progress) = music_player.get_progress().filter(|_| music_player.duration().is_some()) else {
+        let text = format!("Live {} / --:--", music_player.get_elapsed_time());
+        f.render_widget(Paragraph::new(text).style(style), area);
+        return None;
+    };
+    let (time, total) = music_player.get_time_info();
+    let label = format!("{} / {}", time, total);
+    // The gauge leaves a space between the label and the line
+    let line_start = area.x + label.chars().count() as u16 + 1;
+    let gauge = LineGauge::default()
+        .label(label)
+        .ratio(progress.clamp(0.0, 1.0) as f64)
+        .line_set(symbols::line::THICK)
+        .style(style)
+        .gauge_style(Style::default().fg(colors.progress).bg(colors.progress_rest));
+    f.render_widget(gauge, area);
+    (line_start < area.right()).then(|| Rect::new(line_start, area.y, area.right() - line_start, 1))
+}
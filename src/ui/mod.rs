@@ -1,165 +1,958 @@
-use crate::player::MusicPlayer;
+mod albums;
+mod dialog;
+mod kiosk;
+mod mini;
+mod split;
+mod text_input;
+mod theme;
+
+pub use albums::{AlbumGrid, Covers, Graphics};
+pub use dialog::{Confirm, Dialogs};
+pub use text_input::{InputAction, TextInput};
+pub use split::{Pane, Split};
+pub use theme::{Label, Theme};
+
+use crate::library::Matcher;
+use crate::logging;
+use crate::toast;
+use crate::player::dsp::CompressorPreset;
+use crate::player::{MusicPlayer, QueueEnd, Repeat, Shuffle};
+use crate::utils;
+use theme::Palette;
 use tui::{
     backend::Backend,
+    buffer::Buffer,
     layout::{Alignment, Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
+    style::{Modifier, Style},
     symbols,
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Sparkline},
+    text::{Span, Spans},
+    widgets::{Block, BorderType, Borders, Cell, Clear, LineGauge, List, ListItem, ListState, Paragraph, Row, Table, TableState, Widget},
     Frame,
 };
-use unicode_width::UnicodeWidthStr;
+use std::ops::Range;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Popup listing cast renderers or output devices, `devices` is None while searching
+pub struct DevicePicker {
+    pub title: String,
+    pub devices: Option<Vec<String>>,
+    pub state: ListState,
+    // Text box above the list, for popups that narrow it as you type
+    pub search: Option<TextInput>,
+}
+
+impl DevicePicker {
+    /// What is typed in the search box, empty without one.
+    pub fn query(&self) -> &str {
+        self.search.as_ref().map_or("", TextInput::value)
+    }
+}
+
+/// Optional parts of the interface, set from the config and toggled by keys.
+pub struct View {
+    pub show_stream_info: bool,
+    // The log panel replaces the playlist
+    pub show_log: bool,
+    // Template for the status bar, the built-in layout when None
+    pub status_format: Option<String>,
+    pub theme: Theme,
+    // Only ASCII goes to the terminal, for slow links and limited fonts
+    pub ascii: bool,
+    // The last search, marked in the playlist until Esc
+    pub highlight: Option<Matcher>,
+    // Large now-playing panel and the playlist, nothing else
+    pub kiosk: bool,
+    // Four lines of what is playing in place of everything else
+    pub mini: bool,
+    // Playlist on the left and the queue on the right, on wide terminals
+    pub split: Split,
+    // Colours of the label chips after titles
+    pub labels: Vec<Label>,
+    // The albums grid, while it replaces the playlist
+    pub albums: Option<AlbumGrid>,
+}
+
+/// A playlist column, chosen with `playlist_columns` in the config.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    Number,
+    Title,
+    Artist,
+    Album,
+    Duration,
+    Size,
+    Bpm,
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "number" | "#" => Ok(Column::Number),
+            "title" => Ok(Column::Title),
+            "artist" => Ok(Column::Artist),
+            "album" => Ok(Column::Album),
+            "duration" | "time" => Ok(Column::Duration),
+            "size" => Ok(Column::Size),
+            "bpm" => Ok(Column::Bpm),
+            _ => Err(format!("unknown column '{}'", s)),
+        }
+    }
+}
+
+impl Column {
+    pub fn defaults() -> Vec<Column> {
+        vec![Column::Number, Column::Title, Column::Artist, Column::Album, Column::Duration]
+    }
+
+    fn heading(self) -> &'static str {
+        match self {
+            Column::Number => "#",
+            Column::Title => "Title",
+            Column::Artist => "Artist",
+            Column::Album => "Album",
+            Column::Duration => "Time",
+            Column::Size => "Size",
+            Column::Bpm => "BPM",
+        }
+    }
+
+    // Width of columns whose content has a known size, None for text columns
+    fn fixed_width(self, len: usize) -> Option<u16> {
+        match self {
+            Column::Number => Some(len.to_string().len().max(2) as u16),
+            Column::Duration => Some(7),
+            Column::Size => Some(8),
+            Column::Bpm => Some(3),
+            Column::Title | Column::Artist | Column::Album => None,
+        }
+    }
+
+    // Share of the remaining width taken by text columns
+    fn weight(self) -> u16 {
+        match self {
+            Column::Title => 3,
+            Column::Artist | Column::Album => 2,
+            _ => 0,
+        }
+    }
+}
+
+/// Selection and scroll position of the playlist. Only the visible rows are
+/// built each frame, so long libraries stay fast.
+pub struct PlaylistState {
+    selected: Option<usize>,
+    offset: usize,
+    // Rows visible in the last frame
+    height: usize,
+    columns: Vec<Column>,
+}
+
+impl PlaylistState {
+    pub fn new(columns: Vec<Column>) -> PlaylistState {
+        PlaylistState {
+            selected: None,
+            offset: 0,
+            height: 0,
+            columns,
+        }
+    }
+
+    /// Indices of the tracks on screen in the last frame.
+    pub fn visible(&self) -> Range<usize> {
+        self.offset..self.offset + self.height
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Puts back a selection and scroll position saved earlier. The next
+    /// draw keeps them within the playlist.
+    pub fn restore(&mut self, selected: Option<usize>, offset: usize) {
+        self.selected = selected;
+        self.offset = offset;
+    }
+
+    pub fn select(&mut self, index: Option<usize>) {
+        self.selected = index;
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.height.max(1)
+    }
+
+    /// Moves the selection and the view together, as paging does, so the
+    /// selection keeps its place on screen.
+    pub fn scroll(&mut self, delta: isize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let last = len as isize - 1;
+        let selected = self.selected.map_or(0, |i| i as isize);
+        self.selected = Some((selected + delta).clamp(0, last) as usize);
+        let max_offset = len.saturating_sub(self.page_size()) as isize;
+        self.offset = (self.offset as isize + delta).clamp(0, max_offset) as usize;
+    }
+
+    // Keeps the selection on screen and the view within the list
+    fn fit(&mut self, len: usize, height: usize) {
+        self.height = height;
+        let height = height.max(1);
+        if let Some(selected) = self.selected {
+            if selected < self.offset {
+                self.offset = selected;
+            } else if selected >= self.offset + height {
+                self.offset = selected + 1 - height;
+            }
+        }
+        self.offset = self.offset.min(len.saturating_sub(height));
+    }
+}
+
+pub fn draw<B: Backend>(
+    f: &mut Frame<B>,
+    music_player: &MusicPlayer,
+    list_state: &mut PlaylistState,
+    activity: &[String],
+    dialogs: Dialogs,
+    view: &mut View,
+) -> Option<Rect> {
+    view.split.shown = !view.mini && !view.kiosk && view.split.percent > 0 && f.size().width >= split::MIN_WIDTH;
+    if view.mini {
+        let colors = view.theme.palette();
+        let seek_bar = mini::draw(f, music_player, activity, &colors);
+        draw_overlays(f, dialogs, view, &colors);
+        return seek_bar;
+    }
+    if view.kiosk {
+        let colors = view.theme.palette();
+        kiosk::draw(f, music_player, list_state, activity, view, &colors);
+        draw_overlays(f, dialogs, view, &colors);
+        return None;
+    }
+    let colors = view.theme.palette();
+    if view.split.shown {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(0),    // Panes
+                Constraint::Length(3), // Status
+                Constraint::Length(3), // Controls
+            ].as_ref())
+            .margin(1)
+            .split(f.size());
+        let panes = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(view.split.percent), Constraint::Percentage(100 - view.split.percent)].as_ref())
+            .split(chunks[0]);
+        draw_browser(f, music_player, list_state, view, &colors, panes[0]);
+        let seek_bar = split::draw_queue(f, music_player, &mut view.split, view.show_stream_info, &colors, panes[1]);
+        draw_status(f, music_player, activity, view.status_format.as_deref(), &colors, chunks[1]);
+        draw_controls(f, &colors, chunks[2]);
+        draw_overlays(f, dialogs, view, &colors);
+        return seek_bar;
+    }
 
-pub fn draw<B: Backend>(f: &mut Frame<B>, music_player: &MusicPlayer, list_state: &mut ListState) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
             Constraint::Percentage(60),  // Playlist
             Constraint::Length(3),       // Progress bar
+            Constraint::Length(match (view.show_stream_info, music_player.current_note()) {
+                (false, _) => 0,
+                (true, None) => 3,
+                (true, Some(_)) => 4, // With the track's note
+            }), // Stream details
             Constraint::Length(3),       // Status
             Constraint::Length(3),       // Controls
         ].as_ref())
         .margin(1)
         .split(f.size());
 
-    draw_playlist(f, music_player, list_state, chunks[0]);
-    draw_progress(f, music_player, chunks[1]);
-    draw_status(f, music_player, chunks[2]);
-    draw_controls(f, chunks[3]);
+    draw_browser(f, music_player, list_state, view, &colors, chunks[0]);
+    let seek_bar = draw_progress(f, music_player, &colors, chunks[1]);
+    if view.show_stream_info {
+        draw_stream_info(f, music_player, &colors, chunks[2]);
+    }
+    draw_status(f, music_player, activity, view.status_format.as_deref(), &colors, chunks[3]);
+    draw_controls(f, &colors, chunks[4]);
+    draw_overlays(f, dialogs, view, &colors);
+    seek_bar
+}
+
+// The albums grid, the log or the playlist, whichever is open
+fn draw_browser<B: Backend>(
+    f: &mut Frame<B>,
+    music_player: &MusicPlayer,
+    list_state: &mut PlaylistState,
+    view: &mut View,
+    colors: &Palette,
+    area: Rect,
+) {
+    if let Some(albums) = &mut view.albums {
+        albums::draw(f, albums, colors, view.ascii, area);
+    } else if view.show_log {
+        draw_log(f, colors, area);
+    } else {
+        draw_playlist(f, music_player, list_state, view, colors, area);
+    }
+}
+
+// Dialogs over either layout
+fn draw_overlays<B: Backend>(f: &mut Frame<B>, dialogs: Dialogs, view: &View, colors: &Palette) {
+    // The mini-player has no room to spare
+    if !view.mini {
+        draw_toasts(f, colors);
+    }
+    if let Some(picker) = dialogs.choice {
+        draw_device_picker(f, picker, colors);
+    }
+    if let Some(prompt) = dialogs.input {
+        text_input::draw_text_input(f, prompt, colors);
+    }
+    if let Some(confirm) = dialogs.confirm {
+        dialog::draw_confirm(f, confirm, colors);
+    }
+    if view.ascii {
+        f.render_widget(Ascii, f.size());
+    }
+}
+
+// Toasts stacked in the bottom-right corner above the status bar and
+// controls, the newest at the bottom
+fn draw_toasts<B: Backend>(f: &mut Frame<B>, colors: &Palette) {
+    let area = f.size();
+    let max_width = area.width / 2;
+    let mut bottom = area.bottom().saturating_sub(7);
+    for toast in toast::shown().iter().rev() {
+        if bottom < area.y + 3 || max_width < 5 {
+            break;
+        }
+        let width = (toast.text.chars().count() as u16).saturating_add(4).min(max_width);
+        let rect = Rect::new(area.right().saturating_sub(width + 1), bottom - 3, width, 3);
+        let color = match toast.kind {
+            toast::Kind::Info => colors.status,
+            toast::Kind::Error => colors.error,
+        };
+        let widget = Paragraph::new(truncate(&toast.text, width as usize - 4))
+            .style(Style::default().fg(color))
+            .block(Block::default()
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(color)))
+            .alignment(Alignment::Center);
+        f.render_widget(Clear, rect);
+        f.render_widget(widget, rect);
+        bottom -= 3;
+    }
+}
+
+// Swaps the symbols and box-drawing lines of a finished frame for ASCII ones.
+// Text from tags is left as it is.
+struct Ascii;
+
+impl Widget for Ascii {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                let cell = buf.get_mut(x, y);
+                let plain = match cell.symbol.as_str() {
+                    "─" | "━" | "–" => "-",
+                    "│" | "┃" => "|",
+                    "┌" | "┐" | "└" | "┘" | "┏" | "┓" | "┗" | "┛" => "+",
+                    "▶" | "→" => ">",
+                    "←" => "<",
+                    "↑" => "^",
+                    "↓" => "v",
+                    "⏸" | "⇄" => "=",
+                    "■" | "█" => "#",
+                    "◆" | "●" | "✎" => "*",
+                    "×" => "x",
+                    // The spinner's quarters keep turning
+                    "◐" => "|",
+                    "◓" => "/",
+                    "◑" => "-",
+                    "◒" => "\\",
+                    _ => continue,
+                };
+                cell.set_symbol(plain);
+            }
+        }
+    }
+}
+
+fn draw_device_picker<B: Backend>(f: &mut Frame<B>, picker: &mut DevicePicker, colors: &Palette) {
+    let items: Vec<ListItem> = match &picker.devices {
+        None => vec![ListItem::new("Searching...")],
+        Some(devices) if devices.is_empty() => vec![ListItem::new("Nothing found")],
+        Some(devices) => devices.iter().map(|device| ListItem::new(device.as_str())).collect(),
+    };
+    let search_rows = u16::from(picker.search.is_some());
+    let rows = (items.len() as u16).saturating_add(search_rows);
+    let inner = dialog::draw_frame(f, &picker.title, 50, rows, colors.picker);
+    let list = List::new(items)
+        .highlight_style(Style::default()
+            .fg(colors.selected)
+            .bg(colors.picker)
+            .add_modifier(Modifier::BOLD));
+
+    let list_area = match &picker.search {
+        Some(search) => {
+            text_input::draw_field(f, search, colors, inner);
+            Rect::new(inner.x, inner.y + 1, inner.width, inner.height.saturating_sub(1))
+        }
+        None => inner,
+    };
+    f.render_stateful_widget(list, list_area, &mut picker.state);
+}
+
+fn centered_rect(percent_x: u16, height: u16, area: Rect) -> Rect {
+    let width = (u32::from(area.width) * u32::from(percent_x) / 100) as u16;
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
 }
 
 fn draw_playlist<B: Backend>(
     f: &mut Frame<B>,
     music_player: &MusicPlayer,
-    list_state: &mut ListState,
+    list_state: &mut PlaylistState,
+    view: &View,
+    colors: &Palette,
     area: Rect,
 ) {
-    let items: Vec<ListItem> = music_player
-        .tracks
-        .iter()
-        .enumerate()
-        .map(|(i, track)| {
-            let filename = track.file_name().unwrap().to_str().unwrap();
-            let prefix = if Some(i) == music_player.current_track {
-                if music_player.is_playing() { "▶ ".to_string() } else { "■ ".to_string() }
-            } else {
-                format!("{:2} ", i + 1)
-            };
-            
-            // Get file size
-            let size = if let Ok(metadata) = std::fs::metadata(track) {
-                let size_mb = metadata.len() as f64 / 1_048_576.0;
-                format!(" ({:.1}MB)", size_mb)
-            } else {
-                String::new()
-            };
-            
-            // Truncate filename if it's too long
-            let max_width = area.width.saturating_sub(15 + size.len() as u16) as usize;
-            let display_name = if filename.len() > max_width {
-                format!("{}...", &filename[..max_width - 3])
-            } else {
-                filename.to_string()
-            };
+    let len = music_player.tracks.len();
+    // Borders and the header row
+    list_state.fit(len, area.height.saturating_sub(3) as usize);
+    let offset = list_state.offset;
+    let columns = fit_columns(&list_state.columns, len, area.width.saturating_sub(2));
+    let widths: Vec<Constraint> = columns.iter().map(|&(_, width)| Constraint::Length(width)).collect();
 
-            ListItem::new(format!("{}{}{}", prefix, display_name, size))
-                .style(Style::default().fg(if Some(i) == music_player.current_track {
-                    Color::Cyan
-                } else {
-                    Color::White
-                }))
+    let header = Row::new(columns.iter().map(|(column, _)| column.heading()))
+        .style(Style::default().fg(colors.playlist).add_modifier(Modifier::BOLD));
+    let rows: Vec<Row> = (offset..len)
+        .take(list_state.height)
+        .map(|i| {
+            let cells = columns.iter().map(|&(column, width)| {
+                let chips = match (column, music_player.track_record(i)) {
+                    (Column::Title, Some(record)) => label_chips(&record.labels, &view.labels, colors, width as usize / 2),
+                    _ => Vec::new(),
+                };
+                let chips_width: usize = chips.iter().map(|chip| chip.width()).sum();
+                let text = truncate(&column_text(music_player, column, i), width as usize - chips_width);
+                let mut spans = match &view.highlight {
+                    Some(highlight) => highlight_matches(text, highlight, colors),
+                    None => Spans::from(text),
+                };
+                spans.0.extend(chips);
+                Cell::from(spans)
+            });
+            Row::new(cells).style(Style::default().fg(if Some(i) == music_player.current_track {
+                colors.playlist
+            } else if music_player.never_plays(i) {
+                colors.excluded
+            } else {
+                colors.text
+            }))
         })
         .collect();
 
-    let list = List::new(items)
+    // Position of the view rather than the selection, like a pager's
+    let title = if len > list_state.height {
+        let last = (offset + list_state.height).min(len);
+        format!(" Playlist {}-{}/{} ", offset + 1, last, len)
+    } else {
+        " Playlist ".to_string()
+    };
+    let table = Table::new(rows)
+        .header(header)
+        .widths(&widths)
+        .column_spacing(COLUMN_SPACING)
         .block(Block::default()
-            .title(" Playlist ")
+            .title(title)
             .borders(Borders::ALL)
-            .border_style(Style::default().fg(Color::Cyan)))
+            .border_type(if view.split.shown && view.split.focus == Pane::Browser { BorderType::Thick } else { BorderType::Plain })
+            .border_style(Style::default().fg(colors.playlist)))
         .highlight_style(Style::default()
-            .fg(Color::Black)
-            .bg(Color::Cyan)
+            .fg(colors.selected)
+            .bg(colors.playlist)
             .add_modifier(Modifier::BOLD))
-        .highlight_symbol(">> ");
+        .highlight_symbol(HIGHLIGHT_SYMBOL);
 
-    f.render_stateful_widget(list, area, list_state);
+    let mut state = TableState::default();
+    state.select(list_state.selected.and_then(|selected| selected.checked_sub(offset)));
+    f.render_stateful_widget(table, area, &mut state);
+    let rows_area = Rect::new(area.x, area.y + 1, area.width, area.height.saturating_sub(1));
+    draw_scrollbar(f, offset, list_state.height, len, colors, rows_area);
 }
 
-fn draw_progress<B: Backend>(f: &mut Frame<B>, music_player: &MusicPlayer, area: Rect) {
-    let (progress_text, duration_text) = if let Some(progress) = music_player.get_progress() {
-        let percentage = (progress * 100.0) as u8;
-        let bar_width = area.width as usize - 20;
-        let filled = (bar_width as f32 * progress) as usize;
-        
-        let progress_bar = format!(
-            "{}{} {}%",
-            "━".repeat(filled),
-            "─".repeat(bar_width - filled),
-            percentage
-        );
+const COLUMN_SPACING: u16 = 1;
+const HIGHLIGHT_SYMBOL: &str = ">> ";
 
-        let time = music_player.get_elapsed_time();
-        let total = music_player.get_total_time();
-        let time_text = format!("{} / {}", time, total);
+// Widths for the configured columns. Fixed-size columns keep their width and
+// the text columns share the rest, album and then artist being dropped when
+// the terminal is too narrow for them.
+fn fit_columns(columns: &[Column], len: usize, width: u16) -> Vec<(Column, u16)> {
+    let mut columns = columns.to_vec();
+    loop {
+        let fixed: u16 = columns.iter().filter_map(|column| column.fixed_width(len)).sum();
+        let spacing = COLUMN_SPACING * columns.len().saturating_sub(1) as u16;
+        let free = width.saturating_sub(HIGHLIGHT_SYMBOL.len() as u16 + fixed + spacing);
+        let weights: u16 = columns.iter().map(|column| column.weight()).sum();
+        let narrowest = [Column::Album, Column::Artist]
+            .into_iter()
+            .find(|column| columns.contains(column));
+        if let Some(narrowest) = narrowest.filter(|_| weights > 0 && free / weights < 4) {
+            columns.retain(|column| *column != narrowest);
+            continue;
+        }
 
-        (progress_bar, time_text)
+        let mut left = free;
+        let mut remaining = weights;
+        return columns
+            .into_iter()
+            .map(|column| {
+                let width = match column.fixed_width(len) {
+                    Some(width) => width,
+                    None => {
+                        // Rounding leftovers go to the last text column
+                        let share = left * column.weight() / remaining.max(1);
+                        left -= share;
+                        remaining -= column.weight();
+                        share
+                    }
+                };
+                (column, width)
+            })
+            .collect();
+    }
+}
+
+fn column_text(music_player: &MusicPlayer, column: Column, index: usize) -> String {
+    let record = music_player.track_record(index);
+    match column {
+        Column::Number => {
+            if Some(index) == music_player.current_track {
+                if music_player.is_playing() { "▶".to_string() } else { "■".to_string() }
+            } else {
+                (index + 1).to_string()
+            }
+        }
+        Column::Title => music_player.track_title(index),
+        Column::Artist => record.and_then(|record| record.artist.clone()).unwrap_or_default(),
+        Column::Album => record.and_then(|record| record.album.clone()).unwrap_or_default(),
+        Column::Duration => record.and_then(|record| record.duration).map(format_duration).unwrap_or_default(),
+        Column::Size => record
+            .and_then(|record| record.size)
+            .map(|size| format!("{:.1}MB", size as f64 / 1_048_576.0))
+            .unwrap_or_default(),
+        Column::Bpm => music_player.bpm(index).map(|bpm| format!("{:.0}", bpm)).unwrap_or_default(),
+    }
+}
+
+// Thumb over the playlist's right border, sized to the share of tracks in view
+// The latest log lines, newest at the bottom
+fn draw_log<B: Backend>(f: &mut Frame<B>, colors: &Palette, area: Rect) {
+    let lines = logging::recent();
+    let height = area.height.saturating_sub(2) as usize;
+    let items: Vec<ListItem> = lines[lines.len().saturating_sub(height)..]
+        .iter()
+        .map(|line| {
+            let color = match line.level {
+                log::Level::Error => colors.error,
+                log::Level::Warn => colors.warning,
+                log::Level::Info => colors.text,
+                log::Level::Debug | log::Level::Trace => colors.debug,
+            };
+            ListItem::new(line.text.as_str()).style(Style::default().fg(color))
+        })
+        .collect();
+
+    let log = List::new(items).block(
+        Block::default()
+            .title(" Log (F12: Close) ")
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(colors.playlist)),
+    );
+    f.render_widget(log, area);
+}
+
+fn draw_scrollbar<B: Backend>(f: &mut Frame<B>, offset: usize, height: usize, len: usize, colors: &Palette, area: Rect) {
+    if height == 0 || len <= height || area.width < 2 {
+        return;
+    }
+    let thumb = (height * height / len).max(1);
+    let start = offset * (height - thumb) / (len - height);
+    let bar: Vec<Spans> = (0..height)
+        .map(|row| {
+            let symbol = if (start..start + thumb).contains(&row) { "█" } else { "│" };
+            Spans::from(Span::styled(symbol, Style::default().fg(colors.playlist)))
+        })
+        .collect();
+    let track = Rect::new(area.right() - 1, area.y + 1, 1, height as u16);
+    f.render_widget(Paragraph::new(bar), track);
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
     } else {
-        ("Not playing".to_string(), "00:00 / 00:00".to_string())
-    };
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+// Underlined so matches still show on the selection bar, which takes over
+// the colours
+fn highlight_matches(text: String, highlight: &Matcher, colors: &Palette) -> Spans<'static> {
+    let style = Style::default().fg(colors.marker).add_modifier(Modifier::UNDERLINED);
+    let mut spans = Vec::new();
+    let mut at = 0;
+    for found in highlight.find(&text) {
+        spans.push(Span::raw(text[at..found.start].to_string()));
+        spans.push(Span::styled(text[found.clone()].to_string(), style));
+        at = found.end;
+    }
+    spans.push(Span::raw(text[at..].to_string()));
+    Spans::from(spans)
+}
+
+// A track's labels as coloured chips, or as coloured dots when the names
+// don't fit in `width`
+fn label_chips(names: &[String], labels: &[Label], colors: &Palette, width: usize) -> Vec<Span<'static>> {
+    // Labels no longer in the config are greyed out
+    let color = |name: &String| labels.iter().find(|label| label.name == *name).map_or(colors.excluded, |label| label.color);
+    let full: usize = names.iter().map(|name| name.chars().count() + 3).sum();
+    if full <= width {
+        return names
+            .iter()
+            .flat_map(|name| {
+                let style = Style::default().fg(colors.selected).bg(color(name));
+                [Span::raw(" "), Span::styled(format!(" {} ", name), style)]
+            })
+            .collect();
+    }
+    if names.len() * 2 <= width {
+        return names
+            .iter()
+            .flat_map(|name| [Span::raw(" "), Span::styled("●", Style::default().fg(color(name)))])
+            .collect();
+    }
+    Vec::new()
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(width.saturating_sub(3)).collect();
+    format!("{}...", kept)
+}
 
-    let progress_block = Block::default()
+// Draws the position as a line gauge with loop and bookmark markers, returning
+// the line's area so clicks on it can seek
+fn draw_progress<B: Backend>(f: &mut Frame<B>, music_player: &MusicPlayer, colors: &Palette, area: Rect) -> Option<Rect> {
+    let block = Block::default()
         .title(" Progress ")
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(Color::Green));
-
-    let progress_widget = Paragraph::new(format!("{}\n{}", progress_text, duration_text))
-        .block(progress_block)
-        .alignment(Alignment::Center)
-        .style(Style::default().fg(Color::Green));
-
-    f.render_widget(progress_widget, area);
-}
-
-fn draw_status<B: Backend>(f: &mut Frame<B>, music_player: &MusicPlayer, area: Rect) {
-    let status = if let Some(current) = music_player.current_track {
-        let track_name = music_player.tracks[current]
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy();
-        
-        format!(
-            "Playing: {} | Vol: {:.0}% | {}",
-            track_name,
-            music_player.volume * 100.0,
-            if music_player.is_playing() { 
-                "▶ Playing" 
-            } else { 
-                "⏸ Paused" 
+        .border_style(Style::default().fg(colors.progress));
+    let inner = block.inner(area);
+    let style = Style::default().fg(colors.progress);
+
+    let (Some(progress), Some(duration)) = (music_player.get_progress(), music_player.duration()) else {
+        let text = if music_player.is_playing() {
+            // Live streams have no length to measure progress against
+            format!("Live {} / --:--", music_player.get_elapsed_time())
+        } else {
+            "Not playing".to_string()
+        };
+        let widget = Paragraph::new(text).block(block).alignment(Alignment::Center).style(style);
+        f.render_widget(widget, area);
+        return None;
+    };
+
+    let (time, total) = music_player.get_time_info();
+    let label = format!("{} / {} {:5.1}%", time, total, progress * 100.0);
+    // The gauge leaves a space between the label and the line
+    let line_start = inner.x + label.chars().count() as u16 + 1;
+    let gauge = LineGauge::default()
+        .block(block)
+        .label(label)
+        .ratio(progress as f64)
+        .line_set(symbols::line::THICK)
+        .style(style)
+        .gauge_style(Style::default().fg(colors.progress).bg(colors.progress_rest));
+    f.render_widget(gauge, area);
+
+    if inner.height == 0 || line_start >= inner.right() {
+        return None;
+    }
+    let line = Rect::new(line_start, inner.y, inner.right() - line_start, 1);
+    let (loop_start, loop_end) = music_player.ab_loop();
+    let markers = music_player
+        .bookmarks()
+        .into_iter()
+        .map(|mark| (mark, "◆"))
+        .chain(loop_start.map(|start| (start, "A")))
+        .chain(loop_end.map(|end| (end, "B")));
+    for (position, symbol) in markers {
+        let fraction = (position.as_secs_f64() / duration.as_secs_f64()).min(1.0);
+        let x = line.x + ((line.width - 1) as f64 * fraction).round() as u16;
+        let marker = Paragraph::new(symbol).style(Style::default().fg(colors.marker).add_modifier(Modifier::BOLD));
+        f.render_widget(marker, Rect::new(x, line.y, 1, 1));
+    }
+    Some(line)
+}
+
+fn draw_stream_info<B: Backend>(f: &mut Frame<B>, music_player: &MusicPlayer, colors: &Palette, area: Rect) {
+    let text = match music_player.stream_info() {
+        Some(info) => {
+            let mut details = vec![info.codec.clone()];
+            if let Some(rate) = info.sample_rate {
+                details.push(format!("{} kHz", rate as f32 / 1000.0));
             }
-        )
-    } else {
-        "No track selected".to_string()
+            if let Some(bits) = info.bits_per_sample {
+                details.push(format!("{}-bit", bits));
+            }
+            if let Some(channels) = info.channels {
+                details.push(match channels {
+                    1 => "Mono".to_string(),
+                    2 => "Stereo".to_string(),
+                    n => format!("{} channels", n),
+                });
+            }
+            if let Some(bitrate) = info.bitrate_kbps {
+                details.push(format!("{} kbps", bitrate));
+            }
+            if let Some(resampling) = music_player.resampling() {
+                details.push(resampling);
+            }
+            if let Some(((frames, ms), underruns)) = music_player.output_buffer() {
+                details.push(format!("Buffer {} frames ({:.0} ms)", frames, ms));
+                if underruns > 0 {
+                    details.push(format!("{} underruns", underruns));
+                }
+            }
+            details.join(" | ")
+        }
+        None => "No stream".to_string(),
+    };
+    let text = match music_player.current_note() {
+        Some(note) => format!("{}\n✎ {}", text, note),
+        None => text,
+    };
+
+    let info_widget = Paragraph::new(text)
+        .block(Block::default()
+            .title(" Stream ")
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(colors.stream)))
+        .style(Style::default().fg(colors.stream))
+        .alignment(Alignment::Center);
+
+    f.render_widget(info_widget, area);
+}
+
+fn draw_status<B: Backend>(
+    f: &mut Frame<B>,
+    music_player: &MusicPlayer,
+    activity: &[String],
+    format: Option<&str>,
+    colors: &Palette,
+    area: Rect,
+) {
+    let status = match (music_player.current_track, format) {
+        (Some(current), Some(format)) => format_status(format, music_player, current, activity),
+        (Some(current), None) => {
+            let mut track_name = music_player.track_title(current);
+            if let Some(chapter) = music_player.current_chapter() {
+                let title = &music_player.chapters()[chapter].title;
+                track_name += &format!(" (Ch. {}/{}: {})", chapter + 1, music_player.chapters().len(), title);
+            }
+
+            let modes: String = mode_indicators(music_player)
+                .iter()
+                .chain(activity)
+                .map(|mode| format!(" | {}", mode))
+                .collect();
+
+            let up_next = music_player
+                .up_next()
+                .map(|next| format!(" | Up next: {}", music_player.track_title(next)))
+                .unwrap_or_default();
+            let (total, left) = music_player.queue_time();
+
+            format!(
+                "Playing: {} | Vol: {:.0}%{} | {} | Queue: {} left of {}{}",
+                track_name,
+                music_player.volume * 100.0,
+                modes,
+                if music_player.is_playing() {
+                    "▶ Playing"
+                } else {
+                    "⏸ Paused"
+                },
+                format_duration(left),
+                format_duration(total),
+                up_next
+            )
+        }
+        (None, _) => {
+            let activity: String = activity.iter().map(|item| format!(" | {}", item)).collect();
+            format!("No track selected{}", activity)
+        }
     };
 
     let status_widget = Paragraph::new(status)
         .block(Block::default()
             .title(" Status ")
             .borders(Borders::ALL)
-            .border_style(Style::default().fg(Color::Yellow)))
-        .style(Style::default().fg(Color::Yellow))
+            .border_style(Style::default().fg(colors.status)))
+        .style(Style::default().fg(colors.status))
         .alignment(Alignment::Left);
 
     f.render_widget(status_widget, area);
 }
 
-fn draw_controls<B: Backend>(f: &mut Frame<B>, area: Rect) {
-    let controls = "↑/↓: Select | Enter: Play | Space: Pause | ←/→: Prev/Next | +/-: Volume | q: Quit";
+// Fills a `status_format` template
+fn format_status(format: &str, music_player: &MusicPlayer, current: usize, activity: &[String]) -> String {
+    let record = music_player.track_record(current);
+    utils::fill_template(format, |field| {
+        Some(match field {
+            "title" => music_player.track_title(current),
+            "artist" => record.and_then(|record| record.artist.clone()).unwrap_or_default(),
+            "album" => record.and_then(|record| record.album.clone()).unwrap_or_default(),
+            "file" => music_player.tracks[current].file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            "number" => (current + 1).to_string(),
+            "elapsed" => music_player.get_elapsed_time(),
+            "total" => music_player.get_total_time(),
+            "volume" => format!("{:.0}", music_player.volume * 100.0),
+            "state" => if music_player.is_playing() { "▶" } else { "⏸" }.to_string(),
+            "next" => music_player.up_next().map(|next| music_player.track_title(next)).unwrap_or_default(),
+            "queue_total" => format_duration(music_player.queue_time().0),
+            "queue_left" => format_duration(music_player.queue_time().1),
+            "chapter" => music_player
+                .current_chapter()
+                .map(|chapter| music_player.chapters()[chapter].title.clone())
+                .unwrap_or_default(),
+            "modes" | "mode_icons" => mode_indicators(music_player)
+                .iter()
+                .chain(activity)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" | "),
+            _ => return None,
+        })
+    })
+}
+
+// Short labels for every non-default playback and DSP setting
+fn mode_indicators(music_player: &MusicPlayer) -> Vec<String> {
+    let mut modes = Vec::new();
+    match music_player.shuffle() {
+        Shuffle::Off => {}
+        Shuffle::Tracks => modes.push("Shuffle".to_string()),
+        Shuffle::Albums => modes.push("Shuffle albums".to_string()),
+        Shuffle::Smart => modes.push("Smart shuffle".to_string()),
+    }
+    if music_player.shuffle() != Shuffle::Off && music_player.auto_skips() == Some(false) {
+        modes.push("Auto-skip off".to_string());
+    }
+    match music_player.repeat() {
+        Repeat::All => {}
+        Repeat::One => modes.push("Repeat one".to_string()),
+        Repeat::Off => modes.push("No repeat".to_string()),
+    }
+    if music_player.repeat() == Repeat::All && music_player.queue_end() != QueueEnd::Repeat {
+        let queue_end = music_player.queue_end();
+        modes.push(format!("{} At end: {}", queue_end.icon(), queue_end.name()));
+    }
+    let balance = music_player.balance();
+    if balance < 0.0 {
+        modes.push(format!("Bal: L{:.0}", -balance * 100.0));
+    } else if balance > 0.0 {
+        modes.push(format!("Bal: R{:.0}", balance * 100.0));
+    }
+    if music_player.pitch() != 0 {
+        modes.push(format!("Pitch {:+}", music_player.pitch()));
+    }
+    if music_player.speed() != 1.0 {
+        modes.push(format!("{}×", music_player.speed()));
+    }
+    if music_player.channels_swapped() {
+        modes.push("L⇄R".to_string());
+    }
+    if music_player.is_mono() {
+        modes.push("MONO".to_string());
+    }
+    if music_player.skips_silence() {
+        modes.push("Skip silence".to_string());
+    }
+    if music_player.crossfeeds() {
+        modes.push("Crossfeed".to_string());
+    }
+    let track_gain = music_player.current_track.map_or(0.0, |index| music_player.track_gain_db(index));
+    if track_gain != 0.0 {
+        modes.push(format!("Track gain {:+.0} dB", track_gain));
+    }
+    // With how far the compressor is turning the sound down right now
+    let reduction = music_player.gain_reduction();
+    let reducing = if reduction >= 1.0 { format!(" -{:.0} dB", reduction) } else { String::new() };
+    if music_player.is_night() {
+        modes.push(format!("Night (max {:.0}%){}", music_player.night_volume() * 100.0, reducing));
+    } else if music_player.compressor() != CompressorPreset::Off {
+        modes.push(format!("Comp: {}{}", music_player.compressor().name(), reducing));
+    }
+    if music_player.auto_mix {
+        modes.push("Auto-mix".to_string());
+    }
+    if music_player.auto_fills() {
+        modes.push("Endless".to_string());
+    }
+    match music_player.ab_loop() {
+        (Some(start), Some(end)) => modes.push(format!("Loop {}-{}", format_duration(start), format_duration(end))),
+        (Some(start), None) => modes.push(format!("Loop from {}", format_duration(start))),
+        _ => {}
+    }
+    let (audio_track, audio_tracks) = music_player.audio_track();
+    if audio_tracks > 1 {
+        modes.push(format!("Audio {}/{}", audio_track + 1, audio_tracks));
+    }
+    if let Some(status) = music_player.bit_perfect_status() {
+        modes.push(status);
+    }
+    if let Some(name) = music_player.casting_to() {
+        match music_player.cast_error() {
+            Some(error) => modes.push(format!("Cast to {} failed: {}", name, error)),
+            None => modes.push(format!("Casting to {}", name)),
+        }
+    }
+    if let Some(path) = music_player.recording_path() {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        modes.push(format!("● REC {}", name));
+    }
+    modes
+}
+
+fn draw_controls<B: Backend>(f: &mut Frame<B>, colors: &Palette, area: Rect) {
+    let controls = "C-p: Commands | ↑/↓/PgUp/PgDn/Home/End: Select | Enter: Play | Space: Pause | ←/→: Prev/Next | ,/.: Back/Forward | (/): Chapter | c: Chapters | v: Skip up next | V: Never shuffle | W: History | z: Shuffle | r: Repeat | +/-: Volume | [/]: Balance | {/}: Pitch | </>: Speed | x: Swap L/R | m: Mono | Z: Skip silence | a: Auto-mix | E: Endless | R: Record | T: Transcode | L: Scan loudness | o: Open | /: Search | g/G: Go to track/time | b: A-B loop | M/': Bookmark/Jump | C: Cast | O: Outputs | S: Settings | p: Playlists | F: Focus | Q/@: Record/replay macro | i: Stream info | A: Audio track | F12: Log | q: Quit";
     
     let controls_widget = Paragraph::new(controls)
         .block(Block::default()
             .title(" Controls ")
             .borders(Borders::ALL)
-            .border_style(Style::default().fg(Color::White)))
-        .style(Style::default().fg(Color::White))
+            .border_style(Style::default().fg(colors.text)))
+        .style(Style::default().fg(colors.text))
         .alignment(Alignment::Center);
 
     f.render_widget(controls_widget, area);
@@ -0,0 +1,205 @@
+// The split layout: the playlist, or whatever browser replaces it, on the
+// left, and what is playing with the tracks coming up on the right
+
+use super::theme::Palette;
+use super::{draw_progress, draw_stream_info, truncate};
+use crate::player::MusicPlayer;
+use tui::{
+    backend::Backend,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, BorderType, Borders, List, ListItem, ListState, Paragraph},
+    Frame,
+};
+
+// Narrower terminals get the stacked layout, two panes would be too cramped
+pub(super) const MIN_WIDTH: u16 = 100;
+// The left pane's share of the width, within these bounds while resizing
+const MIN_PERCENT: u16 = 20;
+const MAX_PERCENT: u16 = 80;
+// Tracks listed in the up-next pane
+const UPCOMING: usize = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pane {
+    // The playlist, albums grid or log
+    Browser,
+    // Tracks coming up next
+    Queue,
+}
+
+/// Width of the two panes, which one has the keys, and the selection in the
+/// up-next list.
+pub struct Split {
+    // The left pane's share of the width in percent, 0 for the stacked layout
+    pub percent: u16,
+    pub focus: Pane,
+    // Whether the last frame was wide enough for two panes
+    pub(super) shown: bool,
+    // Tracks listed in the last frame, by their index in the playlist
+    upcoming: Vec<usize>,
+    state: ListState,
+    // Rows of the list visible in the last frame
+    height: usize,
+}
+
+impl Split {
+    pub fn new(percent: u16) -> Split {
+        Split {
+            percent: if percent == 0 { 0 } else { percent.clamp(MIN_PERCENT, MAX_PERCENT) },
+            focus: Pane::Browser,
+            shown: false,
+            upcoming: Vec::new(),
+            state: ListState::default(),
+            height: 0,
+        }
+    }
+
+    /// Whether keys that move and play go to the up-next list.
+    pub fn queue_focused(&self) -> bool {
+        self.shown && self.focus == Pane::Queue
+    }
+
+    /// Gives the keys to the other pane, false when only one is on screen.
+    pub fn switch_focus(&mut self) -> bool {
+        if !self.shown {
+            return false;
+        }
+        self.focus = match self.focus {
+            Pane::Browser => Pane::Queue,
+            Pane::Queue => Pane::Browser,
+        };
+        true
+    }
+
+    /// Moves the divider by `delta` percent of the width, false when only
+    /// one pane is on screen.
+    pub fn resize(&mut self, delta: i16) -> bool {
+        if !self.shown {
+            return false;
+        }
+        self.percent = (self.percent as i16 + delta).clamp(MIN_PERCENT as i16, MAX_PERCENT as i16) as u16;
+        true
+    }
+
+    /// Moves the selection in the up-next list, stopping at its ends.
+    pub fn move_selection(&mut self, delta: isize) {
+        if self.upcoming.is_empty() {
+            return;
+        }
+        let last = self.upcoming.len() as isize - 1;
+        let selected = self.state.selected().map_or(0, |i| i as isize);
+        self.state.select(Some((selected + delta).clamp(0, last) as usize));
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.height.max(1)
+    }
+
+    /// The playlist index of the track selected in the up-next list.
+    pub fn selected_track(&self) -> Option<usize> {
+        self.state.selected().and_then(|i| self.upcoming.get(i).copied())
+    }
+}
+
+// Draws the right pane, returning the progress line for seeking with the mouse
+pub(super) fn draw_queue<B: Backend>(
+    f: &mut Frame<B>,
+    music_player: &MusicPlayer,
+    split: &mut Split,
+    stream_info: bool,
+    colors: &Palette,
+    area: Rect,
+) -> Option<Rect> {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(4), // Now playing
+            Constraint::Length(3), // Progress bar
+            Constraint::Length(match (stream_info, music_player.current_note()) {
+                (false, _) => 0,
+                (true, None) => 3,
+                (true, Some(_)) => 4,
+            }), // Stream details
+            Constraint::Min(0),    // Up next
+        ].as_ref())
+        .split(area);
+
+    draw_now_playing(f, music_player, colors, chunks[0]);
+    let seek_bar = draw_progress(f, music_player, colors, chunks[1]);
+    if stream_info {
+        draw_stream_info(f, music_player, colors, chunks[2]);
+    }
+    draw_upcoming(f, music_player, split, colors, chunks[3]);
+    seek_bar
+}
+
+fn draw_now_playing<B: Backend>(f: &mut Frame<B>, music_player: &MusicPlayer, colors: &Palette, area: Rect) {
+    let width = area.width.saturating_sub(2) as usize;
+    let style = Style::default().fg(colors.status);
+    let lines = match music_player.current_track {
+        Some(current) => {
+            let record = music_player.track_record(current);
+            let by = [record.and_then(|record| record.artist.clone()), record.and_then(|record| record.album.clone())]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(" — ");
+            vec![
+                Spans::from(Span::styled(truncate(&music_player.track_title(current), width), style.add_modifier(Modifier::BOLD))),
+                Spans::from(Span::styled(truncate(&by, width), style)),
+            ]
+        }
+        None => vec![Spans::from(Span::styled("Nothing playing", style))],
+    };
+    let widget = Paragraph::new(lines)
+        .block(Block::default()
+            .title(" Now playing ")
+            .borders(Borders::ALL)
+            .border_style(style))
+        .alignment(Alignment::Center);
+    f.render_widget(widget, area);
+}
+
+fn draw_upcoming<B: Backend>(f: &mut Frame<B>, music_player: &MusicPlayer, split: &mut Split, colors: &Palette, area: Rect) {
+    // The selection stays on the same track as the list moves along
+    let selected = split.selected_track();
+    split.upcoming = music_player.upcoming(UPCOMING);
+    split.height = area.height.saturating_sub(2) as usize;
+    let position = selected.and_then(|track| split.upcoming.iter().position(|&i| i == track));
+    let fallback = (!split.upcoming.is_empty()).then(|| split.state.selected().unwrap_or(0).min(split.upcoming.len() - 1));
+    split.state.select(position.or(fallback));
+
+    let width = area.width.saturating_sub(2) as usize;
+    let items: Vec<ListItem> = if split.upcoming.is_empty() {
+        vec![ListItem::new("Nothing up next")]
+    } else {
+        split
+            .upcoming
+            .iter()
+            .map(|&i| {
+                let label = match music_player.track_record(i).and_then(|record| record.artist.as_deref()) {
+                    Some(artist) => format!("{:>4}  {} – {}", i + 1, music_player.track_title(i), artist),
+                    None => format!("{:>4}  {}", i + 1, music_player.track_title(i)),
+                };
+                let color = if music_player.never_plays(i) { colors.excluded } else { colors.text };
+                ListItem::new(truncate(&label, width)).style(Style::default().fg(color))
+            })
+            .collect()
+    };
+
+    let focused = split.focus == Pane::Queue;
+    let list = List::new(items)
+        .block(Block::default()
+            .title(" Up next ")
+            .borders(Borders::ALL)
+            .border_type(if focused { BorderType::Thick } else { BorderType::Plain })
+            .border_style(Style::default().fg(colors.playlist)))
+        .highlight_style(if focused {
+            Style::default().fg(colors.selected).bg(colors.playlist).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().add_modifier(Modifier::BOLD)
+        });
+    f.render_stateful_widget(list, area, &mut split.state);
+}
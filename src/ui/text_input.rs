@@ -0,0 +1,150 @@
+use super::dialog::draw_frame;
+use super::theme::Palette;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::ops::Range;
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    style::Style,
+    widgets::Paragraph,
+    Frame,
+};
+
+/// What a key press did to a text input.
+pub enum InputAction {
+    Submit,
+    Cancel,
+    Edited,
+}
+
+/// Single-line text field with a cursor, drawn as a popup.
+pub struct TextInput {
+    pub title: String,
+    // Shown as stars, for passphrases
+    pub secret: bool,
+    value: String,
+    // Cursor position in characters
+    cursor: usize,
+}
+
+impl TextInput {
+    pub fn new(title: &str) -> TextInput {
+        TextInput {
+            title: title.to_string(),
+            secret: false,
+            value: String::new(),
+            cursor: 0,
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> InputAction {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        // Shortcuts typed with Ctrl or Alt aren't text
+        let typed = !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+        match key.code {
+            KeyCode::Enter => return InputAction::Submit,
+            KeyCode::Esc => return InputAction::Cancel,
+            // Readline's editing keys
+            KeyCode::Char('w') if ctrl => self.remove(self.word_start()..self.cursor),
+            KeyCode::Char('u') if ctrl => self.remove(0..self.cursor),
+            KeyCode::Char('k') if ctrl => self.remove(self.cursor..self.len()),
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.len(),
+            KeyCode::Char(c) if typed => self.insert(&c.to_string()),
+            KeyCode::Backspace if !typed => self.remove(self.word_start()..self.cursor),
+            KeyCode::Backspace if self.cursor > 0 => self.remove(self.cursor - 1..self.cursor),
+            KeyCode::Delete if self.cursor < self.len() => self.remove(self.cursor..self.cursor + 1),
+            KeyCode::Left if !typed => self.cursor = self.word_start(),
+            KeyCode::Right if !typed => self.cursor = self.word_end(),
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.len(),
+            _ => {}
+        }
+        InputAction::Edited
+    }
+
+    // Pasted text is inserted as one piece, line breaks dropped
+    pub fn insert(&mut self, text: &str) {
+        let text: String = text.chars().filter(|c| !c.is_control()).collect();
+        self.value.insert_str(self.byte_at(self.cursor), &text);
+        self.cursor += text.chars().count();
+    }
+
+    fn len(&self) -> usize {
+        self.value.chars().count()
+    }
+
+    // Removes the characters in `range`, leaving the cursor where they were
+    fn remove(&mut self, range: Range<usize>) {
+        let bytes = self.byte_at(range.start)..self.byte_at(range.end);
+        self.value.replace_range(bytes, "");
+        self.cursor = range.start;
+    }
+
+    // Start of the word before the cursor, spaces after it included
+    fn word_start(&self) -> usize {
+        let chars: Vec<char> = self.value.chars().take(self.cursor).collect();
+        let mut start = chars.len();
+        while start > 0 && chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        start
+    }
+
+    // End of the word after the cursor
+    fn word_end(&self) -> usize {
+        let chars: Vec<char> = self.value.chars().collect();
+        let mut end = self.cursor;
+        while end < chars.len() && chars[end].is_whitespace() {
+            end += 1;
+        }
+        while end < chars.len() && !chars[end].is_whitespace() {
+            end += 1;
+        }
+        end
+    }
+
+    fn byte_at(&self, chars: usize) -> usize {
+        self.value
+            .char_indices()
+            .nth(chars)
+            .map_or(self.value.len(), |(index, _)| index)
+    }
+}
+
+pub fn draw_text_input<B: Backend>(f: &mut Frame<B>, input: &TextInput, colors: &Palette) {
+    let inner = draw_frame(f, &input.title, 70, 1, colors.prompt);
+    draw_field(f, input, colors, inner);
+}
+
+/// Draws the value on the first row of `area` with the terminal's cursor in
+/// it, for text boxes inside other widgets.
+pub fn draw_field<B: Backend>(f: &mut Frame<B>, input: &TextInput, colors: &Palette, area: Rect) {
+    let width = area.width as usize;
+    // Too small a terminal leaves no room inside the borders
+    if width == 0 || area.height == 0 {
+        return;
+    }
+    // Scroll so the cursor stays visible in long values
+    let skip = (input.cursor + 1).saturating_sub(width);
+    let visible: String = input
+        .value
+        .chars()
+        .skip(skip)
+        .take(width)
+        .map(|c| if input.secret { '*' } else { c })
+        .collect();
+
+    let row = Rect::new(area.x, area.y, area.width, 1);
+    f.render_widget(Paragraph::new(visible).style(Style::default().fg(colors.text)), row);
+    f.set_cursor(area.x + (input.cursor - skip) as u16, area.y);
+}
@@ -0,0 +1,229 @@
+// Colour palettes for the interface, chosen with `theme` in the config. The
+// colour-blind palettes use the Okabe-Ito hues, which stay apart with red and
+// green cone deficiencies, as 256-colour indices most terminals support.
+
+use std::str::FromStr;
+use tui::style::Color;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Theme {
+    Default,
+    HighContrast,
+    Deuteranopia,
+    Protanopia,
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "default" => Ok(Theme::Default),
+            "high-contrast" | "high_contrast" => Ok(Theme::HighContrast),
+            "deuteranopia" => Ok(Theme::Deuteranopia),
+            "protanopia" => Ok(Theme::Protanopia),
+            _ => Err(format!("unknown theme '{}'", s)),
+        }
+    }
+}
+
+impl Theme {
+    /// The name as written in clap.conf.
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Default => "default",
+            Theme::HighContrast => "high-contrast",
+            Theme::Deuteranopia => "deuteranopia",
+            Theme::Protanopia => "protanopia",
+        }
+    }
+
+    pub fn palette(self) -> Palette {
+        match self {
+            Theme::Default => Palette {
+                playlist: Color::Cyan,
+                text: Color::White,
+                excluded: Color::DarkGray,
+                selected: Color::Black,
+                picker: Color::Magenta,
+                progress: Color::Green,
+                progress_rest: Color::DarkGray,
+                marker: Color::Yellow,
+                stream: Color::Blue,
+                status: Color::Yellow,
+                prompt: Color::Yellow,
+                error: Color::Red,
+                warning: Color::Yellow,
+                debug: Color::DarkGray,
+            },
+            // Bright text on the terminal's background, selections in reverse
+            Theme::HighContrast => Palette {
+                playlist: Color::White,
+                text: Color::White,
+                excluded: Color::Gray,
+                selected: Color::Black,
+                picker: Color::Yellow,
+                progress: Color::White,
+                progress_rest: Color::Gray,
+                marker: Color::LightYellow,
+                stream: Color::White,
+                status: Color::LightYellow,
+                prompt: Color::LightYellow,
+                error: Color::LightRed,
+                warning: Color::LightYellow,
+                debug: Color::Gray,
+            },
+            // Blue against orange and yellow, no green
+            Theme::Deuteranopia => Palette {
+                playlist: Color::Indexed(75),
+                text: Color::White,
+                excluded: Color::Indexed(243),
+                selected: Color::Black,
+                picker: Color::Indexed(214),
+                progress: Color::Indexed(75),
+                progress_rest: Color::Indexed(240),
+                marker: Color::Indexed(214),
+                stream: Color::Indexed(175),
+                status: Color::Indexed(227),
+                prompt: Color::Indexed(214),
+                error: Color::Indexed(166),
+                warning: Color::Indexed(227),
+                debug: Color::Indexed(245),
+            },
+            // Reds look dark without red cones, so errors are purple instead
+            Theme::Protanopia => Palette {
+                playlist: Color::Indexed(75),
+                text: Color::White,
+                excluded: Color::Indexed(243),
+                selected: Color::Black,
+                picker: Color::Indexed(227),
+                progress: Color::Indexed(32),
+                progress_rest: Color::Indexed(240),
+                marker: Color::Indexed(227),
+                stream: Color::Indexed(153),
+                status: Color::Indexed(227),
+                prompt: Color::Indexed(227),
+                error: Color::Indexed(177),
+                warning: Color::Indexed(214),
+                debug: Color::Indexed(245),
+            },
+        }
+    }
+}
+
+/// The colour of each part of the interface.
+#[derive(Clone, Copy)]
+pub struct Palette {
+    // Playlist and log borders, the header, the playing track and the
+    // selection bar
+    pub playlist: Color,
+    pub text: Color,
+    // Tracks left out of shuffle and endless play
+    pub excluded: Color,
+    // Text on selection bars
+    pub selected: Color,
+    pub picker: Color,
+    pub progress: Color,
+    // The part of the progress line still to play
+    pub progress_rest: Color,
+    // Loop and bookmark markers on the progress line
+    pub marker: Color,
+    pub stream: Color,
+    pub status: Color,
+    pub prompt: Color,
+    pub error: Color,
+    pub warning: Color,
+    pub debug: Color,
+}
+
+/// A label for tracks, such as `workout`, and the colour of its chips.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Label {
+    pub name: String,
+    pub color: Color,
+}
+
+const COLOR_NAMES: [(&str, Color); 16] = [
+    ("black", Color::Black),
+    ("red", Color::Red),
+    ("green", Color::Green),
+    ("yellow", Color::Yellow),
+    ("blue", Color::Blue),
+    ("magenta", Color::Magenta),
+    ("cyan", Color::Cyan),
+    ("gray", Color::Gray),
+    ("darkgray", Color::DarkGray),
+    ("lightred", Color::LightRed),
+    ("lightgreen", Color::LightGreen),
+    ("lightyellow", Color::LightYellow),
+    ("lightblue", Color::LightBlue),
+    ("lightmagenta", Color::LightMagenta),
+    ("lightcyan", Color::LightCyan),
+    ("white", Color::White),
+];
+
+// Taken in turn by labels given without a colour
+const LABEL_COLORS: [Color; 6] = [Color::Red, Color::Blue, Color::Magenta, Color::Green, Color::Yellow, Color::Cyan];
+
+impl Label {
+    /// Whether a name can be a label: commas, colons and semicolons separate
+    /// them in clap.conf and library.db.
+    pub fn is_name(name: &str) -> bool {
+        !name.is_empty() && !name.contains([',', ':', ';', '\t', '\n', '\r'])
+    }
+
+    /// Reads `name[:colour]` entries separated by `;`, where the colour is a
+    /// name such as `lightblue`, a 256-colour index or `#rrggbb`.
+    pub fn parse_list(text: &str) -> Result<Vec<Label>, String> {
+        let mut labels = Vec::new();
+        for entry in text.split(';').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (name, color) = match entry.split_once(':') {
+                Some((name, color)) => {
+                    let color = parse_color(color.trim()).ok_or_else(|| format!("unknown colour '{}'", color.trim()))?;
+                    (name.trim(), color)
+                }
+                None => (entry, LABEL_COLORS[labels.len() % LABEL_COLORS.len()]),
+            };
+            if !Label::is_name(name) {
+                return Err(format!("'{}' can't be a label", name));
+            }
+            labels.push(Label { name: name.to_string(), color });
+        }
+        Ok(labels)
+    }
+
+    /// Labels as written in clap.conf.
+    pub fn list_to_string(labels: &[Label]) -> String {
+        labels
+            .iter()
+            .map(|label| format!("{}:{}", label.name, color_name(label.color)))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    /// A new label, coloured after the ones there are.
+    pub fn next(name: &str, labels: &[Label]) -> Label {
+        Label { name: name.to_string(), color: LABEL_COLORS[labels.len() % LABEL_COLORS.len()] }
+    }
+}
+
+fn parse_color(text: &str) -> Option<Color> {
+    let text = text.to_lowercase();
+    if let Some(hex) = text.strip_prefix('#') {
+        let value = u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 6)?;
+        return Some(Color::Rgb((value >> 16) as u8, (value >> 8) as u8, value as u8));
+    }
+    if let Ok(index) = text.parse() {
+        return Some(Color::Indexed(index));
+    }
+    let text = text.replace(['-', '_', ' '], "").replace("grey", "gray");
+    COLOR_NAMES.iter().find(|(name, _)| *name == text).map(|&(_, color)| color)
+}
+
+fn color_name(color: Color) -> String {
+    match color {
+        Color::Rgb(r, g, b) => format!("#{:02x}{:02x}{:02x}", r, g, b),
+        Color::Indexed(index) => index.to_string(),
+        color => COLOR_NAMES.iter().find(|(_, named)| *named == color).map_or("white", |&(name, _)| name).to_string(),
+    }
+}
@@ -0,0 +1,113 @@
+// src/utils/audio.rs
+
+use crate::player::dsp::SILENCE_THRESHOLD;
+use crate::decode;
+use rodio::Source;
+use std::path::Path;
+use std::time::Duration;
+
+// Audible content starts this much earlier than detected to keep soft fade-ins
+const TRIM_MARGIN: Duration = Duration::from_millis(100);
+// Frames per step of the onset envelope used for tempo detection
+const ENVELOPE_HOP: u64 = 256;
+// Only the first couple of minutes are needed to find the tempo
+const TEMPO_WINDOW_SECS: f64 = 120.0;
+const MIN_BPM: f64 = 60.0;
+const MAX_BPM: f64 = 200.0;
+
+#[derive(Default)]
+pub struct Analysis {
+    // Start and end of the audible part
+    pub silence: Option<(Duration, Duration)>,
+    pub bpm: Option<f32>,
+    // Average RMS level of the audible part in dBFS
+    pub loudness: Option<f32>,
+}
+
+/// Decodes the whole file once and gathers everything the library caches about it.
+pub fn analyze_track(path: &Path) -> Analysis {
+    let Ok(source) = decode::open(path, 0) else {
+        return Analysis::default();
+    };
+    let channels = source.channels().max(1) as u64;
+    let rate = source.sample_rate().max(1) as f64;
+
+    let mut first = None;
+    let mut last = 0;
+    let mut envelope = Vec::new();
+    let mut energy = 0.0f64;
+    let mut loud_energy = 0.0f64;
+    let mut loud_samples = 0u64;
+    for (i, sample) in source.enumerate() {
+        let frame = i as u64 / channels;
+        if sample.abs() >= SILENCE_THRESHOLD {
+            first.get_or_insert(frame);
+            last = frame + 1;
+        }
+
+        energy += (sample * sample) as f64;
+        if first.is_some() {
+            loud_energy += (sample * sample) as f64;
+            loud_samples += 1;
+        }
+        if (i as u64 + 1).is_multiple_of(ENVELOPE_HOP * channels) {
+            envelope.push(energy);
+            energy = 0.0;
+        }
+    }
+
+    let silence = first.map(|first| {
+        let start = Duration::from_secs_f64(first as f64 / rate).saturating_sub(TRIM_MARGIN);
+        let end = Duration::from_secs_f64(last as f64 / rate) + TRIM_MARGIN;
+        (start, end)
+    });
+    let bpm = detect_bpm(&envelope, rate / ENVELOPE_HOP as f64);
+    let loudness = (loud_samples > 0 && loud_energy > 0.0)
+        .then(|| (10.0 * (loud_energy / loud_samples as f64).log10()) as f32);
+    Analysis { silence, bpm, loudness }
+}
+
+// Autocorrelates the onset strength (rises in energy) and picks the strongest
+// beat period, favoring tempos around 120 BPM to avoid half/double errors
+fn detect_bpm(envelope: &[f64], envelope_rate: f64) -> Option<f32> {
+    let window = (TEMPO_WINDOW_SECS * envelope_rate) as usize;
+    let onsets: Vec<f64> = envelope
+        .windows(2)
+        .take(window)
+        .map(|pair| (pair[1] - pair[0]).max(0.0))
+        .collect();
+
+    let min_lag = (60.0 * envelope_rate / MAX_BPM).floor() as usize;
+    let max_lag = (60.0 * envelope_rate / MIN_BPM).ceil() as usize;
+    if min_lag == 0 || onsets.len() < max_lag * 4 {
+        return None;
+    }
+
+    let correlation: Vec<f64> = (min_lag..=max_lag + 1)
+        .map(|lag| onsets.iter().zip(&onsets[lag..]).map(|(a, b)| a * b).sum())
+        .collect();
+
+    let mut best = None;
+    let mut best_score = 0.0;
+    for (i, value) in correlation.iter().enumerate().take(correlation.len() - 1).skip(1) {
+        let bpm = 60.0 * envelope_rate / (min_lag + i) as f64;
+        let weight = (-0.5 * (bpm / 120.0).log2().powi(2) / 0.5f64.powi(2)).exp();
+        let score = value * weight;
+        if score > best_score {
+            best_score = score;
+            best = Some(i);
+        }
+    }
+
+    // Refine the peak between lags with a parabolic fit
+    let i = best?;
+    let (prev, peak, next) = (correlation[i - 1], correlation[i], correlation[i + 1]);
+    let denominator = prev - 2.0 * peak + next;
+    let offset = if denominator.abs() > f64::EPSILON {
+        (0.5 * (prev - next) / denominator).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+    let lag = (min_lag + i) as f64 + offset;
+    Some((60.0 * envelope_rate / lag) as f32)
+}
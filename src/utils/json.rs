@@ -0,0 +1,240 @@
+// Just enough JSON to read the answers of media servers
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Debug, PartialEq)]
+pub enum Json {
+    Null,
+    // Nothing reads flags yet
+    #[allow(dead_code)]
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut chars = text.chars().peekable();
+        let value = parse_value(&mut chars)?;
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            None => Ok(value),
+            Some(c) => Err(format!("unexpected '{}' after the JSON value", c)),
+        }
+    }
+
+    /// The field of an object, None for other values and missing fields.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn expect(chars: &mut Peekable<Chars>, word: &str) -> Result<(), String> {
+    for expected in word.chars() {
+        if chars.next() != Some(expected) {
+            return Err(format!("expected '{}'", word));
+        }
+    }
+    Ok(())
+}
+
+fn parse_value(chars: &mut Peekable<Chars>) -> Result<Json, String> {
+    skip_whitespace(chars);
+    match chars.peek().copied() {
+        Some('{') => {
+            chars.next();
+            let mut fields = Vec::new();
+            skip_whitespace(chars);
+            if chars.next_if_eq(&'}').is_some() {
+                return Ok(Json::Object(fields));
+            }
+            loop {
+                skip_whitespace(chars);
+                let key = parse_string(chars)?;
+                skip_whitespace(chars);
+                expect(chars, ":")?;
+                fields.push((key, parse_value(chars)?));
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some('}') => return Ok(Json::Object(fields)),
+                    _ => return Err("expected ',' or '}' in an object".to_string()),
+                }
+            }
+        }
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            skip_whitespace(chars);
+            if chars.next_if_eq(&']').is_some() {
+                return Ok(Json::Array(items));
+            }
+            loop {
+                items.push(parse_value(chars)?);
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(']') => return Ok(Json::Array(items)),
+                    _ => return Err("expected ',' or ']' in an array".to_string()),
+                }
+            }
+        }
+        Some('"') => parse_string(chars).map(Json::String),
+        Some('t') => expect(chars, "true").map(|_| Json::Bool(true)),
+        Some('f') => expect(chars, "false").map(|_| Json::Bool(false)),
+        Some('n') => expect(chars, "null").map(|_| Json::Null),
+        Some(c) if c == '-' || c.is_ascii_digit() => {
+            let mut number = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+                number.push(c);
+            }
+            number.parse().map(Json::Number).map_err(|_| format!("invalid number '{}'", number))
+        }
+        Some(c) => Err(format!("unexpected '{}'", c)),
+        None => Err("unexpected end of JSON".to_string()),
+    }
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    expect(chars, "\"")?;
+    let mut text = String::new();
+    loop {
+        match chars.next().ok_or("unterminated string")? {
+            '"' => return Ok(text),
+            '\\' => match chars.next().ok_or("unterminated string")? {
+                'n' => text.push('\n'),
+                'r' => text.push('\r'),
+                't' => text.push('\t'),
+                'b' => text.push('\u{8}'),
+                'f' => text.push('\u{c}'),
+                'u' => {
+                    let code = hex4(chars)?;
+                    // Characters outside the BMP come as a surrogate pair; a
+                    // half of one on its own is replaced, and what follows kept
+                    if (0xD800..0xDC00).contains(&code) && chars.next_if_eq(&'\\').is_some() {
+                        expect(chars, "u")?;
+                        let low = hex4(chars)?;
+                        if (0xDC00..0xE000).contains(&low) {
+                            text.push(char::from_u32(0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00)).unwrap_or('\u{FFFD}'));
+                        } else {
+                            text.push('\u{FFFD}');
+                            text.push(char::from_u32(low).unwrap_or('\u{FFFD}'));
+                        }
+                    } else {
+                        text.push(char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    }
+                }
+                c => text.push(c),
+            },
+            c => text.push(c),
+        }
+    }
+}
+
+fn hex4(chars: &mut Peekable<Chars>) -> Result<u32, String> {
+    let digits: String = chars.by_ref().take(4).collect();
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid escape '\\u{}'", digits));
+    }
+    u32::from_str_radix(&digits, 16).map_err(|_| format!("invalid escape '\\u{}'", digits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Json;
+
+    fn string(json: &str) -> String {
+        Json::parse(json).unwrap().as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(string(r#""a\"b\\c\/d""#), "a\"b\\c/d");
+        assert_eq!(string(r#""\n\r\t\b\f""#), "\n\r\t\u{8}\u{c}");
+    }
+
+    #[test]
+    fn unicode() {
+        assert_eq!(string(r#""caf\u00e9""#), "café");
+        assert_eq!(string(r#""Björk – Jóga""#), "Björk – Jóga");
+        assert_eq!(string(r#""\ud83c\udfb5""#), "🎵");
+        // Halves of a pair on their own
+        assert_eq!(string(r#""\ud83c""#), "\u{FFFD}");
+        assert_eq!(string(r#""\ud83c\u0041""#), "\u{FFFD}A");
+        assert_eq!(string(r#""\udfb5x""#), "\u{FFFD}x");
+    }
+
+    #[test]
+    fn nesting() {
+        let json = Json::parse(r#" {"Items": [{"Name": "Intro", "RunTimeTicks": 1.5e7, "Tags": []}, null, true],
+            "Total": -2, "Empty": {}} "#)
+        .unwrap();
+        let items = json.get("Items").unwrap().as_array();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].get("Name").and_then(Json::as_str), Some("Intro"));
+        assert_eq!(items[0].get("RunTimeTicks").and_then(Json::as_f64), Some(15_000_000.0));
+        assert!(items[0].get("Tags").unwrap().as_array().is_empty());
+        assert_eq!(items[1], Json::Null);
+        assert_eq!(items[2], Json::Bool(true));
+        assert_eq!(json.get("Total").and_then(Json::as_f64), Some(-2.0));
+        assert_eq!(json.get("Empty"), Some(&Json::Object(Vec::new())));
+        assert_eq!(json.get("Missing"), None);
+        assert_eq!(Json::parse("[[[[1]]]]").unwrap().as_array()[0].as_array()[0].as_array()[0].as_array()[0], Json::Number(1.0));
+    }
+
+    #[test]
+    fn malformed() {
+        for json in [
+            "",
+            "   ",
+            r#""unterminated"#,
+            r#""bad \u12""#,
+            r#""bad \u+041""#,
+            r#"{"key" 1}"#,
+            r#"{"key": 1,}"#,
+            r#"{key: 1}"#,
+            "[1, 2",
+            "[1,]",
+            "[1 2]",
+            "tru",
+            "nul",
+            "-",
+            "1.2.3",
+            "{} {}",
+            "[1] x",
+        ] {
+            assert!(Json::parse(json).is_err(), "{:?} parsed", json);
+        }
+    }
+}
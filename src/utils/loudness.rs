@@ -0,0 +1,157 @@
+// src/utils/loudness.rs
+
+use crate::decode;
+use rodio::Source;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::path::Path;
+
+// Gating thresholds from EBU R128 / ITU-R BS.1770
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+pub struct TrackLoudness {
+    // Mean square energy of every 400 ms block, 75% overlapping
+    pub blocks: Vec<f64>,
+    pub peak: f32,
+}
+
+/// Measures a file for integrated loudness calculations.
+pub fn measure(path: &Path) -> Option<TrackLoudness> {
+    let source = decode::open(path, 0).ok()?;
+    let channels = source.channels().max(1) as usize;
+    let mut meter = LoudnessMeter::new(channels, source.sample_rate());
+
+    let mut frame = Vec::with_capacity(channels);
+    for sample in source {
+        frame.push(sample);
+        if frame.len() == channels {
+            meter.push_frame(&frame);
+            frame.clear();
+        }
+    }
+    Some(TrackLoudness {
+        blocks: meter.blocks,
+        peak: meter.peak,
+    })
+}
+
+/// Integrated loudness in LUFS of one or more tracks' blocks.
+pub fn integrated_loudness<'a>(blocks: impl Iterator<Item = &'a f64> + Clone) -> Option<f64> {
+    let absolute_gate = loudness_to_energy(ABSOLUTE_GATE_LUFS);
+    let gated_mean = |threshold: f64| {
+        let (sum, count) = blocks
+            .clone()
+            .filter(|&&energy| energy > threshold)
+            .fold((0.0, 0usize), |(sum, count), energy| (sum + energy, count + 1));
+        (count > 0).then(|| sum / count as f64)
+    };
+
+    let relative_gate = energy_to_loudness(gated_mean(absolute_gate)?) + RELATIVE_GATE_LU;
+    gated_mean(loudness_to_energy(relative_gate).max(absolute_gate)).map(energy_to_loudness)
+}
+
+fn energy_to_loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn loudness_to_energy(loudness: f64) -> f64 {
+    10f64.powf((loudness + 0.691) / 10.0)
+}
+
+struct LoudnessMeter {
+    // K-weighting pre-filter and high-pass per channel
+    filters: Vec<(Biquad, Biquad)>,
+    sub_block_frames: usize,
+    frames: usize,
+    energy: f64,
+    // Energy of the last four 100 ms sub-blocks
+    sub_blocks: VecDeque<f64>,
+    blocks: Vec<f64>,
+    peak: f32,
+}
+
+impl LoudnessMeter {
+    fn new(channels: usize, sample_rate: u32) -> Self {
+        let rate = sample_rate.max(1) as f64;
+        LoudnessMeter {
+            filters: (0..channels)
+                .map(|_| (Biquad::pre_filter(rate), Biquad::high_pass(rate)))
+                .collect(),
+            sub_block_frames: (rate / 10.0).round().max(1.0) as usize,
+            frames: 0,
+            energy: 0.0,
+            sub_blocks: VecDeque::with_capacity(4),
+            blocks: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    fn push_frame(&mut self, frame: &[f32]) {
+        for (sample, (shelf, high_pass)) in frame.iter().zip(self.filters.iter_mut()) {
+            self.peak = self.peak.max(sample.abs());
+            let weighted = high_pass.process(shelf.process(*sample as f64));
+            self.energy += weighted * weighted;
+        }
+
+        self.frames += 1;
+        if self.frames == self.sub_block_frames {
+            if self.sub_blocks.len() == 4 {
+                self.sub_blocks.pop_front();
+            }
+            self.sub_blocks.push_back(self.energy / self.sub_block_frames as f64);
+            if self.sub_blocks.len() == 4 {
+                self.blocks.push(self.sub_blocks.iter().sum::<f64>() / 4.0);
+            }
+            self.frames = 0;
+            self.energy = 0.0;
+        }
+    }
+}
+
+// Coefficients follow BS.1770's analog prototypes so any sample rate works
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn pre_filter(rate: f64) -> Self {
+        let f0 = 1681.974450955533;
+        let gain_db = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Biquad {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn high_pass(rate: f64) -> Self {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
@@ -1,26 +1,329 @@
 // src/utils/mod.rs
 
 pub mod audio;
+pub mod json;
+pub mod loudness;
+pub mod text;
 
 // Export any additional utility functions here as needed
 
 use std::path::{Path, PathBuf};
 use std::fs;
 
+use crate::player::{Repeat, Shuffle};
+use crate::rules::Rule;
+use crate::{archive, remote};
+use std::error::Error;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+fn is_music_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            matches!(
+                extension.to_lowercase().as_str(),
+                "mp3" | "wav" | "flac" | "ogg" | "mid" | "midi" | "aif" | "aiff" | "aifc" | "mka" | "m4a" | "m4b"
+                    | "wv" | "ape"
+            )
+        })
+}
+
 pub fn scan_music_directory(dir: &Path) -> Vec<PathBuf> {
     let mut music_files = Vec::new();
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(extension) = path.extension() {
-                    match extension.to_str().unwrap_or("").to_lowercase().as_str() {
-                        "mp3" | "wav" | "flac" | "ogg" => music_files.push(path),
-                        _ => continue,
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!(target: "scanner", "could not read {}: {}", dir.display(), e);
+            return music_files;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        if archive::is_archive(&path) {
+            // Tracks inside archives are listed as if the archive were a folder
+            let archived = match archive::entries(&path) {
+                Ok(archived) => archived,
+                Err(e) => {
+                    log::warn!(target: "scanner", "skipping archive {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let mut tracks: Vec<PathBuf> = archived
+                .iter()
+                .map(|entry| path.join(&entry.name))
+                .filter(|track| is_music_file(track))
+                .collect();
+            tracks.sort();
+            music_files.extend(tracks);
+        } else if is_music_file(&path) {
+            music_files.push(path);
+        } else {
+            log::debug!(target: "scanner", "skipping {}: not a supported format", path.display());
+        }
+    }
+    log::info!(target: "scanner", "found {} track(s) in {}", music_files.len(), dir.display());
+    music_files
+}
+
+/// Lists the tracks in a configured library root, which may be a local folder
+/// or an smb://, sftp://, dav:// or jellyfin:// URL.
+pub fn scan_library_root(root: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if remote::is_jellyfin(Path::new(root)) {
+        return remote::jellyfin::list(root);
+    }
+    if remote::is_webdav(Path::new(root)) {
+        return Ok(remote::webdav::list(root)?.into_iter().filter(|path| is_music_file(path)).collect());
+    }
+    let dir = remote::resolve_root(root)?;
+    if remote::is_sftp(&dir) {
+        return Ok(remote::list(&dir)?.into_iter().filter(|path| is_music_file(path)).collect());
+    }
+    if !dir.is_dir() {
+        return Err("not a folder".into());
+    }
+    Ok(scan_music_directory(&dir))
+}
+
+pub fn is_playlist(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| matches!(extension.to_lowercase().as_str(), "m3u" | "m3u8" | "pls"))
+}
+
+pub struct Playlist {
+    pub entries: Vec<String>,
+    pub settings: PlaylistSettings,
+    // From `#CLAP-RULE:` lines, picking tracks from the library
+    pub rules: Vec<Rule>,
+}
+
+/// Playback settings a playlist asks for with `#CLAP:` lines, e.g.
+/// `#CLAP: shuffle=off repeat=off speed=1.5`.
+#[derive(Default)]
+pub struct PlaylistSettings {
+    pub shuffle: Option<Shuffle>,
+    pub repeat: Option<Repeat>,
+    pub crossfade: Option<Duration>,
+    pub speed: Option<f32>,
+    pub trim_silence: Option<bool>,
+}
+
+impl PlaylistSettings {
+    // Unknown or malformed settings are ignored, like in clap.conf
+    fn parse(&mut self, line: &str) {
+        for (key, value) in line.split_whitespace().filter_map(|setting| setting.split_once('=')) {
+            match key.to_lowercase().as_str() {
+                "shuffle" => self.shuffle = value.parse().ok().or(self.shuffle),
+                "repeat" => self.repeat = value.parse().ok().or(self.repeat),
+                "crossfade" => {
+                    let seconds = value.parse::<f32>().ok().filter(|seconds| seconds.is_finite() && *seconds >= 0.0);
+                    self.crossfade = seconds.map(Duration::from_secs_f32).or(self.crossfade);
+                }
+                "speed" => self.speed = value.trim_end_matches(['x', '×']).parse().ok().or(self.speed),
+                "trim" => {
+                    self.trim_silence = match value.to_lowercase().as_str() {
+                        "on" | "true" => Some(true),
+                        "off" | "false" => Some(false),
+                        _ => self.trim_silence,
                     }
                 }
+                _ => {}
             }
         }
     }
-    music_files
-}
\ No newline at end of file
+
+    pub fn is_empty(&self) -> bool {
+        self.shuffle.is_none()
+            && self.repeat.is_none()
+            && self.crossfade.is_none()
+            && self.speed.is_none()
+            && self.trim_silence.is_none()
+    }
+}
+
+/// Reads the entries of an M3U or PLS playlist and the settings and rules it
+/// asks for. Relative paths are resolved against the playlist's folder, URLs
+/// are kept as they are.
+pub fn read_playlist(path: &Path) -> Result<Playlist, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    let is_pls = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pls"));
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut settings = PlaylistSettings::default();
+    let mut rules = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        if let Some(line) = line.trim().strip_prefix("#CLAP:") {
+            settings.parse(line);
+        } else if let Some(rule) = line.trim().strip_prefix("#CLAP-RULE:") {
+            // A rule read wrong would fill the playlist with the wrong tracks
+            rules.push(Rule::parse(rule).map_err(|e| format!("line {}: {}", number + 1, e))?);
+        }
+    }
+    let entries = contents
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| {
+            if is_pls {
+                // PLS entries are File1=..., File2=...
+                let (key, value) = line.split_once('=')?;
+                key.to_lowercase().starts_with("file").then_some(value.trim())
+            } else {
+                (!line.is_empty() && !line.starts_with('#')).then_some(line)
+            }
+        })
+        .map(|entry| {
+            if entry.contains("://") || Path::new(entry).is_absolute() {
+                entry.to_string()
+            } else {
+                dir.join(entry).to_string_lossy().into_owned()
+            }
+        })
+        .collect();
+    Ok(Playlist { entries, settings, rules })
+}
+
+/// Lists the playlists in a folder, sorted by name.
+pub fn list_playlists(dir: &Path) -> Vec<PathBuf> {
+    let mut playlists: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).filter(|path| is_playlist(path)).collect())
+        .unwrap_or_default();
+    playlists.sort();
+    playlists
+}
+
+/// Scores how well `query` matches `text` when its letters appear in order,
+/// ignoring case. Letters next to each other or at the start of a word score
+/// higher, and shorter texts win ties. None when a letter is missing.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text::fold(text).0.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous = None;
+    for wanted in text::fold(query).0.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = (next..text.len()).find(|&i| text[i] == wanted)?;
+        score += 100;
+        if found > 0 && previous == Some(found - 1) {
+            score += 500;
+        }
+        if found == 0 || !text[found - 1].is_alphanumeric() {
+            score += 300;
+        }
+        previous = Some(found);
+        next = found + 1;
+    }
+    Some(score - text.len() as i32)
+}
+
+/// Whether a file name matches a pattern where `*` stands for any run of
+/// characters and `?` for one, ignoring case.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and how much of the name it has taken so far
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Replaces a file through a temporary one next to it, so a crash while
+/// writing leaves the previous contents in place.
+pub fn write_atomically(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, contents)?;
+    fs::rename(temporary, path)
+}
+
+/// Parses a time typed as seconds, `m:ss` or `h:mm:ss`.
+pub fn parse_time(text: &str) -> Option<Duration> {
+    let mut seconds = 0.0;
+    for part in text.trim().split(':') {
+        let value: f64 = part.trim().parse().ok()?;
+        if value < 0.0 {
+            return None;
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    seconds.is_finite().then(|| Duration::from_secs_f64(seconds))
+}
+
+/// Replaces `{field}` in a template with the value returned for it. Unknown
+/// fields are left as typed so mistakes stay visible.
+pub fn fill_template(template: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    let mut filled = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        filled.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('}').map(|close| open + close) else {
+            rest = &rest[open..];
+            break;
+        };
+        match value(&rest[open + 1..close]) {
+            Some(text) => filled.push_str(&text),
+            None => filled.push_str(&rest[open..=close]),
+        }
+        rest = &rest[close + 1..];
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// Runs curl and returns what it downloaded as text. The options, such as
+/// `url`, `user` and `header`, are passed in curl's config format on its
+/// input, which keeps passwords and tokens out of the process list. Values
+/// with a line break are refused, as the next line would be read as an option.
+pub fn curl(options: &[(&str, &str)], args: &[&str]) -> Result<String, Box<dyn Error>> {
+    Ok(String::from_utf8_lossy(&curl_bytes(options, args)?).into_owned())
+}
+
+pub fn curl_bytes(options: &[(&str, &str)], args: &[&str]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if let Some((name, _)) = options.iter().find(|(_, value)| value.contains(['\r', '\n'])) {
+        return Err(format!("the {} for curl has a line break in it", name).into());
+    }
+    let mut curl = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--config", "-"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not start curl: {}", e))?;
+    if let Some(mut stdin) = curl.stdin.take() {
+        let config: String = options
+            .iter()
+            .map(|(name, value)| format!("{} = \"{}\"\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")))
+            .collect();
+        stdin.write_all(config.as_bytes())?;
+    }
+    let output = curl.wait_with_output()?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().trim_start_matches("curl: ").into());
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,52 @@
+// Searches compare text folded to one form, so a query typed on a Persian
+// keyboard finds tags written with Arabic letters and the other way round,
+// and accents, vowel marks or the digits used don't get in the way
+
+/// Folds text for searching, keeping case. The second value gives, for each
+/// byte of the result and one past its end, the byte of `text` it came from.
+pub fn fold(text: &str) -> (String, Vec<usize>) {
+    let mut folded = String::with_capacity(text.len());
+    let mut origins = Vec::with_capacity(text.len() + 1);
+    for (at, c) in text.char_indices() {
+        if let Some(c) = fold_char(c) {
+            folded.push(c);
+            origins.extend(std::iter::repeat_n(at, c.len_utf8()));
+        }
+    }
+    origins.push(text.len());
+    (folded, origins)
+}
+
+// None for marks that are dropped
+fn fold_char(c: char) -> Option<char> {
+    let folded = match c {
+        // Harakat, superscript alef, tatweel, joiners and Latin combining accents
+        '\u{064B}'..='\u{065F}' | '\u{0670}' | '\u{0640}' | '\u{200C}' | '\u{200D}' | '\u{0300}'..='\u{036F}' => return None,
+        // Arabic yeh and kaf are the Persian letters' look-alikes
+        'ي' | 'ى' => 'ی',
+        'ك' => 'ک',
+        'ة' => 'ه',
+        'أ' | 'إ' | 'آ' | 'ٱ' => 'ا',
+        'ؤ' => 'و',
+        '٠'..='٩' => char::from(b'0' + (c as u32 - '٠' as u32) as u8),
+        '۰'..='۹' => char::from(b'0' + (c as u32 - '۰' as u32) as u8),
+        'À'..='Å' => 'A',
+        'à'..='å' => 'a',
+        'Ç' => 'C',
+        'ç' => 'c',
+        'È'..='Ë' => 'E',
+        'è'..='ë' => 'e',
+        'Ì'..='Ï' => 'I',
+        'ì'..='ï' => 'i',
+        'Ñ' => 'N',
+        'ñ' => 'n',
+        'Ò'..='Ö' | 'Ø' => 'O',
+        'ò'..='ö' | 'ø' => 'o',
+        'Ù'..='Ü' => 'U',
+        'ù'..='ü' => 'u',
+        'Ý' => 'Y',
+        'ý' | 'ÿ' => 'y',
+        _ => c,
+    };
+    Some(folded)
+}
@@ -0,0 +1,102 @@
+// Audio from video sites, fetched with yt-dlp into a local cache
+
+use crate::config::config_dir;
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+// Formats the decoders handle natively come first, anything else plays through ffmpeg
+const FORMAT: &str = "bestaudio[ext=m4a]/bestaudio[ext=mp3]/bestaudio/best";
+
+pub fn is_url(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+// Web pages to hand to yt-dlp, as opposed to direct links to audio files and
+// streams, which play as they are
+pub fn is_page(location: &str) -> bool {
+    let path = location.split(['?', '#']).next().unwrap_or(location);
+    let extension = path.rsplit_once('.').map(|(_, extension)| extension.to_lowercase());
+    is_url(location)
+        && !matches!(
+            extension.as_deref(),
+            Some("mp3" | "aac" | "ogg" | "opus" | "flac" | "wav" | "m4a" | "m3u8")
+        )
+}
+
+/// A downloaded track and the title the site gives it.
+pub struct Fetched {
+    pub url: String,
+    pub path: PathBuf,
+    pub title: String,
+}
+
+/// Downloads the audio of a page, or returns the cached copy from an earlier fetch.
+pub fn fetch(url: &str) -> Result<Fetched, Box<dyn Error>> {
+    let cache = config_dir().join("cache");
+    std::fs::create_dir_all(&cache)?;
+    let output = Command::new("yt-dlp")
+        .args(["--no-playlist", "--no-simulate", "--no-progress", "-f", FORMAT])
+        .arg("-o")
+        .arg(cache.join("%(id)s.%(ext)s"))
+        .args(["--print", "title", "--print", "after_move:filepath", "--"])
+        .arg(url)
+        .output()
+        .map_err(|e| format!("could not start yt-dlp: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or("yt-dlp failed");
+        return Err(reason.trim_start_matches("ERROR: ").to_string().into());
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines().filter(|line| !line.is_empty());
+    let (Some(title), Some(path)) = (lines.next(), lines.next_back()) else {
+        return Err("yt-dlp returned no file".into());
+    };
+    Ok(Fetched {
+        url: url.to_string(),
+        path: PathBuf::from(path),
+        title: title.to_string(),
+    })
+}
+
+/// Runs fetches started from the UI on a background thread.
+pub struct Fetcher {
+    jobs: Sender<String>,
+    results: Receiver<Result<Fetched, String>>,
+    pending: usize,
+}
+
+impl Fetcher {
+    pub fn new() -> Self {
+        let (jobs, job_rx) = mpsc::channel::<String>();
+        let (result_tx, results) = mpsc::channel();
+        thread::spawn(move || {
+            for url in job_rx {
+                let result = fetch(&url).map_err(|e| format!("{}: {}", url, e));
+                if result_tx.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+        Fetcher { jobs, results, pending: 0 }
+    }
+
+    pub fn queue(&mut self, url: String) {
+        if self.jobs.send(url).is_ok() {
+            self.pending += 1;
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    pub fn poll(&mut self) -> Vec<Result<Fetched, String>> {
+        let finished: Vec<_> = self.results.try_iter().collect();
+        self.pending -= finished.len();
+        finished
+    }
+}